@@ -1,12 +1,50 @@
-use clap::{Parser, Subcommand};
-use indicatif::{ProgressBar, ProgressStyle};
-use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand};
+use indicatif::{HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use chrono::NaiveDate;
 use nextdownloader_core::{
+    Aria2cTool,
+    BandwidthConfig,
+    CollisionPolicy,
+    Config,
+    Decision,
+    DecisionAction,
+    DownloadArchive,
+    DownloadError,
     DownloadManager, 
     Downloader, 
     DownloadOptions, 
+    DownloadTask,
+    ErrorCode,
+    ExternalTool,
+    FilenameTemplate,
+    FormatInfo,
+    FormatSelection,
+    InstallOutcome,
+    InstallerConfig,
+    OptionOverrides,
+    Playlist,
+    PlaylistEntry,
+    PlaylistItems,
+    PlaylistSelection,
     ProgressInfo,
-    VideoFormat
+    QueueConfig,
+    SpeedLimit,
+    SubtitleFormat,
+    TaskStatus,
+    ToolManager,
+    ToolManifest,
+    ToolPaths,
+    VideoFormat,
+    DEFAULT_FILENAME_TEMPLATE,
+    DEFAULT_PLAYLIST_FILENAME_TEMPLATE,
+    content_type_from_url,
+    describe_limit,
+    may_be_playlist,
+    parse_cookie,
+    parse_date,
+    parse_header
 };
 use anyhow::{Result, Context};
 
@@ -17,72 +55,473 @@ use anyhow::{Result, Context};
 struct Cli {
     #[clap(subcommand)]
     command: Commands,
+
+    #[clap(flatten)]
+    tools: ToolArgs,
+
+    /// 全体の速度制限（例: 2M、0で無制限。設定ファイルの [bandwidth] の代わりに使う）
+    #[clap(long, global = true)]
+    overall_limit: Option<SpeedLimit>,
+}
+
+/// 外部ツールの場所の指定
+///
+/// 指定しなかったツールは環境変数、PATH、インストールしたツール、同梱ツールのディレクトリの順に探す。
+#[derive(Args)]
+struct ToolArgs {
+    /// yt-dlpの実行ファイル（環境変数 NEXTDOWNLOADER_YTDLP でも指定可能）
+    #[clap(long, global = true)]
+    ytdlp_path: Option<PathBuf>,
+
+    /// aria2cの実行ファイル（環境変数 NEXTDOWNLOADER_ARIA2C でも指定可能）
+    #[clap(long, global = true)]
+    aria2c_path: Option<PathBuf>,
+
+    /// ffmpegの実行ファイル（環境変数 NEXTDOWNLOADER_FFMPEG でも指定可能）
+    #[clap(long, global = true)]
+    ffmpeg_path: Option<PathBuf>,
+}
+
+impl ToolArgs {
+    /// 設定ファイルの指定に重ねて外部ツールの場所の設定を作る
+    fn tool_paths(&self, config: &ToolPaths) -> ToolPaths {
+        ToolPaths {
+            ytdlp: self.ytdlp_path.clone().or_else(|| config.ytdlp.clone()),
+            aria2c: self.aria2c_path.clone().or_else(|| config.aria2c.clone()),
+            ffmpeg: self.ffmpeg_path.clone().or_else(|| config.ffmpeg.clone()),
+            managed_dir: config.managed_dir.clone(),
+            bundled_dir: config.bundled_dir.clone(),
+        }
+    }
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// URLから動画をダウンロード
     Download {
-        /// ダウンロードするURL
-        #[clap(short, long)]
-        url: String,
+        #[clap(flatten)]
+        args: Box<DownloadArgs>,
         
+        /// ダウンロードせずに選択可能なフォーマットを一覧表示
+        #[clap(long)]
+        list_formats: bool,
+    },
+    
+    /// ダウンロードキューを操作
+    Queue {
+        #[clap(subcommand)]
+        command: QueueCommands,
+    },
+    
+    /// 出力ディレクトリ内の中断されたダウンロードを再開
+    Resume {
         /// 出力ディレクトリ
-        #[clap(short, long, default_value = ".")]
+        #[clap(default_value = ".")]
         output: PathBuf,
+    },
+    
+    /// システム状態を確認
+    Check,
+    
+    /// 設定ファイルを操作
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommands,
+    },
+    
+    /// 外部ツールをインストール・更新
+    Tools {
+        #[clap(subcommand)]
+        command: ToolsCommands,
+    },
+    
+    /// ダウンロード済みの記録（ダウンロードアーカイブ）を操作
+    Archive {
+        #[clap(subcommand)]
+        command: ArchiveCommands,
+    },
+}
+
+/// ダウンロード対象とオプション
+#[derive(Args)]
+struct DownloadArgs {
+    /// ダウンロードするURL
+    #[clap(short, long)]
+    url: String,
+    
+    /// 出力ディレクトリ（省略時は設定ファイルの値、なければカレントディレクトリ）
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+    
+    /// 出力ファイル名のテンプレート（例: "{title} [{id}] {height}p"、"/" でサブディレクトリ。
+    /// 省略時は設定ファイルのテンプレート、なければタイトル）
+    #[clap(short, long, value_parser = parse_template)]
+    filename: Option<String>,
+    
+    /// 出力先に同じ名前のファイルがある場合の扱い（overwrite、skip、rename。既定はrename）
+    #[clap(long)]
+    collision: Option<CollisionPolicy>,
+    
+    /// 設定ファイルのプロファイル
+    #[clap(short = 'P', long)]
+    profile: Option<String>,
+    
+    /// 出力フォーマット（mp4, mkv, mp3。既定はmp4）
+    #[clap(short = 'F', long)]
+    format: Option<String>,
+    
+    /// 並列コネクション数（既定は16）
+    #[clap(short, long)]
+    connections: Option<u32>,
+    
+    /// ファイル分割数（既定は16）
+    #[clap(short = 's', long)]
+    splits: Option<u32>,
+    
+    /// チャンクサイズ (MB、既定は4)
+    #[clap(short = 'k', long)]
+    chunk_size: Option<u32>,
+    
+    /// 画質・トラックの選択（例: "1080p,av1,h264,lang=ja"、"id=137"）
+    #[clap(short, long, value_parser = parse_quality)]
+    quality: Option<String>,
+    
+    /// 優先して使うバックエンド（aria2c, yt-dlp, http など）
+    #[clap(long)]
+    backend: Option<String>,
+    
+    /// このダウンロードの速度制限（例: 2M、500K）
+    #[clap(long)]
+    limit_rate: Option<SpeedLimit>,
+    
+    /// 追加のHTTPヘッダー（「名前: 値」、複数指定可）
+    #[clap(short = 'H', long = "header", value_parser = parse_header)]
+    headers: Vec<(String, String)>,
+    
+    /// 送信するCookie（「名前=値」、複数指定可）
+    #[clap(long = "cookie", value_parser = parse_cookie)]
+    cookies: Vec<(String, String)>,
+    
+    /// Netscape形式のCookieファイル
+    #[clap(long)]
+    cookie_file: Option<PathBuf>,
+    
+    /// User-Agent
+    #[clap(long)]
+    user_agent: Option<String>,
+    
+    /// リファラー
+    #[clap(long)]
+    referer: Option<String>,
+    
+    /// プレイリスト・チャンネルのうちダウンロードする項目の番号（例: 1-10,15、20-）
+    #[clap(long)]
+    playlist_items: Option<PlaylistItems>,
+    
+    /// プレイリスト・チャンネルの項目を逆順に追加
+    #[clap(long)]
+    playlist_reverse: bool,
+    
+    /// 投稿日がこの日以降の項目のみ（例: 20240131、2024-01-31、today-2weeks）
+    #[clap(long, value_parser = parse_date)]
+    date_after: Option<NaiveDate>,
+    
+    /// 投稿日がこの日以前の項目のみ
+    #[clap(long, value_parser = parse_date)]
+    date_before: Option<NaiveDate>,
+    
+    /// ダウンロード済みの記録のファイル（記録済みのものはスキップし、完了したものを追記する）
+    #[clap(long)]
+    archive: Option<PathBuf>,
+    
+    /// ダウンロード済みの記録があってもダウンロードし直す
+    #[clap(long)]
+    force: bool,
+    
+    /// プレイリスト・チャンネルとして展開せず、1つのダウンロードとして扱う
+    #[clap(long, conflicts_with_all = ["playlist_items", "playlist_reverse", "date_after", "date_before"])]
+    no_playlist: bool,
+    
+    /// 取得する字幕の言語（例: ja,en、ja*、all。カンマ区切り）
+    #[clap(long, value_delimiter = ',')]
+    sub_langs: Option<Vec<String>>,
+    
+    /// 字幕がない言語は自動生成字幕を取得する
+    #[clap(long)]
+    auto_subs: bool,
+    
+    /// 字幕の保存形式（vtt, srt, ass。省略時は取得した形式のまま）
+    #[clap(long)]
+    sub_format: Option<SubtitleFormat>,
+    
+    /// 字幕を動画に埋め込む（MP4・MKVのみ。ffmpegが必要）
+    #[clap(long)]
+    embed_subs: bool,
+    
+    /// タイトル・投稿者・投稿日・説明をメタデータとして書き込む（ffmpegが必要）
+    #[clap(long)]
+    embed_metadata: bool,
+    
+    /// サムネイルをカバーアートとして添付する（ffmpegが必要）
+    #[clap(long)]
+    embed_thumbnail: bool,
+}
+
+impl DownloadArgs {
+    /// プレイリストの項目の選び方
+    fn playlist_selection(&self) -> PlaylistSelection {
+        PlaylistSelection {
+            items: self.playlist_items.clone(),
+            date_after: self.date_after,
+            date_before: self.date_before,
+            reverse: self.playlist_reverse,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// 値を表示（キーはドット区切り。例: profiles.audio.format）
+    Get {
+        /// キー
+        key: String,
+    },
+    
+    /// 値を設定（例: rules.0.host "*.example.com"）
+    Set {
+        /// キー
+        key: String,
         
-        /// 出力ファイル名（拡張子なし）
-        #[clap(short, long)]
-        filename: Option<String>,
+        /// 値（TOMLの値として解釈できない場合は文字列）
+        value: String,
+    },
+    
+    /// 設定されている値を一覧表示
+    List,
+    
+    /// 設定ファイルのパスを表示
+    Path,
+}
+
+#[derive(Subcommand)]
+enum ArchiveCommands {
+    /// yt-dlpのアーカイブファイル（--download-archive）から取り込む
+    Import {
+        /// 取り込むyt-dlpのアーカイブファイル
+        source: PathBuf,
         
-        /// 出力フォーマット（mp4, mkv, mp3）
-        #[clap(short, long, default_value = "mp4")]
-        format: String,
+        /// 取り込み先（省略時は設定ファイルの archive）
+        #[clap(long)]
+        archive: Option<PathBuf>,
+    },
+    
+    /// 記録されている件数を表示
+    Info {
+        /// アーカイブのファイル（省略時は設定ファイルの archive）
+        #[clap(long)]
+        archive: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ToolsCommands {
+    /// マニフェストの版のツールをインストール（省略時はすべて）
+    Install {
+        /// ツール（yt-dlp, aria2c, ffmpeg）
+        tools: Vec<ExternalTool>,
         
-        /// 並列コネクション数
-        #[clap(short, long, default_value_t = 16)]
-        connections: u32,
+        /// インストール済みでも取得し直す
+        #[clap(long)]
+        force: bool,
         
-        /// ファイル分割数
-        #[clap(short = 's', long, default_value_t = 16)]
-        splits: u32,
+        #[clap(flatten)]
+        source: ManifestArgs,
+    },
+    
+    /// インストールしたツールをマニフェストの版に更新（省略時はインストール済みのすべて）
+    Update {
+        /// ツール（yt-dlp, aria2c, ffmpeg）
+        tools: Vec<ExternalTool>,
         
-        /// チャンクサイズ (MB)
-        #[clap(short, long, default_value_t = 4)]
-        chunk_size: u32,
+        #[clap(flatten)]
+        source: ManifestArgs,
     },
     
-    /// システム状態を確認
-    Check,
+    /// インストールしたツールを一覧表示
+    List {
+        #[clap(flatten)]
+        source: ManifestArgs,
+    },
+    
+    /// インストールしたツールを削除
+    Remove {
+        /// ツール（yt-dlp, aria2c, ffmpeg）
+        #[clap(required = true)]
+        tools: Vec<ExternalTool>,
+    },
+}
+
+/// ツールのマニフェストと取得元の指定
+#[derive(Args)]
+struct ManifestArgs {
+    /// マニフェストのURLまたはパス（設定ファイルの installer.manifest より優先）
+    #[clap(long)]
+    manifest: Option<String>,
+    
+    /// ファイルを取得するミラーのURLまたはパス（オフラインでのインストールなど）
+    #[clap(long)]
+    mirror: Option<String>,
+}
+
+impl ManifestArgs {
+    /// 設定ファイルの指定に重ねる
+    fn settings(&self, config: &InstallerConfig) -> InstallerConfig {
+        InstallerConfig {
+            manifest: self.manifest.clone().or_else(|| config.manifest.clone()),
+            mirror: self.mirror.clone().or_else(|| config.mirror.clone()),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum QueueCommands {
+    /// キューにダウンロードを追加
+    Add {
+        #[clap(flatten)]
+        args: Box<DownloadArgs>,
+        
+        /// 優先度（大きいほど先に実行）
+        #[clap(short, long, default_value_t = 0)]
+        priority: i32,
+    },
+    
+    /// キュー内のタスクを一覧表示
+    List,
+    
+    /// タスクをキャンセル
+    Cancel {
+        /// タスクID
+        task_id: String,
+    },
+    
+    /// タスクを一時停止
+    Pause {
+        /// タスクID
+        task_id: String,
+    },
+    
+    /// 一時停止したタスクを再開待ちに戻す
+    Resume {
+        /// タスクID
+        task_id: String,
+    },
+    
+    /// タスクの優先度を変更
+    Priority {
+        /// タスクID
+        task_id: String,
+        
+        /// 新しい優先度
+        priority: i32,
+    },
+    
+    /// タスクの速度制限を変更（実行中のタスクにも反映する）
+    Limit {
+        /// タスクID
+        task_id: String,
+        
+        /// 速度制限（例: 2M、0で無制限）
+        rate: SpeedLimit,
+    },
+    
+    /// 待機中のタスクをすべて実行
+    Run {
+        /// 同時実行数
+        #[clap(short, long, default_value_t = 3)]
+        jobs: usize,
+        
+        /// aria2cを1つだけ常駐させ、JSON-RPCでダウンロードを追加する
+        #[clap(long)]
+        aria2c_rpc: bool,
+    },
+}
+
+/// 失敗したタスクのエラー（終了コードの決定に使う）
+#[derive(Debug)]
+struct TaskFailed {
+    code: ErrorCode,
+    message: String,
+}
+
+impl std::fmt::Display for TaskFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ダウンロード中にエラーが発生しました: {}", self.message)
+    }
 }
 
+impl std::error::Error for TaskFailed {}
+
 /// メイン関数
+///
+/// 失敗した場合はエラーの種類（`ErrorCode` の値）を終了コードにする。
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
     let cli = Cli::parse();
     
+    if let Err(err) = run(cli).await {
+        eprintln!("Error: {:?}", err);
+        std::process::exit(exit_code(&err));
+    }
+}
+
+/// エラーの原因をたどって終了コードを決める
+fn exit_code(err: &anyhow::Error) -> i32 {
+    err.chain()
+        .find_map(|cause| {
+            cause
+                .downcast_ref::<TaskFailed>()
+                .map(|failed| failed.code)
+                .or_else(|| cause.downcast_ref::<DownloadError>().map(ErrorCode::from))
+        })
+        .unwrap_or(ErrorCode::UnknownError) as i32
+}
+
+/// サブコマンドを実行
+async fn run(cli: Cli) -> Result<()> {
+    let config = Config::load().context("設定ファイルの読み込みに失敗しました")?;
+    let tools = cli.tools.tool_paths(&config.tools);
+    
+    // 全体の速度制限（指定された場合は時間帯ごとの制限も使わない）
+    let bandwidth = match cli.overall_limit {
+        Some(limit) => BandwidthConfig::fixed(Some(limit.bytes_per_second())),
+        None => config.bandwidth.clone(),
+    };
+    let follow_config = cli.overall_limit.is_none();
+    
     match cli.command {
-        Commands::Download { 
-            url, 
-            output, 
-            filename, 
-            format,
-            connections,
-            splits,
-            chunk_size
-        } => {
-            download_command(
-                &url, 
-                &output, 
-                filename.as_deref(), 
-                &format,
-                connections,
-                splits,
-                chunk_size
-            ).await?;
+        Commands::Download { args, list_formats: true } => {
+            list_formats_command(&args, &config, &tools).await?;
+        }
+        Commands::Download { args, .. } => {
+            download_command(&args, &config, &tools, bandwidth).await?;
+        }
+        Commands::Queue { command } => {
+            queue_command(command, &config, &tools, bandwidth, follow_config).await?;
+        }
+        Commands::Resume { output } => {
+            resume_command(&output, &tools, bandwidth, follow_config).await?;
         }
         Commands::Check => {
-            check_command().await?;
+            check_command(&tools).await?;
+        }
+        Commands::Config { command } => {
+            config_command(command, config)?;
+        }
+        Commands::Tools { command } => {
+            tools_command(command, &config, &tools).await?;
+        }
+        Commands::Archive { command } => {
+            archive_command(command, &config)?;
         }
     }
     
@@ -91,100 +530,820 @@ async fn main() -> Result<()> {
 
 /// ダウンロードコマンドの実装
 async fn download_command(
-    url: &str, 
-    output_path: &PathBuf, 
-    filename_opt: Option<&str>,
-    format_str: &str,
-    connections: u32,
-    splits: u32,
-    chunk_size: u32
+    args: &DownloadArgs,
+    config: &Config,
+    tools: &ToolPaths,
+    bandwidth: BandwidthConfig
 ) -> Result<()> {
     // ダウンロードマネージャーの初期化
-    let downloader = DownloadManager::new();
+    let downloader = Arc::new(
+        DownloadManager::new()
+            .with_tool_paths(tools.clone())
+            .with_bandwidth(bandwidth)
+    );
     
-    // システム状態のチェック
+    // システム状態のチェック（HLSなど外部ツールなしで扱える形式もあるため警告のみ）
     let status = downloader.system_status().await;
     if !status.is_ready() {
-        println!("{}", status.description());
-        return Ok(());
+        println!("警告: {}", status.description());
     }
     
-    // フォーマット解析
-    let format = match format_str.to_lowercase().as_str() {
-        "mp4" => VideoFormat::Mp4,
-        "mkv" => VideoFormat::Mkv,
-        "mp3" => VideoFormat::Mp3,
-        _ => {
-            println!("サポートされていないフォーマット: {}。MP4を使用します。", format_str);
-            VideoFormat::Mp4
-        }
-    };
-    
-    // ファイル名の生成
-    let filename = if let Some(name) = filename_opt {
-        name.to_string()
-    } else {
-        // URLからファイル名を抽出
-        url.split('/')
-            .last()
-            .unwrap_or("download")
-            .split('?')
-            .next()
-            .unwrap_or("download")
-            .to_string()
-    };
+    let url = args.url.clone();
+    let (output, template, options) = task_parameters(args, config)?;
     
-    // ダウンロードオプション
-    let options = DownloadOptions {
-        connections,
-        splits,
-        chunk_size,
-        format,
-        ..Default::default()
-    };
+    // プレイリスト・チャンネルは項目ごとのタスクにする
+    if let Some((playlist, entries)) = resolve_playlist(&downloader, args, &options).await? {
+        let template = entry_template(template.as_deref());
+        return download_playlist(&downloader, args, &output, &template, options, &playlist, &entries).await;
+    }
+    let filename = template.as_deref().unwrap_or(DEFAULT_FILENAME_TEMPLATE);
     
     // プログレスバーの設定
     let pb = ProgressBar::new(100);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{msg}\n{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {percent}% ({eta})")
-            .unwrap()
-            .progress_chars("#>-"),
-    );
+    pb.set_style(progress_style());
     pb.set_message(format!("ダウンロード中: {}", url));
     
     // プログレスコールバック
+    let callback_pb = pb.clone();
+    let callback_url = url.clone();
     let progress_callback = Box::new(move |info: ProgressInfo| {
-        pb.set_position((info.progress * 100.0) as u64);
-        pb.set_message(format!(
-            "ダウンロード中: {} (速度: {}, 残り時間: {})",
-            url, info.speed, info.eta
+        callback_pb.set_position((info.progress * 100.0) as u64);
+        callback_pb.set_message(format!(
+            "{}: {} {}",
+            info.phase.description(), callback_url, progress_details(&info)
         ));
     });
     
     // ダウンロード実行
-    let result = downloader
-        .download(url, output_path, &filename, Some(options), Some(progress_callback))
+    let task_id = downloader
+        .enqueue(&url, &output, filename, Some(options), 0, Some(progress_callback))
+        .await
+        .context("ダウンロードの登録に失敗しました")?;
+    let task = downloader
+        .wait_for(&task_id)
         .await
         .context("ダウンロード中にエラーが発生しました")?;
+    
+    let result = match (task.status, task.output_file.clone()) {
+        (TaskStatus::Completed, Some(path)) => path,
+        (TaskStatus::Skipped, Some(path)) => {
+            pb.finish_and_clear();
+            println!("同じ名前のファイルがあるためスキップしました: {}", path.to_string_lossy());
+            return Ok(());
+        }
+        (TaskStatus::Skipped, None) => {
+            pb.finish_and_clear();
+            println!("ダウンロード済みのためスキップしました: {}", url);
+            return Ok(());
+        }
+        (status, _) => {
+            pb.abandon();
+            print_decisions(&task.decisions);
+            let (code, message) = match status {
+                TaskStatus::Cancelled => (ErrorCode::Cancelled, DownloadError::Cancelled.to_string()),
+                _ => (
+                    task.error_code.unwrap_or(ErrorCode::UnknownError),
+                    task.error.unwrap_or_else(|| "不明なエラー".to_string()),
+                ),
+            };
+            return Err(TaskFailed { code, message }.into());
+        }
+    };
         
     pb.finish_with_message(format!("ダウンロード完了: {}", result.to_string_lossy()));
+    print_decisions(&task.decisions);
     
     println!("\nファイルを保存しました: {}", result.to_string_lossy());
     
     Ok(())
 }
 
+/// プレイリストの項目をそれぞれタスクとしてダウンロード
+///
+/// 実行中の項目ごとにプログレスバーを表示し、終わったものから結果を表示する。
+async fn download_playlist(
+    downloader: &Arc<DownloadManager>,
+    args: &DownloadArgs,
+    output: &Path,
+    template: &str,
+    options: DownloadOptions,
+    playlist: &Playlist,
+    entries: &[PlaylistEntry]
+) -> Result<()> {
+    println!(
+        "{} の{}件をダウンロードします",
+        playlist.title.as_deref().unwrap_or(&args.url),
+        entries.len()
+    );
+    
+    let progress = MultiProgress::new();
+    let overall = progress.add(ProgressBar::new(entries.len() as u64));
+    overall.set_style(ProgressStyle::default_bar().template("{msg} {pos}/{len}").unwrap());
+    overall.set_message("完了");
+    
+    let mut waits = tokio::task::JoinSet::new();
+    for entry in entries {
+        let label = format!("#{} {}", entry.index, entry.title.as_deref().unwrap_or(&entry.url));
+        
+        // 開始した項目から順にプログレスバーを表示する
+        let bar = Arc::new(std::sync::OnceLock::<ProgressBar>::new());
+        let callback_bar = Arc::clone(&bar);
+        let callback_progress = progress.clone();
+        let callback_label = label.clone();
+        let progress_callback = Box::new(move |info: ProgressInfo| {
+            let pb = callback_bar.get_or_init(|| {
+                let pb = callback_progress.add(ProgressBar::new(100));
+                pb.set_style(progress_style());
+                pb
+            });
+            pb.set_position((info.progress * 100.0) as u64);
+            pb.set_message(format!(
+                "{}: {} {}",
+                info.phase.description(), callback_label, progress_details(&info)
+            ));
+        });
+        
+        let task_id = downloader
+            .add_playlist_entry(
+                playlist,
+                entry,
+                output,
+                template,
+                Some(options.clone()),
+                0,
+                Some(progress_callback)
+            )
+            .await
+            .context("ダウンロードの登録に失敗しました")?;
+        
+        let waiter = Arc::clone(downloader);
+        waits.spawn(async move { (waiter.wait_for(&task_id).await, bar, label) });
+    }
+    downloader.start_pending();
+    
+    let mut failed = Vec::new();
+    let mut skipped = 0;
+    while let Some(joined) = waits.join_next().await {
+        let (task, bar, label) = joined?;
+        let task = task.context("ダウンロード中にエラーが発生しました")?;
+        if let Some(pb) = bar.get() {
+            pb.finish_and_clear();
+            progress.remove(pb);
+        }
+        overall.inc(1);
+        
+        match (&task.status, &task.output_file) {
+            (TaskStatus::Completed, Some(path)) => {
+                progress.suspend(|| println!("完了: {}", path.to_string_lossy()));
+            }
+            (TaskStatus::Skipped, Some(path)) => {
+                progress.suspend(|| println!("スキップ（同じ名前のファイルがあります）: {}", path.to_string_lossy()));
+                skipped += 1;
+            }
+            (TaskStatus::Skipped, None) => {
+                progress.suspend(|| println!("スキップ（ダウンロード済み）: {}", label));
+                skipped += 1;
+            }
+            _ => {
+                let error = task.error.as_deref().and_then(|error| error.lines().next()).unwrap_or("不明なエラー");
+                progress.suspend(|| println!("失敗: {}: {}", label, error));
+                failed.push(task);
+            }
+        }
+    }
+    overall.finish();
+    
+    println!(
+        "\n{}件中{}件のダウンロードが完了しました（スキップ: {}件）",
+        entries.len(),
+        entries.len() - failed.len() - skipped,
+        skipped
+    );
+    if let Some(task) = failed.first() {
+        return Err(TaskFailed {
+            code: task.error_code.unwrap_or(ErrorCode::UnknownError),
+            message: format!("{}件のダウンロードに失敗しました", failed.len()),
+        }
+        .into());
+    }
+    
+    Ok(())
+}
+
+/// URLがプレイリスト・チャンネルの場合は項目の一覧を取得し、指定された項目を選ぶ
+///
+/// 項目の指定がない場合は、調べられなくても（yt-dlpがないなど）単一のダウンロードとして続ける。
+async fn resolve_playlist(
+    downloader: &DownloadManager,
+    args: &DownloadArgs,
+    options: &DownloadOptions
+) -> Result<Option<(Playlist, Vec<PlaylistEntry>)>> {
+    let selection = args.playlist_selection();
+    let explicit = selection != PlaylistSelection::default();
+    if args.no_playlist || (!explicit && !may_be_playlist(&args.url)) {
+        return Ok(None);
+    }
+    
+    let playlist = match downloader.resolve_entries(&args.url, &options.request).await {
+        Ok(Some(playlist)) => playlist,
+        Ok(None) => return Ok(None),
+        Err(err) if explicit => return Err(err).context("プレイリストの項目の取得に失敗しました"),
+        Err(_) => return Ok(None),
+    };
+    let entries = selection.select(&playlist.entries);
+    if entries.is_empty() {
+        anyhow::bail!("プレイリストに該当する項目がありません（全{}件）", playlist.entries.len());
+    }
+    Ok(Some((playlist, entries)))
+}
+
+/// プレイリストの項目の出力ファイル名のテンプレート
+///
+/// 項目を埋め込まないファイル名が指定された場合は「名前-番号」、指定がなければ「番号 - タイトル」にする。
+fn entry_template(template: Option<&str>) -> String {
+    match template {
+        Some(template) if template.contains('{') => template.to_string(),
+        Some(name) => format!("{}-{{playlist_index}}", name),
+        None => DEFAULT_PLAYLIST_FILENAME_TEMPLATE.to_string(),
+    }
+}
+
+/// ダウンロードのプログレスバーの表示形式
+fn progress_style() -> ProgressStyle {
+    ProgressStyle::default_bar()
+        .template("{msg}\n{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {percent}%")
+        .unwrap()
+        .progress_chars("#>-")
+}
+
+/// 進捗情報のバイト数・速度・残り時間を表示用に整形
+fn progress_details(info: &ProgressInfo) -> String {
+    let mut details = match info.total_bytes {
+        Some(total) => format!("{} / {}", HumanBytes(info.downloaded_bytes), HumanBytes(total)),
+        None if info.downloaded_bytes > 0 => HumanBytes(info.downloaded_bytes).to_string(),
+        None => return String::new(),
+    };
+    if let Some(speed) = info.speed {
+        details.push_str(&format!(", 速度: {}/s", HumanBytes(speed as u64)));
+    }
+    if let Some(eta) = info.eta {
+        details.push_str(&format!(", 残り時間: {}", HumanDuration(eta)));
+    }
+    format!("({})", details)
+}
+
+/// キューコマンドの実装
+async fn queue_command(
+    command: QueueCommands,
+    config: &Config,
+    tools: &ToolPaths,
+    bandwidth: BandwidthConfig,
+    follow_config: bool
+) -> Result<()> {
+    let jobs = match &command {
+        QueueCommands::Run { jobs, .. } => *jobs,
+        _ => QueueConfig::default().max_concurrent,
+    };
+    let aria2c_rpc = matches!(command, QueueCommands::Run { aria2c_rpc: true, .. });
+    let queue_config = QueueConfig {
+        max_concurrent: jobs,
+        ..QueueConfig::persistent()
+    };
+    let mut downloader = DownloadManager::with_queue_config(queue_config)
+        .context("キューの読み込みに失敗しました")?
+        .with_tool_paths(tools.clone())
+        .with_bandwidth(bandwidth);
+    if aria2c_rpc {
+        downloader = downloader.with_aria2c(Aria2cTool::with_path(tools.path(ExternalTool::Aria2c)).with_rpc());
+    }
+    let downloader = Arc::new(downloader);
+    
+    match command {
+        QueueCommands::Add { args, priority } => {
+            let (output, template, options) = task_parameters(&args, config)?;
+            if let Some((playlist, entries)) = resolve_playlist(&downloader, &args, &options).await? {
+                let template = entry_template(template.as_deref());
+                for entry in &entries {
+                    let task_id = downloader
+                        .add_playlist_entry(
+                            &playlist,
+                            entry,
+                            &output,
+                            &template,
+                            Some(options.clone()),
+                            priority,
+                            None
+                        )
+                        .await
+                        .context("キューへの追加に失敗しました")?;
+                    println!("キューに追加しました: {}  #{} {}", task_id, entry.index, entry.title.as_deref().unwrap_or(&entry.url));
+                }
+                println!(
+                    "{} の{}件をキューに追加しました",
+                    playlist.title.as_deref().unwrap_or(&args.url),
+                    entries.len()
+                );
+                return Ok(());
+            }
+            let task_id = downloader
+                .add_task(&args.url, &output, template.as_deref().unwrap_or(DEFAULT_FILENAME_TEMPLATE), Some(options), priority, None)
+                .await
+                .context("キューへの追加に失敗しました")?;
+            println!("キューに追加しました: {}", task_id);
+        }
+        QueueCommands::List => {
+            let tasks = downloader.list_tasks().await?;
+            if tasks.is_empty() {
+                println!("キューは空です");
+            }
+            for task in tasks {
+                print_task(&task);
+            }
+        }
+        QueueCommands::Cancel { task_id } => {
+            downloader.cancel_download(&task_id).await?;
+            println!("キャンセルしました: {}", task_id);
+        }
+        QueueCommands::Pause { task_id } => {
+            downloader.pause(&task_id).await?;
+            println!("一時停止しました: {}", task_id);
+        }
+        QueueCommands::Resume { task_id } => {
+            downloader.resume(&task_id).await?;
+            println!("再開待ちに戻しました: {}", task_id);
+        }
+        QueueCommands::Priority { task_id, priority } => {
+            downloader.set_priority(&task_id, priority).await?;
+            println!("優先度を変更しました: {} -> {}", task_id, priority);
+        }
+        QueueCommands::Limit { task_id, rate } => {
+            let limit = Some(rate.bytes_per_second()).filter(|limit| *limit > 0);
+            downloader.set_task_speed_limit(&task_id, limit).await?;
+            println!("速度制限を変更しました: {} -> {}", task_id, describe_limit(limit));
+        }
+        QueueCommands::Run { .. } => {
+            run_queue(&downloader, follow_config).await?;
+        }
+    }
+    
+    Ok(())
+}
+
+/// 再開コマンドの実装
+async fn resume_command(
+    output_path: &Path,
+    tools: &ToolPaths,
+    bandwidth: BandwidthConfig,
+    follow_config: bool
+) -> Result<()> {
+    let downloader = Arc::new(
+        DownloadManager::with_queue_config(QueueConfig::persistent())
+            .context("キューの読み込みに失敗しました")?
+            .with_tool_paths(tools.clone())
+            .with_bandwidth(bandwidth)
+    );
+    
+    let resumed = downloader
+        .resume_interrupted(output_path)
+        .await
+        .context("中断されたタスクの検索に失敗しました")?;
+    if resumed.is_empty() {
+        println!("{} に中断されたタスクはありません", output_path.to_string_lossy());
+        return Ok(());
+    }
+    println!("{}件のタスクを再開します", resumed.len());
+    
+    run_queue(&downloader, follow_config).await
+}
+
+/// 待機中のタスクがなくなるまでキューを実行
+///
+/// `follow_config` が `true` の場合は、実行中に変更された設定ファイルの
+/// 速度制限（`[bandwidth]`）を読み直して反映する。
+async fn run_queue(downloader: &Arc<DownloadManager>, follow_config: bool) -> Result<()> {
+    let mut events = downloader.subscribe();
+    let runner = Arc::clone(downloader);
+    let mut run = tokio::spawn(async move { runner.run_until_idle().await });
+    let mut reload = tokio::time::interval(std::time::Duration::from_secs(5));
+    
+    // 完了するまでタスクの状態変化を表示
+    let result = loop {
+        tokio::select! {
+            Ok(task) = events.recv() => {
+                if task.status != TaskStatus::Pending {
+                    print_task(&task);
+                }
+            }
+            _ = reload.tick(), if follow_config => {
+                // 編集途中などで読み込めない場合は現在の制限を使い続ける
+                if let Ok(config) = Config::load() {
+                    downloader.set_bandwidth(config.bandwidth);
+                }
+            }
+            result = &mut run => break result,
+        }
+    };
+    while let Ok(task) = events.try_recv() {
+        print_task(&task);
+    }
+    result??;
+    
+    println!("\n待機中のタスクはありません");
+    Ok(())
+}
+
+/// タスクを1行で表示
+fn print_task(task: &DownloadTask) {
+    let status = match task.status {
+        TaskStatus::Pending => "待機中",
+        TaskStatus::Running => "実行中",
+        TaskStatus::Paused => "一時停止",
+        TaskStatus::Completed => "完了",
+        TaskStatus::Failed => "失敗",
+        TaskStatus::Cancelled => "キャンセル",
+        TaskStatus::Skipped => "スキップ",
+    };
+    
+    let detail = match (&task.output_file, &task.error) {
+        (Some(path), _) => path.to_string_lossy().to_string(),
+        (None, Some(error)) => error.lines().next().unwrap_or_default().to_string(),
+        (None, None) => task.url.clone(),
+    };
+    
+    let speed_limit = task
+        .speed_limit
+        .map(|limit| format!("  速度制限:{}", describe_limit(Some(limit))))
+        .unwrap_or_default();
+    
+    let playlist = task
+        .playlist
+        .as_ref()
+        .map(|playlist| format!("  プレイリスト:#{}", playlist.index))
+        .unwrap_or_default();
+    
+    println!("{}  [{}] 優先度:{}{}{}  {}", task.id, status, task.priority, speed_limit, playlist, detail);
+    if task.status.is_finished() {
+        print_decisions(&task.decisions);
+    }
+}
+
+/// ダウンロード方法を切り替えた・再試行した経過を表示（1回で成功した場合は表示しない）
+fn print_decisions(decisions: &[Decision]) {
+    if decisions.iter().all(|decision| decision.action == DecisionAction::Succeeded) {
+        return;
+    }
+    for decision in decisions {
+        println!("    {}", decision);
+    }
+}
+
+/// 引数と設定ファイルから出力先・ファイル名のテンプレート・オプションを決める
+///
+/// ファイル名のテンプレートは指定がなければNone（既定のテンプレートを使う）。
+fn task_parameters(args: &DownloadArgs, config: &Config) -> Result<(PathBuf, Option<String>, DownloadOptions)> {
+    let content_type = content_type_from_url(&args.url);
+    let resolved = config
+        .resolve(&args.url, content_type.as_ref(), args.profile.as_deref(), &option_flags(args))
+        .context("設定の適用に失敗しました")?;
+    
+    let mut options = resolved.options;
+    options.ignore_archive = args.force;
+    
+    let output = resolved.output_dir.unwrap_or_else(|| PathBuf::from("."));
+    Ok((output, resolved.filename_template, options))
+}
+
+/// コマンドラインで指定されたオプション（設定ファイルより優先する）
+fn option_flags(args: &DownloadArgs) -> OptionOverrides {
+    // フォーマット解析
+    let format = args.format.as_ref().map(|format| match format.to_lowercase().as_str() {
+        "mp4" => VideoFormat::Mp4,
+        "mkv" => VideoFormat::Mkv,
+        "mp3" => VideoFormat::Mp3,
+        _ => {
+            println!("サポートされていないフォーマット: {}。MP4を使用します。", format);
+            VideoFormat::Mp4
+        }
+    });
+    
+    OptionOverrides {
+        output_dir: args.output.clone(),
+        filename_template: args.filename.clone(),
+        format,
+        quality: args.quality.clone(),
+        connections: args.connections,
+        splits: args.splits,
+        chunk_size: args.chunk_size,
+        headers: args.headers.iter().cloned().collect(),
+        cookies: args.cookies.iter().cloned().collect(),
+        cookie_file: args.cookie_file.clone(),
+        user_agent: args.user_agent.clone(),
+        referer: args.referer.clone(),
+        backend: args.backend.clone(),
+        speed_limit: args.limit_rate,
+        archive: args.archive.clone(),
+        collision: args.collision,
+        subtitle_languages: args.sub_langs.clone(),
+        auto_subtitles: args.auto_subs.then_some(true),
+        subtitle_format: args.sub_format,
+        embed_subtitles: args.embed_subs.then_some(true),
+        embed_metadata: args.embed_metadata.then_some(true),
+        embed_thumbnail: args.embed_thumbnail.then_some(true),
+        ..Default::default()
+    }
+}
+
+/// ファイル名のテンプレートを確認する
+fn parse_template(value: &str) -> Result<String, String> {
+    value.parse::<FilenameTemplate>()?;
+    Ok(value.to_string())
+}
+
+/// 画質・トラックの選択の指定を確認する
+fn parse_quality(value: &str) -> Result<String, String> {
+    value.parse::<FormatSelection>()?;
+    Ok(value.to_string())
+}
+
+/// 設定コマンドの実装
+fn config_command(command: ConfigCommands, mut config: Config) -> Result<()> {
+    let path = Config::default_path().context("設定ディレクトリが見つかりません")?;
+    
+    match command {
+        ConfigCommands::Get { key } => {
+            let value = config.get(&key)?.with_context(|| format!("設定されていません: {}", key))?;
+            match value.as_str() {
+                Some(text) => println!("{}", text),
+                None => println!("{}", value),
+            }
+        }
+        ConfigCommands::Set { key, value } => {
+            config.set(&key, &value)?;
+            config.save_to(&path).context("設定ファイルの保存に失敗しました")?;
+            if let Some(value) = config.get(&key)? {
+                println!("{} = {}", key, value);
+            }
+        }
+        ConfigCommands::List => {
+            let entries = config.entries()?;
+            if entries.is_empty() {
+                println!("設定はありません（{}）", path.display());
+            }
+            for (key, value) in entries {
+                println!("{} = {}", key, value);
+            }
+        }
+        ConfigCommands::Path => {
+            println!("{}", path.display());
+        }
+    }
+    
+    Ok(())
+}
+
+/// アーカイブコマンドの実装
+fn archive_command(command: ArchiveCommands, config: &Config) -> Result<()> {
+    let archive_path = |archive: Option<PathBuf>| {
+        archive
+            .or_else(|| config.defaults.archive.clone())
+            .context("アーカイブのファイルを --archive か設定ファイルの archive で指定してください")
+    };
+    
+    match command {
+        ArchiveCommands::Import { source, archive } => {
+            let path = archive_path(archive)?;
+            let mut archive = DownloadArchive::load(&path).context("アーカイブの読み込みに失敗しました")?;
+            let added = archive
+                .import(&source)
+                .with_context(|| format!("{} の取り込みに失敗しました", source.display()))?;
+            println!("{}件を取り込みました（合計{}件）: {}", added, archive.len(), path.display());
+        }
+        ArchiveCommands::Info { archive } => {
+            let path = archive_path(archive)?;
+            let archive = DownloadArchive::load(&path).context("アーカイブの読み込みに失敗しました")?;
+            println!("{}: {}件", path.display(), archive.len());
+        }
+    }
+    
+    Ok(())
+}
+
+/// ツールコマンドの実装
+async fn tools_command(command: ToolsCommands, config: &Config, tools: &ToolPaths) -> Result<()> {
+    let dir = tools.managed_dir().context("インストール先のディレクトリが見つかりません")?;
+    let manager = |source: &ManifestArgs| {
+        ToolManager::new(dir.clone()).with_settings(source.settings(&config.installer))
+    };
+    
+    match command {
+        ToolsCommands::Install { tools: selected, force, source } => {
+            let manager = manager(&source);
+            let manifest = manager.manifest().await.context("マニフェストの取得に失敗しました")?;
+            let selected = if selected.is_empty() { ExternalTool::ALL.to_vec() } else { selected };
+            install_tools(&manager, &manifest, &selected, force).await?;
+        }
+        ToolsCommands::Update { tools: selected, source } => {
+            let manager = manager(&source);
+            let selected = if selected.is_empty() {
+                manager.installed()?.iter().map(|installed| installed.tool).collect()
+            } else {
+                selected
+            };
+            if selected.is_empty() {
+                println!("インストールしたツールはありません");
+                return Ok(());
+            }
+            let manifest = manager.manifest().await.context("マニフェストの取得に失敗しました")?;
+            install_tools(&manager, &manifest, &selected, false).await?;
+        }
+        ToolsCommands::List { source } => {
+            let settings = source.settings(&config.installer);
+            let has_manifest = settings.manifest.is_some() || settings.mirror.is_some();
+            let manager = ToolManager::new(dir.clone()).with_settings(settings);
+            let installed = manager.installed()?;
+            
+            // マニフェストが指定されていれば更新の有無も表示
+            let manifest = if has_manifest {
+                match manager.manifest().await {
+                    Ok(manifest) => Some(manifest),
+                    Err(err) => {
+                        println!("警告: マニフェストを取得できません: {}", err);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            
+            println!("インストール先: {}", dir.display());
+            for tool in ExternalTool::ALL {
+                let latest = manifest
+                    .as_ref()
+                    .and_then(|manifest| manifest.release(tool))
+                    .map(|release| release.version.as_str());
+                match (installed.iter().find(|installed| installed.tool == tool), latest) {
+                    (Some(installed), Some(latest)) if installed.version != latest => println!(
+                        "{}: {}（{} に更新できます）: {}",
+                        tool, installed.version, latest, installed.path.display()
+                    ),
+                    (Some(installed), _) => {
+                        println!("{}: {}: {}", tool, installed.version, installed.path.display())
+                    }
+                    (None, Some(latest)) => println!("{}: 未インストール（{} をインストールできます）", tool, latest),
+                    (None, None) => println!("{}: 未インストール", tool),
+                }
+            }
+        }
+        ToolsCommands::Remove { tools: selected } => {
+            let manager = ToolManager::new(dir.clone());
+            for tool in selected {
+                if manager.remove(tool)? {
+                    println!("{} を削除しました", tool);
+                } else {
+                    println!("{} はインストールされていません", tool);
+                }
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+/// ツールを順にインストールし、失敗したものがあれば最初のエラーを返す
+async fn install_tools(
+    manager: &ToolManager,
+    manifest: &ToolManifest,
+    selected: &[ExternalTool],
+    force: bool
+) -> Result<()> {
+    let mut first_error = None;
+    
+    for &tool in selected {
+        match manager.install(manifest, tool, force).await {
+            Ok(InstallOutcome::Installed(installed)) => println!(
+                "✅ {} {} をインストールしました: {}",
+                tool, installed.version, installed.path.display()
+            ),
+            Ok(InstallOutcome::Updated { previous, installed }) => println!(
+                "✅ {} を {} から {} に更新しました: {}",
+                tool, previous, installed.version, installed.path.display()
+            ),
+            Ok(InstallOutcome::UpToDate(installed)) => {
+                println!("{} {} は最新です", tool, installed.version)
+            }
+            Err(err) => {
+                println!("❌ {}: {}", tool, err);
+                first_error.get_or_insert(err);
+            }
+        }
+    }
+    
+    match first_error {
+        Some(err) => Err(err).context("一部のツールをインストールできませんでした"),
+        None => Ok(()),
+    }
+}
+
+/// フォーマット一覧コマンドの実装
+async fn list_formats_command(args: &DownloadArgs, config: &Config, tools: &ToolPaths) -> Result<()> {
+    let url = args.url.as_str();
+    let (_, _, options) = task_parameters(args, config)?;
+    let downloader = DownloadManager::new().with_tool_paths(tools.clone());
+    let formats = downloader
+        .list_formats(url, &options.request)
+        .await
+        .context("フォーマットの取得に失敗しました")?;
+    
+    if formats.is_empty() {
+        println!("選択可能なフォーマットがありません");
+    } else {
+        print_formats(&formats);
+    }
+    
+    match downloader.list_subtitles(url, &options).await {
+        Ok(tracks) if tracks.is_empty() => {}
+        Ok(tracks) => {
+            println!();
+            println!("{:<12} {:<24} {:<6} {:<8}", "言語", "名前", "形式", "種類");
+            for track in &tracks {
+                println!(
+                    "{:<12} {:<24} {:<6} {:<8}",
+                    track.language,
+                    track.name.as_deref().unwrap_or("-"),
+                    track.format.to_string(),
+                    if track.automatic { "自動生成" } else { "字幕" },
+                );
+            }
+        }
+        Err(err) => eprintln!("字幕の一覧を取得できませんでした: {}", err),
+    }
+    
+    Ok(())
+}
+
+/// フォーマットの一覧を表で表示
+fn print_formats(formats: &[FormatInfo]) {
+    println!(
+        "{:<24} {:<5} {:>10} {:>6} {:<16} {:<12} {:>9} {:>10} {:<6}",
+        "ID", "拡張子", "解像度", "FPS", "映像", "音声", "kbps", "サイズ", "言語"
+    );
+    for format in formats {
+        let resolution = match (format.width, format.height) {
+            (Some(width), Some(height)) => format!("{}x{}", width, height),
+            (None, Some(height)) => format!("{}p", height),
+            _ if format.has_video() => "-".to_string(),
+            _ => "音声のみ".to_string(),
+        };
+        println!(
+            "{:<24} {:<5} {:>10} {:>6} {:<16} {:<12} {:>9} {:>10} {:<6}",
+            format.format_id.as_deref().unwrap_or("-"),
+            format.ext.as_deref().unwrap_or("-"),
+            resolution,
+            format.fps.map(|fps| format!("{:.0}", fps)).unwrap_or_else(|| "-".to_string()),
+            format.vcodec.as_deref().unwrap_or("-"),
+            format.acodec.as_deref().unwrap_or("-"),
+            format.tbr.map(|tbr| format!("{:.0}", tbr)).unwrap_or_else(|| "-".to_string()),
+            format.filesize.map(format_size).unwrap_or_else(|| "-".to_string()),
+            format.language.as_deref().unwrap_or("-"),
+        );
+    }
+}
+
+/// バイト数を読みやすい単位に変換
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", size, UNITS[unit])
+}
+
 /// システム状態確認コマンドの実装
-async fn check_command() -> Result<()> {
-    let downloader = DownloadManager::new();
+async fn check_command(tools: &ToolPaths) -> Result<()> {
+    let downloader = DownloadManager::new().with_tool_paths(tools.clone());
     let (ytdlp, aria2c, ffmpeg) = downloader.check_dependencies().await;
     
     println!("NextDownloader システム状態:");
     println!("============================");
-    println!("yt-dlp: {}", if ytdlp { "✅ 利用可能" } else { "❌ 見つかりません" });
-    println!("aria2c: {}", if aria2c { "✅ 利用可能" } else { "❌ 見つかりません" });
-    println!("ffmpeg: {}", if ffmpeg { "✅ 利用可能" } else { "❌ 見つかりません" });
+    for (tool, available) in [(ExternalTool::YtDlp, ytdlp), (ExternalTool::Aria2c, aria2c), (ExternalTool::Ffmpeg, ffmpeg)] {
+        let status = match downloader.tool_location(tool) {
+            Some(location) if available => {
+                format!("✅ 利用可能: {}（{}）", location.path.display(), location.source.description())
+            }
+            Some(location) => {
+                format!("❌ 実行できません: {}（{}）", location.path.display(), location.source.description())
+            }
+            None => "❌ 見つかりません".to_string(),
+        };
+        if tool == ExternalTool::Aria2c && !available {
+            println!("{}: {}（MP4は内蔵のダウンローダーで取得します）", tool, status);
+        } else {
+            println!("{}: {}", tool, status);
+        }
+    }
     
     if ytdlp && aria2c && ffmpeg {
         println!("\n✅ システムは正常に動作しています");
@@ -0,0 +1,247 @@
+//! ダウンロード済みの記録（ダウンロードアーカイブ）
+//!
+//! yt-dlpの `--download-archive` と同じ形式のテキストファイルで、1行に1件
+//! 「抽出器 動画ID」（例: `youtube dQw4w9WgXcQ`）を記録する。動画IDがわからない
+//! URLは正規化したURLのハッシュを `url <SHA-256>` の形で記録する。
+//!
+//! 同じ形式のため、yt-dlpのアーカイブファイルをそのまま使うことも、取り込むこともできる。
+
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use sha2::{Digest, Sha256};
+use url::Url;
+use crate::detect::content_type_from_url;
+use crate::types::{ContentType, DownloadError};
+
+/// 動画IDがわからないURLの抽出器の名前
+const URL_EXTRACTOR: &str = "url";
+
+/// ダウンロードアーカイブ
+#[derive(Debug, Clone)]
+pub struct DownloadArchive {
+    path: PathBuf,
+    entries: HashSet<String>,
+}
+
+impl DownloadArchive {
+    /// ファイルから読み込む（存在しない場合は空のアーカイブ）
+    pub fn load(path: &Path) -> Result<Self, DownloadError> {
+        let entries = match std::fs::read_to_string(path) {
+            Ok(text) => parse_entries(&text).collect(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashSet::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    /// ファイルのパス
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 記録されている件数
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// 記録が1件もないかどうか
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// 記録済みかどうか
+    pub fn contains(&self, key: &str) -> bool {
+        self.entries.contains(key.trim())
+    }
+
+    /// 記録してファイルに追記する
+    ///
+    /// 新しく記録した場合は `true`、記録済みの場合は何もせず `false` を返す。
+    pub fn record(&mut self, key: &str) -> Result<bool, DownloadError> {
+        let key = key.trim();
+        if !self.entries.insert(key.to_string()) {
+            return Ok(false);
+        }
+        self.append(&[key])?;
+        Ok(true)
+    }
+
+    /// yt-dlpのアーカイブファイル（`--download-archive`）から取り込む
+    ///
+    /// 記録済みでないものだけを追記し、追加した件数を返す。
+    pub fn import(&mut self, source: &Path) -> Result<usize, DownloadError> {
+        let text = std::fs::read_to_string(source)?;
+        let mut added = Vec::new();
+        for key in parse_entries(&text) {
+            if self.entries.insert(key.clone()) {
+                added.push(key);
+            }
+        }
+        let keys: Vec<&str> = added.iter().map(String::as_str).collect();
+        self.append(&keys)?;
+        Ok(added.len())
+    }
+
+    fn append(&self, keys: &[&str]) -> Result<(), DownloadError> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let mut text = String::new();
+        for key in keys {
+            text.push_str(key);
+            text.push('\n');
+        }
+        file.write_all(text.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// アーカイブのファイルの各行（空行は除く）
+fn parse_entries(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+}
+
+/// 抽出器と動画IDからアーカイブのキーを作る（yt-dlpと同じく抽出器は小文字）
+pub fn archive_key(extractor: &str, id: &str) -> String {
+    format!("{} {}", extractor.to_lowercase(), id)
+}
+
+/// URLのハッシュによるアーカイブのキー（動画IDがわからない場合に使う）
+pub fn url_archive_key(url: &str) -> String {
+    let digest = Sha256::digest(normalize_url(url).as_bytes());
+    let hash: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    archive_key(URL_EXTRACTOR, &hash)
+}
+
+/// URLだけで動画IDがわかる場合のアーカイブのキー（YouTubeの動画のURL）
+pub fn archive_key_from_url(url: &str) -> Option<String> {
+    if content_type_from_url(url) != Some(ContentType::YouTube) {
+        return None;
+    }
+    let parsed = Url::parse(url).ok()?;
+    let mut segments = parsed.path_segments()?.filter(|segment| !segment.is_empty());
+    let id = if parsed.host_str()?.ends_with("youtu.be") {
+        segments.next().map(str::to_string)
+    } else {
+        match segments.next() {
+            Some("watch") => parsed.query_pairs().find(|(key, _)| key == "v").map(|(_, id)| id.to_string()),
+            Some("shorts" | "embed" | "live" | "v") => segments.next().map(str::to_string),
+            _ => None,
+        }
+    }?;
+    Some(archive_key("youtube", &id))
+}
+
+/// 同じ内容を指すURLが同じ文字列になるよう正規化する
+///
+/// スキームとホスト名は小文字に（`Url` が行う）、既定のポートとフラグメントは除き、
+/// クエリのパラメータは名前順に並べる。末尾の `/` は除く。
+fn normalize_url(url: &str) -> String {
+    let Ok(mut parsed) = Url::parse(url.trim()) else {
+        return url.trim().to_string();
+    };
+    parsed.set_fragment(None);
+
+    let mut pairs: Vec<(String, String)> = parsed.query_pairs().into_owned().collect();
+    pairs.sort();
+    if pairs.is_empty() {
+        parsed.set_query(None);
+    } else {
+        parsed.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+
+    let path = parsed.path().trim_end_matches('/').to_string();
+    if !path.is_empty() {
+        parsed.set_path(&path);
+    }
+    parsed.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn imports_ytdlp_archive_without_duplicates() {
+        let dir = TempDir::new();
+        let path = dir.path().join("archive.txt");
+        std::fs::write(&path, "youtube dQw4w9WgXcQ\n").unwrap();
+        let source = dir.path().join("yt-dlp-archive.txt");
+        // yt-dlpのアーカイブ（CRLFや空行、前後の空白が混じっていてもよい）
+        std::fs::write(&source, "youtube dQw4w9WgXcQ\r\n\r\n  niconico sm9  \nyoutube jNQXAC9IVRw\nniconico sm9\n").unwrap();
+
+        let mut archive = DownloadArchive::load(&path).unwrap();
+        assert_eq!(archive.import(&source).unwrap(), 2);
+
+        assert_eq!(archive.len(), 3);
+        assert!(archive.contains("niconico sm9"));
+        assert!(archive.contains(" youtube jNQXAC9IVRw "));
+        // 既存の記録の後ろに、取り込んだ順で追記する
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "youtube dQw4w9WgXcQ\nniconico sm9\nyoutube jNQXAC9IVRw\n"
+        );
+
+        // 同じファイルをもう一度取り込んでも増えない
+        assert_eq!(archive.import(&source).unwrap(), 0);
+        assert_eq!(DownloadArchive::load(&path).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn importing_a_missing_file_is_an_error() {
+        let dir = TempDir::new();
+        let mut archive = DownloadArchive::load(&dir.path().join("archive.txt")).unwrap();
+
+        assert!(archive.import(&dir.path().join("missing.txt")).is_err());
+        assert!(archive.is_empty());
+        assert!(!dir.path().join("archive.txt").exists());
+    }
+
+    #[test]
+    fn records_once_and_creates_parent_directories() {
+        let dir = TempDir::new();
+        let path = dir.path().join("state").join("archive.txt");
+        let mut archive = DownloadArchive::load(&path).unwrap();
+        assert!(archive.is_empty());
+
+        assert!(archive.record("youtube abc").unwrap());
+        assert!(!archive.record("youtube abc ").unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "youtube abc\n");
+    }
+
+    #[test]
+    fn keys_match_ytdlp_format() {
+        assert_eq!(archive_key("Youtube", "dQw4w9WgXcQ"), "youtube dQw4w9WgXcQ");
+        for url in [
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42",
+            "https://youtu.be/dQw4w9WgXcQ",
+            "https://www.youtube.com/shorts/dQw4w9WgXcQ",
+        ] {
+            assert_eq!(archive_key_from_url(url).as_deref(), Some("youtube dQw4w9WgXcQ"), "{}", url);
+        }
+        assert_eq!(archive_key_from_url("https://www.youtube.com/@channel"), None);
+        assert_eq!(archive_key_from_url("https://example.com/watch?v=abc"), None);
+    }
+
+    #[test]
+    fn url_keys_ignore_equivalent_differences() {
+        let key = url_archive_key("https://example.com/video.mp4?b=2&a=1");
+
+        assert!(key.starts_with("url "));
+        assert_eq!(key.len(), "url ".len() + 64);
+        assert_eq!(url_archive_key("HTTPS://Example.com:443/video.mp4/?a=1&b=2#t=10"), key);
+        assert_ne!(url_archive_key("https://example.com/video.mp4?a=2&b=1"), key);
+    }
+}
@@ -0,0 +1,331 @@
+//! 組み込みのバックエンド
+//!
+//! 既定の優先度での試行順は次の通り。
+//!
+//! - MP4: aria2c → yt-dlp → 内蔵HTTP
+//! - HLS / DASH: 内蔵HLS / 内蔵DASH → yt-dlp
+//! - YouTube・その他のページ: yt-dlp（aria2c） → yt-dlp
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use async_trait::async_trait;
+use crate::backend::{BackendCapabilities, BackendRegistry, DownloadBackend};
+use crate::tools::{fetcher, Aria2cTool, DashDownloadTool, FFmpegTool, HlsDownloadTool, HttpDownloadTool, YtDlpTool};
+use crate::types::{ContentType, DownloadError, DownloadOptions, ProgressCallback};
+
+/// 各形式に特化したバックエンドの優先度
+const PRIMARY_PRIORITY: i32 = 100;
+
+/// yt-dlp内蔵のダウンローダーの優先度（特化したバックエンドが失敗した場合の代替）
+const FALLBACK_PRIORITY: i32 = 50;
+
+/// 内蔵のHTTPダウンローダーの優先度（最後の手段）
+const LAST_RESORT_PRIORITY: i32 = 10;
+
+/// aria2cで直接ファイルを取得するバックエンド
+pub struct Aria2cBackend {
+    aria2c: Arc<Aria2cTool>,
+}
+
+impl Aria2cBackend {
+    /// 新しいAria2cBackendを作成
+    pub fn new(aria2c: Arc<Aria2cTool>) -> Self {
+        Self { aria2c }
+    }
+}
+
+#[async_trait]
+impl DownloadBackend for Aria2cBackend {
+    fn name(&self) -> &str {
+        "aria2c"
+    }
+
+    fn priority(&self) -> i32 {
+        PRIMARY_PRIORITY
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities {
+            resume: true,
+            request_context: true,
+            parallel: true,
+            external_tool: true,
+            ..Default::default()
+        }
+    }
+
+    fn can_handle(&self, _url: &str, content_type: &ContentType) -> bool {
+        *content_type == ContentType::Mp4
+    }
+
+    async fn is_available(&self) -> bool {
+        self.aria2c.is_available().await
+    }
+
+    async fn download(
+        &self,
+        url: &str,
+        output_path: &Path,
+        filename: &str,
+        options: &DownloadOptions,
+        progress_callback: Option<ProgressCallback>
+    ) -> Result<PathBuf, DownloadError> {
+        self.aria2c
+            .download(url, output_path, filename, options, progress_callback)
+            .await
+    }
+}
+
+/// yt-dlpで取得するバックエンド
+///
+/// aria2cを外部ダウンローダーとして使うものと、yt-dlp内蔵のダウンローダーを
+/// 使うものの2種類がある。
+pub struct YtDlpBackend {
+    ytdlp: Arc<YtDlpTool>,
+    aria2c: Option<Arc<Aria2cTool>>,
+}
+
+impl YtDlpBackend {
+    /// 外部ダウンローダーとしてaria2cを使うYtDlpBackendを作成
+    ///
+    /// 動画サイトのページ（YouTubeなど）で最初に試す。
+    pub fn with_aria2c(ytdlp: Arc<YtDlpTool>, aria2c: Arc<Aria2cTool>) -> Self {
+        Self {
+            ytdlp,
+            aria2c: Some(aria2c),
+        }
+    }
+
+    /// yt-dlp内蔵のダウンローダーを使うYtDlpBackendを作成
+    ///
+    /// すべてのコンテンツタイプで、特化したバックエンドが失敗した場合の代替として使う。
+    pub fn native(ytdlp: Arc<YtDlpTool>) -> Self {
+        Self { ytdlp, aria2c: None }
+    }
+}
+
+#[async_trait]
+impl DownloadBackend for YtDlpBackend {
+    fn name(&self) -> &str {
+        if self.aria2c.is_some() {
+            "yt-dlp+aria2c"
+        } else {
+            "yt-dlp"
+        }
+    }
+
+    fn priority(&self) -> i32 {
+        if self.aria2c.is_some() {
+            PRIMARY_PRIORITY
+        } else {
+            FALLBACK_PRIORITY
+        }
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities {
+            resume: true,
+            format_selection: true,
+            request_context: true,
+            parallel: self.aria2c.is_some(),
+            external_tool: true,
+        }
+    }
+
+    fn can_handle(&self, _url: &str, content_type: &ContentType) -> bool {
+        self.aria2c.is_none() || matches!(content_type, ContentType::YouTube | ContentType::Unknown)
+    }
+
+    async fn is_available(&self) -> bool {
+        match &self.aria2c {
+            Some(aria2c) => {
+                let (ytdlp, aria2c) = tokio::join!(self.ytdlp.is_available(), aria2c.is_available());
+                ytdlp && aria2c
+            }
+            None => self.ytdlp.is_available().await,
+        }
+    }
+
+    async fn download(
+        &self,
+        url: &str,
+        output_path: &Path,
+        filename: &str,
+        options: &DownloadOptions,
+        progress_callback: Option<ProgressCallback>
+    ) -> Result<PathBuf, DownloadError> {
+        let output_path = output_path.to_path_buf();
+        if self.aria2c.is_some() {
+            self.ytdlp.download(url, &output_path, filename, options, progress_callback).await
+        } else {
+            self.ytdlp.download_native(url, &output_path, filename, options, progress_callback).await
+        }
+    }
+}
+
+/// 内蔵のHTTPダウンローダーで取得するバックエンド
+#[derive(Default)]
+pub struct HttpBackend {
+    http: HttpDownloadTool,
+}
+
+impl HttpBackend {
+    /// 新しいHttpBackendを作成
+    pub fn new(http: HttpDownloadTool) -> Self {
+        Self { http }
+    }
+}
+
+#[async_trait]
+impl DownloadBackend for HttpBackend {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    fn priority(&self) -> i32 {
+        LAST_RESORT_PRIORITY
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities {
+            resume: true,
+            request_context: true,
+            parallel: true,
+            ..Default::default()
+        }
+    }
+
+    fn can_handle(&self, _url: &str, content_type: &ContentType) -> bool {
+        *content_type == ContentType::Mp4
+    }
+
+    async fn download(
+        &self,
+        url: &str,
+        output_path: &Path,
+        filename: &str,
+        options: &DownloadOptions,
+        progress_callback: Option<ProgressCallback>
+    ) -> Result<PathBuf, DownloadError> {
+        self.http.download(url, output_path, filename, options, progress_callback).await
+    }
+}
+
+/// 内蔵のHLSダウンローダーで取得するバックエンド
+#[derive(Default)]
+pub struct HlsBackend {
+    hls: HlsDownloadTool,
+}
+
+impl HlsBackend {
+    /// 新しいHlsBackendを作成
+    pub fn new(hls: HlsDownloadTool) -> Self {
+        Self { hls }
+    }
+}
+
+#[async_trait]
+impl DownloadBackend for HlsBackend {
+    fn name(&self) -> &str {
+        "hls"
+    }
+
+    fn priority(&self) -> i32 {
+        PRIMARY_PRIORITY
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities {
+            resume: true,
+            format_selection: true,
+            request_context: true,
+            parallel: true,
+            ..Default::default()
+        }
+    }
+
+    fn can_handle(&self, _url: &str, content_type: &ContentType) -> bool {
+        *content_type == ContentType::Hls
+    }
+
+    async fn download(
+        &self,
+        url: &str,
+        output_path: &Path,
+        filename: &str,
+        options: &DownloadOptions,
+        progress_callback: Option<ProgressCallback>
+    ) -> Result<PathBuf, DownloadError> {
+        self.hls.download(url, output_path, filename, options, progress_callback).await
+    }
+}
+
+/// 内蔵のDASHダウンローダーで取得するバックエンド
+#[derive(Default)]
+pub struct DashBackend {
+    dash: DashDownloadTool,
+}
+
+impl DashBackend {
+    /// 新しいDashBackendを作成
+    pub fn new(dash: DashDownloadTool) -> Self {
+        Self { dash }
+    }
+}
+
+#[async_trait]
+impl DownloadBackend for DashBackend {
+    fn name(&self) -> &str {
+        "dash"
+    }
+
+    fn priority(&self) -> i32 {
+        PRIMARY_PRIORITY
+    }
+
+    fn capabilities(&self) -> BackendCapabilities {
+        BackendCapabilities {
+            resume: true,
+            format_selection: true,
+            request_context: true,
+            parallel: true,
+            ..Default::default()
+        }
+    }
+
+    fn can_handle(&self, _url: &str, content_type: &ContentType) -> bool {
+        *content_type == ContentType::Dash
+    }
+
+    async fn download(
+        &self,
+        url: &str,
+        output_path: &Path,
+        filename: &str,
+        options: &DownloadOptions,
+        progress_callback: Option<ProgressCallback>
+    ) -> Result<PathBuf, DownloadError> {
+        self.dash.download(url, output_path, filename, options, progress_callback).await
+    }
+}
+
+impl BackendRegistry {
+    /// 組み込みのバックエンドを登録したレジストリを作成
+    pub fn with_builtin(ytdlp: Arc<YtDlpTool>, aria2c: Arc<Aria2cTool>, ffmpeg: FFmpegTool) -> Self {
+        let mut registry = Self::new();
+        registry.register_builtin(ytdlp, aria2c, ffmpeg);
+        registry
+    }
+
+    /// 組み込みのバックエンドを登録する（同じ名前のものは置き換える）
+    ///
+    /// aria2cを使うバックエンドは同じ `Aria2cTool` を共有する（RPCモードの常駐プロセスなど）。
+    pub fn register_builtin(&mut self, ytdlp: Arc<YtDlpTool>, aria2c: Arc<Aria2cTool>, ffmpeg: FFmpegTool) {
+        self.register(Arc::new(Aria2cBackend::new(Arc::clone(&aria2c))));
+        self.register(Arc::new(YtDlpBackend::with_aria2c(Arc::clone(&ytdlp), aria2c)));
+        self.register(Arc::new(YtDlpBackend::native(ytdlp)));
+        self.register(Arc::new(HttpBackend::default()));
+        self.register(Arc::new(HlsBackend::new(HlsDownloadTool::with_tools(ffmpeg.clone(), fetcher::http_client()))));
+        self.register(Arc::new(DashBackend::new(DashDownloadTool::with_tools(ffmpeg, fetcher::http_client()))));
+    }
+}
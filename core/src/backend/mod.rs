@@ -0,0 +1,364 @@
+//! ダウンロード方法（バックエンド）の抽象化
+//!
+//! `DownloadManager` は登録されたバックエンドのうち、URLとコンテンツタイプを
+//! 扱えるものを優先度の高い順に試す。独自のCDN向けのバックエンドや、
+//! テスト用の偽のバックエンドも同じトレイトで登録できる。
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use crate::types::{ContentType, DownloadError, DownloadOptions, ProgressCallback};
+
+pub mod builtin;
+
+pub use self::builtin::{Aria2cBackend, DashBackend, HlsBackend, HttpBackend, YtDlpBackend};
+
+/// バックエンドが対応する機能
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackendCapabilities {
+    /// 中断したダウンロードを途中から再開できる
+    pub resume: bool,
+    /// 画質・トラックの選択条件（`FormatSelection`）に対応する
+    pub format_selection: bool,
+    /// ヘッダー・Cookieなどのリクエスト情報を送る
+    pub request_context: bool,
+    /// 複数の接続で並列に取得する
+    pub parallel: bool,
+    /// 外部ツールを必要とする
+    pub external_tool: bool,
+}
+
+/// ダウンロード方法のインターフェース
+#[async_trait]
+pub trait DownloadBackend: Send + Sync {
+    /// 名前（登録の識別と判断の記録に使う）
+    fn name(&self) -> &str;
+
+    /// 優先度（大きいほど先に試す）
+    fn priority(&self) -> i32;
+
+    /// 対応する機能
+    fn capabilities(&self) -> BackendCapabilities;
+
+    /// URLとコンテンツタイプを扱えるか
+    fn can_handle(&self, url: &str, content_type: &ContentType) -> bool;
+
+    /// 現在の環境で利用できるか（外部ツールの有無など）
+    async fn is_available(&self) -> bool {
+        true
+    }
+
+    /// コンテンツをダウンロードし、出力ファイルのパスを返す
+    async fn download(
+        &self,
+        url: &str,
+        output_path: &Path,
+        filename: &str,
+        options: &DownloadOptions,
+        progress_callback: Option<ProgressCallback>
+    ) -> Result<PathBuf, DownloadError>;
+}
+
+/// 登録されたバックエンドの一覧
+#[derive(Clone, Default)]
+pub struct BackendRegistry {
+    backends: Vec<Arc<dyn DownloadBackend>>,
+}
+
+impl BackendRegistry {
+    /// 空のレジストリを作成
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// バックエンドを登録する（同じ名前のものがあれば置き換える）
+    pub fn register(&mut self, backend: Arc<dyn DownloadBackend>) {
+        match self.backends.iter_mut().find(|registered| registered.name() == backend.name()) {
+            Some(registered) => *registered = backend,
+            None => self.backends.push(backend),
+        }
+    }
+
+    /// 指定した名前のバックエンドを登録から外す
+    pub fn unregister(&mut self, name: &str) -> Option<Arc<dyn DownloadBackend>> {
+        let index = self.backends.iter().position(|backend| backend.name() == name)?;
+        Some(self.backends.remove(index))
+    }
+
+    /// 指定した名前のバックエンド
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn DownloadBackend>> {
+        self.backends.iter().find(|backend| backend.name() == name)
+    }
+
+    /// 登録されたバックエンド（登録順）
+    pub fn backends(&self) -> &[Arc<dyn DownloadBackend>] {
+        &self.backends
+    }
+
+    /// URLとコンテンツタイプを扱えるバックエンドを優先度の高い順に返す
+    ///
+    /// 優先度が同じ場合は先に登録したものを先にする。
+    pub fn candidates(&self, url: &str, content_type: &ContentType) -> Vec<Arc<dyn DownloadBackend>> {
+        let mut candidates: Vec<_> = self
+            .backends
+            .iter()
+            .filter(|backend| backend.can_handle(url, content_type))
+            .cloned()
+            .collect();
+        candidates.sort_by_key(|backend| std::cmp::Reverse(backend.priority()));
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+    use crate::downloader::DownloadManager;
+    use crate::retry::{DecisionAction, DecisionLog};
+    use crate::test_util::TempDir;
+
+    /// 決めた結果を順に返す偽のバックエンド
+    struct FakeBackend {
+        name: &'static str,
+        priority: i32,
+        handles: Vec<ContentType>,
+        available: bool,
+        results: Mutex<VecDeque<Result<&'static str, DownloadError>>>,
+        calls: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl FakeBackend {
+        fn new(name: &'static str, priority: i32, calls: &Arc<Mutex<Vec<&'static str>>>) -> Self {
+            Self {
+                name,
+                priority,
+                handles: vec![ContentType::Mp4],
+                available: true,
+                results: Mutex::new(VecDeque::new()),
+                calls: Arc::clone(calls),
+            }
+        }
+
+        fn handling(mut self, handles: Vec<ContentType>) -> Self {
+            self.handles = handles;
+            self
+        }
+
+        fn unavailable(mut self) -> Self {
+            self.available = false;
+            self
+        }
+
+        fn returning(self, result: Result<&'static str, DownloadError>) -> Self {
+            self.results.lock().unwrap().push_back(result);
+            self
+        }
+    }
+
+    #[async_trait]
+    impl DownloadBackend for FakeBackend {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn priority(&self) -> i32 {
+            self.priority
+        }
+
+        fn capabilities(&self) -> BackendCapabilities {
+            BackendCapabilities::default()
+        }
+
+        fn can_handle(&self, _url: &str, content_type: &ContentType) -> bool {
+            self.handles.contains(content_type)
+        }
+
+        async fn is_available(&self) -> bool {
+            self.available
+        }
+
+        async fn download(
+            &self,
+            _url: &str,
+            output_path: &Path,
+            filename: &str,
+            _options: &DownloadOptions,
+            _progress_callback: Option<ProgressCallback>
+        ) -> Result<PathBuf, DownloadError> {
+            self.calls.lock().unwrap().push(self.name);
+            let result = self.results.lock().unwrap().pop_front().unwrap_or_else(|| {
+                Err(DownloadError::ProcessFailed(format!("{}: 結果が残っていません", self.name)))
+            });
+            result.map(|extension| output_path.join(format!("{}.{}", filename, extension)))
+        }
+    }
+
+    fn names(backends: &[Arc<dyn DownloadBackend>]) -> Vec<&str> {
+        backends.iter().map(|backend| backend.name()).collect()
+    }
+
+    fn registry(backends: Vec<FakeBackend>) -> BackendRegistry {
+        let mut registry = BackendRegistry::new();
+        for backend in backends {
+            registry.register(Arc::new(backend));
+        }
+        registry
+    }
+
+    fn actions(log: &DecisionLog) -> Vec<(String, u32, DecisionAction)> {
+        log.entries()
+            .into_iter()
+            .map(|decision| (decision.backend, decision.attempt, decision.action))
+            .collect()
+    }
+
+    fn options(backend: Option<&str>) -> DownloadOptions {
+        DownloadOptions {
+            retry_wait: 0,
+            backend: backend.map(str::to_string),
+            ..DownloadOptions::default()
+        }
+    }
+
+    #[test]
+    fn candidates_are_ordered_by_priority_then_registration() {
+        let calls = Arc::default();
+        let registry = registry(vec![
+            FakeBackend::new("low", 10, &calls),
+            FakeBackend::new("high", 90, &calls),
+            FakeBackend::new("middle", 50, &calls),
+            FakeBackend::new("middle-later", 50, &calls),
+        ]);
+
+        let candidates = registry.candidates("https://example.com/video.mp4", &ContentType::Mp4);
+        assert_eq!(names(&candidates), vec!["high", "middle", "middle-later", "low"]);
+    }
+
+    #[test]
+    fn candidates_only_include_backends_that_can_handle_the_content() {
+        let calls = Arc::default();
+        let registry = registry(vec![
+            FakeBackend::new("direct", 10, &calls),
+            FakeBackend::new("hls", 50, &calls).handling(vec![ContentType::Hls]),
+            FakeBackend::new("any", 0, &calls).handling(vec![ContentType::Mp4, ContentType::Hls]),
+        ]);
+
+        let url = "https://example.com/master.m3u8";
+        assert_eq!(names(&registry.candidates(url, &ContentType::Hls)), vec!["hls", "any"]);
+        assert_eq!(names(&registry.candidates(url, &ContentType::Mp4)), vec!["direct", "any"]);
+        assert!(registry.candidates(url, &ContentType::Dash).is_empty());
+    }
+
+    #[test]
+    fn registering_the_same_name_replaces_the_backend() {
+        let calls = Arc::default();
+        let mut registry = registry(vec![
+            FakeBackend::new("first", 10, &calls),
+            FakeBackend::new("second", 20, &calls),
+        ]);
+        registry.register(Arc::new(FakeBackend::new("first", 30, &calls)));
+
+        assert_eq!(names(registry.backends()), vec!["first", "second"]);
+        assert_eq!(registry.get("first").map(|backend| backend.priority()), Some(30));
+        assert!(registry.unregister("second").is_some());
+        assert_eq!(names(registry.backends()), vec!["first"]);
+    }
+
+    #[tokio::test]
+    async fn preferred_backend_is_tried_first() {
+        let dir = TempDir::new();
+        let calls = Arc::default();
+        let manager = DownloadManager::new().with_backends(registry(vec![
+            FakeBackend::new("high", 90, &calls).returning(Ok("mp4")),
+            FakeBackend::new("low", 10, &calls).returning(Ok("mp4")),
+        ]));
+
+        let log = DecisionLog::new();
+        let output = manager
+            .download_with_log("https://example.com/video.mp4", dir.path(), "video", Some(options(Some("low"))), None, &log)
+            .await
+            .unwrap();
+
+        assert_eq!(output, dir.path().join("video.mp4"));
+        assert_eq!(*calls.lock().unwrap(), vec!["low"]);
+        assert_eq!(actions(&log), vec![("low".to_string(), 1, DecisionAction::Succeeded)]);
+    }
+
+    #[tokio::test]
+    async fn preferred_backend_that_cannot_handle_the_url_keeps_the_normal_order() {
+        let dir = TempDir::new();
+        let calls = Arc::default();
+        let manager = DownloadManager::new().with_backends(registry(vec![
+            FakeBackend::new("direct", 10, &calls).returning(Ok("mp4")),
+            FakeBackend::new("hls", 90, &calls).handling(vec![ContentType::Hls]),
+        ]));
+
+        let log = DecisionLog::new();
+        manager
+            .download_with_log("https://example.com/video.mp4", dir.path(), "video", Some(options(Some("hls"))), None, &log)
+            .await
+            .unwrap();
+
+        assert_eq!(*calls.lock().unwrap(), vec!["direct"]);
+    }
+
+    #[tokio::test]
+    async fn retries_then_falls_back_to_the_next_backend() {
+        let dir = TempDir::new();
+        let calls = Arc::default();
+        let manager = DownloadManager::new().with_backends(registry(vec![
+            FakeBackend::new("missing", 100, &calls).unavailable(),
+            FakeBackend::new("flaky", 90, &calls)
+                .returning(Err(DownloadError::HttpStatus { status: 503, url: "https://example.com/video.mp4".to_string() }))
+                .returning(Err(DownloadError::HttpStatus { status: 503, url: "https://example.com/video.mp4".to_string() })),
+            FakeBackend::new("broken", 50, &calls)
+                .returning(Err(DownloadError::ProcessFailed("終了コード 1".to_string()))),
+            FakeBackend::new("fallback", 10, &calls).returning(Ok("mp4")),
+        ]));
+
+        let log = DecisionLog::new();
+        let output = manager
+            .download_with_log("https://example.com/video.mp4", dir.path(), "video", Some(options(None)), None, &log)
+            .await
+            .unwrap();
+
+        assert_eq!(output, dir.path().join("video.mp4"));
+        assert_eq!(*calls.lock().unwrap(), vec!["flaky", "flaky", "broken", "fallback"]);
+        assert_eq!(actions(&log), vec![
+            ("missing".to_string(), 0, DecisionAction::Skipped),
+            ("flaky".to_string(), 1, DecisionAction::Retry),
+            ("flaky".to_string(), 2, DecisionAction::Fallback),
+            ("broken".to_string(), 1, DecisionAction::Fallback),
+            ("fallback".to_string(), 1, DecisionAction::Succeeded),
+        ]);
+        let entries = log.entries();
+        assert_eq!(entries[1].wait_seconds, Some(0.0));
+        assert_eq!(entries[2].error_code, Some(crate::types::ErrorCode::from(&DownloadError::HttpStatus {
+            status: 503,
+            url: String::new(),
+        })));
+    }
+
+    #[tokio::test]
+    async fn fatal_error_stops_without_falling_back() {
+        let dir = TempDir::new();
+        let calls = Arc::default();
+        let manager = DownloadManager::new().with_backends(registry(vec![
+            FakeBackend::new("first", 90, &calls)
+                .returning(Err(DownloadError::HttpStatus { status: 404, url: "https://example.com/video.mp4".to_string() })),
+            FakeBackend::new("second", 10, &calls).returning(Ok("mp4")),
+        ]));
+
+        let log = DecisionLog::new();
+        let result = manager
+            .download_with_log("https://example.com/video.mp4", dir.path(), "video", Some(options(None)), None, &log)
+            .await;
+
+        assert!(matches!(result, Err(DownloadError::HttpStatus { status: 404, .. })));
+        assert_eq!(*calls.lock().unwrap(), vec!["first"]);
+        assert_eq!(actions(&log), vec![("first".to_string(), 1, DecisionAction::Abort)]);
+    }
+}
@@ -0,0 +1,647 @@
+//! 速度制限と時間帯ごとの制限
+//!
+//! 制限は全体（すべてのダウンロードの合計）とタスクごとの2段階で、
+//! どちらも実行中のダウンロードに即座に反映する。
+//!
+//! - 内蔵のダウンローダー（HTTP・HLS・DASH）: 受信したデータ量をトークンバケットで制限する
+//! - aria2c: `--max-overall-download-limit`（全体）と `--max-download-limit`（タスク）。
+//!   RPCモードでは `aria2.changeGlobalOption` / `aria2.changeOption` で変更する
+//! - yt-dlp: `--limit-rate`（外部ダウンローダーのaria2cには上記の引数）
+//!
+//! 外部ツールのプロセスは起動後に制限を変更できないため、制限が変わった場合は
+//! プロセスを起動し直して続きから取得する。
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use chrono::{Datelike, Timelike};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tokio::sync::watch;
+
+/// 速度（バイト/秒）
+///
+/// `2M`（2 MiB/s）、`500K`、`1.5M`、`1048576` の形式で指定する。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpeedLimit(pub u64);
+
+impl SpeedLimit {
+    /// バイト/秒
+    pub fn bytes_per_second(&self) -> u64 {
+        self.0
+    }
+}
+
+impl FromStr for SpeedLimit {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let trimmed = value.trim();
+        let number = trimmed.trim_end_matches(['B', '/', 's']).trim_end_matches('i');
+        let (number, multiplier) = match number.chars().last().map(|c| c.to_ascii_uppercase()) {
+            Some('K') => (&number[..number.len() - 1], 1u64 << 10),
+            Some('M') => (&number[..number.len() - 1], 1 << 20),
+            Some('G') => (&number[..number.len() - 1], 1 << 30),
+            _ => (number, 1),
+        };
+        let number: f64 = number
+            .trim()
+            .parse()
+            .map_err(|_| format!("不正な速度: {}（例: 2M、500K）", value))?;
+        if !number.is_finite() || number < 0.0 {
+            return Err(format!("不正な速度: {}（例: 2M、500K）", value));
+        }
+        Ok(Self((number * multiplier as f64).round() as u64))
+    }
+}
+
+impl fmt::Display for SpeedLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (unit, size) in [("G", 1u64 << 30), ("M", 1 << 20), ("K", 1 << 10)] {
+            if self.0 >= size && self.0.is_multiple_of(size) {
+                return write!(f, "{}{}", self.0 / size, unit);
+            }
+        }
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Serialize for SpeedLimit {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for SpeedLimit {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u64),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(bytes) => Ok(Self(bytes)),
+            Raw::Text(text) => text.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// 速度制限の表示（`2M/s`、`無制限`）
+pub fn describe_limit(limit: Option<u64>) -> String {
+    match limit {
+        Some(limit) => format!("{}/s", SpeedLimit(limit)),
+        None => "無制限".to_string(),
+    }
+}
+
+/// 変更できる速度制限とトークンバケット
+///
+/// 複製したものは同じ制限と残量を共有する。0または `None` は無制限。
+#[derive(Clone)]
+pub struct RateLimiter {
+    limit: Arc<watch::Sender<Option<u64>>>,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("RateLimiter").field(&self.limit()).finish()
+    }
+}
+
+impl RateLimiter {
+    /// 新しいRateLimiterを作成
+    pub fn new(limit: Option<u64>) -> Self {
+        let (limit, _) = watch::channel(limit.filter(|limit| *limit > 0));
+        Self {
+            limit: Arc::new(limit),
+            bucket: Arc::new(Mutex::new(Bucket::new())),
+        }
+    }
+
+    /// 現在の制限（バイト/秒）
+    pub fn limit(&self) -> Option<u64> {
+        *self.limit.borrow()
+    }
+
+    /// 制限を変更する（変わった場合は `true`）
+    pub fn set_limit(&self, limit: Option<u64>) -> bool {
+        let limit = limit.filter(|limit| *limit > 0);
+        let changed = self.limit.send_if_modified(|current| {
+            if *current == limit {
+                return false;
+            }
+            *current = limit;
+            true
+        });
+        if changed {
+            // 以前の制限で溜まった待ち時間を持ち越さない
+            *self.bucket.lock().unwrap() = Bucket::new();
+        }
+        changed
+    }
+
+    /// 制限が変わるまで待機
+    pub async fn changed(&self) {
+        let mut receiver = self.limit.subscribe();
+        let _ = receiver.changed().await;
+    }
+
+    /// `bytes` バイトの受信を制限の範囲に収まるまで待機
+    ///
+    /// 待機中に制限が変わった場合は待機を打ち切る（以降は新しい制限で計算する）。
+    pub async fn acquire(&self, bytes: u64) {
+        let mut receiver = self.limit.subscribe();
+        let Some(limit) = *receiver.borrow_and_update() else {
+            return;
+        };
+        let wait = self.bucket.lock().unwrap().take(limit, bytes);
+        if wait.is_zero() {
+            return;
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = receiver.changed() => {}
+        }
+    }
+}
+
+/// トークンバケット（1秒分まで溜められる）
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn new() -> Self {
+        Self { tokens: 0.0, updated_at: Instant::now() }
+    }
+
+    /// トークンを消費し、足りない分を取り戻すまでの待機時間を返す
+    fn take(&mut self, limit: u64, bytes: u64) -> Duration {
+        let rate = limit as f64;
+        let now = Instant::now();
+        self.tokens = (self.tokens + now.duration_since(self.updated_at).as_secs_f64() * rate).min(rate);
+        self.updated_at = now;
+        self.tokens -= bytes as f64;
+
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / rate)
+        }
+    }
+}
+
+/// ダウンロードに適用する速度制限（全体とタスクごと）
+///
+/// ダウンロードマネージャーが [`DownloadOptions`](crate::types::DownloadOptions) に設定する。
+#[derive(Clone, Default)]
+pub struct Throttle {
+    global: RateLimiter,
+    task: RateLimiter,
+}
+
+impl fmt::Debug for Throttle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Throttle")
+            .field("global", &self.global_limit())
+            .field("task", &self.task_limit())
+            .finish()
+    }
+}
+
+impl Throttle {
+    /// 全体の制限とタスクの制限からThrottleを作成
+    pub fn new(global: RateLimiter, task: RateLimiter) -> Self {
+        Self { global, task }
+    }
+
+    /// 全体の制限
+    pub fn global_limit(&self) -> Option<u64> {
+        self.global.limit()
+    }
+
+    /// タスクの制限
+    pub fn task_limit(&self) -> Option<u64> {
+        self.task.limit()
+    }
+
+    /// 実際に適用される制限（厳しい方）
+    pub fn limit(&self) -> Option<u64> {
+        match (self.global_limit(), self.task_limit()) {
+            (Some(global), Some(task)) => Some(global.min(task)),
+            (global, task) => global.or(task),
+        }
+    }
+
+    /// `bytes` バイトの受信を両方の制限の範囲に収まるまで待機
+    pub async fn acquire(&self, bytes: u64) {
+        self.global.acquire(bytes).await;
+        self.task.acquire(bytes).await;
+    }
+
+    /// どちらかの制限が変わるまで待機
+    pub async fn changed(&self) {
+        tokio::select! {
+            _ = self.global.changed() => {}
+            _ = self.task.changed() => {}
+        }
+    }
+}
+
+/// 曜日の集合
+///
+/// `mon-fri`、`sat,sun`、`weekdays`、`weekends`、`daily` の形式で指定する。
+/// 省略した場合は毎日。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Days(u8);
+
+/// 曜日の名前（月曜始まり）
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+impl Default for Days {
+    fn default() -> Self {
+        Self(0x7f)
+    }
+}
+
+impl Days {
+    /// 曜日（月曜が0）を含むか
+    pub fn contains(&self, weekday: u32) -> bool {
+        weekday < 7 && self.0 & (1 << weekday) != 0
+    }
+
+    fn day_index(name: &str) -> Option<u32> {
+        let name = name.trim().to_lowercase();
+        DAY_NAMES
+            .iter()
+            .position(|day| name.starts_with(day))
+            .map(|index| index as u32)
+    }
+}
+
+impl FromStr for Days {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut bits = 0u8;
+        for part in value.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            bits |= match part.to_lowercase().as_str() {
+                "daily" | "everyday" => 0x7f,
+                "weekdays" => 0x1f,
+                "weekends" => 0x60,
+                _ => {
+                    let (start, end) = part.split_once('-').unwrap_or((part, part));
+                    let invalid = || format!("不正な曜日: {}（例: mon-fri、sat,sun、weekdays）", part);
+                    let start = Self::day_index(start).ok_or_else(invalid)?;
+                    let end = Self::day_index(end).ok_or_else(invalid)?;
+                    // 金曜〜月曜のように週をまたぐ指定にも対応する
+                    let mut bits = 0u8;
+                    let mut day = start;
+                    loop {
+                        bits |= 1 << day;
+                        if day == end {
+                            break;
+                        }
+                        day = (day + 1) % 7;
+                    }
+                    bits
+                }
+            };
+        }
+        if bits == 0 {
+            return Err(format!("曜日が指定されていません: {}", value));
+        }
+        Ok(Self(bits))
+    }
+}
+
+impl fmt::Display for Days {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let days: Vec<&str> = (0..7).filter(|day| self.contains(*day)).map(|day| DAY_NAMES[day as usize]).collect();
+        f.write_str(&days.join(","))
+    }
+}
+
+impl Serialize for Days {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Days {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// 時刻（`09:00` の形式、終了時刻には `24:00` も指定できる）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeOfDay(u32);
+
+impl TimeOfDay {
+    /// 1日の終わり（`24:00`）
+    pub fn end_of_day() -> Self {
+        Self(24 * 60)
+    }
+
+    /// 0時からの分
+    pub fn minutes(&self) -> u32 {
+        self.0
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("不正な時刻: {}（例: 09:00）", value);
+        let (hour, minute) = value.trim().split_once(':').ok_or_else(invalid)?;
+        let hour: u32 = hour.parse().map_err(|_| invalid())?;
+        let minute: u32 = minute.parse().map_err(|_| invalid())?;
+        if minute >= 60 || hour > 24 || (hour == 24 && minute > 0) {
+            return Err(invalid());
+        }
+        Ok(Self(hour * 60 + minute))
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.0 / 60, self.0 % 60)
+    }
+}
+
+impl Serialize for TimeOfDay {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeOfDay {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// 時間帯ごとの速度制限
+///
+/// 終了時刻が開始時刻より前の場合は日をまたぐ（`22:00`〜`06:00` など）。
+/// その場合の曜日は開始した日で判断する。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BandwidthRule {
+    /// 曜日（省略時は毎日）
+    #[serde(default)]
+    pub days: Days,
+    /// 開始時刻（省略時は `00:00`）
+    #[serde(default)]
+    pub start: TimeOfDay,
+    /// 終了時刻（この時刻は含まない。省略時は `24:00`）
+    #[serde(default = "TimeOfDay::end_of_day")]
+    pub end: TimeOfDay,
+    /// 全体の速度制限（省略時は無制限）
+    #[serde(default)]
+    pub limit: Option<SpeedLimit>,
+}
+
+impl BandwidthRule {
+    /// 曜日（月曜が0）と時刻（0時からの分）が時間帯に含まれるか
+    pub fn matches(&self, weekday: u32, minutes: u32) -> bool {
+        let (start, end) = (self.start.minutes(), self.end.minutes());
+        if start == end {
+            return self.days.contains(weekday);
+        }
+        if start < end {
+            return self.days.contains(weekday) && (start..end).contains(&minutes);
+        }
+        let previous_day = (weekday + 6) % 7;
+        (self.days.contains(weekday) && minutes >= start) || (self.days.contains(previous_day) && minutes < end)
+    }
+}
+
+/// 全体の速度制限の設定
+///
+/// 時間帯の指定（`schedule`）のうち先に書いたものを優先し、どれにも当てはまらない
+/// 時間は `limit` を使う。
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BandwidthConfig {
+    /// 全体の速度制限（省略時は無制限）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<SpeedLimit>,
+    /// 時間帯ごとの速度制限
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub schedule: Vec<BandwidthRule>,
+}
+
+impl BandwidthConfig {
+    /// 常に同じ制限を使う設定
+    pub fn fixed(limit: Option<u64>) -> Self {
+        Self {
+            limit: limit.map(SpeedLimit),
+            schedule: Vec::new(),
+        }
+    }
+
+    /// 曜日（月曜が0）と時刻（0時からの分）での制限（バイト/秒）
+    pub fn limit_at(&self, weekday: u32, minutes: u32) -> Option<u64> {
+        self.schedule
+            .iter()
+            .find(|rule| rule.matches(weekday, minutes))
+            .map_or(self.limit, |rule| rule.limit)
+            .map(|limit| limit.bytes_per_second())
+            .filter(|limit| *limit > 0)
+    }
+
+    /// 現在（ローカル時刻）の制限
+    pub fn current_limit(&self) -> Option<u64> {
+        let now = chrono::Local::now();
+        self.limit_at(now.weekday().num_days_from_monday(), now.hour() * 60 + now.minute())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MON: u32 = 0;
+    const FRI: u32 = 4;
+    const SAT: u32 = 5;
+    const SUN: u32 = 6;
+
+    fn at(hour: u32, minute: u32) -> u32 {
+        hour * 60 + minute
+    }
+
+    fn config(text: &str) -> BandwidthConfig {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn parses_speed_limits() {
+        assert_eq!("2M".parse(), Ok(SpeedLimit(2 << 20)));
+        assert_eq!("500k".parse(), Ok(SpeedLimit(500 << 10)));
+        assert_eq!("1.5MiB/s".parse(), Ok(SpeedLimit(3 << 19)));
+        assert_eq!("1048576".parse(), Ok(SpeedLimit(1 << 20)));
+        assert!("-1M".parse::<SpeedLimit>().is_err());
+        assert!("fast".parse::<SpeedLimit>().is_err());
+        assert_eq!(SpeedLimit(3 << 19).to_string(), "1536K");
+        assert_eq!(SpeedLimit(1000).to_string(), "1000");
+        assert_eq!(describe_limit(Some(2 << 20)), "2M/s");
+        assert_eq!(describe_limit(None), "無制限");
+    }
+
+    #[test]
+    fn parses_days() {
+        assert_eq!("mon-fri".parse::<Days>().unwrap().to_string(), "mon,tue,wed,thu,fri");
+        assert_eq!("Saturday, sun".parse::<Days>().unwrap().to_string(), "sat,sun");
+        assert_eq!("fri-mon".parse::<Days>().unwrap().to_string(), "mon,fri,sat,sun");
+        assert_eq!("weekends".parse::<Days>(), "sat-sun".parse());
+        assert_eq!("daily".parse::<Days>(), Ok(Days::default()));
+        assert!("mon-xyz".parse::<Days>().is_err());
+        assert!(" , ".parse::<Days>().is_err());
+    }
+
+    #[test]
+    fn parses_times_of_day() {
+        assert_eq!("09:30".parse::<TimeOfDay>().unwrap().minutes(), at(9, 30));
+        assert_eq!("24:00".parse::<TimeOfDay>(), Ok(TimeOfDay::end_of_day()));
+        assert!("24:01".parse::<TimeOfDay>().is_err());
+        assert!("12:60".parse::<TimeOfDay>().is_err());
+        assert!("0930".parse::<TimeOfDay>().is_err());
+        assert_eq!(TimeOfDay::end_of_day().to_string(), "24:00");
+    }
+
+    #[test]
+    fn first_matching_rule_wins_and_falls_back_to_limit() {
+        let config = config(
+            r#"
+            limit = "10M"
+
+            [[schedule]]
+            days = "mon-fri"
+            start = "09:00"
+            end = "18:00"
+            limit = "2M"
+
+            [[schedule]]
+            days = "daily"
+            start = "12:00"
+            end = "13:00"
+            limit = "512K"
+
+            [[schedule]]
+            days = "weekends"
+            "#,
+        );
+
+        assert_eq!(config.limit_at(MON, at(8, 59)), Some(10 << 20));
+        assert_eq!(config.limit_at(MON, at(9, 0)), Some(2 << 20));
+        // 先に書いた平日の昼の制限が優先される
+        assert_eq!(config.limit_at(FRI, at(12, 30)), Some(2 << 20));
+        // 終了時刻は含まない
+        assert_eq!(config.limit_at(FRI, at(18, 0)), Some(10 << 20));
+        assert_eq!(config.limit_at(SAT, at(12, 30)), Some(512 << 10));
+        // 制限を省略した時間帯は無制限
+        assert_eq!(config.limit_at(SAT, at(14, 0)), None);
+        assert_eq!(config.limit_at(SUN, at(23, 59)), None);
+    }
+
+    #[test]
+    fn overnight_rule_uses_the_starting_day() {
+        let config = config(
+            r#"
+            [[schedule]]
+            days = "fri"
+            start = "22:00"
+            end = "06:00"
+            limit = "1M"
+            "#,
+        );
+
+        assert_eq!(config.limit_at(FRI, at(21, 59)), None);
+        assert_eq!(config.limit_at(FRI, at(22, 0)), Some(1 << 20));
+        assert_eq!(config.limit_at(SAT, at(5, 59)), Some(1 << 20));
+        assert_eq!(config.limit_at(SAT, at(6, 0)), None);
+        assert_eq!(config.limit_at(SAT, at(23, 0)), None);
+        // 木曜の夜から続く時間ではない
+        assert_eq!(config.limit_at(FRI, at(3, 0)), None);
+    }
+
+    #[test]
+    fn rule_with_equal_start_and_end_covers_the_whole_day() {
+        let rule = BandwidthRule {
+            days: "sun".parse().unwrap(),
+            start: "00:00".parse().unwrap(),
+            end: "00:00".parse().unwrap(),
+            limit: Some(SpeedLimit(100)),
+        };
+        assert!(rule.matches(SUN, at(0, 0)));
+        assert!(rule.matches(SUN, at(23, 59)));
+        assert!(!rule.matches(MON, at(0, 0)));
+
+        // 省略した時刻は1日中
+        let config = config("[[schedule]]\ndays = \"sun\"\nlimit = 100\n");
+        assert_eq!(config.schedule[0].start.minutes(), 0);
+        assert_eq!(config.schedule[0].end, TimeOfDay::end_of_day());
+        assert_eq!(config.limit_at(SUN, at(23, 59)), Some(100));
+    }
+
+    #[test]
+    fn zero_limit_means_unlimited() {
+        assert_eq!(BandwidthConfig::fixed(Some(0)).limit_at(MON, 0), None);
+        assert_eq!(config("limit = \"0\"").limit_at(MON, 0), None);
+        assert_eq!(BandwidthConfig::fixed(Some(4096)).limit_at(SUN, at(23, 59)), Some(4096));
+    }
+
+    #[test]
+    fn rejects_invalid_schedule() {
+        assert!(toml::from_str::<BandwidthConfig>("[[schedule]]\nstart = \"25:00\"\n").is_err());
+        assert!(toml::from_str::<BandwidthConfig>("[[schedule]]\ndays = \"someday\"\n").is_err());
+    }
+
+    #[test]
+    fn serializes_config_round_trip() {
+        let original = config(
+            r#"
+            limit = "10M"
+
+            [[schedule]]
+            days = "sat,sun"
+            start = "22:00"
+            end = "06:00"
+            limit = "1536K"
+            "#,
+        );
+
+        let text = toml::to_string(&original).unwrap();
+
+        assert!(text.contains("days = \"sat,sun\""), "{}", text);
+        assert_eq!(config(&text), original);
+        assert_eq!(toml::to_string(&BandwidthConfig::default()).unwrap(), "");
+    }
+
+    #[test]
+    fn set_limit_reports_changes() {
+        let limiter = RateLimiter::new(Some(0));
+        assert_eq!(limiter.limit(), None);
+        assert!(limiter.set_limit(Some(1024)));
+        assert!(!limiter.set_limit(Some(1024)));
+        assert!(limiter.clone().set_limit(None));
+        assert_eq!(limiter.limit(), None);
+
+        let throttle = Throttle::new(RateLimiter::new(Some(4096)), RateLimiter::new(Some(1024)));
+        assert_eq!(throttle.limit(), Some(1024));
+        assert_eq!(Throttle::new(RateLimiter::new(Some(4096)), RateLimiter::default()).limit(), Some(4096));
+    }
+}
@@ -0,0 +1,528 @@
+//! ユーザー設定ファイル
+//!
+//! OSの設定ディレクトリの `NextDownloader/config.toml`（Linuxでは
+//! `$XDG_CONFIG_HOME/NextDownloader/config.toml`）から読み込む。
+//!
+//! ダウンロードオプションは次の順に重ね、後のものほど優先する。
+//!
+//! 1. コンテンツタイプに適した組み込みの既定値（[`DownloadOptions::for_content_type`]）
+//! 2. 設定ファイルの最上位の値（`output_dir` など）
+//! 3. コンテンツタイプごとの値（`[content_types.hls]` など）
+//! 4. ホスト名に一致するドメインルール（`[[rules]]`。一致したものをファイルの順に重ねる）
+//! 5. プロファイル（`[profiles.<名前>]`）
+//! 6. コマンドラインなどで明示的に指定された値
+//!
+//! ```toml
+//! output_dir = "/home/user/Videos"
+//! filename_template = "{uploader}/{title} [{id}] {height}p"
+//! collision = "rename"
+//! subtitle_languages = ["ja", "en"]
+//! embed_metadata = true
+//! archive = "/home/user/Videos/archive.txt"
+//!
+//! [tools]
+//! ytdlp = "/opt/yt-dlp/yt-dlp"
+//!
+//! [bandwidth]
+//! limit = "10M"
+//!
+//! [[bandwidth.schedule]]
+//! days = "mon-fri"
+//! start = "09:00"
+//! end = "18:00"
+//! limit = "2M"
+//!
+//! [content_types.hls]
+//! max_retries = 10
+//!
+//! [profiles.audio]
+//! format = "mp3"
+//!
+//! [[rules]]
+//! host = "*.example.com"
+//! backend = "yt-dlp"
+//! headers = { Referer = "https://www.example.com/" }
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use url::Url;
+use crate::bandwidth::{BandwidthConfig, SpeedLimit};
+use crate::filename::{CollisionPolicy, FilenameTemplate};
+use crate::subtitles::SubtitleFormat;
+use crate::tools::discovery::ToolPaths;
+use crate::tools::manager::InstallerConfig;
+use crate::types::{ContentType, DownloadError, DownloadOptions, FormatSelection, VideoFormat};
+
+/// 設定の1つの層で指定するオプション
+///
+/// 指定しなかった項目は下の層の値をそのまま使う。ヘッダーとCookieは
+/// 名前ごとに上書きする。
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OptionOverrides {
+    /// 出力ディレクトリ
+    pub output_dir: Option<PathBuf>,
+    /// ファイル名のテンプレート（[`FilenameTemplate`](crate::filename::FilenameTemplate) を参照）
+    pub filename_template: Option<String>,
+    /// 出力フォーマット（mp4, mkv, mp3）
+    pub format: Option<VideoFormat>,
+    /// 画質・トラックの選択（例: "1080p,av1,h264,lang=ja"）
+    pub quality: Option<String>,
+    /// 並列コネクション数
+    pub connections: Option<u32>,
+    /// ファイル分割数
+    pub splits: Option<u32>,
+    /// チャンクサイズ (MB)
+    pub chunk_size: Option<u32>,
+    /// 最大リトライ回数
+    pub max_retries: Option<u32>,
+    /// リトライ待機時間 (秒)
+    pub retry_wait: Option<u32>,
+    /// 追加のHTTPヘッダー
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// 送信するCookie
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub cookies: BTreeMap<String, String>,
+    /// Netscape形式のCookieファイル
+    pub cookie_file: Option<PathBuf>,
+    /// User-Agent
+    pub user_agent: Option<String>,
+    /// リファラー
+    pub referer: Option<String>,
+    /// 優先して使うバックエンドの名前
+    pub backend: Option<String>,
+    /// ダウンロードごとの速度制限（例: "2M"、"0"で無制限）
+    pub speed_limit: Option<SpeedLimit>,
+    /// ダウンロード済みの記録のファイル（yt-dlpの `--download-archive` と同じ形式）
+    pub archive: Option<PathBuf>,
+    /// 出力先に同じ名前のファイルがある場合の扱い（overwrite、skip、rename）
+    pub collision: Option<CollisionPolicy>,
+    /// 取得する字幕の言語（例: ["ja", "en"]。"all"ですべて）
+    pub subtitle_languages: Option<Vec<String>>,
+    /// 字幕がない言語は自動生成字幕を取得するか
+    pub auto_subtitles: Option<bool>,
+    /// 字幕の保存形式（vtt, srt, ass。省略時は取得した形式のまま）
+    pub subtitle_format: Option<SubtitleFormat>,
+    /// 字幕を動画に埋め込むか（MP4・MKVのみ）
+    pub embed_subtitles: Option<bool>,
+    /// タイトル・投稿者・投稿日・説明をメタデータとして書き込むか
+    pub embed_metadata: Option<bool>,
+    /// サムネイルをカバーアートとして添付するか
+    pub embed_thumbnail: Option<bool>,
+}
+
+impl OptionOverrides {
+    /// 指定された項目を解決中のオプションに上書きする
+    pub fn apply(&self, resolved: &mut ResolvedOptions) -> Result<(), DownloadError> {
+        let options = &mut resolved.options;
+
+        if let Some(output_dir) = &self.output_dir {
+            resolved.output_dir = Some(output_dir.clone());
+        }
+        if let Some(template) = &self.filename_template {
+            resolved.filename_template = Some(template.clone());
+        }
+        if let Some(format) = &self.format {
+            options.format = format.clone();
+        }
+        if let Some(quality) = &self.quality {
+            options.selection = quality.parse::<FormatSelection>().map_err(DownloadError::Config)?;
+        }
+        if let Some(connections) = self.connections {
+            options.connections = connections;
+        }
+        if let Some(splits) = self.splits {
+            options.splits = splits;
+        }
+        if let Some(chunk_size) = self.chunk_size {
+            options.chunk_size = chunk_size;
+        }
+        if let Some(max_retries) = self.max_retries {
+            options.max_retries = max_retries;
+        }
+        if let Some(retry_wait) = self.retry_wait {
+            options.retry_wait = retry_wait;
+        }
+
+        let request = &mut options.request;
+        for (name, value) in &self.headers {
+            request.headers.retain(|(existing, _)| !existing.eq_ignore_ascii_case(name));
+            request.headers.push((name.clone(), value.clone()));
+        }
+        for (name, value) in &self.cookies {
+            request.cookies.retain(|(existing, _)| existing != name);
+            request.cookies.push((name.clone(), value.clone()));
+        }
+        if let Some(cookie_file) = &self.cookie_file {
+            request.cookie_file = Some(cookie_file.clone());
+        }
+        if let Some(user_agent) = &self.user_agent {
+            request.user_agent = Some(user_agent.clone());
+        }
+        if let Some(referer) = &self.referer {
+            request.referer = Some(referer.clone());
+        }
+        if let Some(backend) = &self.backend {
+            options.backend = Some(backend.clone());
+        }
+        if let Some(speed_limit) = self.speed_limit {
+            options.speed_limit = Some(speed_limit.bytes_per_second()).filter(|limit| *limit > 0);
+        }
+        if let Some(archive) = &self.archive {
+            options.archive = Some(archive.clone());
+        }
+        if let Some(collision) = self.collision {
+            options.collision = collision;
+        }
+        if let Some(languages) = &self.subtitle_languages {
+            options.subtitles.languages = languages.clone();
+        }
+        if let Some(auto_subtitles) = self.auto_subtitles {
+            options.subtitles.auto_captions = auto_subtitles;
+        }
+        if let Some(format) = self.subtitle_format {
+            options.subtitles.format = Some(format);
+        }
+        if let Some(embed) = self.embed_subtitles {
+            options.subtitles.embed = embed;
+        }
+        if let Some(embed) = self.embed_metadata {
+            options.metadata.embed = embed;
+        }
+        if let Some(thumbnail) = self.embed_thumbnail {
+            options.metadata.thumbnail = thumbnail;
+        }
+
+        Ok(())
+    }
+
+    /// 値が正しいか確認する
+    fn validate(&self, scope: &str) -> Result<(), DownloadError> {
+        if let Some(template) = &self.filename_template {
+            template
+                .parse::<FilenameTemplate>()
+                .map_err(|err| DownloadError::Config(format!("{}: {}", scope, err)))?;
+        }
+        if let Some(quality) = &self.quality {
+            quality
+                .parse::<FormatSelection>()
+                .map_err(|err| DownloadError::Config(format!("{}: {}", scope, err)))?;
+        }
+        Ok(())
+    }
+}
+
+/// ホスト名で一致させるドメインルール
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DomainRule {
+    /// ホスト名のパターン（`*` と `?` を使える。例: `*.example.com`）
+    pub host: String,
+    /// 一致した場合に使うオプション
+    #[serde(flatten)]
+    pub options: OptionOverrides,
+}
+
+impl DomainRule {
+    /// ホスト名がパターンに一致するか（大文字小文字は区別しない）
+    pub fn matches(&self, host: &str) -> bool {
+        glob_match(&self.host.to_lowercase(), &host.to_lowercase())
+    }
+}
+
+/// 設定を重ねて決めたダウンロードの条件
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedOptions {
+    /// ダウンロードオプション
+    pub options: DownloadOptions,
+    /// 出力ディレクトリ（どの層でも指定されなかった場合は `None`）
+    pub output_dir: Option<PathBuf>,
+    /// ファイル名のテンプレート（どの層でも指定されなかった場合は `None`）
+    pub filename_template: Option<String>,
+}
+
+/// ユーザー設定
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// すべてのダウンロードに使う値
+    #[serde(flatten)]
+    pub defaults: OptionOverrides,
+    /// 外部ツールの場所
+    #[serde(skip_serializing_if = "is_default")]
+    pub tools: ToolPaths,
+    /// `tools install` のマニフェストと取得元
+    #[serde(skip_serializing_if = "is_default")]
+    pub installer: InstallerConfig,
+    /// 全体の速度制限と時間帯ごとの制限
+    #[serde(skip_serializing_if = "is_default")]
+    pub bandwidth: BandwidthConfig,
+    /// コンテンツタイプごとの値（mp4, hls, dash, youtube, unknown）
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub content_types: BTreeMap<String, OptionOverrides>,
+    /// 名前付きのプロファイル
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, OptionOverrides>,
+    /// ドメインルール
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<DomainRule>,
+}
+
+impl Config {
+    /// デフォルトの設定ファイルのパス（OSの設定ディレクトリ配下）
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("NextDownloader").join("config.toml"))
+    }
+
+    /// デフォルトの設定ファイルから読み込む（存在しない場合は空の設定）
+    pub fn load() -> Result<Self, DownloadError> {
+        match Self::default_path() {
+            Some(path) => Self::load_from(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// 設定ファイルから読み込む（存在しない場合は空の設定）
+    pub fn load_from(path: &Path) -> Result<Self, DownloadError> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let config: Self = toml::from_str(&text)
+            .map_err(|err| DownloadError::Config(format!("{}: {}", path.display(), err)))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 設定ファイルに保存する
+    pub fn save_to(&self, path: &Path) -> Result<(), DownloadError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let text = toml::to_string_pretty(self).map_err(|err| DownloadError::Config(err.to_string()))?;
+        let tmp_path = path.with_extension("toml.tmp");
+        std::fs::write(&tmp_path, text)?;
+        std::fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    /// 値が正しいか確認する
+    pub fn validate(&self) -> Result<(), DownloadError> {
+        self.defaults.validate("既定値")?;
+        for (name, overrides) in &self.content_types {
+            if !CONTENT_TYPE_KEYS.contains(&name.as_str()) {
+                return Err(DownloadError::Config(format!("不明なコンテンツタイプ: {}", name)));
+            }
+            overrides.validate(&format!("content_types.{}", name))?;
+        }
+        for (name, overrides) in &self.profiles {
+            overrides.validate(&format!("profiles.{}", name))?;
+        }
+        for (index, rule) in self.rules.iter().enumerate() {
+            if rule.host.is_empty() {
+                return Err(DownloadError::Config(format!("rules.{}: host を指定してください", index)));
+            }
+            rule.options.validate(&format!("rules.{}", index))?;
+        }
+        Ok(())
+    }
+
+    /// 名前付きのプロファイル
+    pub fn profile(&self, name: &str) -> Result<&OptionOverrides, DownloadError> {
+        self.profiles
+            .get(name)
+            .ok_or_else(|| DownloadError::Config(format!("プロファイルが見つかりません: {}", name)))
+    }
+
+    /// URLのホスト名に一致するドメインルール（ファイルの順）
+    pub fn matching_rules<'a>(&'a self, url: &str) -> impl Iterator<Item = &'a DomainRule> + 'a {
+        let host = Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_string));
+        self.rules
+            .iter()
+            .filter(move |rule| host.as_deref().is_some_and(|host| rule.matches(host)))
+    }
+
+    /// 設定を重ねてダウンロードの条件を決める
+    ///
+    /// 優先順位はモジュールの説明の通り。`flags` には明示的に指定された値を渡す。
+    pub fn resolve(
+        &self,
+        url: &str,
+        content_type: Option<&ContentType>,
+        profile: Option<&str>,
+        flags: &OptionOverrides
+    ) -> Result<ResolvedOptions, DownloadError> {
+        let mut resolved = ResolvedOptions {
+            options: content_type.map(DownloadOptions::for_content_type).unwrap_or_default(),
+            ..Default::default()
+        };
+
+        self.defaults.apply(&mut resolved)?;
+        if let Some(overrides) = content_type.and_then(|content_type| self.content_types.get(content_type_key(content_type))) {
+            overrides.apply(&mut resolved)?;
+        }
+        for rule in self.matching_rules(url) {
+            rule.options.apply(&mut resolved)?;
+        }
+        if let Some(name) = profile {
+            self.profile(name)?.apply(&mut resolved)?;
+        }
+        flags.apply(&mut resolved)?;
+
+        Ok(resolved)
+    }
+
+    /// ドット区切りのキー（例: `profiles.audio.format`、`rules.0.host`）の値
+    pub fn get(&self, key: &str) -> Result<Option<toml::Value>, DownloadError> {
+        let mut value = &self.to_value()?;
+        for segment in key.split('.') {
+            let next = match value {
+                toml::Value::Table(table) => table.get(segment),
+                toml::Value::Array(array) => segment.parse::<usize>().ok().and_then(|index| array.get(index)),
+                _ => None,
+            };
+            match next {
+                Some(next) => value = next,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(value.clone()))
+    }
+
+    /// ドット区切りのキーに値を設定する
+    ///
+    /// 値はTOMLの値として解釈し、解釈できない場合は文字列として扱う。
+    /// 配列の末尾の次の番号を指定すると要素を追加する（例: `rules.0.host`）。
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), DownloadError> {
+        let segments: Vec<&str> = key.split('.').collect();
+        if segments.iter().any(|segment| segment.is_empty()) {
+            return Err(DownloadError::Config(format!("不正なキー: {}", key)));
+        }
+
+        let mut root = self.to_value()?;
+        let mut target = &mut root;
+        for (position, segment) in segments.iter().enumerate() {
+            // 次のキーが番号なら配列、それ以外はテーブルを作る
+            let container = || match segments.get(position + 1) {
+                Some(next) if next.parse::<usize>().is_ok() => toml::Value::Array(Vec::new()),
+                _ => toml::Value::Table(toml::Table::new()),
+            };
+            let child = match target {
+                toml::Value::Table(table) => table.entry(segment.to_string()).or_insert_with(container),
+                toml::Value::Array(array) => {
+                    let index = segment
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|index| *index <= array.len())
+                        .ok_or_else(|| DownloadError::Config(format!("不正な番号: {}", key)))?;
+                    if index == array.len() {
+                        array.push(container());
+                    }
+                    &mut array[index]
+                }
+                _ => return Err(DownloadError::Config(format!("値を設定できないキーです: {}", key))),
+            };
+            if position + 1 == segments.len() {
+                *child = parse_value(value);
+            }
+            target = child;
+        }
+
+        let config: Self = root
+            .try_into()
+            .map_err(|err: toml::de::Error| DownloadError::Config(format!("{}: {}", key, err.message())))?;
+        config.validate()?;
+        *self = config;
+        Ok(())
+    }
+
+    /// 設定されているすべての値（ドット区切りのキー, 値）
+    pub fn entries(&self) -> Result<Vec<(String, toml::Value)>, DownloadError> {
+        let mut entries = Vec::new();
+        flatten_value(String::new(), &self.to_value()?, &mut entries);
+        Ok(entries)
+    }
+
+    /// TOMLの値に変換
+    fn to_value(&self) -> Result<toml::Value, DownloadError> {
+        toml::Value::try_from(self).map_err(|err| DownloadError::Config(err.to_string()))
+    }
+}
+
+/// 既定値のままか（既定値の項目は保存しない）
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// 設定で使うコンテンツタイプの名前
+const CONTENT_TYPE_KEYS: [&str; 5] = ["mp4", "hls", "dash", "youtube", "unknown"];
+
+/// コンテンツタイプの設定上の名前
+fn content_type_key(content_type: &ContentType) -> &'static str {
+    match content_type {
+        ContentType::Mp4 => "mp4",
+        ContentType::Hls => "hls",
+        ContentType::Dash => "dash",
+        ContentType::YouTube => "youtube",
+        ContentType::Unknown => "unknown",
+    }
+}
+
+/// コマンドラインで渡された値をTOMLの値として解釈する
+fn parse_value(value: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+/// テーブルと配列をたどり、末端の値を集める
+fn flatten_value(prefix: String, value: &toml::Value, entries: &mut Vec<(String, toml::Value)>) {
+    let key = |name: &str| if prefix.is_empty() { name.to_string() } else { format!("{}.{}", prefix, name) };
+    match value {
+        toml::Value::Table(table) => {
+            for (name, value) in table {
+                flatten_value(key(name), value, entries);
+            }
+        }
+        toml::Value::Array(array) if array.iter().any(toml::Value::is_table) => {
+            for (index, value) in array.iter().enumerate() {
+                flatten_value(key(&index.to_string()), value, entries);
+            }
+        }
+        _ => entries.push((prefix, value.clone())),
+    }
+}
+
+/// `*`（任意の文字列）と `?`（任意の1文字）を使えるパターンに一致するか
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
@@ -0,0 +1,343 @@
+//! URLとサーバーの応答からのコンテンツタイプの判定
+//!
+//! URLのパス（クエリは除く）の拡張子で判定できない場合は、HEADリクエストか
+//! 先頭だけの範囲指定GETで Content-Type・Content-Disposition・先頭のバイト列を調べる。
+
+use std::time::Duration;
+use url::Url;
+use crate::request::RequestContext;
+use crate::tools::fetcher;
+use crate::types::ContentType;
+
+/// 判定のために読み込む先頭のバイト数
+const SNIFF_LENGTH: usize = 1024;
+
+/// 1回のリクエストの待機時間の上限
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// MPEG-TSのパケット長
+const TS_PACKET_LENGTH: usize = 188;
+
+/// サーバーに問い合わせてコンテンツタイプを判定するための構造体
+pub struct ContentDetector {
+    client: reqwest::Client,
+}
+
+impl Default for ContentDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ContentDetector {
+    /// 新しいContentDetectorを作成
+    pub fn new() -> Self {
+        Self::with_client(fetcher::http_client())
+    }
+
+    /// 指定したHTTPクライアントでContentDetectorを作成
+    pub fn with_client(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+
+    /// URLとサーバーの応答からコンテンツタイプを判定
+    ///
+    /// 判定できない場合（HTMLのページなど）は `None` を返す。
+    pub async fn detect(&self, url: &str, request: &RequestContext) -> Option<ContentType> {
+        if let Some(content_type) = content_type_from_url(url) {
+            return Some(content_type);
+        }
+
+        let url = Url::parse(url).ok()?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return None;
+        }
+        let client = fetcher::request_client(&self.client, request, &url).ok()?;
+        self.probe(&client, &url).await
+    }
+
+    /// HEADで判定できなければ先頭のバイト列を取得して判定する
+    async fn probe(&self, client: &reqwest::Client, url: &Url) -> Option<ContentType> {
+        // HEADに対応していないサーバーもあるため、失敗した場合はGETで確認する
+        if let Ok(response) = client.head(url.clone()).timeout(PROBE_TIMEOUT).send().await {
+            if response.status().is_success() {
+                if let Some(content_type) = content_type_from_headers(&response) {
+                    return Some(content_type);
+                }
+                if is_html(&response) {
+                    return None;
+                }
+            }
+        }
+
+        let mut response = client
+            .get(url.clone())
+            .header(reqwest::header::RANGE, format!("bytes=0-{}", SNIFF_LENGTH - 1))
+            .timeout(PROBE_TIMEOUT)
+            .send()
+            .await
+            .ok()?;
+        if !response.status().is_success() {
+            return None;
+        }
+        if let Some(content_type) = content_type_from_headers(&response) {
+            return Some(content_type);
+        }
+
+        // Rangeを無視して全体を返すサーバーもあるため、必要な分だけ読んで打ち切る
+        let mut head = Vec::with_capacity(SNIFF_LENGTH);
+        while head.len() < SNIFF_LENGTH {
+            match response.chunk().await {
+                Ok(Some(chunk)) => head.extend_from_slice(&chunk),
+                Ok(None) => break,
+                Err(_) if !head.is_empty() => break,
+                Err(_) => return None,
+            }
+        }
+        content_type_from_magic(&head)
+    }
+}
+
+/// URLのパスの拡張子とホストからコンテンツタイプを判定
+///
+/// クエリやフラグメントは無視する（`https://cdn/x.m3u8?token=abc` はHLS）。
+pub fn content_type_from_url(url: &str) -> Option<ContentType> {
+    let url = Url::parse(url).ok()?;
+    if is_youtube(&url) {
+        return Some(ContentType::YouTube);
+    }
+    content_type_from_path(url.path())
+}
+
+/// Content-Typeからコンテンツタイプを判定
+pub fn content_type_from_mime(mime: &str) -> Option<ContentType> {
+    let mime = mime.split(';').next().unwrap_or_default().trim().to_lowercase();
+    match mime.as_str() {
+        "application/vnd.apple.mpegurl"
+        | "application/x-mpegurl"
+        | "audio/mpegurl"
+        | "audio/x-mpegurl" => Some(ContentType::Hls),
+        "application/dash+xml" => Some(ContentType::Dash),
+        _ if mime.starts_with("video/") => Some(ContentType::Mp4),
+        _ => None,
+    }
+}
+
+/// 先頭のバイト列からコンテンツタイプを判定
+///
+/// MPEG-TSは単一のファイルとしてそのまま取得でき、ダウンロード方法もMP4と変わらないため
+/// [`ContentType::Mp4`] として扱う。
+pub fn content_type_from_magic(bytes: &[u8]) -> Option<ContentType> {
+    let text = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    let text = &text[text.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(text.len())..];
+
+    if text.starts_with(b"#EXTM3U") {
+        Some(ContentType::Hls)
+    } else if bytes.get(4..8) == Some(b"ftyp") {
+        Some(ContentType::Mp4)
+    } else if bytes.len() > TS_PACKET_LENGTH && bytes[0] == 0x47 && bytes[TS_PACKET_LENGTH] == 0x47 {
+        // MPEG-TS（パケットの先頭が同期バイト 0x47）
+        Some(ContentType::Mp4)
+    } else if text.starts_with(b"<") && bytes.windows(4).any(|window| window == b"<MPD") {
+        Some(ContentType::Dash)
+    } else {
+        None
+    }
+}
+
+/// 応答のヘッダー（Content-Type、Content-Disposition、リダイレクト後のURL）から判定
+fn content_type_from_headers(response: &reqwest::Response) -> Option<ContentType> {
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+
+    header(reqwest::header::CONTENT_TYPE)
+        .and_then(content_type_from_mime)
+        .or_else(|| {
+            header(reqwest::header::CONTENT_DISPOSITION)
+                .and_then(disposition_filename)
+                .and_then(|filename| content_type_from_path(&filename))
+        })
+        .or_else(|| content_type_from_path(response.url().path()))
+}
+
+/// HTMLのページか
+fn is_html(response: &reqwest::Response) -> bool {
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|mime| mime.trim_start().to_lowercase().starts_with("text/html"))
+        .unwrap_or(false)
+}
+
+/// パス（またはファイル名）の拡張子から判定
+fn content_type_from_path(path: &str) -> Option<ContentType> {
+    let name = path.rsplit('/').next()?;
+    let (_, extension) = name.rsplit_once('.')?;
+    match extension.to_lowercase().as_str() {
+        "m3u8" => Some(ContentType::Hls),
+        "mpd" => Some(ContentType::Dash),
+        "mp4" | "m4v" => Some(ContentType::Mp4),
+        _ => None,
+    }
+}
+
+/// Content-Dispositionのファイル名（`filename*=` を優先する）
+fn disposition_filename(value: &str) -> Option<String> {
+    let mut filename = None;
+    for param in value.split(';').map(str::trim) {
+        let Some((name, value)) = param.split_once('=') else {
+            continue;
+        };
+        match name.trim().to_lowercase().as_str() {
+            "filename*" => {
+                // RFC 5987形式（UTF-8''video.mp4）。判定には拡張子しか使わないため復号しない
+                return value.trim().rsplit('\'').next().map(str::to_string);
+            }
+            "filename" => filename = Some(value.trim().trim_matches('"').to_string()),
+            _ => {}
+        }
+    }
+    filename
+}
+
+/// YouTubeのURLか
+fn is_youtube(url: &Url) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let host = host.to_lowercase();
+    ["youtube.com", "youtu.be", "youtube-nocookie.com"]
+        .iter()
+        .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{Response, TestServer};
+
+    #[test]
+    fn url_extension_ignores_query_and_fragment() {
+        assert_eq!(content_type_from_url("https://cdn.example.com/live/x.m3u8?token=abc"), Some(ContentType::Hls));
+        assert_eq!(content_type_from_url("https://cdn.example.com/a/manifest.MPD#t=10"), Some(ContentType::Dash));
+        assert_eq!(content_type_from_url("https://cdn.example.com/v.m4v"), Some(ContentType::Mp4));
+        assert_eq!(content_type_from_url("https://cdn.example.com/watch?file=x.m3u8"), None);
+        assert_eq!(content_type_from_url("https://m.youtube.com/watch?v=abc"), Some(ContentType::YouTube));
+        assert_eq!(content_type_from_url("https://notyoutube.com/v.mp4"), Some(ContentType::Mp4));
+    }
+
+    #[test]
+    fn mime_types() {
+        assert_eq!(content_type_from_mime("application/vnd.apple.mpegurl; charset=utf-8"), Some(ContentType::Hls));
+        assert_eq!(content_type_from_mime("Application/Dash+XML"), Some(ContentType::Dash));
+        assert_eq!(content_type_from_mime("video/mp2t"), Some(ContentType::Mp4));
+        assert_eq!(content_type_from_mime("application/octet-stream"), None);
+    }
+
+    #[test]
+    fn magic_hls_allows_bom_and_leading_whitespace() {
+        assert_eq!(content_type_from_magic(b"#EXTM3U\n#EXT-X-VERSION:3"), Some(ContentType::Hls));
+        assert_eq!(content_type_from_magic(b"\xEF\xBB\xBF#EXTM3U\n"), Some(ContentType::Hls));
+        assert_eq!(content_type_from_magic(b" \r\n\t#EXTM3U\n"), Some(ContentType::Hls));
+        assert_eq!(content_type_from_magic(b"\xEF\xBB\xBF\n  #EXTM3U"), Some(ContentType::Hls));
+    }
+
+    #[test]
+    fn magic_mpd() {
+        let mpd = b"<?xml version=\"1.0\"?>\n<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\">";
+        assert_eq!(content_type_from_magic(mpd), Some(ContentType::Dash));
+        assert_eq!(content_type_from_magic(b"<html><body>MPD</body></html>"), None);
+    }
+
+    #[test]
+    fn magic_mp4_ftyp() {
+        assert_eq!(content_type_from_magic(b"\x00\x00\x00\x18ftypmp42\x00\x00\x00\x00"), Some(ContentType::Mp4));
+        assert_eq!(content_type_from_magic(b"\x00\x00\x00\x18moov"), None);
+    }
+
+    #[test]
+    fn magic_ts_needs_two_sync_bytes() {
+        let mut ts = vec![0u8; TS_PACKET_LENGTH * 2];
+        ts[0] = 0x47;
+        ts[TS_PACKET_LENGTH] = 0x47;
+        assert_eq!(content_type_from_magic(&ts), Some(ContentType::Mp4));
+
+        ts[TS_PACKET_LENGTH] = 0;
+        assert_eq!(content_type_from_magic(&ts), None);
+        // 1パケットに満たない場合は判定しない
+        assert_eq!(content_type_from_magic(&[0x47; 100]), None);
+    }
+
+    #[test]
+    fn disposition_prefers_extended_filename() {
+        assert_eq!(
+            disposition_filename("attachment; filename=\"fallback.bin\"; filename*=UTF-8''%E5%8B%95%E7%94%BB.mp4"),
+            Some("%E5%8B%95%E7%94%BB.mp4".to_string())
+        );
+        assert_eq!(disposition_filename("attachment; filename=\"my video.m3u8\""), Some("my video.m3u8".to_string()));
+        assert_eq!(disposition_filename("inline; FILENAME=plain.mpd"), Some("plain.mpd".to_string()));
+        assert_eq!(disposition_filename("inline"), None);
+    }
+
+    #[tokio::test]
+    async fn probe_falls_back_to_range_get_when_head_is_rejected() {
+        let server = TestServer::start(|request| match request.method.as_str() {
+            "HEAD" => Response::status(405),
+            _ => Response::ranged(b"#EXTM3U\n#EXT-X-TARGETDURATION:4\n", request)
+                .with_header("Content-Type", "application/octet-stream"),
+        })
+        .await;
+
+        let detected = ContentDetector::new().detect(&server.url("/stream"), &RequestContext::default()).await;
+
+        assert_eq!(detected, Some(ContentType::Hls));
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].method, "HEAD");
+        assert_eq!(requests[1].method, "GET");
+        assert_eq!(requests[1].header("Range"), Some("bytes=0-1023"));
+    }
+
+    #[tokio::test]
+    async fn probe_returns_none_for_html_page() {
+        let server = TestServer::start(|_| {
+            Response::ok("<!doctype html><html></html>").with_header("Content-Type", "text/html; charset=utf-8")
+        })
+        .await;
+
+        let detected = ContentDetector::new().detect(&server.url("/watch/123"), &RequestContext::default()).await;
+
+        assert_eq!(detected, None);
+        // HEADでHTMLとわかった時点で終える
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn probe_reads_only_the_head_when_range_is_ignored() {
+        let mut body = b"\x00\x00\x00\x18ftypisom".to_vec();
+        body.resize(4 * 1024 * 1024, 0);
+        let server = TestServer::start(move |_| Response::ok(body.clone())).await;
+
+        let detected = ContentDetector::new().detect(&server.url("/download"), &RequestContext::default()).await;
+
+        assert_eq!(detected, Some(ContentType::Mp4));
+    }
+
+    #[tokio::test]
+    async fn probe_uses_disposition_filename_from_head() {
+        let server = TestServer::start(|_| {
+            Response::ok("").with_header("Content-Disposition", "attachment; filename=\"manifest.mpd\"")
+        })
+        .await;
+
+        let detected = ContentDetector::new().detect(&server.url("/get?id=1"), &RequestContext::default()).await;
+
+        assert_eq!(detected, Some(ContentType::Dash));
+        assert_eq!(server.requests().len(), 1);
+    }
+}
@@ -1,6 +1,29 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use async_trait::async_trait;
-use crate::types::{ContentType, DownloadOptions, DownloadError, ProgressCallback};
+use tokio::sync::{broadcast, watch, Mutex};
+use url::Url;
+use crate::types::{ContentType, DownloadOptions, DownloadError, ErrorCode, FormatInfo, ProgressCallback, ProgressInfo, ProgressPhase, VideoFormat, VideoInfo};
+use crate::detect::{content_type_from_url, ContentDetector};
+use crate::tools::discovery::{ExternalTool, ToolLocation, ToolPaths};
+use crate::request::RequestContext;
+use crate::queue::{DownloadQueue, DownloadTask, QueueConfig, QueueLock, TaskStatus};
+use crate::playlist::{Playlist, PlaylistEntry, PlaylistSource};
+use crate::backend::{Aria2cBackend, BackendRegistry, DownloadBackend, YtDlpBackend};
+use crate::retry::{Decision, DecisionAction, DecisionLog, RetryPolicy};
+use crate::bandwidth::{self, BandwidthConfig, RateLimiter, Throttle};
+use crate::archive::{self, DownloadArchive};
+use crate::filename::{overwrite_staging, replace_existing, resolve_collision, sanitize_filename, FilenameTemplate, TemplateFields};
+use crate::metadata::{fetch_thumbnail, MediaMetadata};
+use crate::subtitles::{available_tracks, hls_tracks, select_tracks, SubtitleSource};
+use crate::tools::fetcher;
+use crate::tools::hls::{HlsDownloadTool, Playlist as HlsPlaylist};
+
+/// 時間帯ごとの速度制限を確認する間隔
+const BANDWIDTH_SCHEDULE_INTERVAL: Duration = Duration::from_secs(15);
 
 /// ダウンローダーの基本的なインターフェースを定義するトレイト
 #[async_trait]
@@ -12,7 +35,7 @@ pub trait Downloader {
     async fn download(
         &self, 
         url: &str, 
-        output_path: &PathBuf, 
+        output_path: &Path, 
         filename: &str,
         options: Option<DownloadOptions>,
         progress_callback: Option<ProgressCallback>
@@ -20,29 +43,208 @@ pub trait Downloader {
     
     /// ダウンロードをキャンセル
     async fn cancel_download(&self, task_id: &str) -> Result<(), DownloadError>;
+    
+    /// ダウンロードを一時停止（途中までのデータは保持する）
+    async fn pause(&self, task_id: &str) -> Result<(), DownloadError>;
+    
+    /// 一時停止したダウンロードを再開
+    async fn resume(&self, task_id: &str) -> Result<(), DownloadError>;
 }
 
 /// ダウンロードマネージャーの実装
+///
+/// ダウンロード自体は登録されたバックエンド（[`DownloadBackend`]）に任せる。
 pub struct DownloadManager {
-    ytdlp: crate::tools::ytdlp::YtDlpTool,
-    aria2c: crate::tools::aria2c::Aria2cTool,
+    ytdlp: Arc<crate::tools::ytdlp::YtDlpTool>,
+    aria2c: Arc<crate::tools::aria2c::Aria2cTool>,
     ffmpeg: crate::tools::ffmpeg::FFmpegTool,
-    hls: crate::tools::hls::HlsDownloadTool,
-    active_tasks: tokio::sync::Mutex<std::collections::HashMap<String, tokio::task::JoinHandle<()>>>,
+    tool_paths: ToolPaths,
+    detector: ContentDetector,
+    backends: BackendRegistry,
+    queue: Mutex<DownloadQueue>,
+    max_concurrent: usize,
+    callbacks: Mutex<HashMap<String, ProgressCallback>>,
+    active_tasks: Mutex<HashMap<String, watch::Sender<Option<TaskStatus>>>>,
+    /// タスクの実行ロック（保持している間だけこのプロセスでタスクを実行する）
+    runner_lock: std::sync::Mutex<Option<QueueLock>>,
+    scheduling_stopped: AtomicBool,
+    events: broadcast::Sender<DownloadTask>,
+    bandwidth: Arc<std::sync::Mutex<BandwidthConfig>>,
+    global_limit: RateLimiter,
+    schedule_ticker: std::sync::Mutex<Option<tokio::task::JoinHandle<()>>>,
+    task_limits: std::sync::Mutex<HashMap<String, RateLimiter>>,
+    archive_lock: std::sync::Mutex<()>,
+}
+
+impl Drop for DownloadManager {
+    fn drop(&mut self) {
+        if let Some(ticker) = self.schedule_ticker.lock().unwrap().take() {
+            ticker.abort();
+        }
+    }
+}
+
+impl Default for DownloadManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DownloadManager {
-    /// 新しいダウンロードマネージャーを作成
+    /// 新しいダウンロードマネージャーを作成（キューは永続化しない）
     pub fn new() -> Self {
+        Self::with_queue(DownloadQueue::default(), QueueConfig::default().max_concurrent)
+    }
+
+    /// キュー設定を指定してダウンロードマネージャーを作成
+    ///
+    /// 状態ファイルが指定されている場合は、保存済みのタスクを読み込む。
+    pub fn with_queue_config(config: QueueConfig) -> Result<Self, DownloadError> {
+        let queue = DownloadQueue::load(config.state_path)?;
+        Ok(Self::with_queue(queue, config.max_concurrent))
+    }
+
+    fn with_queue(queue: DownloadQueue, max_concurrent: usize) -> Self {
+        let (events, _) = broadcast::channel(256);
+
+        let ytdlp = Arc::new(crate::tools::ytdlp::YtDlpTool::new());
+        let aria2c = Arc::new(crate::tools::aria2c::Aria2cTool::new());
+        let ffmpeg = crate::tools::ffmpeg::FFmpegTool::new();
+        let backends = BackendRegistry::with_builtin(Arc::clone(&ytdlp), Arc::clone(&aria2c), ffmpeg.clone());
+
         Self {
-            ytdlp: crate::tools::ytdlp::YtDlpTool::new(),
-            aria2c: crate::tools::aria2c::Aria2cTool::new(),
-            ffmpeg: crate::tools::ffmpeg::FFmpegTool::new(),
-            hls: crate::tools::hls::HlsDownloadTool::new(),
-            active_tasks: tokio::sync::Mutex::new(std::collections::HashMap::new()),
+            ytdlp,
+            aria2c,
+            ffmpeg,
+            tool_paths: ToolPaths::default(),
+            detector: ContentDetector::new(),
+            backends,
+            queue: Mutex::new(queue),
+            max_concurrent: max_concurrent.max(1),
+            callbacks: Mutex::new(HashMap::new()),
+            active_tasks: Mutex::new(HashMap::new()),
+            runner_lock: std::sync::Mutex::new(None),
+            scheduling_stopped: AtomicBool::new(false),
+            events,
+            bandwidth: Arc::new(std::sync::Mutex::new(BandwidthConfig::default())),
+            global_limit: RateLimiter::default(),
+            schedule_ticker: std::sync::Mutex::new(None),
+            task_limits: std::sync::Mutex::new(HashMap::new()),
+            archive_lock: std::sync::Mutex::new(()),
         }
     }
-    
+
+    /// 外部ツールの場所の設定を指定する
+    ///
+    /// 組み込みのバックエンドも指定した場所のツールを使うよう登録し直す。
+    /// `with_aria2c` でRPCモードのaria2cを指定していた場合、RPCモードは引き継ぐ。
+    pub fn with_tool_paths(mut self, tool_paths: ToolPaths) -> Self {
+        self.ytdlp = Arc::new(crate::tools::ytdlp::YtDlpTool::with_path(tool_paths.path(ExternalTool::YtDlp)));
+        let mut aria2c = crate::tools::aria2c::Aria2cTool::with_path(tool_paths.path(ExternalTool::Aria2c));
+        if self.aria2c.is_rpc() {
+            aria2c = aria2c.with_rpc();
+        }
+        self.aria2c = Arc::new(aria2c);
+        self.ffmpeg = crate::tools::ffmpeg::FFmpegTool::with_path(tool_paths.path(ExternalTool::Ffmpeg));
+        self.backends.register_builtin(Arc::clone(&self.ytdlp), Arc::clone(&self.aria2c), self.ffmpeg.clone());
+        self.tool_paths = tool_paths;
+        self
+    }
+
+    /// 全体の速度制限と時間帯ごとの制限を指定する
+    pub fn with_bandwidth(self, config: BandwidthConfig) -> Self {
+        self.set_bandwidth(config);
+        self
+    }
+
+    /// 全体の速度制限と時間帯ごとの制限を変更する（実行中のダウンロードにも反映する）
+    pub fn set_bandwidth(&self, config: BandwidthConfig) {
+        *self.bandwidth.lock().unwrap() = config;
+        self.apply_bandwidth_schedule();
+        self.start_schedule_ticker();
+    }
+
+    /// 時間帯ごとの制限がある場合、切り替わりを反映するタイマーを起動する（起動済みなら何もしない）
+    ///
+    /// タイマーはマネージャーに1つだけで、マネージャーの破棄とともに停止する。
+    /// 非同期ランタイムの外から呼ばれた場合は、次にキューを更新するときに起動する。
+    fn start_schedule_ticker(&self) {
+        if self.bandwidth.lock().unwrap().schedule.is_empty() {
+            return;
+        }
+        let mut ticker = self.schedule_ticker.lock().unwrap();
+        if ticker.is_some() {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+
+        let config = Arc::clone(&self.bandwidth);
+        let global_limit = self.global_limit.clone();
+        *ticker = Some(runtime.spawn(async move {
+            loop {
+                tokio::time::sleep(BANDWIDTH_SCHEDULE_INTERVAL).await;
+                apply_schedule(&config, &global_limit);
+            }
+        }));
+    }
+
+    /// 全体の速度制限と時間帯ごとの制限
+    pub fn bandwidth(&self) -> BandwidthConfig {
+        self.bandwidth.lock().unwrap().clone()
+    }
+
+    /// 現在の全体の速度制限（バイト/秒、Noneで無制限）
+    pub fn speed_limit(&self) -> Option<u64> {
+        self.global_limit.limit()
+    }
+
+    /// 現在の時刻に当てはまる全体の速度制限を適用する
+    pub fn apply_bandwidth_schedule(&self) {
+        apply_schedule(&self.bandwidth, &self.global_limit);
+    }
+
+    /// 外部ツールの場所の設定
+    pub fn tool_paths(&self) -> &ToolPaths {
+        &self.tool_paths
+    }
+
+    /// 外部ツールの場所（見つからない場合は `None`）
+    pub fn tool_location(&self, tool: ExternalTool) -> Option<ToolLocation> {
+        self.tool_paths.locate(tool)
+    }
+
+    /// 使用するaria2cを差し替える（RPCモードの利用など）
+    ///
+    /// aria2cを使う組み込みのバックエンドも差し替えたaria2cを使うよう登録し直す。
+    pub fn with_aria2c(mut self, aria2c: crate::tools::aria2c::Aria2cTool) -> Self {
+        self.aria2c = Arc::new(aria2c);
+        self.backends.register(Arc::new(Aria2cBackend::new(Arc::clone(&self.aria2c))));
+        self.backends.register(Arc::new(YtDlpBackend::with_aria2c(
+            Arc::clone(&self.ytdlp),
+            Arc::clone(&self.aria2c)
+        )));
+        self
+    }
+
+    /// バックエンドを追加する（同じ名前の組み込みのバックエンドは置き換える）
+    pub fn with_backend(mut self, backend: Arc<dyn DownloadBackend>) -> Self {
+        self.backends.register(backend);
+        self
+    }
+
+    /// 使用するバックエンドをまとめて差し替える（テスト用の偽のバックエンドなど）
+    pub fn with_backends(mut self, backends: BackendRegistry) -> Self {
+        self.backends = backends;
+        self
+    }
+
+    /// 登録されたバックエンド
+    pub fn backends(&self) -> &BackendRegistry {
+        &self.backends
+    }
+
     /// 依存関係をチェック
     pub async fn check_dependencies(&self) -> (bool, bool, bool) {
         let (ytdlp_available, aria2c_available, ffmpeg_available) = tokio::join!(
@@ -50,14 +252,14 @@ impl DownloadManager {
             self.aria2c.is_available(),
             self.ffmpeg.is_available()
         );
-        
+
         (ytdlp_available, aria2c_available, ffmpeg_available)
     }
-    
+
     /// システム状態を取得
     pub async fn system_status(&self) -> crate::types::SystemStatus {
         let (ytdlp, aria2c, ffmpeg) = self.check_dependencies().await;
-        
+
         if ytdlp && aria2c && ffmpeg {
             crate::types::SystemStatus::Ready
         } else {
@@ -68,27 +270,584 @@ impl DownloadManager {
             }
         }
     }
-}
 
-#[async_trait]
-impl Downloader for DownloadManager {
-    async fn detect_content_type(&self, url: &str) -> Result<ContentType, DownloadError> {
-        // URLの拡張子をチェック
-        if url.to_lowercase().ends_with(".mp4") {
-            return Ok(ContentType::Mp4);
-        } else if url.to_lowercase().ends_with(".m3u8") {
-            return Ok(ContentType::Hls);
-        } else if url.to_lowercase().ends_with(".mpd") {
-            return Ok(ContentType::Dash);
+    /// yt-dlpでURLの動画情報を取得
+    pub async fn get_video_info(&self, url: &str) -> Result<VideoInfo, DownloadError> {
+        self.ytdlp.get_video_info(url, &RequestContext::default()).await
+    }
+
+    /// yt-dlpでプレイリスト・チャンネルの項目の一覧を取得（プレイリストでない場合はNone）
+    pub async fn resolve_entries(&self, url: &str, request: &RequestContext) -> Result<Option<Playlist>, DownloadError> {
+        self.ytdlp.resolve_entries(url, request).await
+    }
+
+    /// URLで選択可能なフォーマットの一覧を取得
+    ///
+    /// マニフェストやyt-dlpの取得には `request` のヘッダー・Cookieを使う。
+    pub async fn list_formats(&self, url: &str, request: &RequestContext) -> Result<Vec<FormatInfo>, DownloadError> {
+        let content_type = self.detect_content_type_with(url, request).await?;
+        let manifest_client = || {
+            let parsed = Url::parse(url)
+                .map_err(|err| DownloadError::InvalidManifest(format!("不正なURL {}: {}", url, err)))?;
+            fetcher::request_client(&fetcher::http_client(), request, &parsed)
+        };
+        match content_type {
+            ContentType::Hls => {
+                HlsDownloadTool::with_tools(self.ffmpeg.clone(), manifest_client()?)
+                    .list_formats(url)
+                    .await
+            }
+            ContentType::Dash => {
+                crate::tools::dash::DashDownloadTool::with_tools(self.ffmpeg.clone(), manifest_client()?)
+                    .list_formats(url)
+                    .await
+            }
+            ContentType::Mp4 if content_type_from_url(url) == Some(ContentType::Mp4) => Ok(vec![FormatInfo {
+                format_id: Some("direct".to_string()),
+                url: Some(url.to_string()),
+                ext: Some("mp4".to_string()),
+                ..Default::default()
+            }]),
+            _ => Ok(self.ytdlp.get_video_info(url, request).await?.formats.unwrap_or_default()),
         }
-        
-        // YouTubeのURLをチェック
-        if url.contains("youtube.com") || url.contains("youtu.be") {
-            return Ok(ContentType::YouTube);
+    }
+
+    /// URLで取得可能な字幕のトラックの一覧を取得
+    ///
+    /// HLSはマスタープレイリストの字幕レンディション、動画ページはyt-dlpの字幕・自動生成字幕。
+    /// 動画ファイル・DASHのURLでは空の一覧を返す。
+    pub async fn list_subtitles(&self, url: &str, options: &DownloadOptions) -> Result<Vec<SubtitleSource>, DownloadError> {
+        let content_type = match content_type_from_url(url) {
+            Some(content_type) => Some(content_type),
+            None => self.detector.detect(url, &options.request).await,
+        };
+        match content_type {
+            Some(ContentType::Hls) => {
+                let parsed = Url::parse(url)
+                    .map_err(|err| DownloadError::InvalidManifest(format!("不正なURL {}: {}", url, err)))?;
+                let client = fetcher::request_client(&fetcher::http_client(), &options.request, &parsed)?;
+                match HlsDownloadTool::with_tools(self.ffmpeg.clone(), client).load_playlist(url).await? {
+                    HlsPlaylist::Master(master) => Ok(hls_tracks(&master)),
+                    HlsPlaylist::Media(_) => Ok(Vec::new()),
+                }
+            }
+            Some(ContentType::Mp4 | ContentType::Dash) => Ok(Vec::new()),
+            _ => Ok(available_tracks(&self.ytdlp.get_download_info(url, options).await?)),
+        }
+    }
+
+    /// タスクをキューに追加する（実行は開始しない）
+    ///
+    /// 追加したタスクのIDを返す。
+    pub async fn add_task(
+        &self,
+        url: &str,
+        output_path: &Path,
+        filename: &str,
+        options: Option<DownloadOptions>,
+        priority: i32,
+        progress_callback: Option<ProgressCallback>
+    ) -> Result<String, DownloadError> {
+        let task = DownloadTask::new(url, output_path, filename, options, priority);
+        self.push_task(task, progress_callback).await
+    }
+
+    /// プレイリストの項目をタスクとしてキューに追加する（実行は開始しない）
+    ///
+    /// 項目ごとに別のタスクになり、それぞれ進捗と結果を持つ。追加したタスクのIDを返す。
+    #[allow(clippy::too_many_arguments)]
+    pub async fn add_playlist_entry(
+        &self,
+        playlist: &Playlist,
+        entry: &PlaylistEntry,
+        output_path: &Path,
+        filename: &str,
+        options: Option<DownloadOptions>,
+        priority: i32,
+        progress_callback: Option<ProgressCallback>
+    ) -> Result<String, DownloadError> {
+        let mut task = DownloadTask::new(&entry.url, output_path, filename, options, priority);
+        task.playlist = Some(PlaylistSource::new(playlist, entry));
+        task.archive_key = entry.archive_key();
+        self.push_task(task, progress_callback).await
+    }
+
+    async fn push_task(
+        &self,
+        task: DownloadTask,
+        progress_callback: Option<ProgressCallback>
+    ) -> Result<String, DownloadError> {
+        let task_id = task.id.clone();
+
+        if let Some(callback) = progress_callback {
+            self.callbacks.lock().await.insert(task_id.clone(), callback);
+        }
+
+        let mut queue = self.queue.lock().await;
+        let active = self.active_tasks.lock().await;
+        let _state = queue.lock_state().await?;
+        queue.reload(|id| active.contains_key(id))?;
+        queue.push(task.clone());
+        queue.save()?;
+
+        let _ = self.events.send(task);
+        Ok(task_id)
+    }
+
+    /// タスクをキューに追加し、実行を開始する
+    pub async fn enqueue(
+        self: &Arc<Self>,
+        url: &str,
+        output_path: &Path,
+        filename: &str,
+        options: Option<DownloadOptions>,
+        priority: i32,
+        progress_callback: Option<ProgressCallback>
+    ) -> Result<String, DownloadError> {
+        let task_id = self
+            .add_task(url, output_path, filename, options, priority, progress_callback)
+            .await?;
+        self.start_pending();
+        Ok(task_id)
+    }
+
+    /// 同時実行数の上限まで待機中のタスクを開始する
+    pub fn start_pending(self: &Arc<Self>) {
+        let this = Arc::clone(self);
+        tokio::spawn(async move {
+            if let Err(err) = this.schedule().await {
+                log::error!("キューの更新に失敗しました: {}", err);
+            }
+        });
+    }
+
+    /// キュー内のタスク一覧を取得
+    pub async fn list_tasks(&self) -> Result<Vec<DownloadTask>, DownloadError> {
+        let mut queue = self.queue.lock().await;
+        let active = self.active_tasks.lock().await;
+        queue.reload(|id| active.contains_key(id))?;
+        Ok(queue.tasks().to_vec())
+    }
+
+    /// タスクを取得
+    pub async fn task(&self, task_id: &str) -> Option<DownloadTask> {
+        self.queue.lock().await.get(task_id).cloned()
+    }
+
+    /// タスクの優先度を変更
+    pub async fn set_priority(&self, task_id: &str, priority: i32) -> Result<(), DownloadError> {
+        let mut queue = self.queue.lock().await;
+        let active = self.active_tasks.lock().await;
+        let _state = queue.lock_state().await?;
+        queue.reload(|id| active.contains_key(id))?;
+
+        let task = queue
+            .get_mut(task_id)
+            .ok_or_else(|| DownloadError::Internal("タスクが見つかりません".to_string()))?;
+        task.priority = priority;
+        let task = task.clone();
+        queue.save()?;
+
+        let _ = self.events.send(task);
+        Ok(())
+    }
+
+    /// タスクの速度制限を変更（実行中のタスクにも反映する）
+    pub async fn set_task_speed_limit(&self, task_id: &str, limit: Option<u64>) -> Result<(), DownloadError> {
+        let mut queue = self.queue.lock().await;
+        let active = self.active_tasks.lock().await;
+        let _state = queue.lock_state().await?;
+        queue.reload(|id| active.contains_key(id))?;
+
+        let task = queue
+            .get_mut(task_id)
+            .ok_or_else(|| DownloadError::Internal("タスクが見つかりません".to_string()))?;
+        task.speed_limit = limit.filter(|limit| *limit > 0);
+        let task = task.clone();
+        queue.save()?;
+
+        if let Some(limiter) = self.task_limits.lock().unwrap().get(task_id) {
+            limiter.set_limit(task.speed_limit);
+        }
+
+        let _ = self.events.send(task);
+        Ok(())
+    }
+
+    /// 出力ディレクトリ内の中断されたタスクを待機中に戻す
+    ///
+    /// 一時停止中のタスクと、途中データ（`.aria2`や`.part`）を残して失敗した
+    /// タスクが対象。待機中に戻したタスクのIDを返す。
+    pub async fn resume_interrupted(&self, output_path: &Path) -> Result<Vec<String>, DownloadError> {
+        let target = output_path.canonicalize()?;
+
+        let mut queue = self.queue.lock().await;
+        let active = self.active_tasks.lock().await;
+        let _state = queue.lock_state().await?;
+        queue.reload(|id| active.contains_key(id))?;
+
+        let resumed: Vec<String> = queue
+            .tasks()
+            .iter()
+            .filter(|task| {
+                let same_dir = task
+                    .output_path
+                    .canonicalize()
+                    .map(|path| path == target)
+                    .unwrap_or(false);
+                let interrupted = match task.status {
+                    TaskStatus::Paused => true,
+                    TaskStatus::Failed => !task.partial_files().is_empty(),
+                    _ => false,
+                };
+                same_dir && interrupted
+            })
+            .map(|task| task.id.clone())
+            .collect();
+
+        for task_id in &resumed {
+            if let Some(task) = queue.get_mut(task_id) {
+                task.status = TaskStatus::Pending;
+                task.error = None;
+                task.error_code = None;
+                let _ = self.events.send(task.clone());
+            }
+        }
+        queue.save()?;
+
+        Ok(resumed)
+    }
+
+    /// タスクの状態変化を購読する
+    pub fn subscribe(&self) -> broadcast::Receiver<DownloadTask> {
+        self.events.subscribe()
+    }
+
+    /// タスクが終了するまで待機する
+    pub async fn wait_for(&self, task_id: &str) -> Result<DownloadTask, DownloadError> {
+        let mut events = self.subscribe();
+
+        loop {
+            match self.task(task_id).await {
+                Some(task) if task.status.is_finished() => return Ok(task),
+                Some(_) => {}
+                None => return Err(DownloadError::Internal("タスクが見つかりません".to_string())),
+            }
+
+            if let Err(broadcast::error::RecvError::Closed) = events.recv().await {
+                return Err(DownloadError::Internal("タスクの監視が終了しました".to_string()));
+            }
+        }
+    }
+
+    /// 待機中のタスクがなくなるまでキューを処理する
+    ///
+    /// 他のプロセスからの追加やキャンセルを取り込むため、一定間隔で
+    /// 状態ファイルを読み直す。他のプロセスがタスクの実行ロックを保持している間は、
+    /// 待機中のタスクをそのプロセスが実行し終えるまで待つ。
+    pub async fn run_until_idle(self: &Arc<Self>) -> Result<(), DownloadError> {
+        let mut events = self.subscribe();
+
+        loop {
+            self.schedule().await?;
+
+            let idle = {
+                let queue = self.queue.lock().await;
+                let active = self.active_tasks.lock().await;
+                active.is_empty() && !queue.has_pending()
+            };
+            if idle {
+                return Ok(());
+            }
+
+            let _ = tokio::time::timeout(Duration::from_secs(1), events.recv()).await;
+        }
+    }
+
+    /// 新しいタスクの開始をやめる
+    ///
+    /// 実行中のタスクがなくなった時点でタスクの実行ロックを解放し、他のプロセスが
+    /// 待機中のタスクを実行できるようにする。
+    pub async fn stop_scheduling(&self) {
+        self.scheduling_stopped.store(true, Ordering::SeqCst);
+        if self.active_tasks.lock().await.is_empty() {
+            self.runner_lock.lock().unwrap().take();
+        }
+    }
+
+    /// タスクの実行ロックを取得する（取得済みの場合はそのまま使う）
+    ///
+    /// 他のプロセスが保持している場合と、開始をやめた後は `false` を返す。
+    fn acquire_runner_lock(&self, queue: &DownloadQueue) -> Result<bool, DownloadError> {
+        if self.scheduling_stopped.load(Ordering::SeqCst) {
+            return Ok(false);
+        }
+        let mut runner_lock = self.runner_lock.lock().unwrap();
+        if runner_lock.is_none() {
+            *runner_lock = queue.try_lock_runner()?;
+        }
+        Ok(runner_lock.is_some())
+    }
+
+    /// 待機中のタスクを同時実行数の上限まで開始する
+    ///
+    /// タスクを開始するのはタスクの実行ロックを取得できた場合だけで、状態ファイルを
+    /// 共有する複数のプロセスが同じタスクを実行しないようにする。実行中のタスクが
+    /// なくなったらロックを解放する。
+    async fn schedule(self: &Arc<Self>) -> Result<(), DownloadError> {
+        let mut queue = self.queue.lock().await;
+        let mut active = self.active_tasks.lock().await;
+        let _state = queue.lock_state().await?;
+
+        // 他のプロセスでキャンセル・一時停止されたタスクを停止
+        for (task_id, status) in queue.reload(|id| active.contains_key(id))? {
+            if let Some(stop) = active.get(&task_id) {
+                let _ = stop.send(Some(status));
+            }
+        }
+
+        // 他のプロセスで変更された速度制限を実行中のタスクに反映
+        for (task_id, limiter) in self.task_limits.lock().unwrap().iter() {
+            if let Some(task) = queue.get(task_id) {
+                limiter.set_limit(task.speed_limit);
+            }
+        }
+        self.apply_bandwidth_schedule();
+        self.start_schedule_ticker();
+
+        let mut started = Vec::new();
+        if queue.has_pending() && active.len() < self.max_concurrent && self.acquire_runner_lock(&queue)? {
+            while active.len() < self.max_concurrent {
+                let Some(task) = queue.next_pending() else {
+                    break;
+                };
+                task.status = TaskStatus::Running;
+                task.owner_pid = Some(std::process::id());
+                let task = task.clone();
+
+                let (stop_tx, stop_rx) = watch::channel(None);
+                active.insert(task.id.clone(), stop_tx);
+                let callback = self.callbacks.lock().await.remove(&task.id);
+
+                tokio::spawn(Arc::clone(self).run_task(task.clone(), stop_rx, callback));
+                started.push(task);
+            }
+        }
+
+        if active.is_empty() {
+            self.runner_lock.lock().unwrap().take();
+        }
+        if !started.is_empty() {
+            queue.save()?;
+        }
+        for task in started {
+            let _ = self.events.send(task);
+        }
+
+        Ok(())
+    }
+
+    /// タスクを実行し、結果をキューに反映する
+    ///
+    /// 停止要求を受けた場合はダウンロード処理を破棄する。外部ツールのプロセスは
+    /// 終了されるが、途中までのデータは出力先に残り、再開時に引き継がれる。
+    /// 結果をキューに反映するまで実行中タスクとして扱う。
+    async fn run_task(
+        self: Arc<Self>,
+        task: DownloadTask,
+        mut stop: watch::Receiver<Option<TaskStatus>>,
+        progress_callback: Option<ProgressCallback>
+    ) {
+        let stop_requested = async {
+            stop.wait_for(Option::is_some).await.ok().and_then(|status| *status)
+        };
+        let decisions = DecisionLog::new();
+        let output_stem = OnceLock::new();
+
+        // 速度制限は実行中も変更できるよう、タスクごとに保持する
+        let task_limit = RateLimiter::new(task.speed_limit);
+        self.task_limits.lock().unwrap().insert(task.id.clone(), task_limit.clone());
+
+        // ダウンロードアーカイブに記録済みの場合は実行しない
+        let archive = task.options.as_ref().and_then(|options| options.archive.clone());
+        let ignore_archive = task.options.as_ref().is_some_and(|options| options.ignore_archive);
+        let run = async {
+            let archive_key = match &archive {
+                Some(_) => Some(self.archive_key(&task).await),
+                None => None,
+            };
+            if let (Some(path), Some(key)) = (&archive, &archive_key) {
+                if !ignore_archive && self.is_archived(path, key) {
+                    log::info!("{} はダウンロード済みのためスキップします（{}）", task.url, key);
+                    return (archive_key, Err(TaskStatus::Skipped));
+                }
+            }
+            let result = self.download_limited(
+                &task.url,
+                &task.output_path,
+                &task.filename,
+                task.options.clone(),
+                task.playlist.as_ref(),
+                progress_callback,
+                &decisions,
+                task_limit,
+                Some(&output_stem)
+            ).await;
+            (archive_key, Ok(result))
+        };
+        let (archive_key, result) = tokio::select! {
+            result = run => result,
+            Some(status) = stop_requested => (None, Err(status)),
+        };
+        self.task_limits.lock().unwrap().remove(&task.id);
+
+        {
+            let mut queue = self.queue.lock().await;
+            let mut active = self.active_tasks.lock().await;
+            active.remove(&task.id);
+            let state = queue.lock_state().await;
+            if let Err(err) = &state {
+                log::warn!("キューのロックに失敗しました: {}", err);
+            }
+            if let Err(err) = queue.reload(|id| active.contains_key(id)) {
+                log::warn!("キューの読み込みに失敗しました: {}", err);
+            }
+
+            if let Some(stored) = queue.get_mut(&task.id) {
+                stored.owner_pid = None;
+                stored.decisions = decisions.entries();
+                if let Some(stem) = output_stem.get() {
+                    stored.output_stem = Some(stem.clone());
+                }
+                match result {
+                    Ok(Ok(output_file)) => {
+                        if let (Some(path), Some(key)) = (&archive, &archive_key) {
+                            self.record_archive(path, key);
+                        }
+                        stored.status = TaskStatus::Completed;
+                        stored.output_file = Some(output_file);
+                        stored.error = None;
+                        stored.error_code = None;
+                    }
+                    Ok(Err(DownloadError::Cancelled)) => stored.status = TaskStatus::Cancelled,
+                    Ok(Err(DownloadError::AlreadyExists(output_file))) => {
+                        log::info!("{} は同じ名前のファイルがあるためスキップします", output_file.display());
+                        stored.status = TaskStatus::Skipped;
+                        stored.output_file = Some(output_file);
+                    }
+                    Ok(Err(err)) => {
+                        stored.status = TaskStatus::Failed;
+                        stored.error_code = Some(ErrorCode::from(&err));
+                        stored.error = Some(err.to_string());
+                    }
+                    Err(status) => stored.status = status,
+                }
+                let _ = self.events.send(stored.clone());
+            }
+
+            if let Err(err) = queue.save() {
+                log::error!("キューの保存に失敗しました: {}", err);
+            }
+        }
+
+        self.start_pending();
+    }
+
+    /// タスクのダウンロードアーカイブのキー
+    ///
+    /// プレイリストの項目は展開時にわかった動画ID、YouTubeの動画はURLの動画IDを使う。
+    /// その他の動画ページはyt-dlpで動画IDを調べ、わからない場合（動画ファイルのURLなど）は
+    /// 正規化したURLのハッシュを使う。
+    pub async fn archive_key(&self, task: &DownloadTask) -> String {
+        if let Some(key) = task.archive_key.clone().or_else(|| archive::archive_key_from_url(&task.url)) {
+            return key;
+        }
+        if matches!(content_type_from_url(&task.url), None | Some(ContentType::Unknown)) {
+            let request = task.options.as_ref().map(|options| options.request.clone()).unwrap_or_default();
+            if let Ok(VideoInfo { extractor_key: Some(extractor), id: Some(id), .. }) = self.ytdlp.get_video_info(&task.url, &request).await {
+                return archive::archive_key(&extractor, &id);
+            }
+        }
+        archive::url_archive_key(&task.url)
+    }
+
+    /// ダウンロードアーカイブに記録済みかどうか（読み込めない場合は未記録として扱う）
+    fn is_archived(&self, path: &Path, key: &str) -> bool {
+        let _guard = self.archive_lock.lock().unwrap();
+        match DownloadArchive::load(path) {
+            Ok(archive) => archive.contains(key),
+            Err(err) => {
+                log::warn!("ダウンロードアーカイブの読み込みに失敗しました: {}: {}", path.display(), err);
+                false
+            }
+        }
+    }
+
+    /// ダウンロードアーカイブに記録する（失敗してもダウンロード自体は成功として扱う）
+    fn record_archive(&self, path: &Path, key: &str) {
+        let _guard = self.archive_lock.lock().unwrap();
+        if let Err(err) = DownloadArchive::load(path).and_then(|mut archive| archive.record(key)) {
+            log::warn!("ダウンロードアーカイブへの記録に失敗しました: {}: {}", path.display(), err);
+        }
+    }
+
+    /// ファイル名のテンプレートを展開して、出力ディレクトリと拡張子なしのファイル名を決める
+    ///
+    /// `info` が指定されていればその動画情報を使う。指定されず、テンプレートにURL・プレイリストから
+    /// わからない項目（タイトルや解像度など）がある場合は、動画ファイル・ストリーミングのURLを除き
+    /// yt-dlpで動画情報を取得する。取得できない場合はわかっている値だけで展開する。
+    pub async fn output_name(
+        &self,
+        url: &str,
+        output_path: &Path,
+        template: &str,
+        options: &DownloadOptions,
+        playlist: Option<&PlaylistSource>,
+        info: Option<&VideoInfo>
+    ) -> Result<(PathBuf, String), DownloadError> {
+        let template: FilenameTemplate = template
+            .parse()
+            .map_err(|err| DownloadError::Config(format!("ファイル名のテンプレート: {}", err)))?;
+        let mut fields = TemplateFields::from_url(url).with_ext(&options.format.to_string().to_lowercase());
+        if let Some(playlist) = playlist {
+            fields = fields.with_playlist(playlist);
+        }
+        if let Some(info) = info {
+            fields = fields.with_video_info(info);
+        } else if is_page(url) && template.needs_video_info(&fields) {
+            match self.ytdlp.get_download_info(url, options).await {
+                Ok(info) => fields = fields.with_video_info(&info),
+                Err(err) => log::warn!("ファイル名に使う動画情報を取得できませんでした: {}: {}", url, err),
+            }
+        }
+
+        let relative = template.render(&fields);
+        let stem = relative
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "download".to_string());
+        let output_dir = match relative.parent() {
+            Some(parent) => output_path.join(parent),
+            None => output_path.to_path_buf(),
+        };
+        Ok((output_dir, stem))
+    }
+
+    /// リクエスト情報（ヘッダー・Cookie）を指定してコンテンツタイプを検出
+    ///
+    /// URLのパスで判定できない場合はサーバーの応答（Content-Typeや先頭のバイト列）を調べ、
+    /// それでも判定できない場合（動画ページなど）に限りyt-dlpで解析する。
+    pub async fn detect_content_type_with(
+        &self,
+        url: &str,
+        request: &RequestContext
+    ) -> Result<ContentType, DownloadError> {
+        if let Some(content_type) = self.detector.detect(url, request).await {
+            return Ok(content_type);
         }
         
         // yt-dlpを使用してコンテンツタイプを検出
-        match self.ytdlp.get_video_info(url).await {
+        match self.ytdlp.get_video_info(url, request).await {
             Ok(info) => {
                 if let Some(formats) = &info.formats {
                     for format in formats {
@@ -108,60 +867,676 @@ impl Downloader for DownloadManager {
             Err(_) => Ok(ContentType::Unknown),
         }
     }
-    
-    async fn download(
-        &self, 
-        url: &str, 
-        output_path: &PathBuf, 
+
+    /// コンテンツをダウンロードし、ダウンロード方法の選択と再試行の経過を記録する
+    ///
+    /// コンテンツタイプごとの順序でダウンロード方法を試す。一時的なエラーは
+    /// 同じ方法で再試行し、それでも失敗した場合は次の方法に切り替える。
+    /// 他の方法でも解決しないエラー（地域制限やキャンセルなど）の場合はその時点で中止する。
+    pub async fn download_with_log(
+        &self,
+        url: &str,
+        output_path: &Path,
         filename: &str,
         options: Option<DownloadOptions>,
-        progress_callback: Option<ProgressCallback>
+        progress_callback: Option<ProgressCallback>,
+        log: &DecisionLog
+    ) -> Result<PathBuf, DownloadError> {
+        let task_limit = RateLimiter::new(options.as_ref().and_then(|options| options.speed_limit));
+        self.download_limited(url, output_path, filename, options, None, progress_callback, log, task_limit, None).await
+    }
+
+    /// タスクの速度制限を指定してダウンロードする
+    ///
+    /// `filename` はファイル名のテンプレート（[`FilenameTemplate`]）として展開する。
+    /// `output_stem` には展開し衝突を解決した出力ファイルのパス（拡張子なし）を設定する。
+    #[allow(clippy::too_many_arguments)]
+    async fn download_limited(
+        &self,
+        url: &str,
+        output_path: &Path,
+        filename: &str,
+        options: Option<DownloadOptions>,
+        playlist: Option<&PlaylistSource>,
+        progress_callback: Option<ProgressCallback>,
+        log: &DecisionLog,
+        task_limit: RateLimiter,
+        output_stem: Option<&OnceLock<PathBuf>>
     ) -> Result<PathBuf, DownloadError> {
         // コンテンツタイプを検出
-        let content_type = self.detect_content_type(url).await?;
+        let request = options.as_ref().map(|options| options.request.clone()).unwrap_or_default();
+        let content_type = self.detect_content_type_with(url, &request).await?;
         
         // オプションが指定されていない場合は、コンテンツタイプに基づいて最適なオプションを使用
-        let download_options = options.unwrap_or_else(|| {
-            match content_type {
-                ContentType::Mp4 => DownloadOptions::default(),
-                ContentType::Hls => DownloadOptions {
-                    chunk_size: 1,
-                    retry_wait: 1,
-                    max_retries: 10,
-                    ..Default::default()
-                },
-                ContentType::Dash => DownloadOptions {
-                    connections: 8,
-                    splits: 8,
-                    chunk_size: 1,
-                    use_quic: true,
-                    ..Default::default()
-                },
-                _ => DownloadOptions::default(),
+        let mut download_options = options.unwrap_or_else(|| DownloadOptions::for_content_type(&content_type));
+        download_options.throttle = Throttle::new(self.global_limit.clone(), task_limit);
+        let policy = RetryPolicy::from_options(&download_options);
+        
+        // メタデータを書き込む場合は、ファイル名と共通の動画情報を先に取得する
+        let info = if download_options.metadata.is_enabled() && is_page(url) {
+            match self.ytdlp.get_download_info(url, &download_options).await {
+                Ok(info) => Some(info),
+                Err(err) => {
+                    log::warn!("メタデータに使う動画情報を取得できませんでした: {}: {}", url, err);
+                    None
+                }
             }
-        });
+        } else {
+            None
+        };
         
-        // コンテンツタイプに応じたダウンロード方法を選択
-        match content_type {
-            ContentType::Mp4 => {
-                self.aria2c.download(url, output_path, filename, &download_options, progress_callback).await
-            },
-            ContentType::Hls => {
-                self.hls.download(url, output_path, filename, &download_options, progress_callback).await
-            },
-            ContentType::Dash | ContentType::YouTube | ContentType::Unknown => {
-                self.ytdlp.download(url, output_path, filename, &download_options, progress_callback).await
+        // 出力先のディレクトリがなければ作成し、既存のファイルと衝突しないファイル名を決める
+        let (output_dir, stem) = self
+            .output_name(url, output_path, filename, &download_options, playlist, info.as_ref())
+            .await?;
+        tokio::fs::create_dir_all(&output_dir).await?;
+        let extension = download_options.format.to_string().to_lowercase();
+        let final_name = resolve_collision(&output_dir, &stem, &extension, download_options.collision)?;
+        // 上書きする場合は別の名前に取得し、成功してから既存のファイルを置き換える
+        let staging = overwrite_staging(&output_dir, &final_name, &extension, download_options.collision)?;
+        let filename = staging.clone().unwrap_or_else(|| final_name.clone());
+        if let Some(output_stem) = output_stem {
+            let _ = output_stem.set(output_dir.join(&filename));
+        }
+        let (output_path, filename) = (output_dir.as_path(), filename.as_str());
+        
+        // 方法を切り替えても同じコールバックに通知する
+        let progress_callback: Option<Arc<dyn Fn(ProgressInfo) + Send + Sync>> = progress_callback.map(Arc::from);
+        let callback = || {
+            progress_callback.clone().map(|callback| {
+                Box::new(move |info: ProgressInfo| callback(info)) as ProgressCallback
+            })
+        };
+        
+        // 優先するバックエンドが指定されていれば先頭に移す
+        let mut candidates = self.backends.candidates(url, &content_type);
+        if let Some(preferred) = &download_options.backend {
+            match candidates.iter().position(|backend| backend.name() == preferred) {
+                Some(index) => {
+                    let backend = candidates.remove(index);
+                    candidates.insert(0, backend);
+                }
+                None => log::warn!("{} はこのURLを扱えないため、通常の順に試します", preferred),
+            }
+        }
+        
+        let mut last_error = None;
+        for backend in candidates {
+            let name = backend.name();
+            if !backend.is_available().await {
+                let reason = if backend.capabilities().external_tool {
+                    "必要な外部ツールが見つかりません"
+                } else {
+                    "利用できません"
+                };
+                log.record(Decision {
+                    backend: name.to_string(),
+                    attempt: 0,
+                    action: DecisionAction::Skipped,
+                    reason: reason.to_string(),
+                    error_code: None,
+                    wait_seconds: None,
+                });
+                continue;
+            }
+            
+            let mut attempt = 0;
+            loop {
+                attempt += 1;
+                let err = match backend
+                    .download(url, output_path, filename, &download_options, callback())
+                    .await
+                {
+                    Ok(output_file) => {
+                        let output_file = match &staging {
+                            Some(_) => replace_existing(&output_file, &final_name)?,
+                            None => output_file,
+                        };
+                        log.record(Decision {
+                            backend: name.to_string(),
+                            attempt,
+                            action: DecisionAction::Succeeded,
+                            reason: output_file.to_string_lossy().to_string(),
+                            error_code: None,
+                            wait_seconds: None,
+                        });
+                        if download_options.subtitles.is_enabled() {
+                            self.save_subtitles(url, &output_file, &download_options, callback()).await;
+                        }
+                        if download_options.metadata.is_enabled() {
+                            self.embed_metadata(url, &output_file, &download_options, info.as_ref(), callback()).await;
+                        }
+                        return Ok(output_file);
+                    }
+                    Err(err) => err,
+                };
+                
+                if err.is_fatal() {
+                    log.record(Decision::failed(name, attempt, DecisionAction::Abort, &err));
+                    return Err(err);
+                }
+                if err.is_retryable() && attempt <= policy.backend_retries {
+                    let wait = policy.delay(attempt);
+                    log.record(Decision {
+                        wait_seconds: Some(wait.as_secs_f64()),
+                        ..Decision::failed(name, attempt, DecisionAction::Retry, &err)
+                    });
+                    tokio::time::sleep(wait).await;
+                    continue;
+                }
+                
+                log.record(Decision::failed(name, attempt, DecisionAction::Fallback, &err));
+                last_error = Some(err);
+                break;
+            }
+        }
+        
+        Err(last_error.unwrap_or_else(|| {
+            DownloadError::ProcessFailed("利用できるダウンロード方法がありません".to_string())
+        }))
+    }
+
+    /// 字幕を取得して動画と同じ場所に保存し、指定があれば動画に埋め込む
+    ///
+    /// 字幕を取得できなくてもダウンロードは成功として扱い、警告を記録するだけにする。
+    async fn save_subtitles(
+        &self,
+        url: &str,
+        video: &Path,
+        options: &DownloadOptions,
+        progress_callback: Option<ProgressCallback>
+    ) {
+        if let Some(callback) = &progress_callback {
+            callback(ProgressInfo::new(ProgressPhase::Subtitles));
+        }
+        let tracks = match self.list_subtitles(url, options).await {
+            Ok(tracks) => select_tracks(&tracks, &options.subtitles),
+            Err(err) => {
+                log::warn!("字幕の一覧を取得できませんでした: {}: {}", url, err);
+                return;
+            }
+        };
+        if tracks.is_empty() {
+            log::warn!("指定した言語の字幕がありません: {}", url);
+            return;
+        }
+
+        let mut saved = Vec::new();
+        for track in &tracks {
+            match self.save_subtitle(track, video, options).await {
+                Ok(path) => saved.push((path, track.language.clone())),
+                Err(err) => log::warn!("字幕（{}）を取得できませんでした: {}", track.language, err),
+            }
+        }
+
+        if !options.subtitles.embed || saved.is_empty() {
+            return;
+        }
+        if !matches!(options.format, VideoFormat::Mp4 | VideoFormat::Mkv) {
+            log::warn!("{}には字幕を埋め込めないため、字幕のファイルを残します", options.format);
+            return;
+        }
+        if !self.ffmpeg.is_available().await {
+            log::warn!("ffmpegが見つからないため、字幕のファイルを残します");
+            return;
+        }
+        match self.ffmpeg.embed_subtitles(video, &saved, &options.format).await {
+            Ok(_) => {
+                for (path, _) in &saved {
+                    let _ = tokio::fs::remove_file(path).await;
+                }
+            }
+            Err(err) => log::warn!("字幕を埋め込めませんでした（字幕のファイルを残します）: {}", err),
+        }
+    }
+
+    /// 動画情報のメタデータとサムネイルを出力ファイルに書き込む
+    ///
+    /// 書き込めなくてもダウンロードは成功として扱い、警告を記録するだけにする。
+    async fn embed_metadata(
+        &self,
+        url: &str,
+        output_file: &Path,
+        options: &DownloadOptions,
+        info: Option<&VideoInfo>,
+        progress_callback: Option<ProgressCallback>
+    ) {
+        let Some(info) = info else {
+            log::warn!("動画情報がないため、メタデータを書き込みません: {}", url);
+            return;
+        };
+        if !self.ffmpeg.is_available().await {
+            log::warn!("ffmpegが見つからないため、メタデータを書き込みません");
+            return;
+        }
+        if let Some(callback) = &progress_callback {
+            callback(ProgressInfo::new(ProgressPhase::PostProcessing));
+        }
+
+        let metadata = if options.metadata.embed {
+            MediaMetadata::from_video_info(info, url)
+        } else {
+            MediaMetadata::default()
+        };
+        let cover = match (&info.thumbnail, options.metadata.thumbnail) {
+            (Some(thumbnail), true) => match self.save_thumbnail(thumbnail, output_file, options).await {
+                Ok(path) => Some(path),
+                Err(err) => {
+                    log::warn!("サムネイルを取得できませんでした: {}: {}", thumbnail, err);
+                    None
+                }
             },
+            (None, true) => {
+                log::warn!("サムネイルがありません: {}", url);
+                None
+            }
+            (_, false) => None,
+        };
+        if metadata.is_empty() && cover.is_none() {
+            return;
+        }
+
+        if let Err(err) = self
+            .ffmpeg
+            .embed_metadata(output_file, &metadata, cover.as_deref(), &options.format)
+            .await
+        {
+            log::warn!("メタデータを書き込めませんでした: {}", err);
+        }
+        if let Some(cover) = cover {
+            let _ = tokio::fs::remove_file(cover).await;
         }
     }
+
+    /// サムネイルを「出力ファイル名.cover.拡張子」に一時的に保存する
+    async fn save_thumbnail(
+        &self,
+        thumbnail: &str,
+        output_file: &Path,
+        options: &DownloadOptions
+    ) -> Result<PathBuf, DownloadError> {
+        let url = Url::parse(thumbnail)
+            .map_err(|err| DownloadError::InvalidManifest(format!("不正なURL {}: {}", thumbnail, err)))?;
+        let client = fetcher::request_client(&fetcher::http_client(), &options.request, &url)?;
+        let stem = output_file.file_stem().unwrap_or_default().to_string_lossy();
+        let path = output_file.with_file_name(format!("{}.cover", stem));
+        fetch_thumbnail(client, RetryPolicy::from_options(options), thumbnail, &path).await
+    }
+
+    /// 字幕のトラックを取得し、「動画のファイル名.言語.拡張子」に保存する
+    async fn save_subtitle(
+        &self,
+        track: &SubtitleSource,
+        video: &Path,
+        options: &DownloadOptions
+    ) -> Result<PathBuf, DownloadError> {
+        let url = Url::parse(&track.url)
+            .map_err(|err| DownloadError::InvalidManifest(format!("不正なURL {}: {}", track.url, err)))?;
+        let client = fetcher::request_client(&fetcher::http_client(), &options.request, &url)?;
+        let subtitle = track.fetch(client, RetryPolicy::from_options(options)).await?;
+
+        let format = options.subtitles.format.unwrap_or(track.format);
+        let stem = video.file_stem().unwrap_or_default().to_string_lossy();
+        let path = video.with_file_name(format!(
+            "{}.{}.{}",
+            stem,
+            sanitize_filename(&track.language),
+            format.extension()
+        ));
+        tokio::fs::write(&path, subtitle.render(format)).await?;
+        Ok(path)
+    }
+}
+
+#[async_trait]
+impl Downloader for DownloadManager {
+    async fn detect_content_type(&self, url: &str) -> Result<ContentType, DownloadError> {
+        self.detect_content_type_with(url, &RequestContext::default()).await
+    }
+    
+    async fn download(
+        &self, 
+        url: &str, 
+        output_path: &Path, 
+        filename: &str,
+        options: Option<DownloadOptions>,
+        progress_callback: Option<ProgressCallback>
+    ) -> Result<PathBuf, DownloadError> {
+        self.download_with_log(url, output_path, filename, options, progress_callback, &DecisionLog::new()).await
+    }
     
     async fn cancel_download(&self, task_id: &str) -> Result<(), DownloadError> {
-        let mut tasks = self.active_tasks.lock().await;
-        if let Some(handle) = tasks.remove(task_id) {
-            handle.abort();
-            Ok(())
-        } else {
-            Err(DownloadError::Internal("タスクが見つかりません".to_string()))
+        let mut queue = self.queue.lock().await;
+        let active = self.active_tasks.lock().await;
+        let _state = queue.lock_state().await?;
+        queue.reload(|id| active.contains_key(id))?;
+        
+        // 実行中のタスクは停止を通知し、状態の更新は実行側に任せる
+        if let Some(stop) = active.get(task_id) {
+            let _ = stop.send(Some(TaskStatus::Cancelled));
+            return Ok(());
+        }
+        
+        let task = queue
+            .get_mut(task_id)
+            .ok_or_else(|| DownloadError::Internal("タスクが見つかりません".to_string()))?;
+        if task.status.is_finished() {
+            return Err(DownloadError::Internal("タスクは既に終了しています".to_string()));
+        }
+        task.status = TaskStatus::Cancelled;
+        let task = task.clone();
+        queue.save()?;
+        self.callbacks.lock().await.remove(task_id);
+        
+        let _ = self.events.send(task);
+        Ok(())
+    }
+    
+    async fn pause(&self, task_id: &str) -> Result<(), DownloadError> {
+        let mut queue = self.queue.lock().await;
+        let active = self.active_tasks.lock().await;
+        let _state = queue.lock_state().await?;
+        queue.reload(|id| active.contains_key(id))?;
+        
+        if let Some(stop) = active.get(task_id) {
+            let _ = stop.send(Some(TaskStatus::Paused));
+            return Ok(());
+        }
+        
+        let task = queue
+            .get_mut(task_id)
+            .ok_or_else(|| DownloadError::Internal("タスクが見つかりません".to_string()))?;
+        match task.status {
+            // 他のプロセスで実行中のタスクは、そのプロセスが状態ファイルを読み直した際に停止する
+            TaskStatus::Pending | TaskStatus::Running => task.status = TaskStatus::Paused,
+            TaskStatus::Paused => return Ok(()),
+            _ => return Err(DownloadError::Internal("タスクは既に終了しています".to_string())),
+        }
+        let task = task.clone();
+        queue.save()?;
+        
+        let _ = self.events.send(task);
+        Ok(())
+    }
+    
+    async fn resume(&self, task_id: &str) -> Result<(), DownloadError> {
+        let mut queue = self.queue.lock().await;
+        let active = self.active_tasks.lock().await;
+        let _state = queue.lock_state().await?;
+        queue.reload(|id| active.contains_key(id))?;
+        
+        let task = queue
+            .get_mut(task_id)
+            .ok_or_else(|| DownloadError::Internal("タスクが見つかりません".to_string()))?;
+        match task.status {
+            TaskStatus::Paused | TaskStatus::Failed => {
+                task.status = TaskStatus::Pending;
+                task.error = None;
+                task.error_code = None;
+            }
+            TaskStatus::Pending | TaskStatus::Running => return Ok(()),
+            _ => return Err(DownloadError::Internal("このタスクは再開できません".to_string())),
         }
+        let task = task.clone();
+        queue.save()?;
+        
+        let _ = self.events.send(task);
+        Ok(())
+    }
+}
+
+/// 現在の時刻に当てはまる制限を全体の速度制限に反映する
+fn apply_schedule(config: &std::sync::Mutex<BandwidthConfig>, global_limit: &RateLimiter) {
+    let limit = config.lock().unwrap().current_limit();
+    if global_limit.set_limit(limit) {
+        log::info!("全体の速度制限を {} に変更しました", bandwidth::describe_limit(limit));
+    }
+}
+
+/// 動画ページ（動画ファイル・ストリーミングのURLでないもの）か
+fn is_page(url: &str) -> bool {
+    !matches!(
+        content_type_from_url(url),
+        Some(ContentType::Mp4 | ContentType::Hls | ContentType::Dash)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bandwidth::{BandwidthRule, Days, SpeedLimit, TimeOfDay};
+    use crate::filename::CollisionPolicy;
+    use crate::test_util::{Response, TempDir, TestServer};
+
+    fn manager(dir: &TempDir) -> DownloadManager {
+        DownloadManager::with_queue_config(QueueConfig {
+            max_concurrent: 1,
+            state_path: Some(dir.path().join("queue.json")),
+        })
+        .unwrap()
+    }
+
+    async fn add(downloader: &DownloadManager, output_path: &Path, filename: &str) -> String {
+        downloader
+            .add_task("https://example.com/video.mp4", output_path, filename, None, 0, None)
+            .await
+            .unwrap()
+    }
+
+    async fn set_status(downloader: &DownloadManager, task_id: &str, status: TaskStatus) {
+        let mut queue = downloader.queue.lock().await;
+        queue.get_mut(task_id).unwrap().status = status;
+        queue.save().unwrap();
+    }
+
+    async fn status(downloader: &DownloadManager, task_id: &str) -> TaskStatus {
+        downloader.task(task_id).await.unwrap().status
+    }
+
+    #[tokio::test]
+    async fn pause_and_resume_queued_tasks() {
+        let dir = TempDir::new();
+        let downloader = manager(&dir);
+        let task_id = add(&downloader, dir.path(), "video").await;
+
+        downloader.pause(&task_id).await.unwrap();
+        assert_eq!(status(&downloader, &task_id).await, TaskStatus::Paused);
+        // 一時停止中のタスクは実行対象にならない
+        assert!(!downloader.queue.lock().await.has_pending());
+        downloader.pause(&task_id).await.unwrap();
+
+        downloader.resume(&task_id).await.unwrap();
+        assert_eq!(status(&downloader, &task_id).await, TaskStatus::Pending);
+        downloader.resume(&task_id).await.unwrap();
+        assert_eq!(status(&downloader, &task_id).await, TaskStatus::Pending);
+
+        // 状態は他のプロセスからも見える
+        let other = manager(&dir);
+        other.pause(&task_id).await.unwrap();
+        assert_eq!(other.list_tasks().await.unwrap()[0].status, TaskStatus::Paused);
+        assert_eq!(downloader.list_tasks().await.unwrap()[0].status, TaskStatus::Paused);
+    }
+
+    #[tokio::test]
+    async fn finished_tasks_cannot_be_paused_and_only_failed_ones_resumed() {
+        let dir = TempDir::new();
+        let downloader = manager(&dir);
+        let failed = add(&downloader, dir.path(), "failed").await;
+        let completed = add(&downloader, dir.path(), "completed").await;
+        set_status(&downloader, &failed, TaskStatus::Failed).await;
+        set_status(&downloader, &completed, TaskStatus::Completed).await;
+
+        assert!(downloader.pause(&failed).await.is_err());
+        assert!(downloader.pause(&completed).await.is_err());
+        assert!(downloader.resume(&completed).await.is_err());
+        assert!(downloader.pause("missing").await.is_err());
+
+        downloader.resume(&failed).await.unwrap();
+        assert_eq!(status(&downloader, &failed).await, TaskStatus::Pending);
+    }
+
+    #[tokio::test]
+    async fn resume_interrupted_picks_paused_and_partially_downloaded_tasks() {
+        let dir = TempDir::new();
+        let output = dir.path().join("output");
+        let elsewhere = dir.path().join("elsewhere");
+        std::fs::create_dir_all(&output).unwrap();
+        std::fs::create_dir_all(&elsewhere).unwrap();
+        std::fs::write(output.join("partial.mp4.aria2"), b"").unwrap();
+
+        let downloader = manager(&dir);
+        let paused = add(&downloader, &output, "paused").await;
+        let partial = add(&downloader, &output, "partial").await;
+        let failed = add(&downloader, &output, "failed").await;
+        let other_dir = add(&downloader, &elsewhere, "paused").await;
+        set_status(&downloader, &paused, TaskStatus::Paused).await;
+        set_status(&downloader, &partial, TaskStatus::Failed).await;
+        set_status(&downloader, &failed, TaskStatus::Failed).await;
+        set_status(&downloader, &other_dir, TaskStatus::Paused).await;
+
+        let mut resumed = downloader.resume_interrupted(&output).await.unwrap();
+        resumed.sort();
+        let mut expected = vec![paused.clone(), partial.clone()];
+        expected.sort();
+
+        assert_eq!(resumed, expected);
+        assert_eq!(status(&downloader, &paused).await, TaskStatus::Pending);
+        assert_eq!(status(&downloader, &partial).await, TaskStatus::Pending);
+        // 途中データのない失敗と、別のディレクトリのタスクはそのまま
+        assert_eq!(status(&downloader, &failed).await, TaskStatus::Failed);
+        assert_eq!(status(&downloader, &other_dir).await, TaskStatus::Paused);
+    }
+
+    #[tokio::test]
+    async fn pending_tasks_are_left_to_the_process_holding_the_runner_lock() {
+        let dir = TempDir::new();
+        let downloader = Arc::new(manager(&dir));
+        let task_id = add(&downloader, dir.path(), "video").await;
+
+        // 別のプロセスがタスクを実行中
+        let runner = DownloadQueue::load(Some(dir.path().join("queue.json"))).unwrap();
+        let lock = runner.try_lock_runner().unwrap();
+        assert!(lock.is_some());
+        downloader.schedule().await.unwrap();
+        assert_eq!(status(&downloader, &task_id).await, TaskStatus::Pending);
+        assert!(downloader.active_tasks.lock().await.is_empty());
+
+        // 開始をやめた後はロックが空いても実行しない
+        drop(lock);
+        downloader.stop_scheduling().await;
+        downloader.schedule().await.unwrap();
+        assert_eq!(status(&downloader, &task_id).await, TaskStatus::Pending);
+        assert!(downloader.runner_lock.lock().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn list_formats_sends_the_request_headers_for_manifests() {
+        let server = TestServer::start(|request| match request.header("X-Token") {
+            Some("secret") => Response::ok("#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXTINF:4,\nsegment.ts\n#EXT-X-ENDLIST\n"),
+            _ => Response::status(403),
+        })
+        .await;
+        let url = server.url("/media.m3u8");
+        let downloader = DownloadManager::new();
+
+        assert!(downloader.list_formats(&url, &RequestContext::default()).await.is_err());
+
+        let request = RequestContext {
+            headers: vec![("X-Token".to_string(), "secret".to_string())],
+            ..RequestContext::default()
+        };
+        let formats = downloader.list_formats(&url, &request).await.unwrap();
+        assert_eq!(formats.len(), 1);
+        assert_eq!(formats[0].format_id.as_deref(), Some("hls"));
+    }
+
+    #[tokio::test]
+    async fn overwrite_replaces_the_existing_file_only_after_a_successful_download() {
+        let dir = TempDir::new();
+        let existing = dir.path().join("video.mp4");
+        std::fs::write(&existing, b"old").unwrap();
+        let options = DownloadOptions {
+            collision: CollisionPolicy::Overwrite,
+            max_retries: 0,
+            ..DownloadOptions::default()
+        };
+        let downloader = DownloadManager::new();
+
+        // 失敗した場合は既存のファイルが残る
+        let server = TestServer::start(|_| Response::status(404)).await;
+        let result = downloader
+            .download_with_log(&server.url("/video.mp4"), dir.path(), "video", Some(options.clone()), None, &DecisionLog::new())
+            .await;
+        assert!(result.is_err());
+        assert_eq!(std::fs::read(&existing).unwrap(), b"old");
+
+        let server = TestServer::files(vec![("/video.mp4", b"new".to_vec())]).await;
+        let output_file = downloader
+            .download_with_log(&server.url("/video.mp4"), dir.path(), "video", Some(options), None, &DecisionLog::new())
+            .await
+            .unwrap();
+        assert_eq!(output_file, existing);
+        assert_eq!(std::fs::read(&existing).unwrap(), b"new");
+        assert!(!dir.path().join("video.overwrite.mp4").exists());
+    }
+
+    #[test]
+    fn tool_paths_keep_the_rpc_mode_of_aria2c() {
+        let tool_paths = ToolPaths {
+            aria2c: Some(PathBuf::from("/opt/aria2c")),
+            ..ToolPaths::default()
+        };
+
+        let downloader = DownloadManager::new()
+            .with_aria2c(crate::tools::aria2c::Aria2cTool::new().with_rpc())
+            .with_tool_paths(tool_paths.clone());
+        assert!(downloader.aria2c.is_rpc());
+
+        let downloader = DownloadManager::new().with_tool_paths(tool_paths);
+        assert!(!downloader.aria2c.is_rpc());
+    }
+
+    fn ticker_id(manager: &DownloadManager) -> Option<tokio::task::Id> {
+        manager.schedule_ticker.lock().unwrap().as_ref().map(|ticker| ticker.id())
+    }
+
+    fn all_day(limit: u64) -> BandwidthConfig {
+        BandwidthConfig {
+            limit: None,
+            schedule: vec![BandwidthRule {
+                days: Days::default(),
+                start: TimeOfDay::default(),
+                end: TimeOfDay::end_of_day(),
+                limit: Some(SpeedLimit(limit)),
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn schedule_ticker_starts_once_per_manager() {
+        let manager = DownloadManager::new().with_bandwidth(BandwidthConfig::fixed(Some(4096)));
+        assert_eq!(manager.speed_limit(), Some(4096));
+        // 時間帯の指定がなければタイマーは不要
+        assert!(ticker_id(&manager).is_none());
+
+        manager.set_bandwidth(all_day(1024));
+        assert_eq!(manager.speed_limit(), Some(1024));
+        let first = ticker_id(&manager).expect("タイマーが起動するはず");
+
+        manager.set_bandwidth(all_day(2048));
+        manager.apply_bandwidth_schedule();
+        assert_eq!(manager.speed_limit(), Some(2048));
+        assert_eq!(ticker_id(&manager), Some(first));
+    }
+
+    #[test]
+    fn schedule_ticker_waits_for_a_runtime() {
+        let manager = DownloadManager::new().with_bandwidth(all_day(1024));
+        assert_eq!(manager.speed_limit(), Some(1024));
+        assert!(ticker_id(&manager).is_none());
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async { manager.start_schedule_ticker() });
+        assert!(ticker_id(&manager).is_some());
     }
 }
@@ -1,66 +1,298 @@
+use std::any::Any;
+use std::cell::RefCell;
 use std::ffi::{CStr, CString};
-use std::os::raw::c_char;
+use std::future::Future;
+use std::os::raw::{c_char, c_void};
+use std::panic::AssertUnwindSafe;
 use std::path::PathBuf;
-use crate::{DownloadManager, Downloader, DownloadOptions, ContentType, VideoFormat, ErrorCode};
+use std::sync::OnceLock;
+use tokio::runtime::Runtime;
+use tokio::sync::watch;
+use crate::{
+    ContentType,
+    DownloadError,
+    DownloadManager,
+    Downloader,
+    DownloadOptions,
+    ErrorCode,
+    ProgressCallback,
+    ProgressInfo,
+    ProgressPhase
+};
 
 // FFI用のエクスポート関数
+//
+// すべての関数は共有のランタイムとダウンロードマネージャーを使う。
+// 失敗した場合はエラーコードを返し、呼び出したスレッドの最後のエラーとして
+// メッセージを保存する（`nd_last_error_message` で取得できる）。
+// 内部でパニックした場合も呼び出し側には伝えず、`UnknownError` として返す。
+
+/// 全呼び出しで共有する非同期ランタイム
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+/// 全呼び出しで共有するダウンロードマネージャー
+static MANAGER: OnceLock<DownloadManager> = OnceLock::new();
+
+/// このライブラリのバージョン（NUL終端）
+static VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "\0");
+
+thread_local! {
+    /// 呼び出したスレッドで最後に発生したエラーのメッセージ
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn runtime() -> &'static Runtime {
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("nextdownloader-ffi")
+            .build()
+            .expect("非同期ランタイムの作成に失敗しました")
+    })
+}
+
+fn manager() -> &'static DownloadManager {
+    MANAGER.get_or_init(|| {
+        let _guard = runtime().enter();
+        DownloadManager::new()
+    })
+}
+
+/// 文字列をC文字列に変換（途中のNUL文字は取り除く）
+fn c_string(value: impl Into<String>) -> CString {
+    let mut value = value.into();
+    value.retain(|c| c != '\0');
+    CString::new(value).unwrap_or_default()
+}
+
+/// 最後のエラーを保存し、エラーコードを返す
+fn fail(code: ErrorCode, message: impl Into<String>) -> libc::c_int {
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(c_string(message)));
+    code as libc::c_int
+}
+
+/// パニックを `extern "C"` の境界の外に出さず、最後のエラーとして保存する
+///
+/// パニックした場合は `on_panic` を返す。
+fn catch_panic<T>(on_panic: T, body: impl FnOnce() -> T) -> T {
+    std::panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+        fail(ErrorCode::UnknownError, format!("内部エラー: {}", panic_message(payload.as_ref())));
+        on_panic
+    })
+}
+
+/// パニックのメッセージ
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("不明なエラー")
+}
+
+/// 共有のランタイムで実行し、終わるまで待つ
+///
+/// 呼び出したスレッドが別の非同期ランタイムの中にあっても使えるよう、`block_on` は使わず
+/// ランタイムに実行させてチャネルで結果を受け取る。実行中にパニックした場合はエラーコードを返す。
+fn run<F>(future: F) -> Result<F::Output, libc::c_int>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (sender, receiver) = std::sync::mpsc::channel();
+    runtime().spawn(async move {
+        let _ = sender.send(future.await);
+    });
+    receiver
+        .recv()
+        .map_err(|_| fail(ErrorCode::UnknownError, "内部エラー: 非同期処理が中断されました"))
+}
+
+/// 引数のC文字列をUTF-8として読み取る
+///
+/// # Safety
+///
+/// `ptr` はNULLまたは有効なNUL終端文字列を指していること。
+unsafe fn str_arg<'a>(ptr: *const c_char, name: &str) -> Result<&'a str, libc::c_int> {
+    if ptr.is_null() {
+        return Err(fail(ErrorCode::InvalidArgument, format!("{} がNULLです", name)));
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|_| fail(ErrorCode::InvalidArgument, format!("{} が不正なUTF-8です", name)))
+}
+
+/// オプションのJSON文字列を解析（NULLの場合はデフォルト値）
+///
+/// # Safety
+///
+/// `options_json` はNULLまたは有効なNUL終端文字列を指していること。
+unsafe fn options_arg(options_json: *const c_char) -> Result<Option<DownloadOptions>, libc::c_int> {
+    if options_json.is_null() {
+        return Ok(None);
+    }
+    let options_json = str_arg(options_json, "options_json")?;
+    serde_json::from_str(options_json)
+        .map(Some)
+        .map_err(|err| fail(ErrorCode::JsonError, format!("オプションの解析に失敗しました: {}", err)))
+}
+
+/// 進捗情報
+#[repr(C)]
+pub struct NdProgress {
+    /// 処理の段階 (0: 解析中, 1: 映像, 2: 音声, 3: 結合, 4: 後処理, 5: 字幕)
+    pub phase: libc::c_int,
+    /// 現在の段階の進捗（0.0〜1.0）
+    pub progress: f64,
+    /// ダウンロード済みのバイト数
+    pub downloaded_bytes: u64,
+    /// 合計バイト数（不明な場合は -1）
+    pub total_bytes: i64,
+    /// ダウンロード速度（バイト/秒、不明な場合は -1）
+    pub speed: f64,
+    /// 残り時間（秒、不明な場合は -1）
+    pub eta_seconds: f64,
+}
+
+impl From<&ProgressInfo> for NdProgress {
+    fn from(info: &ProgressInfo) -> Self {
+        Self {
+            phase: match info.phase {
+                ProgressPhase::Probing => 0,
+                ProgressPhase::DownloadingVideo => 1,
+                ProgressPhase::DownloadingAudio => 2,
+                ProgressPhase::Merging => 3,
+                ProgressPhase::PostProcessing => 4,
+                ProgressPhase::Subtitles => 5,
+            },
+            progress: info.progress,
+            downloaded_bytes: info.downloaded_bytes,
+            total_bytes: info.total_bytes.and_then(|total| i64::try_from(total).ok()).unwrap_or(-1),
+            speed: info.speed.unwrap_or(-1.0),
+            eta_seconds: info.eta.map(|eta| eta.as_secs_f64()).unwrap_or(-1.0),
+        }
+    }
+}
+
+/// 進捗コールバック
+///
+/// `progress` はコールバックの呼び出し中のみ有効。
+pub type NdProgressCallback = Option<extern "C" fn(user_data: *mut c_void, progress: *const NdProgress)>;
+
+/// 完了コールバック
+///
+/// `error_code` が成功の場合 `result` は保存したファイルのパス、それ以外は
+/// エラーメッセージ。`result` はコールバックの呼び出し中のみ有効。
+pub type NdCompletionCallback =
+    Option<extern "C" fn(user_data: *mut c_void, error_code: libc::c_int, result: *const c_char)>;
+
+/// ダウンロードタスクのハンドル（内部構造は公開しない）
+pub struct NdTask {
+    cancel: watch::Sender<bool>,
+}
+
+/// コールバックに渡す呼び出し側のポインタ
+///
+/// ポインタの扱いは呼び出し側の責任とし、ランタイムのスレッドに持ち出せるようにする。
+#[derive(Clone, Copy)]
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
+impl UserData {
+    fn get(self) -> *mut c_void {
+        self.0
+    }
+}
+
+/// ライブラリのバージョンを取得する
+///
+/// # 戻り値
+///
+/// NUL終端のバージョン文字列（静的領域のため解放不要）
+#[no_mangle]
+pub extern "C" fn nd_version() -> *const c_char {
+    VERSION.as_ptr() as *const c_char
+}
+
+/// 呼び出したスレッドで最後に発生したエラーのメッセージを取得する
+///
+/// # 戻り値
+///
+/// メッセージのコピー（`nd_string_free` で解放する必要あり）。エラーがない場合はNULL
+#[no_mangle]
+pub extern "C" fn nd_last_error_message() -> *mut c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map(|message| message.clone().into_raw())
+            .unwrap_or(std::ptr::null_mut())
+    })
+}
 
 /// システムの依存関係をチェックする
-/// 
+///
+/// # 引数
+///
+/// * `result` - 3バイトの配列。順に yt-dlp, aria2c, ffmpegが利用可能かどうかを示す。
+///   1なら利用可能、0なら利用不可。
+///
 /// # 戻り値
-/// 
-/// 3バイトの配列。順に yt-dlp, aria2c, ffmpegが利用可能かどうかを示す。
-/// 1なら利用可能、0なら利用不可。
+///
+/// エラーコード
+///
+/// # Safety
+///
+/// `result` はNULLまたは書き込み可能な3バイトの配列を指していること。
 #[no_mangle]
-pub extern "C" fn check_dependencies(result: *mut [u8; 3]) -> libc::c_int {
-    let download_manager = DownloadManager::new();
-    
-    // 非同期関数を同期的に実行
-    let rt = tokio::runtime::Runtime::new().unwrap();
-    let (ytdlp, aria2c, ffmpeg) = rt.block_on(download_manager.check_dependencies());
-    
-    if !result.is_null() {
-        unsafe {
-            (*result)[0] = if ytdlp { 1 } else { 0 };
-            (*result)[1] = if aria2c { 1 } else { 0 };
-            (*result)[2] = if ffmpeg { 1 } else { 0 };
+pub unsafe extern "C" fn nd_check_dependencies(result: *mut [u8; 3]) -> libc::c_int {
+    catch_panic(ErrorCode::UnknownError as libc::c_int, || {
+        if result.is_null() {
+            return fail(ErrorCode::InvalidArgument, "result がNULLです");
         }
-    }
-    
-    ErrorCode::Success as libc::c_int
+
+        let (ytdlp, aria2c, ffmpeg) = match run(manager().check_dependencies()) {
+            Ok(available) => available,
+            Err(code) => return code,
+        };
+        *result = [ytdlp as u8, aria2c as u8, ffmpeg as u8];
+
+        ErrorCode::Success as libc::c_int
+    })
 }
 
 /// URLからコンテンツタイプを検出する
-/// 
+///
 /// # 引数
-/// 
+///
 /// * `url` - 検出するURL
 /// * `result` - 結果を格納するポインタ (0: MP4, 1: HLS, 2: DASH, 3: YouTube, 4: Unknown)
-/// 
+///
 /// # 戻り値
-/// 
+///
 /// エラーコード
+///
+/// # Safety
+///
+/// `url` は有効なNUL終端文字列、`result` は書き込み可能な領域を指していること。
 #[no_mangle]
-pub extern "C" fn detect_content_type(
+pub unsafe extern "C" fn nd_detect_content_type(
     url: *const c_char,
     result: *mut libc::c_int
 ) -> libc::c_int {
-    if url.is_null() || result.is_null() {
-        return ErrorCode::UnknownError as libc::c_int;
-    }
-    
-    let c_url = unsafe { CStr::from_ptr(url) };
-    let url_str = match c_url.to_str() {
-        Ok(s) => s,
-        Err(_) => return ErrorCode::UnknownError as libc::c_int,
-    };
-    
-    let download_manager = DownloadManager::new();
-    let rt = tokio::runtime::Runtime::new().unwrap();
-    
-    match rt.block_on(download_manager.detect_content_type(url_str)) {
-        Ok(content_type) => {
-            unsafe {
+    catch_panic(ErrorCode::UnknownError as libc::c_int, || {
+        if result.is_null() {
+            return fail(ErrorCode::InvalidArgument, "result がNULLです");
+        }
+        let url = match str_arg(url, "url") {
+            Ok(url) => url.to_string(),
+            Err(code) => return code,
+        };
+
+        match run(async move { manager().detect_content_type(&url).await }) {
+            Ok(Ok(content_type)) => {
                 *result = match content_type {
                     ContentType::Mp4 => 0,
                     ContentType::Hls => 1,
@@ -68,171 +300,345 @@ pub extern "C" fn detect_content_type(
                     ContentType::YouTube => 3,
                     ContentType::Unknown => 4,
                 };
+                ErrorCode::Success as libc::c_int
+            }
+            Ok(Err(err)) => {
+                *result = 4;
+                fail(ErrorCode::from(&err), err.to_string())
+            }
+            Err(code) => {
+                *result = 4;
+                code
             }
-            ErrorCode::Success as libc::c_int
-        },
-        Err(_) => {
-            unsafe { *result = 4 }; // Unknown
-            ErrorCode::UnknownError as libc::c_int
         }
-    }
+    })
 }
 
 /// URLからビデオ情報を取得する
-/// 
+///
 /// # 引数
-/// 
+///
 /// * `url` - 取得するURL
-/// * `json_result` - JSON形式の結果を格納するポインタ (呼び出し側で解放する必要あり)
-/// 
+/// * `json_result` - JSON形式の結果を格納するポインタ (`nd_string_free` で解放する必要あり)
+///
 /// # 戻り値
-/// 
+///
 /// エラーコード
+///
+/// # Safety
+///
+/// `url` は有効なNUL終端文字列、`json_result` は書き込み可能な領域を指していること。
 #[no_mangle]
-pub extern "C" fn get_video_info(
+pub unsafe extern "C" fn nd_get_video_info(
     url: *const c_char,
     json_result: *mut *mut c_char
 ) -> libc::c_int {
-    if url.is_null() || json_result.is_null() {
-        return ErrorCode::UnknownError as libc::c_int;
-    }
-    
-    let c_url = unsafe { CStr::from_ptr(url) };
-    let url_str = match c_url.to_str() {
-        Ok(s) => s,
-        Err(_) => return ErrorCode::UnknownError as libc::c_int,
-    };
-    
-    let download_manager = DownloadManager::new();
-    let rt = tokio::runtime::Runtime::new().unwrap();
-    
-    match rt.block_on(download_manager.ytdlp.get_video_info(url_str)) {
-        Ok(video_info) => {
-            match serde_json::to_string(&video_info) {
-                Ok(json) => {
-                    let c_json = match CString::new(json) {
-                        Ok(s) => s,
-                        Err(_) => return ErrorCode::UnknownError as libc::c_int,
-                    };
-                    
-                    unsafe {
-                        *json_result = c_json.into_raw();
-                    }
-                    
-                    ErrorCode::Success as libc::c_int
-                },
-                Err(_) => ErrorCode::JsonError as libc::c_int,
+    catch_panic(ErrorCode::UnknownError as libc::c_int, || {
+        if json_result.is_null() {
+            return fail(ErrorCode::InvalidArgument, "json_result がNULLです");
+        }
+        let url = match str_arg(url, "url") {
+            Ok(url) => url.to_string(),
+            Err(code) => return code,
+        };
+
+        let video_info = match run(async move { manager().get_video_info(&url).await }) {
+            Ok(Ok(video_info)) => video_info,
+            Ok(Err(err)) => return fail(ErrorCode::from(&err), err.to_string()),
+            Err(code) => return code,
+        };
+        match serde_json::to_string(&video_info) {
+            Ok(json) => {
+                *json_result = c_string(json).into_raw();
+                ErrorCode::Success as libc::c_int
             }
-        },
-        Err(_) => ErrorCode::ProcessFailed as libc::c_int,
-    }
+            Err(err) => fail(ErrorCode::JsonError, err.to_string()),
+        }
+    })
 }
 
-/// URLからビデオをダウンロードする
-/// 
+/// ダウンロードを開始する
+///
+/// ダウンロードはバックグラウンドで実行され、この関数はすぐに戻る。
+/// コールバックはライブラリ内部のスレッドから呼ばれる。
+///
 /// # 引数
-/// 
+///
 /// * `url` - ダウンロードするURL
 /// * `output_path` - 出力先ディレクトリ
 /// * `filename` - ファイル名
 /// * `options_json` - オプションのJSON文字列 (NULLの場合はデフォルト値を使用)
-/// * `result_path` - 結果のファイルパスを格納するポインタ (呼び出し側で解放する必要あり)
-/// 
+/// * `progress_callback` - 進捗コールバック (NULL可)
+/// * `completion_callback` - 完了コールバック (NULL可)。成功・失敗・キャンセルのいずれでも1回だけ呼ばれる
+/// * `user_data` - コールバックにそのまま渡すポインタ
+///
 /// # 戻り値
-/// 
-/// エラーコード
+///
+/// タスクのハンドル（`nd_task_free` で解放する必要あり）。開始できなかった場合はNULL
+///
+/// # Safety
+///
+/// 文字列の引数は有効なNUL終端文字列（`options_json` はNULL可）を指していること。
+/// `user_data` は完了コールバックが呼ばれるまで有効であること。
 #[no_mangle]
-pub extern "C" fn download_video(
+pub unsafe extern "C" fn nd_download_start(
     url: *const c_char,
     output_path: *const c_char,
     filename: *const c_char,
     options_json: *const c_char,
-    result_path: *mut *mut c_char
-) -> libc::c_int {
-    if url.is_null() || output_path.is_null() || filename.is_null() || result_path.is_null() {
-        return ErrorCode::UnknownError as libc::c_int;
-    }
-    
-    let c_url = unsafe { CStr::from_ptr(url) };
-    let c_output_path = unsafe { CStr::from_ptr(output_path) };
-    let c_filename = unsafe { CStr::from_ptr(filename) };
-    
-    let url_str = match c_url.to_str() {
-        Ok(s) => s,
-        Err(_) => return ErrorCode::UnknownError as libc::c_int,
-    };
-    
-    let output_path_str = match c_output_path.to_str() {
-        Ok(s) => s,
-        Err(_) => return ErrorCode::UnknownError as libc::c_int,
-    };
-    
-    let filename_str = match c_filename.to_str() {
-        Ok(s) => s,
-        Err(_) => return ErrorCode::UnknownError as libc::c_int,
-    };
-    
-    // オプションのJSONがあれば解析
-    let options = if !options_json.is_null() {
-        let c_options_json = unsafe { CStr::from_ptr(options_json) };
-        let options_json_str = match c_options_json.to_str() {
-            Ok(s) => s,
-            Err(_) => return ErrorCode::UnknownError as libc::c_int,
+    progress_callback: NdProgressCallback,
+    completion_callback: NdCompletionCallback,
+    user_data: *mut c_void
+) -> *mut NdTask {
+    catch_panic(std::ptr::null_mut(), || {
+        let args = (|| {
+            Ok::<_, libc::c_int>((
+                str_arg(url, "url")?.to_string(),
+                PathBuf::from(str_arg(output_path, "output_path")?),
+                str_arg(filename, "filename")?.to_string(),
+                options_arg(options_json)?,
+            ))
+        })();
+        let Ok((url, output_path, filename, options)) = args else {
+            return std::ptr::null_mut();
         };
-        
-        match serde_json::from_str::<DownloadOptions>(options_json_str) {
-            Ok(opts) => Some(opts),
-            Err(_) => None,
-        }
-    } else {
-        None
-    };
-    
-    let download_manager = DownloadManager::new();
-    let rt = tokio::runtime::Runtime::new().unwrap();
-    
-    match rt.block_on(download_manager.download(
-        url_str,
-        &PathBuf::from(output_path_str),
-        filename_str,
-        options,
-        None
-    )) {
-        Ok(path) => {
-            let path_str = path.to_string_lossy().to_string();
-            let c_path = match CString::new(path_str) {
-                Ok(s) => s,
-                Err(_) => return ErrorCode::UnknownError as libc::c_int,
+
+        let (cancel, mut cancelled) = watch::channel(false);
+        let user_data = UserData(user_data);
+
+        runtime().spawn(async move {
+            let progress_callback = progress_callback.map(|callback| -> ProgressCallback {
+                Box::new(move |info: ProgressInfo| {
+                    let progress = NdProgress::from(&info);
+                    callback(user_data.get(), &progress);
+                })
+            });
+
+            // ハンドルが解放されても（送信側が閉じても）キャンセル扱いにはしない
+            let result = tokio::select! {
+                result = manager().download(&url, &output_path, &filename, options, progress_callback) => result,
+                Ok(_) = cancelled.wait_for(|cancelled| *cancelled) => Err(DownloadError::Cancelled),
             };
-            
-            unsafe {
-                *result_path = c_path.into_raw();
-            }
-            
-            ErrorCode::Success as libc::c_int
-        },
-        Err(err) => {
-            match err {
-                crate::DownloadError::FileNotFound => ErrorCode::FileNotFound as libc::c_int,
-                crate::DownloadError::ProcessFailed(_) => ErrorCode::ProcessFailed as libc::c_int,
-                crate::DownloadError::Io(_) => ErrorCode::IoError as libc::c_int,
-                crate::DownloadError::Json(_) => ErrorCode::JsonError as libc::c_int,
-                _ => ErrorCode::UnknownError as libc::c_int,
+
+            let (code, result) = match result {
+                Ok(path) => (ErrorCode::Success, path.to_string_lossy().to_string()),
+                Err(err) => (ErrorCode::from(&err), err.to_string()),
+            };
+            if let Some(callback) = completion_callback {
+                let result = c_string(result);
+                callback(user_data.get(), code as libc::c_int, result.as_ptr());
             }
+        });
+
+        Box::into_raw(Box::new(NdTask { cancel }))
+    })
+}
+
+/// ダウンロードをキャンセルする
+///
+/// 完了コールバックは `Cancelled` のエラーコードで呼ばれる。
+/// すでに終了したタスクに対しては何もしない。
+///
+/// # Safety
+///
+/// `task` は `nd_download_start` が返し、まだ解放していないハンドルであること。
+#[no_mangle]
+pub unsafe extern "C" fn nd_cancel(task: *mut NdTask) -> libc::c_int {
+    catch_panic(ErrorCode::UnknownError as libc::c_int, || {
+        let Some(task) = task.as_ref() else {
+            return fail(ErrorCode::InvalidArgument, "task がNULLです");
+        };
+        task.cancel.send_replace(true);
+        ErrorCode::Success as libc::c_int
+    })
+}
+
+/// タスクのハンドルを解放する
+///
+/// ダウンロードは中断されず、完了コールバックも通常どおり呼ばれる。
+/// 中断する場合は先に `nd_cancel` を呼ぶ。
+///
+/// # Safety
+///
+/// `task` はNULLまたは `nd_download_start` が返し、まだ解放していないハンドルであること。
+#[no_mangle]
+pub unsafe extern "C" fn nd_task_free(task: *mut NdTask) {
+    catch_panic((), || {
+        if !task.is_null() {
+            drop(Box::from_raw(task));
         }
-    }
+    })
 }
 
-/// C文字列を解放する
-/// 
+/// このライブラリが返したC文字列を解放する
+///
 /// # 引数
-/// 
+///
 /// * `ptr` - 解放するC文字列ポインタ
+///
+/// # Safety
+///
+/// `ptr` はNULLまたはこのライブラリが返し、まだ解放していない文字列であること。
 #[no_mangle]
-pub extern "C" fn free_string(ptr: *mut c_char) {
+pub unsafe extern "C" fn nd_string_free(ptr: *mut c_char) {
     if !ptr.is_null() {
+        drop(CString::from_raw(ptr));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    /// 呼び出したスレッドの最後のエラー（取得した文字列は解放する）
+    fn last_error() -> Option<String> {
+        let ptr = nd_last_error_message();
+        if ptr.is_null() {
+            return None;
+        }
+        let message = unsafe { CStr::from_ptr(ptr) }.to_string_lossy().to_string();
+        unsafe { nd_string_free(ptr) };
+        Some(message)
+    }
+
+    extern "C" fn send_completion(user_data: *mut c_void, error_code: libc::c_int, result: *const c_char) {
+        let sender = unsafe { &*(user_data as *const mpsc::Sender<(libc::c_int, String)>) };
+        let result = unsafe { CStr::from_ptr(result) }.to_string_lossy().to_string();
+        let _ = sender.send((error_code, result));
+    }
+
+    #[test]
+    fn version_is_the_crate_version() {
+        let version = unsafe { CStr::from_ptr(nd_version()) };
+        assert_eq!(version.to_str().unwrap(), env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn last_error_is_kept_per_thread() {
+        std::thread::spawn(|| {
+            assert_eq!(last_error(), None);
+
+            let code = unsafe { nd_cancel(std::ptr::null_mut()) };
+            assert_eq!(code, ErrorCode::InvalidArgument as libc::c_int);
+            let message = last_error().unwrap();
+            assert!(message.contains("task"), "{}", message);
+            // 取得しても消えない
+            assert_eq!(last_error(), Some(message));
+
+            std::thread::spawn(|| assert_eq!(last_error(), None)).join().unwrap();
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn free_functions_accept_null() {
         unsafe {
-            let _ = CString::from_raw(ptr);
+            nd_task_free(std::ptr::null_mut());
+            nd_string_free(std::ptr::null_mut());
         }
     }
+
+    #[test]
+    fn strings_returned_by_the_library_can_be_freed() {
+        let ptr = c_string("message").into_raw();
+        unsafe { nd_string_free(ptr) };
+    }
+
+    #[test]
+    fn download_start_rejects_invalid_arguments() {
+        std::thread::spawn(|| {
+            let task = unsafe {
+                nd_download_start(
+                    std::ptr::null(),
+                    c"/tmp".as_ptr(),
+                    c"video".as_ptr(),
+                    std::ptr::null(),
+                    None,
+                    None,
+                    std::ptr::null_mut(),
+                )
+            };
+            assert!(task.is_null());
+            assert!(last_error().unwrap().contains("url"));
+
+            let task = unsafe {
+                nd_download_start(
+                    c"https://example.com/video.mp4".as_ptr(),
+                    c"/tmp".as_ptr(),
+                    c"video".as_ptr(),
+                    c"{".as_ptr(),
+                    None,
+                    None,
+                    std::ptr::null_mut(),
+                )
+            };
+            assert!(task.is_null());
+            assert!(last_error().unwrap().contains("オプション"));
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn blocking_calls_work_inside_another_runtime() {
+        let mut result = [0u8; 3];
+        assert_eq!(unsafe { nd_check_dependencies(&mut result) }, ErrorCode::Success as libc::c_int);
+
+        let mut content_type = -1;
+        let code = unsafe { nd_detect_content_type(c"https://example.com/index.m3u8".as_ptr(), &mut content_type) };
+        assert_eq!(code, ErrorCode::Success as libc::c_int);
+        assert_eq!(content_type, 1);
+    }
+
+    #[test]
+    fn panics_are_reported_as_errors() {
+        std::thread::spawn(|| {
+            let code = catch_panic(ErrorCode::UnknownError as libc::c_int, || panic!("壊れた状態"));
+            assert_eq!(code, ErrorCode::UnknownError as libc::c_int);
+            assert_eq!(last_error().as_deref(), Some("内部エラー: 壊れた状態"));
+
+            let task = catch_panic(std::ptr::null_mut::<NdTask>(), || panic!("{}", 42));
+            assert!(task.is_null());
+            assert_eq!(last_error().as_deref(), Some("内部エラー: 42"));
+
+            assert!(matches!(run(async { panic!("非同期") }), Err(code) if code == ErrorCode::UnknownError as libc::c_int));
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    fn cancelled_download_completes_once_and_the_handle_can_be_freed() {
+        // 接続を受け付けるだけで応答しないサーバー
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = c_string(format!("http://{}/video.mp4", listener.local_addr().unwrap()));
+        let dir = std::env::temp_dir().join(format!("nextdownloader-ffi-{}", uuid::Uuid::new_v4()));
+        let output_path = c_string(dir.to_string_lossy());
+        let (sender, receiver) = mpsc::channel::<(libc::c_int, String)>();
+
+        let task = unsafe {
+            nd_download_start(
+                url.as_ptr(),
+                output_path.as_ptr(),
+                c"video".as_ptr(),
+                std::ptr::null(),
+                None,
+                Some(send_completion),
+                &sender as *const _ as *mut c_void,
+            )
+        };
+        assert!(!task.is_null());
+        assert_eq!(unsafe { nd_cancel(task) }, ErrorCode::Success as libc::c_int);
+
+        let (code, _) = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(code, ErrorCode::Cancelled as libc::c_int);
+        // キャンセル済みのタスクへのキャンセルは何もしない
+        assert_eq!(unsafe { nd_cancel(task) }, ErrorCode::Success as libc::c_int);
+        unsafe { nd_task_free(task) };
+        assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
 }
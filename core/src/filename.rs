@@ -0,0 +1,700 @@
+//! 出力ファイル名のテンプレートと、OSで安全に使えるファイル名への変換
+//!
+//! テンプレートは `{title} [{id}] {height}p.{ext}` のように `{項目名}` で値を埋め込む
+//! （波括弧そのものは `{{`、`}}` と書く）。`/` で区切るとサブディレクトリに保存する。
+//!
+//! | 項目 | 値 |
+//! |---|---|
+//! | `name` | URLの末尾のファイル名（拡張子なし） |
+//! | `host` | URLのホスト名 |
+//! | `title` | タイトル（わからない場合は `name`） |
+//! | `id` | 動画ID |
+//! | `ext` | 拡張子（出力フォーマットで決まる） |
+//! | `width` / `height` | 選んだ画質の幅・高さ |
+//! | `uploader` | 投稿者 |
+//! | `upload_date` | 投稿日（YYYYMMDD） |
+//! | `extractor` | 抽出器の名前 |
+//! | `playlist_index` | プレイリスト内の番号（項目数の桁数にゼロ埋め） |
+//! | `playlist_title` / `playlist_id` | プレイリストのタイトル・ID |
+//!
+//! わからない値は `NA` になる。拡張子は出力フォーマットに合わせて付けるため、
+//! 末尾の `.{ext}` は省略してもよい。
+//!
+//! 埋め込む値の `/` などはサブディレクトリにならないよう置き換え、展開後のパスも
+//! 要素ごとに `..`、Windowsの予約名（`CON`、`NUL` など）や使えない文字を除いてから使う。
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use url::Url;
+use crate::playlist::PlaylistSource;
+use crate::types::{DownloadError, VideoInfo};
+
+/// ファイル名のテンプレートの既定値
+pub const DEFAULT_FILENAME_TEMPLATE: &str = "{title}";
+
+/// プレイリストの項目のファイル名のテンプレートの既定値
+pub const DEFAULT_PLAYLIST_FILENAME_TEMPLATE: &str = "{playlist_index} - {title}";
+
+/// わからない値の表記
+const NOT_AVAILABLE: &str = "NA";
+
+/// パスの要素（ファイル名・ディレクトリ名）の最大バイト数
+///
+/// 多くのファイルシステムの上限（255バイト）から、連番や拡張子・一時ファイルの分を除いた長さ。
+const MAX_COMPONENT_BYTES: usize = 200;
+
+/// 連番を付ける場合に試す最大の番号
+const MAX_RENAME_SUFFIX: u32 = 9999;
+
+/// テンプレートで使える項目
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Name,
+    Host,
+    Title,
+    Id,
+    Ext,
+    Width,
+    Height,
+    Uploader,
+    UploadDate,
+    Extractor,
+    PlaylistIndex,
+    PlaylistTitle,
+    PlaylistId,
+}
+
+impl Field {
+    const ALL: [Field; 13] = [
+        Field::Name,
+        Field::Host,
+        Field::Title,
+        Field::Id,
+        Field::Ext,
+        Field::Width,
+        Field::Height,
+        Field::Uploader,
+        Field::UploadDate,
+        Field::Extractor,
+        Field::PlaylistIndex,
+        Field::PlaylistTitle,
+        Field::PlaylistId,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Field::Name => "name",
+            Field::Host => "host",
+            Field::Title => "title",
+            Field::Id => "id",
+            Field::Ext => "ext",
+            Field::Width => "width",
+            Field::Height => "height",
+            Field::Uploader => "uploader",
+            Field::UploadDate => "upload_date",
+            Field::Extractor => "extractor",
+            Field::PlaylistIndex => "playlist_index",
+            Field::PlaylistTitle => "playlist_title",
+            Field::PlaylistId => "playlist_id",
+        }
+    }
+
+    /// 動画の情報（[`VideoInfo`]）から埋める項目かどうか
+    fn is_video_info(self) -> bool {
+        matches!(
+            self,
+            Field::Title
+                | Field::Id
+                | Field::Width
+                | Field::Height
+                | Field::Uploader
+                | Field::UploadDate
+                | Field::Extractor
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Field(Field),
+}
+
+/// 出力ファイル名のテンプレート
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilenameTemplate {
+    source: String,
+    parts: Vec<Part>,
+}
+
+impl FilenameTemplate {
+    /// 値を埋め込んで、出力ディレクトリからの相対パス（拡張子なし）を作る
+    pub fn render(&self, fields: &TemplateFields) -> PathBuf {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => rendered.push_str(text),
+                Part::Field(field) => {
+                    let value = fields.value(*field);
+                    rendered.push_str(&sanitize_filename(value.as_deref().unwrap_or(NOT_AVAILABLE)));
+                }
+            }
+        }
+        sanitize_relative_path(&rendered)
+    }
+
+    /// 埋めるには動画の情報の取得が必要かどうか（すでにわかっている項目は除く）
+    pub fn needs_video_info(&self, fields: &TemplateFields) -> bool {
+        self.parts.iter().any(|part| match part {
+            // タイトルはわからなければURLのファイル名で埋めるが、取得できるなら取得する
+            Part::Field(Field::Title) => fields.title.is_none(),
+            Part::Field(field) => field.is_video_info() && fields.value(*field).is_none(),
+            Part::Literal(_) => false,
+        })
+    }
+}
+
+impl FromStr for FilenameTemplate {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = value.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("`{{` が閉じられていません: {}", value)),
+                        }
+                    }
+                    let field = Field::ALL
+                        .into_iter()
+                        .find(|field| field.name() == name.trim())
+                        .ok_or_else(|| {
+                            let names: Vec<&str> = Field::ALL.iter().map(|field| field.name()).collect();
+                            format!("不明な項目: {{{}}}（使える項目: {}）", name, names.join(", "))
+                        })?;
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Field(field));
+                }
+                '}' => return Err(format!("対応する `{{` がない `}}` があります: {}", value)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        // 拡張子は出力フォーマットに合わせて付けるため、末尾の `.{ext}` は除く
+        if parts.last() == Some(&Part::Field(Field::Ext)) {
+            if let Some(Part::Literal(text)) = parts.len().checked_sub(2).and_then(|index| parts.get(index)) {
+                if let Some(stem) = text.strip_suffix('.') {
+                    let stem = stem.to_string();
+                    parts.pop();
+                    parts.pop();
+                    if !stem.is_empty() {
+                        parts.push(Part::Literal(stem));
+                    }
+                }
+            }
+        }
+        if parts.is_empty() {
+            return Err("ファイル名のテンプレートが空です".to_string());
+        }
+
+        Ok(Self {
+            source: value.to_string(),
+            parts,
+        })
+    }
+}
+
+impl fmt::Display for FilenameTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// テンプレートに埋め込む値
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TemplateFields {
+    /// URLの末尾のファイル名（拡張子なし）
+    pub name: Option<String>,
+    /// URLのホスト名
+    pub host: Option<String>,
+    /// タイトル
+    pub title: Option<String>,
+    /// 動画ID
+    pub id: Option<String>,
+    /// 拡張子
+    pub ext: Option<String>,
+    /// 幅
+    pub width: Option<u32>,
+    /// 高さ
+    pub height: Option<u32>,
+    /// 投稿者
+    pub uploader: Option<String>,
+    /// 投稿日（YYYYMMDD）
+    pub upload_date: Option<String>,
+    /// 抽出器の名前
+    pub extractor: Option<String>,
+    /// プレイリスト内の番号（ゼロ埋め済み）
+    pub playlist_index: Option<String>,
+    /// プレイリストのタイトル
+    pub playlist_title: Option<String>,
+    /// プレイリストのID
+    pub playlist_id: Option<String>,
+}
+
+impl TemplateFields {
+    /// URLからわかる値（`name`、`host`）
+    pub fn from_url(url: &str) -> Self {
+        let parsed = Url::parse(url).ok();
+        let name = parsed
+            .as_ref()
+            .and_then(|url| url.path_segments()?.rfind(|segment| !segment.is_empty()).map(str::to_string))
+            .map(|segment| match segment.rsplit_once('.') {
+                Some((stem, _)) if !stem.is_empty() => stem.to_string(),
+                _ => segment,
+            })
+            .unwrap_or_else(|| "download".to_string());
+        let host = parsed.as_ref().and_then(|url| url.host_str().map(str::to_string));
+
+        Self {
+            name: Some(name),
+            host,
+            ..Default::default()
+        }
+    }
+
+    /// 拡張子を設定する
+    pub fn with_ext(mut self, ext: &str) -> Self {
+        self.ext = Some(ext.to_string());
+        self
+    }
+
+    /// プレイリストの項目の値を設定する
+    pub fn with_playlist(mut self, source: &PlaylistSource) -> Self {
+        let width = source.playlist_count.max(source.index).to_string().len();
+        self.playlist_index = Some(format!("{:0width$}", source.index, width = width));
+        self.playlist_title = source.playlist_title.clone();
+        self.playlist_id = source.playlist_id.clone();
+        self.title = self.title.or_else(|| source.title.clone());
+        self.id = self.id.or_else(|| source.id.clone());
+        self
+    }
+
+    /// 動画の情報の値を設定する（わかっている値は上書きする）
+    pub fn with_video_info(mut self, info: &VideoInfo) -> Self {
+        self.title = info.title.clone().or(self.title);
+        self.id = info.id.clone().or(self.id);
+        self.width = info.width.or(self.width);
+        self.height = info.height.or(self.height);
+        self.uploader = info.uploader.clone().or(self.uploader);
+        self.upload_date = info.upload_date.clone().or(self.upload_date);
+        self.extractor = info.extractor_key.clone().or(self.extractor);
+        self
+    }
+
+    fn value(&self, field: Field) -> Option<String> {
+        match field {
+            Field::Name => self.name.clone(),
+            Field::Host => self.host.clone(),
+            Field::Title => self
+                .title
+                .clone()
+                .filter(|title| !title.trim().is_empty())
+                .or_else(|| self.name.clone()),
+            Field::Id => self.id.clone(),
+            Field::Ext => self.ext.clone(),
+            Field::Width => self.width.map(|width| width.to_string()),
+            Field::Height => self.height.map(|height| height.to_string()),
+            Field::Uploader => self.uploader.clone(),
+            Field::UploadDate => self.upload_date.clone(),
+            Field::Extractor => self.extractor.clone(),
+            Field::PlaylistIndex => self.playlist_index.clone(),
+            Field::PlaylistTitle => self.playlist_title.clone(),
+            Field::PlaylistId => self.playlist_id.clone(),
+        }
+    }
+}
+
+/// ファイル名（パスの1要素）をOSで安全に使える形にする
+///
+/// 使えない文字（`/ \ : * ? " < > |` と制御文字）は `_` に置き換え、前後の空白と末尾の `.`
+/// を除く。`.`、`..` や空になった場合は `_`、Windowsの予約名（`CON`、`COM1.txt` など）は
+/// 先頭に `_` を付ける。長すぎる場合は切り詰める。
+pub fn sanitize_filename(name: &str) -> String {
+    let replaced: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    let mut sanitized = String::new();
+    for c in replaced.trim().chars() {
+        if sanitized.len() + c.len_utf8() > MAX_COMPONENT_BYTES {
+            break;
+        }
+        sanitized.push(c);
+    }
+    let sanitized = sanitized.trim_end_matches(['.', ' ']).trim_end();
+
+    if sanitized.is_empty() || sanitized.chars().all(|c| c == '.') {
+        return "_".to_string();
+    }
+    if is_reserved_name(sanitized) {
+        return format!("_{}", sanitized);
+    }
+    sanitized.to_string()
+}
+
+/// `/` 区切りのパスを、出力ディレクトリの外に出ない相対パスにする
+///
+/// 要素ごとに [`sanitize_filename`] を通し、空の要素・`.`・`..` は除く。
+fn sanitize_relative_path(path: &str) -> PathBuf {
+    let components: Vec<String> = path
+        .split(['/', '\\'])
+        .map(str::trim)
+        .filter(|component| !component.is_empty() && *component != "." && *component != "..")
+        .map(sanitize_filename)
+        .collect();
+    if components.is_empty() {
+        return PathBuf::from("download");
+    }
+    components.iter().collect()
+}
+
+/// Windowsの予約名（拡張子が付いていても使えない）
+fn is_reserved_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).trim_end().to_uppercase();
+    match stem.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" => true,
+        _ => ["COM", "LPT"].iter().any(|prefix| {
+            stem.strip_prefix(prefix)
+                .is_some_and(|number| matches!(number, "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9"))
+        }),
+    }
+}
+
+/// 出力先に同じ名前のファイルがある場合の扱い
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CollisionPolicy {
+    /// 上書きする
+    Overwrite,
+    /// ダウンロードしない
+    Skip,
+    /// 「名前 (1)」のように連番を付ける
+    #[default]
+    Rename,
+}
+
+impl FromStr for CollisionPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "overwrite" => Ok(CollisionPolicy::Overwrite),
+            "skip" => Ok(CollisionPolicy::Skip),
+            "rename" => Ok(CollisionPolicy::Rename),
+            _ => Err(format!("不明な指定: {}（overwrite、skip、renameのいずれか）", value)),
+        }
+    }
+}
+
+impl fmt::Display for CollisionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CollisionPolicy::Overwrite => write!(f, "overwrite"),
+            CollisionPolicy::Skip => write!(f, "skip"),
+            CollisionPolicy::Rename => write!(f, "rename"),
+        }
+    }
+}
+
+/// 既存のファイルとの衝突を解決して、出力ファイル名（拡張子なし）を決める
+///
+/// 同じ名前のダウンロードが途中の場合（aria2cの制御ファイル `.aria2` がある場合）は
+/// 続きから取得するため衝突とみなさない。連番は空いている最小の番号を使うため、
+/// 同じ状態のディレクトリでは常に同じ名前になる。
+pub fn resolve_collision(
+    dir: &Path,
+    stem: &str,
+    ext: &str,
+    policy: CollisionPolicy
+) -> Result<String, DownloadError> {
+    let output_file = |stem: &str| dir.join(format!("{}.{}", stem, ext));
+    let is_taken = |stem: &str| {
+        let path = output_file(stem);
+        path.exists() && !dir.join(format!("{}.{}.aria2", stem, ext)).exists()
+    };
+
+    match policy {
+        // 既存のファイルはダウンロードに成功してから置き換える（[`overwrite_staging`]）
+        CollisionPolicy::Overwrite => Ok(stem.to_string()),
+        CollisionPolicy::Skip if is_taken(stem) => Err(DownloadError::AlreadyExists(output_file(stem))),
+        CollisionPolicy::Skip => Ok(stem.to_string()),
+        CollisionPolicy::Rename => std::iter::once(stem.to_string())
+            .chain((1..=MAX_RENAME_SUFFIX).map(|number| format!("{} ({})", stem, number)))
+            .find(|candidate| !is_taken(candidate))
+            .ok_or_else(|| DownloadError::AlreadyExists(output_file(stem))),
+    }
+}
+
+/// 上書きする場合に、ダウンロードしたデータを置く一時的なファイル名（拡張子なし）を返す
+///
+/// 既存のファイルがそのままの名前で残っていると途中までのデータとして続きから取得されるため、
+/// `名前.overwrite` に取得してから [`replace_existing`] で置き換える。既存のファイルがなければ
+/// （または上書き以外の指定なら）`None` を返す。一時ファイルは中断後も続きから取得できるよう
+/// 毎回同じ名前にし、取得を終えたまま残っているもの（置き換える前に中断したもの）だけ削除する。
+pub fn overwrite_staging(
+    dir: &Path,
+    stem: &str,
+    ext: &str,
+    policy: CollisionPolicy
+) -> Result<Option<String>, DownloadError> {
+    let output_file = dir.join(format!("{}.{}", stem, ext));
+    if policy != CollisionPolicy::Overwrite
+        || !output_file.exists()
+        || dir.join(format!("{}.{}.aria2", stem, ext)).exists()
+    {
+        return Ok(None);
+    }
+
+    let staging = format!("{}.overwrite", stem);
+    let staged_file = dir.join(format!("{}.{}", staging, ext));
+    if staged_file.exists() && !dir.join(format!("{}.{}.aria2", staging, ext)).exists() {
+        std::fs::remove_file(&staged_file)?;
+    }
+    Ok(Some(staging))
+}
+
+/// 一時的なファイル名で取得したファイルを `stem` の名前に移し、既存のファイルを置き換える
+///
+/// 拡張子は取得したファイルのものをそのまま使う。置き換えたファイルのパスを返す。
+pub fn replace_existing(staged_file: &Path, stem: &str) -> Result<PathBuf, DownloadError> {
+    let name = match staged_file.extension() {
+        Some(ext) => format!("{}.{}", stem, ext.to_string_lossy()),
+        None => stem.to_string(),
+    };
+    let output_file = staged_file.with_file_name(name);
+    std::fs::rename(staged_file, &output_file)?;
+    Ok(output_file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn render(template: &str, fields: &TemplateFields) -> String {
+        let template: FilenameTemplate = template.parse().unwrap();
+        template.render(fields).to_string_lossy().replace('\\', "/")
+    }
+
+    #[test]
+    fn replaces_unsafe_characters_and_trims() {
+        assert_eq!(sanitize_filename("  AC/DC: \"Live\" <2024>?  "), "AC_DC_ _Live_ _2024__");
+        assert_eq!(sanitize_filename("tab\there\nnewline"), "tab_here_newline");
+        assert_eq!(sanitize_filename("ends with dots... "), "ends with dots");
+        assert_eq!(sanitize_filename("日本語のタイトル｜公式"), "日本語のタイトル｜公式");
+    }
+
+    #[test]
+    fn avoids_empty_dot_and_reserved_names() {
+        assert_eq!(sanitize_filename(""), "_");
+        assert_eq!(sanitize_filename(" .. "), "_");
+        assert_eq!(sanitize_filename("CON"), "_CON");
+        assert_eq!(sanitize_filename("com1.txt"), "_com1.txt");
+        assert_eq!(sanitize_filename("LPT0"), "LPT0");
+        assert_eq!(sanitize_filename("CONSOLE"), "CONSOLE");
+    }
+
+    #[test]
+    fn truncates_long_names_on_a_character_boundary() {
+        let sanitized = sanitize_filename(&"あ".repeat(100));
+        assert_eq!(sanitized, "あ".repeat(MAX_COMPONENT_BYTES / 3));
+        assert!(sanitized.len() <= MAX_COMPONENT_BYTES);
+    }
+
+    #[test]
+    fn renders_url_fields_and_strips_the_extension() {
+        let fields = TemplateFields::from_url("https://cdn.example.com/media/Episode.01.mp4?token=abc").with_ext("mkv");
+
+        assert_eq!(render("{host}/{name}.{ext}", &fields), "cdn.example.com/Episode.01");
+        // タイトルがわからなければURLのファイル名、その他は NA
+        assert_eq!(render("{title} [{id}]", &fields), "Episode.01 [NA]");
+        assert_eq!(render("{{{name}}}", &fields), "{Episode.01}");
+        assert_eq!(render("{title}", &TemplateFields::from_url("https://example.com/")), "download");
+    }
+
+    #[test]
+    fn renders_video_info_and_playlist_fields() {
+        let info: VideoInfo = serde_json::from_value(serde_json::json!({
+            "id": "dQw4w9WgXcQ",
+            "extractor_key": "Youtube",
+            "title": "Part 1/2: Intro",
+            "uploader": "Channel",
+            "upload_date": "20240131",
+            "height": 1080,
+        }))
+        .unwrap();
+        let source = PlaylistSource {
+            playlist_id: Some("PL123".to_string()),
+            playlist_title: Some("My List".to_string()),
+            index: 7,
+            playlist_count: 120,
+            id: None,
+            title: Some("Playlist Title".to_string()),
+        };
+        let fields = TemplateFields::from_url("https://www.youtube.com/watch?v=dQw4w9WgXcQ")
+            .with_playlist(&source)
+            .with_video_info(&info);
+
+        assert_eq!(
+            render("{playlist_title}/{playlist_index} - {title} [{id}] {height}p", &fields),
+            "My List/007 - Part 1_2_ Intro [dQw4w9WgXcQ] 1080p"
+        );
+        assert_eq!(render("{extractor}/{uploader}/{upload_date}", &fields), "Youtube/Channel/20240131");
+    }
+
+    #[test]
+    fn keeps_rendered_paths_inside_the_output_directory() {
+        let fields = TemplateFields { title: Some("..".to_string()), ..Default::default() };
+
+        assert_eq!(render("../../{title}/./x", &fields), "_/x");
+        assert_eq!(render("/etc/passwd", &fields), "etc/passwd");
+        assert_eq!(render("//", &fields), "download");
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        assert!("{title".parse::<FilenameTemplate>().unwrap_err().contains("閉じられていません"));
+        assert!("title}".parse::<FilenameTemplate>().unwrap_err().contains("対応する"));
+        assert!("{duration}".parse::<FilenameTemplate>().unwrap_err().contains("不明な項目"));
+        assert!("".parse::<FilenameTemplate>().is_err());
+        assert!(".{ext}".parse::<FilenameTemplate>().is_err());
+    }
+
+    #[test]
+    fn needs_video_info_only_for_unknown_fields() {
+        let template: FilenameTemplate = "{playlist_index} - {title}".parse().unwrap();
+        let source = PlaylistSource {
+            playlist_id: None,
+            playlist_title: None,
+            index: 1,
+            playlist_count: 3,
+            id: Some("abc".to_string()),
+            title: Some("Known".to_string()),
+        };
+
+        assert!(template.needs_video_info(&TemplateFields::from_url("https://example.com/watch")));
+        assert!(!template.needs_video_info(&TemplateFields::default().with_playlist(&source)));
+        assert!(!"{name}".parse::<FilenameTemplate>().unwrap().needs_video_info(&TemplateFields::default()));
+    }
+
+    #[test]
+    fn rename_uses_the_smallest_free_number() {
+        let dir = TempDir::new();
+        for name in ["video.mp4", "video (1).mp4", "video (3).mp4"] {
+            std::fs::write(dir.path().join(name), b"").unwrap();
+        }
+
+        assert_eq!(resolve_collision(dir.path(), "video", "mp4", CollisionPolicy::Rename).unwrap(), "video (2)");
+        assert_eq!(resolve_collision(dir.path(), "other", "mp4", CollisionPolicy::Rename).unwrap(), "other");
+        // 拡張子が違えば衝突しない
+        assert_eq!(resolve_collision(dir.path(), "video", "mkv", CollisionPolicy::Rename).unwrap(), "video");
+    }
+
+    #[test]
+    fn unfinished_download_is_not_a_collision() {
+        let dir = TempDir::new();
+        std::fs::write(dir.path().join("video.mp4"), b"partial").unwrap();
+        std::fs::write(dir.path().join("video.mp4.aria2"), b"").unwrap();
+
+        for policy in [CollisionPolicy::Rename, CollisionPolicy::Skip, CollisionPolicy::Overwrite] {
+            assert_eq!(resolve_collision(dir.path(), "video", "mp4", policy).unwrap(), "video");
+        }
+        assert!(dir.path().join("video.mp4").exists());
+    }
+
+    #[test]
+    fn skip_and_overwrite_existing_files() {
+        let dir = TempDir::new();
+        let existing = dir.path().join("video.mp4");
+        std::fs::write(&existing, b"done").unwrap();
+
+        match resolve_collision(dir.path(), "video", "mp4", CollisionPolicy::Skip) {
+            Err(DownloadError::AlreadyExists(path)) => assert_eq!(path, existing),
+            other => panic!("AlreadyExistsになるはず: {:?}", other),
+        }
+        assert_eq!(resolve_collision(dir.path(), "video", "mp4", CollisionPolicy::Overwrite).unwrap(), "video");
+        // 既存のファイルはダウンロードに成功するまで残す
+        assert!(existing.exists());
+    }
+
+    #[test]
+    fn overwrite_downloads_next_to_the_existing_file() {
+        let dir = TempDir::new();
+        let existing = dir.path().join("video.mp4");
+        std::fs::write(&existing, b"old").unwrap();
+
+        assert_eq!(overwrite_staging(dir.path(), "video", "mp4", CollisionPolicy::Rename).unwrap(), None);
+        assert_eq!(overwrite_staging(dir.path(), "other", "mp4", CollisionPolicy::Overwrite).unwrap(), None);
+        let staging = overwrite_staging(dir.path(), "video", "mp4", CollisionPolicy::Overwrite).unwrap().unwrap();
+        assert_eq!(staging, "video.overwrite");
+
+        let staged_file = dir.path().join("video.overwrite.mp4");
+        std::fs::write(&staged_file, b"new").unwrap();
+        assert_eq!(replace_existing(&staged_file, "video").unwrap(), existing);
+        assert_eq!(std::fs::read(&existing).unwrap(), b"new");
+        assert!(!staged_file.exists());
+    }
+
+    #[test]
+    fn overwrite_staging_keeps_unfinished_data_and_removes_leftovers() {
+        let dir = TempDir::new();
+        std::fs::write(dir.path().join("video.mp4"), b"old").unwrap();
+        let staged_file = dir.path().join("video.overwrite.mp4");
+
+        // 途中までのデータは続きから取得する
+        std::fs::write(&staged_file, b"partial").unwrap();
+        std::fs::write(dir.path().join("video.overwrite.mp4.aria2"), b"").unwrap();
+        overwrite_staging(dir.path(), "video", "mp4", CollisionPolicy::Overwrite).unwrap();
+        assert!(staged_file.exists());
+
+        // 置き換える前に中断して残ったものは使わない
+        std::fs::remove_file(dir.path().join("video.overwrite.mp4.aria2")).unwrap();
+        overwrite_staging(dir.path(), "video", "mp4", CollisionPolicy::Overwrite).unwrap();
+        assert!(!staged_file.exists());
+        assert!(dir.path().join("video.mp4").exists());
+    }
+
+    #[test]
+    fn parses_collision_policies() {
+        assert_eq!(" Skip ".parse::<CollisionPolicy>(), Ok(CollisionPolicy::Skip));
+        assert_eq!(CollisionPolicy::Overwrite.to_string().parse(), Ok(CollisionPolicy::Overwrite));
+        assert!("replace".parse::<CollisionPolicy>().is_err());
+    }
+}
@@ -0,0 +1,218 @@
+//! 出力ファイルに書き込むメタデータとカバーアート
+//!
+//! yt-dlpの動画情報からタイトル・投稿者・投稿日・説明・元のページのURLを取り出し、
+//! ffmpegでコンテナのメタデータ（MP4のアトム、Matroskaのタグ、MP3のID3v2）として書き込む。
+//! サムネイルはカバーアート（MP4・MP3は添付画像、MKVは添付ファイル）として添付する。
+
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use url::Url;
+use crate::retry::RetryPolicy;
+use crate::tools::fetcher::SegmentFetcher;
+use crate::types::{DownloadError, VideoInfo};
+
+/// メタデータ・カバーアートを書き込むかどうか
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetadataOptions {
+    /// タイトル・投稿者・投稿日・説明を書き込む
+    pub embed: bool,
+    /// サムネイルをカバーアートとして添付する
+    pub thumbnail: bool,
+}
+
+impl MetadataOptions {
+    /// メタデータかカバーアートのどちらかを書き込むか
+    pub fn is_enabled(&self) -> bool {
+        self.embed || self.thumbnail
+    }
+}
+
+/// コンテナに書き込むメタデータ
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MediaMetadata {
+    /// タイトル
+    pub title: Option<String>,
+    /// 投稿者
+    pub artist: Option<String>,
+    /// 投稿日（YYYY-MM-DD）
+    pub date: Option<String>,
+    /// 説明
+    pub description: Option<String>,
+    /// 元のページのURL
+    pub source_url: Option<String>,
+}
+
+impl MediaMetadata {
+    /// 動画情報からメタデータを作成（元のページのURLがわからない場合は `url` を使う）
+    pub fn from_video_info(info: &VideoInfo, url: &str) -> Self {
+        let non_empty = |value: &Option<String>| value.clone().filter(|value| !value.trim().is_empty());
+        Self {
+            title: non_empty(&info.title),
+            artist: non_empty(&info.uploader),
+            date: info.upload_date.as_deref().map(format_date),
+            description: non_empty(&info.description),
+            source_url: Some(non_empty(&info.webpage_url).unwrap_or_else(|| url.to_string())),
+        }
+    }
+
+    /// 書き込む値がないか
+    pub fn is_empty(&self) -> bool {
+        self.tags().is_empty()
+    }
+
+    /// ffmpegの `-metadata` に渡す名前と値
+    ///
+    /// ffmpegが形式ごとのタグ（MP4の `©nam`、ID3v2の `TIT2` など）に変換する。
+    /// 元のページのURLはコメントとして書き込む。
+    pub fn tags(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("title", &self.title),
+            ("artist", &self.artist),
+            ("date", &self.date),
+            ("description", &self.description),
+            ("comment", &self.source_url),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value.as_deref()?)))
+        .collect()
+    }
+}
+
+/// yt-dlpの投稿日（YYYYMMDD）をYYYY-MM-DDにする（それ以外の形式はそのまま）
+fn format_date(date: &str) -> String {
+    if date.len() == 8 && date.bytes().all(|byte| byte.is_ascii_digit()) {
+        format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..])
+    } else {
+        date.to_string()
+    }
+}
+
+/// サムネイルを取得し、`path` に画像の形式に合わせた拡張子を付けて保存する
+///
+/// 保存したファイルのパスを返す。
+pub async fn fetch_thumbnail(
+    client: reqwest::Client,
+    retry: RetryPolicy,
+    url: &str,
+    path: &Path
+) -> Result<PathBuf, DownloadError> {
+    let url = Url::parse(url)
+        .map_err(|err| DownloadError::InvalidManifest(format!("不正なURL {}: {}", url, err)))?;
+    let bytes = SegmentFetcher::new(client, retry).fetch(&url, None).await?;
+    let extension = image_extension(&bytes).ok_or_else(|| {
+        DownloadError::ProcessFailed(format!("サムネイルの画像の形式がわかりません: {}", url))
+    })?;
+
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    let path = PathBuf::from(name);
+    tokio::fs::write(&path, &bytes).await?;
+    Ok(path)
+}
+
+/// 先頭のバイト列から画像の形式（拡張子）を判定
+fn image_extension(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("jpg")
+    } else if bytes.starts_with(b"\x89PNG") {
+        Some("png")
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some("webp")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{Response, TempDir, TestServer};
+
+    fn video_info(value: serde_json::Value) -> VideoInfo {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn metadata_from_video_info() {
+        let info = video_info(serde_json::json!({
+            "title": "タイトル",
+            "uploader": "投稿者",
+            "upload_date": "20240131",
+            "description": "説明",
+            "webpage_url": "https://example.com/watch?v=abc",
+        }));
+
+        let metadata = MediaMetadata::from_video_info(&info, "https://example.com/other");
+        assert_eq!(metadata, MediaMetadata {
+            title: Some("タイトル".to_string()),
+            artist: Some("投稿者".to_string()),
+            date: Some("2024-01-31".to_string()),
+            description: Some("説明".to_string()),
+            source_url: Some("https://example.com/watch?v=abc".to_string()),
+        });
+        assert_eq!(metadata.tags(), [
+            ("title", "タイトル"),
+            ("artist", "投稿者"),
+            ("date", "2024-01-31"),
+            ("description", "説明"),
+            ("comment", "https://example.com/watch?v=abc"),
+        ]);
+    }
+
+    #[test]
+    fn empty_values_are_skipped_and_the_url_is_used_as_the_source() {
+        let info = video_info(serde_json::json!({
+            "title": "  ",
+            "description": "",
+            "upload_date": "2024",
+        }));
+
+        let metadata = MediaMetadata::from_video_info(&info, "https://example.com/video.mp4");
+        assert_eq!(metadata.title, None);
+        assert_eq!(metadata.description, None);
+        // YYYYMMDD以外の投稿日はそのまま
+        assert_eq!(metadata.date.as_deref(), Some("2024"));
+        assert_eq!(metadata.tags(), [("date", "2024"), ("comment", "https://example.com/video.mp4")]);
+        assert!(!metadata.is_empty());
+        assert!(MediaMetadata::default().is_empty());
+    }
+
+    #[test]
+    fn options_are_enabled_by_either_flag() {
+        assert!(!MetadataOptions::default().is_enabled());
+        assert!(MetadataOptions { embed: true, thumbnail: false }.is_enabled());
+        assert!(MetadataOptions { embed: false, thumbnail: true }.is_enabled());
+    }
+
+    #[test]
+    fn detects_image_formats() {
+        assert_eq!(image_extension(&[0xFF, 0xD8, 0xFF, 0xE0]), Some("jpg"));
+        assert_eq!(image_extension(b"\x89PNG\r\n\x1a\n"), Some("png"));
+        assert_eq!(image_extension(b"RIFF\0\0\0\0WEBPVP8 "), Some("webp"));
+        assert_eq!(image_extension(b"RIFF\0\0\0\0WAVE"), None);
+        assert_eq!(image_extension(b"<html>"), None);
+    }
+
+    #[tokio::test]
+    async fn saves_thumbnails_with_the_image_extension() {
+        let dir = TempDir::new();
+        let server = TestServer::start(|request| match request.path.as_str() {
+            "/thumbnail" => Response::ok(b"\x89PNG\r\n\x1a\nimage".to_vec()),
+            _ => Response::ok("<html></html>"),
+        })
+        .await;
+        let path = dir.path().join("video");
+
+        let saved = fetch_thumbnail(reqwest::Client::new(), RetryPolicy::none(), &server.url("/thumbnail"), &path)
+            .await
+            .unwrap();
+        assert_eq!(saved, dir.path().join("video.png"));
+        assert_eq!(std::fs::read(&saved).unwrap(), b"\x89PNG\r\n\x1a\nimage");
+
+        let result = fetch_thumbnail(reqwest::Client::new(), RetryPolicy::none(), &server.url("/page"), &path).await;
+        assert!(matches!(result, Err(DownloadError::ProcessFailed(_))), "{:?}", result);
+        assert!(fetch_thumbnail(reqwest::Client::new(), RetryPolicy::none(), "not a url", &path).await.is_err());
+    }
+}
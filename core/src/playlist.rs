@@ -0,0 +1,391 @@
+//! プレイリスト・チャンネルの展開
+//!
+//! yt-dlpの `--flat-playlist -J` で項目の一覧（番号・タイトル・ID）を取得し、
+//! 範囲や投稿日で選んだ項目をそれぞれ別のタスクとしてキューに追加する。
+
+use std::fmt;
+use std::str::FromStr;
+use chrono::{DateTime, Days, Local, Months, NaiveDate};
+use serde::{Deserialize, Serialize};
+use url::Url;
+use crate::archive::archive_key;
+use crate::detect::content_type_from_url;
+use crate::types::ContentType;
+
+/// プレイリストの項目
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlaylistEntry {
+    /// プレイリスト内の番号（1始まり）
+    pub index: usize,
+    /// 動画ID
+    pub id: Option<String>,
+    /// 抽出器の名前（yt-dlpの `ie_key`）
+    pub extractor: Option<String>,
+    /// タイトル
+    pub title: Option<String>,
+    /// 動画のURL
+    pub url: String,
+    /// 投稿日（YYYYMMDD）
+    pub upload_date: Option<String>,
+    /// 投稿日時（UNIX秒）
+    pub timestamp: Option<i64>,
+    /// 長さ（秒）
+    pub duration: Option<f64>,
+}
+
+impl PlaylistEntry {
+    /// 投稿日（わからない場合はNone）
+    pub fn date(&self) -> Option<NaiveDate> {
+        self.upload_date
+            .as_deref()
+            .and_then(|date| NaiveDate::parse_from_str(date, "%Y%m%d").ok())
+            .or_else(|| DateTime::from_timestamp(self.timestamp?, 0).map(|time| time.date_naive()))
+    }
+
+    /// ダウンロードアーカイブのキー（抽出器と動画IDがわかる場合）
+    pub fn archive_key(&self) -> Option<String> {
+        Some(archive_key(self.extractor.as_deref()?, self.id.as_deref()?))
+    }
+}
+
+/// プレイリスト（チャンネル・再生リストなど）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Playlist {
+    /// プレイリストのID
+    pub id: Option<String>,
+    /// タイトル
+    pub title: Option<String>,
+    /// 項目（プレイリスト内の順）
+    pub entries: Vec<PlaylistEntry>,
+}
+
+impl Playlist {
+    /// 最後の項目の番号
+    pub fn last_index(&self) -> usize {
+        self.entries.iter().map(|entry| entry.index).max().unwrap_or(1)
+    }
+}
+
+/// タスクの元になったプレイリストの項目
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlaylistSource {
+    /// プレイリストのID
+    pub playlist_id: Option<String>,
+    /// プレイリストのタイトル
+    pub playlist_title: Option<String>,
+    /// プレイリスト内の番号（1始まり）
+    pub index: usize,
+    /// プレイリストの最後の番号（ファイル名の番号の桁数をそろえるのに使う）
+    #[serde(default)]
+    pub playlist_count: usize,
+    /// 項目の動画ID
+    #[serde(default)]
+    pub id: Option<String>,
+    /// 項目のタイトル
+    pub title: Option<String>,
+}
+
+impl PlaylistSource {
+    /// プレイリストの項目から作成
+    pub fn new(playlist: &Playlist, entry: &PlaylistEntry) -> Self {
+        Self {
+            playlist_id: playlist.id.clone(),
+            playlist_title: playlist.title.clone(),
+            index: entry.index,
+            playlist_count: playlist.last_index(),
+            id: entry.id.clone(),
+            title: entry.title.clone(),
+        }
+    }
+}
+
+/// 項目の番号の範囲
+///
+/// `1-10,15`（1〜10番と15番）、`20-`（20番以降）の形式で指定する。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaylistItems(Vec<(usize, Option<usize>)>);
+
+impl PlaylistItems {
+    /// 番号が範囲に含まれるかどうか
+    pub fn contains(&self, index: usize) -> bool {
+        self.0
+            .iter()
+            .any(|(start, end)| index >= *start && end.is_none_or(|end| index <= end))
+    }
+}
+
+impl FromStr for PlaylistItems {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = |part: &str| format!("不正な項目の指定: {}（例: 1-10,15、20-）", part);
+        let parse = |number: &str, part: &str| match number.trim().parse::<usize>() {
+            Ok(number) if number > 0 => Ok(number),
+            _ => Err(invalid(part)),
+        };
+
+        let mut ranges = Vec::new();
+        for part in value.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let range = match part.split_once('-') {
+                Some((start, end)) if end.trim().is_empty() => (parse(start, part)?, None),
+                Some((start, end)) => {
+                    let (start, end) = (parse(start, part)?, parse(end, part)?);
+                    if start > end {
+                        return Err(invalid(part));
+                    }
+                    (start, Some(end))
+                }
+                None => {
+                    let index = parse(part, part)?;
+                    (index, Some(index))
+                }
+            };
+            ranges.push(range);
+        }
+        if ranges.is_empty() {
+            return Err(format!("項目が指定されていません: {}", value));
+        }
+        Ok(Self(ranges))
+    }
+}
+
+impl fmt::Display for PlaylistItems {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self
+            .0
+            .iter()
+            .map(|range| match range {
+                (start, Some(end)) if start == end => start.to_string(),
+                (start, Some(end)) => format!("{}-{}", start, end),
+                (start, None) => format!("{}-", start),
+            })
+            .collect();
+        f.write_str(&parts.join(","))
+    }
+}
+
+/// 展開する項目の選び方
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlaylistSelection {
+    /// 番号の範囲（Noneの場合はすべて）
+    pub items: Option<PlaylistItems>,
+    /// 投稿日がこの日以降の項目のみ
+    pub date_after: Option<NaiveDate>,
+    /// 投稿日がこの日以前の項目のみ
+    pub date_before: Option<NaiveDate>,
+    /// 逆順（新しい順のチャンネルを古い順に取得する場合など）
+    pub reverse: bool,
+}
+
+impl PlaylistSelection {
+    /// 項目を選ぶ
+    ///
+    /// 番号の範囲はプレイリスト内の元の番号で判定する。投稿日がわからない項目は
+    /// 日付では除外しない（yt-dlpの `--dateafter` と同じ）。
+    pub fn select(&self, entries: &[PlaylistEntry]) -> Vec<PlaylistEntry> {
+        let mut selected: Vec<PlaylistEntry> = entries
+            .iter()
+            .filter(|entry| self.items.as_ref().is_none_or(|items| items.contains(entry.index)))
+            .filter(|entry| match entry.date() {
+                Some(date) => {
+                    self.date_after.is_none_or(|after| date >= after)
+                        && self.date_before.is_none_or(|before| date <= before)
+                }
+                None => true,
+            })
+            .cloned()
+            .collect();
+        if self.reverse {
+            selected.reverse();
+        }
+        selected
+    }
+}
+
+/// URLがプレイリスト・チャンネルの可能性があるかどうか
+///
+/// 動画ファイル・ストリーミングのURLと、再生リストを含まないYouTubeの動画のURLは
+/// yt-dlpで調べるまでもなく単一の動画として扱う。
+pub fn may_be_playlist(url: &str) -> bool {
+    match content_type_from_url(url) {
+        Some(ContentType::Mp4 | ContentType::Hls | ContentType::Dash) => false,
+        Some(ContentType::YouTube) => {
+            let Ok(parsed) = Url::parse(url) else {
+                return true;
+            };
+            let has_list = parsed.query_pairs().any(|(key, _)| key == "list");
+            let single_video = parsed.host_str().is_some_and(|host| host.ends_with("youtu.be"))
+                || parsed.path() == "/watch"
+                || parsed.path().starts_with("/shorts/");
+            has_list || !single_video
+        }
+        _ => true,
+    }
+}
+
+/// 日付を解釈する
+///
+/// `20240131`、`2024-01-31` のほか、`today-2weeks`、`now-7days` のように
+/// 今日からの相対（day・week・month・year）でも指定できる。
+pub fn parse_date(value: &str) -> Result<NaiveDate, String> {
+    let trimmed = value.trim();
+    let invalid = || format!("不正な日付: {}（例: 20240131、2024-01-31、today-2weeks）", value);
+
+    if let Ok(date) = NaiveDate::parse_from_str(trimmed, "%Y%m%d") {
+        return Ok(date);
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(trimmed, "%Y-%m-%d") {
+        return Ok(date);
+    }
+
+    let lower = trimmed.to_lowercase();
+    let offset = ["today", "now"]
+        .iter()
+        .find_map(|base| lower.strip_prefix(base))
+        .ok_or_else(invalid)?;
+    let today = Local::now().date_naive();
+    if offset.is_empty() {
+        return Ok(today);
+    }
+
+    let (sign, offset) = match (offset.strip_prefix('-'), offset.strip_prefix('+')) {
+        (Some(rest), _) => (-1, rest),
+        (_, Some(rest)) => (1, rest),
+        _ => return Err(invalid()),
+    };
+    let digits = offset.find(|c: char| !c.is_ascii_digit()).unwrap_or(offset.len());
+    let count: u32 = offset[..digits].parse().map_err(|_| invalid())?;
+    let date = match offset[digits..].trim_end_matches('s') {
+        "day" => add_days(today, sign, u64::from(count)),
+        "week" => add_days(today, sign, u64::from(count) * 7),
+        "month" => add_months(today, sign, count),
+        "year" => add_months(today, sign, count.saturating_mul(12)),
+        _ => None,
+    };
+    date.ok_or_else(invalid)
+}
+
+fn add_days(date: NaiveDate, sign: i32, days: u64) -> Option<NaiveDate> {
+    if sign < 0 {
+        date.checked_sub_days(Days::new(days))
+    } else {
+        date.checked_add_days(Days::new(days))
+    }
+}
+
+fn add_months(date: NaiveDate, sign: i32, months: u32) -> Option<NaiveDate> {
+    if sign < 0 {
+        date.checked_sub_months(Months::new(months))
+    } else {
+        date.checked_add_months(Months::new(months))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(value: &str) -> PlaylistItems {
+        value.parse().unwrap()
+    }
+
+    fn entry(index: usize, upload_date: Option<&str>) -> PlaylistEntry {
+        PlaylistEntry {
+            index,
+            id: Some(format!("video{}", index)),
+            extractor: Some("Youtube".to_string()),
+            title: None,
+            url: format!("https://www.youtube.com/watch?v=video{}", index),
+            upload_date: upload_date.map(str::to_string),
+            timestamp: None,
+            duration: None,
+        }
+    }
+
+    fn indexes(entries: &[PlaylistEntry]) -> Vec<usize> {
+        entries.iter().map(|entry| entry.index).collect()
+    }
+
+    #[test]
+    fn parses_ranges_single_items_and_open_ends() {
+        let items = items(" 1-3, 7 ,20- ");
+
+        let included: Vec<usize> = (1..=25).filter(|index| items.contains(*index)).collect();
+        assert_eq!(included, vec![1, 2, 3, 7, 20, 21, 22, 23, 24, 25]);
+        assert_eq!(items.to_string(), "1-3,7,20-");
+    }
+
+    #[test]
+    fn overlapping_ranges_are_allowed() {
+        let items = items("2-5,4-6,5");
+        let included: Vec<usize> = (1..=8).filter(|index| items.contains(*index)).collect();
+        assert_eq!(included, vec![2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn rejects_invalid_items() {
+        for value in ["0", "3-1", "a-b", "-5", "1-2-3", "1.5"] {
+            let err = value.parse::<PlaylistItems>().unwrap_err();
+            assert!(err.starts_with("不正な項目の指定"), "{}: {}", value, err);
+        }
+        assert!(" , ".parse::<PlaylistItems>().unwrap_err().starts_with("項目が指定されていません"));
+    }
+
+    #[test]
+    fn selects_by_original_index_and_date_then_reverses() {
+        let entries = vec![
+            entry(1, Some("20240105")),
+            entry(2, Some("20240120")),
+            entry(3, None),
+            entry(5, Some("20240301")),
+            entry(6, Some("20231231")),
+        ];
+        let selection = PlaylistSelection {
+            items: Some(items("2-6")),
+            date_after: Some(NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()),
+            date_before: Some(NaiveDate::from_ymd_opt(2024, 3, 1).unwrap()),
+            reverse: true,
+        };
+
+        // 投稿日のわからない項目（3番）は日付では除外しない
+        assert_eq!(indexes(&selection.select(&entries)), vec![5, 3, 2]);
+        assert_eq!(indexes(&PlaylistSelection::default().select(&entries)), vec![1, 2, 3, 5, 6]);
+    }
+
+    #[test]
+    fn entry_date_falls_back_to_timestamp() {
+        let mut entry = entry(1, None);
+        entry.timestamp = Some(1_706_659_200); // 2024-01-31T00:00:00Z
+        assert_eq!(entry.date(), NaiveDate::from_ymd_opt(2024, 1, 31));
+
+        entry.upload_date = Some("20240201".to_string());
+        assert_eq!(entry.date(), NaiveDate::from_ymd_opt(2024, 2, 1));
+    }
+
+    #[test]
+    fn parses_absolute_and_relative_dates() {
+        let expected = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+        assert_eq!(parse_date("20240131"), Ok(expected));
+        assert_eq!(parse_date(" 2024-01-31 "), Ok(expected));
+
+        let today = Local::now().date_naive();
+        assert_eq!(parse_date("today"), Ok(today));
+        assert_eq!(parse_date("now-2weeks"), Ok(today - chrono::Duration::days(14)));
+        assert_eq!(parse_date("today+1day"), Ok(today + chrono::Duration::days(1)));
+        assert_eq!(parse_date("today-1year"), Ok(today.checked_sub_months(Months::new(12)).unwrap()));
+        assert!(parse_date("yesterday").is_err());
+        assert!(parse_date("today-2fortnights").is_err());
+        assert!(parse_date("2024-02-30").is_err());
+    }
+
+    #[test]
+    fn recognizes_single_videos_and_playlists() {
+        assert!(!may_be_playlist("https://example.com/video.mp4"));
+        assert!(!may_be_playlist("https://example.com/live/master.m3u8"));
+        assert!(!may_be_playlist("https://www.youtube.com/watch?v=dQw4w9WgXcQ"));
+        assert!(!may_be_playlist("https://youtu.be/dQw4w9WgXcQ"));
+        assert!(may_be_playlist("https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PL123"));
+        assert!(may_be_playlist("https://www.youtube.com/@channel/videos"));
+        assert!(may_be_playlist("https://vimeo.com/showcase/123"));
+    }
+}
@@ -0,0 +1,739 @@
+use std::fs::{File, TryLockError};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+use crate::types::{DownloadOptions, DownloadError, ErrorCode};
+use crate::retry::Decision;
+use crate::playlist::PlaylistSource;
+
+/// キュー内タスクの状態
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TaskStatus {
+    /// 実行待ち
+    Pending,
+    /// 実行中
+    Running,
+    /// 一時停止中（途中までのデータを保持）
+    Paused,
+    /// 完了
+    Completed,
+    /// 失敗
+    Failed,
+    /// キャンセル済み
+    Cancelled,
+    /// ダウンロードアーカイブに記録済みのため実行しなかった
+    Skipped,
+}
+
+impl TaskStatus {
+    /// 終了状態（これ以上実行されない状態）かどうか
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Self::Completed | Self::Failed | Self::Cancelled | Self::Skipped)
+    }
+}
+
+/// キューに登録されたダウンロードタスク
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DownloadTask {
+    /// タスクID
+    pub id: String,
+    /// ダウンロードするURL
+    pub url: String,
+    /// 出力ディレクトリ
+    pub output_path: PathBuf,
+    /// 出力ファイル名のテンプレート（[`FilenameTemplate`](crate::filename::FilenameTemplate)）
+    pub filename: String,
+    /// ダウンロードオプション
+    pub options: Option<DownloadOptions>,
+    /// 優先度（大きいほど先に実行）
+    pub priority: i32,
+    /// 速度制限（バイト/秒、Noneで無制限、実行中も変更できる）
+    #[serde(default)]
+    pub speed_limit: Option<u64>,
+    /// プレイリストから展開したタスクの場合は元の項目
+    #[serde(default)]
+    pub playlist: Option<PlaylistSource>,
+    /// ダウンロードアーカイブのキー（プレイリストの展開時などに動画IDがわかった場合）
+    #[serde(default)]
+    pub archive_key: Option<String>,
+    /// 状態
+    pub status: TaskStatus,
+    /// 実行中のプロセスのID（他のプロセスが実行中のタスクを横取りしないために記録する）
+    #[serde(default)]
+    pub owner_pid: Option<u32>,
+    /// テンプレートを展開し衝突を解決した出力ファイルのパス（拡張子なし。実行時に決まる）
+    #[serde(default)]
+    pub output_stem: Option<PathBuf>,
+    /// 完了時の出力ファイル
+    pub output_file: Option<PathBuf>,
+    /// 失敗時のエラーメッセージ
+    pub error: Option<String>,
+    /// 失敗時のエラーの種類
+    #[serde(default)]
+    pub error_code: Option<ErrorCode>,
+    /// 直近の実行でのダウンロード方法の選択と再試行の経過
+    #[serde(default)]
+    pub decisions: Vec<Decision>,
+    /// 登録日時（UNIX秒）
+    pub created_at: u64,
+}
+
+impl DownloadTask {
+    /// 新しい待機中タスクを作成
+    pub fn new(
+        url: &str,
+        output_path: &Path,
+        filename: &str,
+        options: Option<DownloadOptions>,
+        priority: i32
+    ) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let speed_limit = options.as_ref().and_then(|options| options.speed_limit);
+
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            url: url.to_string(),
+            output_path: output_path.to_path_buf(),
+            filename: filename.to_string(),
+            options,
+            priority,
+            speed_limit,
+            playlist: None,
+            archive_key: None,
+            status: TaskStatus::Pending,
+            owner_pid: None,
+            output_stem: None,
+            output_file: None,
+            error: None,
+            error_code: None,
+            decisions: Vec::new(),
+            created_at,
+        }
+    }
+
+    /// 中断時に残った途中データ（aria2cの`.aria2`制御ファイルやyt-dlpの`.part`ファイル）
+    ///
+    /// 一度も実行していないタスク（出力ファイル名が決まっていない）は `filename` をそのまま
+    /// ファイル名とみなす。
+    pub fn partial_files(&self) -> Vec<PathBuf> {
+        let (dir, stem) = match &self.output_stem {
+            Some(stem) => (
+                stem.parent().unwrap_or(&self.output_path).to_path_buf(),
+                stem.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            ),
+            None => (self.output_path.clone(), self.filename.clone()),
+        };
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            return Vec::new();
+        };
+
+        entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                name.strip_prefix(&stem).is_some_and(|rest| rest.starts_with('.'))
+                    && (name.ends_with(".aria2") || name.ends_with(".part") || name.ends_with(".ytdl"))
+            })
+            .collect()
+    }
+}
+
+/// キューの設定
+#[derive(Debug, Clone)]
+pub struct QueueConfig {
+    /// 同時実行数の上限
+    pub max_concurrent: usize,
+    /// 状態ファイルのパス（Noneの場合は永続化しない）
+    pub state_path: Option<PathBuf>,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent: 3,
+            state_path: None,
+        }
+    }
+}
+
+impl QueueConfig {
+    /// デフォルトの状態ファイルを使う永続化キューの設定
+    pub fn persistent() -> Self {
+        Self {
+            state_path: Self::default_state_path(),
+            ..Default::default()
+        }
+    }
+
+    /// デフォルトの状態ファイルのパス（OSのデータディレクトリ配下）
+    pub fn default_state_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("NextDownloader").join("queue.json"))
+    }
+}
+
+/// ダウンロードキュー
+///
+/// タスクは登録順に保持し、状態ファイルが設定されている場合は
+/// JSONとして保存する。複数プロセス（CLIとGUIなど）から同じ状態ファイルを
+/// 扱えるよう、変更時は `lock_state` でロックしてから `reload` でディスク上の内容を取り込み、
+/// ロックを保持したまま `save` する。
+#[derive(Debug, Default)]
+pub struct DownloadQueue {
+    tasks: Vec<DownloadTask>,
+    state_path: Option<PathBuf>,
+}
+
+impl DownloadQueue {
+    /// 状態ファイルからキューを読み込む
+    ///
+    /// 前回の異常終了などで実行中のまま残っているタスク（実行していたプロセスが
+    /// 終了しているもの）は待機中に戻す。他のプロセスが実行中のタスクはそのまま残す。
+    pub fn load(state_path: Option<PathBuf>) -> Result<Self, DownloadError> {
+        let mut queue = Self {
+            tasks: Vec::new(),
+            state_path,
+        };
+
+        // 状態ファイルがなければ戻すタスクもないため、ディレクトリを作らずに済ませる
+        if !queue.state_path.as_ref().is_some_and(|path| path.exists()) {
+            return Ok(queue);
+        }
+
+        let _state = queue.lock_state_blocking()?;
+        queue.tasks = queue.read_state()?;
+        let mut recovered = false;
+        for task in queue.tasks.iter_mut() {
+            if task.status == TaskStatus::Running && !task.owner_pid.is_some_and(process_exists) {
+                task.status = TaskStatus::Pending;
+                task.owner_pid = None;
+                recovered = true;
+            }
+        }
+        if recovered {
+            queue.save()?;
+        }
+
+        Ok(queue)
+    }
+
+    /// すべてのタスク（登録順）
+    pub fn tasks(&self) -> &[DownloadTask] {
+        &self.tasks
+    }
+
+    /// タスクを取得
+    pub fn get(&self, task_id: &str) -> Option<&DownloadTask> {
+        self.tasks.iter().find(|task| task.id == task_id)
+    }
+
+    /// タスクを可変で取得
+    pub fn get_mut(&mut self, task_id: &str) -> Option<&mut DownloadTask> {
+        self.tasks.iter_mut().find(|task| task.id == task_id)
+    }
+
+    /// タスクを追加
+    pub fn push(&mut self, task: DownloadTask) {
+        self.tasks.push(task);
+    }
+
+    /// 待機中のタスクがあるか
+    pub fn has_pending(&self) -> bool {
+        self.tasks.iter().any(|task| task.status == TaskStatus::Pending)
+    }
+
+    /// 次に実行する待機中タスク（優先度が高く、登録が古いもの）
+    pub fn next_pending(&mut self) -> Option<&mut DownloadTask> {
+        let mut next: Option<usize> = None;
+        for (index, task) in self.tasks.iter().enumerate() {
+            if task.status != TaskStatus::Pending {
+                continue;
+            }
+            match next {
+                Some(current) if self.tasks[current].priority >= task.priority => {}
+                _ => next = Some(index),
+            }
+        }
+
+        next.map(move |index| &mut self.tasks[index])
+    }
+
+    /// ディスク上の状態を取り込む
+    ///
+    /// `is_local` が真を返すタスク（このプロセスで実行中のもの）はメモリ上の
+    /// 状態を優先し、それ以外はディスクの内容で置き換える。ディスクから削除された
+    /// タスクは、このプロセスで実行中のものを除いて取り除く。ディスク上で
+    /// キャンセルまたは一時停止されたローカル実行中タスクのIDと、その状態を返す。
+    pub fn reload(&mut self, is_local: impl Fn(&str) -> bool) -> Result<Vec<(String, TaskStatus)>, DownloadError> {
+        let mut stopped = Vec::new();
+        if self.state_path.is_none() {
+            return Ok(stopped);
+        }
+
+        let stored_tasks = self.read_state()?;
+        self.tasks.retain(|task| is_local(&task.id) || stored_tasks.iter().any(|stored| stored.id == task.id));
+        for stored in stored_tasks {
+            match self.tasks.iter_mut().find(|task| task.id == stored.id) {
+                None => self.tasks.push(stored),
+                Some(task) if is_local(&task.id) => {
+                    if matches!(stored.status, TaskStatus::Cancelled | TaskStatus::Paused) {
+                        stopped.push((task.id.clone(), stored.status));
+                    }
+                }
+                Some(task) => *task = stored,
+            }
+        }
+
+        Ok(stopped)
+    }
+
+    /// 状態ファイルを排他的にロックする（破棄すると解放する）
+    ///
+    /// `reload` から `save` までの間保持し、他のプロセスの変更と混ざらないようにする。
+    /// 状態ファイルは保存のたびに置き換わるため、隣のロックファイル（`queue.json.lock`）を
+    /// ロックする。永続化しないキューでは何もしない。他のプロセスが保持している間は
+    /// 非同期のワーカースレッドを止めないよう、ブロッキング用のスレッドで待つ。
+    pub async fn lock_state(&self) -> Result<QueueLock, DownloadError> {
+        let Some(file) = self.state_lock_file()? else {
+            return Ok(QueueLock { _file: None });
+        };
+        let file = tokio::task::spawn_blocking(move || file.lock().map(|()| file))
+            .await
+            .map_err(|err| DownloadError::Internal(format!("キューのロックを待てませんでした: {}", err)))??;
+        Ok(QueueLock { _file: Some(file) })
+    }
+
+    /// [`lock_state`](Self::lock_state) と同じロックを、現在のスレッドで待って取得する
+    pub fn lock_state_blocking(&self) -> Result<QueueLock, DownloadError> {
+        let Some(file) = self.state_lock_file()? else {
+            return Ok(QueueLock { _file: None });
+        };
+        file.lock()?;
+        Ok(QueueLock { _file: Some(file) })
+    }
+
+    /// 状態ファイルのロックファイルを開く（永続化しないキューでは `None`）
+    fn state_lock_file(&self) -> Result<Option<File>, DownloadError> {
+        self.open_lock_file(|path| path.with_extension("json.lock"))
+    }
+
+    /// タスクを実行するプロセスのロックを試みる（破棄すると解放する）
+    ///
+    /// 状態ファイルを共有するプロセス（CLI・GUI・ネイティブメッセージングホストのワーカー）の
+    /// うち、このロックを保持するものだけがタスクを実行する。他のプロセスが保持している
+    /// 場合は `None` を返す。永続化しないキューでは常に取得できる。
+    pub fn try_lock_runner(&self) -> Result<Option<QueueLock>, DownloadError> {
+        let Some(file) = self.open_lock_file(|path| path.with_file_name("worker.lock"))? else {
+            return Ok(Some(QueueLock { _file: None }));
+        };
+        match file.try_lock() {
+            Ok(()) => Ok(Some(QueueLock { _file: Some(file) })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(err)) => Err(err.into()),
+        }
+    }
+
+    /// 状態ファイルのパスから決まるロックファイルを開く（永続化しないキューでは `None`）
+    fn open_lock_file(&self, lock_path: impl FnOnce(&Path) -> PathBuf) -> Result<Option<File>, DownloadError> {
+        let Some(path) = &self.state_path else {
+            return Ok(None);
+        };
+        let lock_path = lock_path(path);
+
+        if let Some(parent) = lock_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(Some(File::create(lock_path)?))
+    }
+
+    /// 状態ファイルに保存
+    pub fn save(&self) -> Result<(), DownloadError> {
+        let Some(path) = &self.state_path else {
+            return Ok(());
+        };
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        // 書き込み途中で壊れないよう一時ファイル経由で置き換える
+        let json = serde_json::to_string_pretty(&self.tasks)?;
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, json)?;
+        std::fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    /// 状態ファイルを読み込む（存在しない場合は空）
+    fn read_state(&self) -> Result<Vec<DownloadTask>, DownloadError> {
+        let Some(path) = &self.state_path else {
+            return Ok(Vec::new());
+        };
+
+        match std::fs::read_to_string(path) {
+            Ok(json) => Ok(serde_json::from_str(&json)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// 状態ファイルのロック
+///
+/// ファイルのロックはプロセスが終了した場合もOSが解放する。
+#[derive(Debug)]
+pub struct QueueLock {
+    _file: Option<File>,
+}
+
+/// プロセスが存在するか
+#[cfg(unix)]
+fn process_exists(pid: u32) -> bool {
+    if pid == std::process::id() {
+        return true;
+    }
+    // シグナル0は送信せずに宛先の存在だけを確かめる（権限がない場合も存在はしている）
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// プロセスが存在するか（確かめられない場合は存在するものとして扱う）
+#[cfg(windows)]
+fn process_exists(pid: u32) -> bool {
+    if pid == std::process::id() {
+        return true;
+    }
+    std::process::Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/FO", "CSV", "/NH"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).contains(&format!("\"{}\"", pid)))
+        .unwrap_or(true)
+}
+
+/// プロセスが存在するか（確かめられないため存在するものとして扱う）
+#[cfg(not(any(unix, windows)))]
+fn process_exists(_pid: u32) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::time::Duration;
+
+    fn touch(path: &Path) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, b"").unwrap();
+    }
+
+    #[test]
+    fn partial_files_match_the_resolved_output_name() {
+        let dir = TempDir::new();
+        let channel = dir.path().join("channel");
+        touch(&channel.join("Title [abc].mp4.aria2"));
+        touch(&channel.join("Title [abc].f137.mp4.part"));
+        touch(&channel.join("Title [abc] 2.mp4.part"));
+        touch(&channel.join("Title [abc].mp4"));
+        touch(&dir.path().join("%(title)s.mp4.part"));
+
+        let mut task = DownloadTask::new("https://example.com/watch", dir.path(), "%(uploader)s/%(title)s [%(id)s]", None, 0);
+        assert!(task.partial_files().is_empty());
+
+        task.output_stem = Some(channel.join("Title [abc]"));
+        let mut files = task.partial_files();
+        files.sort();
+        assert_eq!(files, vec![
+            channel.join("Title [abc].f137.mp4.part"),
+            channel.join("Title [abc].mp4.aria2"),
+        ]);
+    }
+
+    #[test]
+    fn partial_files_fall_back_to_the_filename_without_a_resolved_name() {
+        let dir = TempDir::new();
+        touch(&dir.path().join("video.mp4.ytdl"));
+        touch(&dir.path().join("video2.mp4.part"));
+
+        let task = DownloadTask::new("https://example.com/video.mp4", dir.path(), "video", None, 0);
+        assert_eq!(task.partial_files(), vec![dir.path().join("video.mp4.ytdl")]);
+    }
+
+    #[test]
+    fn partial_files_are_empty_without_an_output_directory() {
+        let dir = TempDir::new();
+        let task = DownloadTask::new("https://example.com/video.mp4", &dir.path().join("missing"), "video", None, 0);
+
+        assert!(task.partial_files().is_empty());
+    }
+
+    #[test]
+    fn reload_reports_local_tasks_paused_or_cancelled_elsewhere() {
+        let dir = TempDir::new();
+        let state_path = Some(dir.path().join("queue.json"));
+        let mut local = DownloadQueue::load(state_path.clone()).unwrap();
+        for name in ["paused", "cancelled", "remote"] {
+            let mut task = DownloadTask::new(&format!("https://example.com/{}", name), dir.path(), name, None, 0);
+            task.status = TaskStatus::Running;
+            local.push(task);
+        }
+        local.save().unwrap();
+        let ids: Vec<String> = local.tasks().iter().map(|task| task.id.clone()).collect();
+
+        // 別のプロセスが一時停止・キャンセルする
+        let mut other = DownloadQueue::load(state_path).unwrap();
+        other.get_mut(&ids[0]).unwrap().status = TaskStatus::Paused;
+        other.get_mut(&ids[1]).unwrap().status = TaskStatus::Cancelled;
+        other.get_mut(&ids[2]).unwrap().status = TaskStatus::Paused;
+        other.save().unwrap();
+
+        let stopped = local.reload(|id| id != ids[2]).unwrap();
+
+        assert_eq!(stopped, vec![(ids[0].clone(), TaskStatus::Paused), (ids[1].clone(), TaskStatus::Cancelled)]);
+        // 実行中のタスクは停止するまでメモリ上の状態を保ち、それ以外はディスクの内容になる
+        assert_eq!(local.get(&ids[0]).unwrap().status, TaskStatus::Running);
+        assert_eq!(local.get(&ids[1]).unwrap().status, TaskStatus::Running);
+        assert_eq!(local.get(&ids[2]).unwrap().status, TaskStatus::Paused);
+    }
+
+    fn task(url: &str, priority: i32, status: TaskStatus) -> DownloadTask {
+        let mut task = DownloadTask::new(url, Path::new("/tmp"), "video", None, priority);
+        task.status = status;
+        task
+    }
+
+    fn statuses(queue: &DownloadQueue) -> Vec<(&str, TaskStatus, Option<u32>)> {
+        queue.tasks().iter().map(|task| (task.url.as_str(), task.status, task.owner_pid)).collect()
+    }
+
+    /// 終了済みのプロセスのID
+    #[cfg(unix)]
+    fn exited_pid() -> u32 {
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        pid
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn load_recovers_only_tasks_whose_owner_has_exited() {
+        let dir = TempDir::new();
+        let state_path = dir.path().join("queue.json");
+        let mut unowned = task("https://example.com/unowned", 0, TaskStatus::Running);
+        unowned.owner_pid = None;
+        let mut orphaned = task("https://example.com/orphaned", 0, TaskStatus::Running);
+        orphaned.owner_pid = Some(exited_pid());
+        let mut running = task("https://example.com/running", 0, TaskStatus::Running);
+        running.owner_pid = Some(std::process::id());
+        let done = task("https://example.com/done", 0, TaskStatus::Completed);
+        let stored = DownloadQueue {
+            tasks: vec![unowned, orphaned, running, done],
+            state_path: Some(state_path.clone()),
+        };
+        stored.save().unwrap();
+
+        let queue = DownloadQueue::load(Some(state_path.clone())).unwrap();
+        let expected = vec![
+            ("https://example.com/unowned", TaskStatus::Pending, None),
+            ("https://example.com/orphaned", TaskStatus::Pending, None),
+            ("https://example.com/running", TaskStatus::Running, Some(std::process::id())),
+            ("https://example.com/done", TaskStatus::Completed, None),
+        ];
+        assert_eq!(statuses(&queue), expected);
+        // 戻したタスクは保存され、他のプロセスからも待機中に見える
+        let reloaded = DownloadQueue::load(Some(state_path)).unwrap();
+        assert_eq!(statuses(&reloaded), expected);
+    }
+
+    #[test]
+    fn load_leaves_the_state_file_untouched_when_nothing_is_recovered() {
+        let dir = TempDir::new();
+        let state_path = dir.path().join("queue.json");
+        let mut running = task("https://example.com/running", 0, TaskStatus::Running);
+        running.owner_pid = Some(std::process::id());
+        let json = serde_json::to_string(&vec![running, task("https://example.com/next", 0, TaskStatus::Pending)]).unwrap();
+        std::fs::write(&state_path, &json).unwrap();
+
+        let queue = DownloadQueue::load(Some(state_path.clone())).unwrap();
+
+        assert_eq!(queue.tasks()[0].status, TaskStatus::Running);
+        assert_eq!(std::fs::read_to_string(&state_path).unwrap(), json);
+    }
+
+    #[test]
+    fn load_without_state_file_starts_empty() {
+        let dir = TempDir::new();
+        let queue = DownloadQueue::load(Some(dir.path().join("missing").join("queue.json"))).unwrap();
+        assert!(queue.tasks().is_empty());
+        assert!(!dir.path().join("missing").exists());
+
+        std::fs::write(dir.path().join("broken.json"), "{").unwrap();
+        assert!(DownloadQueue::load(Some(dir.path().join("broken.json"))).is_err());
+    }
+
+    #[test]
+    fn save_replaces_the_state_file_atomically() {
+        let dir = TempDir::new();
+        let state_path = dir.path().join("data").join("queue.json");
+        let mut queue = DownloadQueue::load(Some(state_path.clone())).unwrap();
+        let mut paused = task("https://example.com/a", 5, TaskStatus::Paused);
+        paused.speed_limit = Some(1024);
+        queue.push(paused);
+        queue.save().unwrap();
+
+        assert!(!state_path.with_extension("json.tmp").exists());
+        let loaded = DownloadQueue::load(Some(state_path)).unwrap();
+        assert_eq!(loaded.tasks().len(), 1);
+        assert_eq!(loaded.tasks()[0].id, queue.tasks()[0].id);
+        assert_eq!(loaded.tasks()[0].status, TaskStatus::Paused);
+        assert_eq!(loaded.tasks()[0].priority, 5);
+        assert_eq!(loaded.tasks()[0].speed_limit, Some(1024));
+    }
+
+    #[test]
+    fn next_pending_prefers_priority_then_registration_order() {
+        let mut queue = DownloadQueue::default();
+        queue.push(task("https://example.com/running", 10, TaskStatus::Running));
+        queue.push(task("https://example.com/first", 1, TaskStatus::Pending));
+        queue.push(task("https://example.com/paused", 9, TaskStatus::Paused));
+        queue.push(task("https://example.com/urgent", 5, TaskStatus::Pending));
+        queue.push(task("https://example.com/urgent-later", 5, TaskStatus::Pending));
+
+        let mut order = Vec::new();
+        while let Some(next) = queue.next_pending() {
+            order.push(next.url.clone());
+            next.status = TaskStatus::Running;
+        }
+
+        assert_eq!(order, vec![
+            "https://example.com/urgent",
+            "https://example.com/urgent-later",
+            "https://example.com/first",
+        ]);
+        assert!(!queue.has_pending());
+    }
+
+    #[test]
+    fn reload_keeps_local_tasks_and_reports_stops_from_other_processes() {
+        let dir = TempDir::new();
+        let state_path = Some(dir.path().join("queue.json"));
+        let mut local = DownloadQueue::load(state_path.clone()).unwrap();
+        local.push(task("https://example.com/local", 0, TaskStatus::Running));
+        local.push(task("https://example.com/remote", 0, TaskStatus::Pending));
+        local.save().unwrap();
+        let local_id = local.tasks()[0].id.clone();
+        let remote_id = local.tasks()[1].id.clone();
+
+        // 別のプロセスが一時停止・実行・追加する
+        let mut other = DownloadQueue::load(state_path).unwrap();
+        other.get_mut(&local_id).unwrap().status = TaskStatus::Paused;
+        other.get_mut(&remote_id).unwrap().status = TaskStatus::Running;
+        other.push(task("https://example.com/added", 0, TaskStatus::Pending));
+        other.save().unwrap();
+
+        let stopped = local.reload(|id| id == local_id).unwrap();
+
+        assert_eq!(stopped, vec![(local_id, TaskStatus::Paused)]);
+        assert_eq!(
+            statuses(&local).into_iter().map(|(url, status, _)| (url, status)).collect::<Vec<_>>(),
+            vec![
+                // 実行中のタスクは停止するまでメモリ上の状態を保つ
+                ("https://example.com/local", TaskStatus::Running),
+                ("https://example.com/remote", TaskStatus::Running),
+                ("https://example.com/added", TaskStatus::Pending),
+            ]
+        );
+    }
+
+    #[test]
+    fn reload_drops_tasks_removed_elsewhere_unless_running_locally() {
+        let dir = TempDir::new();
+        let state_path = Some(dir.path().join("queue.json"));
+        let mut local = DownloadQueue::load(state_path.clone()).unwrap();
+        local.push(task("https://example.com/local", 0, TaskStatus::Running));
+        local.push(task("https://example.com/removed", 0, TaskStatus::Pending));
+        local.save().unwrap();
+        let local_id = local.tasks()[0].id.clone();
+
+        // 別のプロセスがすべてのタスクを削除する
+        let mut other = DownloadQueue::load(state_path).unwrap();
+        other.tasks.clear();
+        other.save().unwrap();
+
+        local.reload(|id| id == local_id).unwrap();
+
+        assert_eq!(statuses(&local), vec![("https://example.com/local", TaskStatus::Running, None)]);
+    }
+
+    #[test]
+    fn updates_under_the_state_lock_are_not_lost() {
+        let dir = TempDir::new();
+        let state_path = dir.path().join("queue.json");
+
+        let workers: Vec<_> = (0..4)
+            .map(|worker| {
+                let state_path = state_path.clone();
+                std::thread::spawn(move || {
+                    let mut queue = DownloadQueue::load(Some(state_path)).unwrap();
+                    for i in 0..10 {
+                        let _state = queue.lock_state_blocking().unwrap();
+                        queue.reload(|_| false).unwrap();
+                        queue.push(task(&format!("https://example.com/{}/{}", worker, i), 0, TaskStatus::Pending));
+                        queue.save().unwrap();
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let queue = DownloadQueue::load(Some(state_path)).unwrap();
+        assert_eq!(queue.tasks().len(), 40);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn waiting_for_the_state_lock_does_not_block_the_runtime() {
+        let dir = TempDir::new();
+        let state_path = Some(dir.path().join("queue.json"));
+        let holder = DownloadQueue::load(state_path.clone()).unwrap();
+        let waiter = DownloadQueue::load(state_path).unwrap();
+
+        let lock = holder.lock_state_blocking().unwrap();
+        let waiting = tokio::spawn(async move { waiter.lock_state().await.map(|_| ()) });
+        // 他のプロセスがロックを保持していても、同じスレッドの他の処理は進む
+        tokio::time::timeout(Duration::from_secs(5), tokio::time::sleep(Duration::from_millis(50)))
+            .await
+            .unwrap();
+        assert!(!waiting.is_finished());
+
+        drop(lock);
+        tokio::time::timeout(Duration::from_secs(5), waiting).await.unwrap().unwrap().unwrap();
+    }
+
+    #[test]
+    fn runner_lock_is_held_by_one_queue_at_a_time() {
+        let dir = TempDir::new();
+        let state_path = Some(dir.path().join("data").join("queue.json"));
+        let first = DownloadQueue::load(state_path.clone()).unwrap();
+        let second = DownloadQueue::load(state_path).unwrap();
+
+        let lock = first.try_lock_runner().unwrap();
+        assert!(lock.is_some());
+        assert!(second.try_lock_runner().unwrap().is_none());
+
+        drop(lock);
+        assert!(second.try_lock_runner().unwrap().is_some());
+        // 永続化しないキューは他と共有されないため常に取得できる
+        assert!(DownloadQueue::default().try_lock_runner().unwrap().is_some());
+    }
+}
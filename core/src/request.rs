@@ -0,0 +1,335 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Serialize, Deserialize};
+use url::Url;
+use crate::types::DownloadError;
+
+/// 認証が必要なダウンロードのためのリクエスト情報
+///
+/// ブラウザ拡張などから受け取ったヘッダーやCookieを、外部ツールと
+/// 内蔵のダウンローダーの両方に引き継ぐ。
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RequestContext {
+    /// 追加のHTTPヘッダー（名前, 値）
+    pub headers: Vec<(String, String)>,
+    /// Cookie（名前, 値）。ダウンロードするURLのホストに送る
+    pub cookies: Vec<(String, String)>,
+    /// Netscape形式のCookieファイル
+    pub cookie_file: Option<PathBuf>,
+    /// User-Agent
+    pub user_agent: Option<String>,
+    /// リファラー
+    pub referer: Option<String>,
+}
+
+impl RequestContext {
+    /// 何も指定されていないか
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// User-AgentとRefererを除いた追加ヘッダー（Cookieの組を含む）
+    ///
+    /// Cookieファイルは外部ツールにそのまま渡すため含めない。
+    pub fn extra_headers(&self) -> Vec<(String, String)> {
+        let mut headers = self.headers.clone();
+        if !self.cookies.is_empty() {
+            headers.push(("Cookie".to_string(), cookie_pairs(&self.cookies)));
+        }
+        headers
+    }
+
+    /// 指定したURLに送るCookieヘッダーの値（Cookieファイルの内容を含む）
+    pub fn cookie_header(&self, url: &Url) -> Result<Option<String>, DownloadError> {
+        let mut cookies = self.cookies.clone();
+        if let Some(path) = &self.cookie_file {
+            cookies.extend(
+                load_netscape_cookies(path)?
+                    .into_iter()
+                    .filter(|cookie| cookie.matches(url))
+                    .map(|cookie| (cookie.name, cookie.value))
+            );
+        }
+        Ok((!cookies.is_empty()).then(|| cookie_pairs(&cookies)))
+    }
+
+    /// この情報を送るHTTPクライアントを作成
+    ///
+    /// Cookieはジャーに登録するため、リダイレクト先やセグメントの取得でも
+    /// ドメインが一致する場合に送られる。
+    pub fn http_client(&self, url: &Url) -> Result<reqwest::Client, DownloadError> {
+        let jar = reqwest::cookie::Jar::default();
+        for (name, value) in &self.cookies {
+            jar.add_cookie_str(&format!("{}={}", name, value), url);
+        }
+        if let Some(path) = &self.cookie_file {
+            for cookie in load_netscape_cookies(path)? {
+                if let Some(origin) = cookie.origin() {
+                    jar.add_cookie_str(&cookie.to_set_cookie(), &origin);
+                }
+            }
+        }
+
+        let mut headers = HeaderMap::new();
+        let referer = self.referer.iter().map(|referer| ("Referer", referer));
+        for (name, value) in self.headers.iter().map(|(name, value)| (name.as_str(), value)).chain(referer) {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| DownloadError::Internal(format!("不正なヘッダー名です: {}", name)))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|_| DownloadError::Internal(format!("ヘッダー {} の値が不正です", name)))?;
+            headers.insert(name, value);
+        }
+
+        let mut builder = reqwest::Client::builder()
+            .cookie_provider(Arc::new(jar))
+            .default_headers(headers);
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// Netscape形式のCookieファイルの1行
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetscapeCookie {
+    /// ドメイン
+    pub domain: String,
+    /// サブドメインにも送るか
+    pub include_subdomains: bool,
+    /// パス
+    pub path: String,
+    /// HTTPSのみで送るか
+    pub secure: bool,
+    /// 有効期限（UNIX時間、0はセッションCookie）
+    pub expires: u64,
+    /// 名前
+    pub name: String,
+    /// 値
+    pub value: String,
+}
+
+impl NetscapeCookie {
+    /// 期限切れか
+    pub fn is_expired(&self) -> bool {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs()).unwrap_or(0);
+        self.expires != 0 && self.expires < now
+    }
+
+    /// 指定したURLに送るCookieか
+    pub fn matches(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let domain = self.domain.trim_start_matches('.');
+        let domain_matches = host == domain
+            || (self.include_subdomains && host.ends_with(&format!(".{}", domain)));
+
+        domain_matches
+            && url.path().starts_with(&self.path)
+            && (!self.secure || url.scheme() == "https")
+            && !self.is_expired()
+    }
+
+    /// Cookieジャーに登録するためのURL
+    fn origin(&self) -> Option<Url> {
+        if self.is_expired() {
+            return None;
+        }
+        let scheme = if self.secure { "https" } else { "http" };
+        Url::parse(&format!("{}://{}{}", scheme, self.domain.trim_start_matches('.'), self.path)).ok()
+    }
+
+    /// `Set-Cookie` 形式の文字列
+    fn to_set_cookie(&self) -> String {
+        let mut cookie = format!("{}={}; Path={}", self.name, self.value, self.path);
+        if self.include_subdomains {
+            cookie.push_str(&format!("; Domain={}", self.domain.trim_start_matches('.')));
+        }
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+        cookie
+    }
+}
+
+/// Netscape形式（curlやyt-dlpと同じ）のCookieファイルを読み込む
+pub fn load_netscape_cookies(path: &Path) -> Result<Vec<NetscapeCookie>, DownloadError> {
+    let text = std::fs::read_to_string(path)?;
+    Ok(parse_netscape_cookies(&text))
+}
+
+/// Netscape形式のCookieを解析（不正な行は読み飛ばす）
+pub fn parse_netscape_cookies(text: &str) -> Vec<NetscapeCookie> {
+    text.lines()
+        .filter_map(|line| {
+            // HttpOnlyのCookieはコメントに見える接頭辞付きで書き出される
+            let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+            if line.trim().is_empty() || line.starts_with('#') {
+                return None;
+            }
+
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [domain, include_subdomains, path, secure, expires, name, value] = fields.as_slice() else {
+                return None;
+            };
+            Some(NetscapeCookie {
+                domain: domain.to_string(),
+                include_subdomains: include_subdomains.eq_ignore_ascii_case("TRUE"),
+                path: path.to_string(),
+                secure: secure.eq_ignore_ascii_case("TRUE"),
+                expires: expires.parse().ok()?,
+                name: name.to_string(),
+                value: value.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// `Name: Value` 形式のヘッダー指定を解析
+pub fn parse_header(value: &str) -> Result<(String, String), String> {
+    let (name, value) = value
+        .split_once(':')
+        .ok_or_else(|| format!("ヘッダーは「名前: 値」の形式で指定してください: {}", value))?;
+    Ok((name.trim().to_string(), value.trim().to_string()))
+}
+
+/// `name=value` 形式のCookie指定を解析
+pub fn parse_cookie(value: &str) -> Result<(String, String), String> {
+    let (name, value) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Cookieは「名前=値」の形式で指定してください: {}", value))?;
+    Ok((name.trim().to_string(), value.trim().to_string()))
+}
+
+fn cookie_pairs(cookies: &[(String, String)]) -> String {
+    cookies
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    /// 2100年（期限内）
+    const FUTURE: u64 = 4_102_444_800;
+
+    fn cookie(domain: &str, include_subdomains: bool, path: &str, secure: bool, expires: u64) -> NetscapeCookie {
+        NetscapeCookie {
+            domain: domain.to_string(),
+            include_subdomains,
+            path: path.to_string(),
+            secure,
+            expires,
+            name: "session".to_string(),
+            value: "abc".to_string(),
+        }
+    }
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn parses_cookies_and_skips_comments_and_blank_lines() {
+        let text = "# Netscape HTTP Cookie File\n\
+                    # https://curl.se/docs/http-cookies.html\n\
+                    \n\
+                    .example.com\tTRUE\t/\tFALSE\t4102444800\tsession\tabc\n\
+                    #HttpOnly_www.example.com\tFALSE\t/videos\tTRUE\t0\ttoken\tx=y\r\n";
+
+        assert_eq!(parse_netscape_cookies(text), vec![
+            cookie(".example.com", true, "/", false, FUTURE),
+            NetscapeCookie {
+                name: "token".to_string(),
+                value: "x=y".to_string(),
+                ..cookie("www.example.com", false, "/videos", true, 0)
+            },
+        ]);
+    }
+
+    #[test]
+    fn skips_malformed_lines() {
+        let text = "example.com\tFALSE\t/\tFALSE\t0\tmissing-value\n\
+                    example.com\tFALSE\t/\tFALSE\t0\ttoo\tmany\tfields\n\
+                    example.com\tFALSE\t/\tFALSE\tsoon\tname\tvalue\n\
+                    example.com FALSE / FALSE 0 spaces value\n\
+                    example.com\tFALSE\t/\tFALSE\t0\tvalid\tvalue\n";
+
+        let cookies = parse_netscape_cookies(text);
+
+        assert_eq!(cookies.len(), 1);
+        assert_eq!(cookies[0].name, "valid");
+    }
+
+    #[test]
+    fn matches_domain_and_subdomains() {
+        let exact = cookie("example.com", false, "/", false, 0);
+        assert!(exact.matches(&url("http://example.com/video")));
+        assert!(!exact.matches(&url("http://www.example.com/video")));
+        assert!(!exact.matches(&url("http://notexample.com/video")));
+
+        let subdomains = cookie(".example.com", true, "/", false, 0);
+        assert!(subdomains.matches(&url("http://example.com/video")));
+        assert!(subdomains.matches(&url("http://cdn.www.example.com/video")));
+        assert!(!subdomains.matches(&url("http://notexample.com/video")));
+    }
+
+    #[test]
+    fn matches_path_and_secure_flag() {
+        let videos = cookie("example.com", false, "/videos", true, 0);
+        assert!(videos.matches(&url("https://example.com/videos/1.mp4")));
+        assert!(!videos.matches(&url("https://example.com/images/1.jpg")));
+        assert!(!videos.matches(&url("http://example.com/videos/1.mp4")));
+    }
+
+    #[test]
+    fn expired_cookies_are_not_sent() {
+        let expired = cookie("example.com", false, "/", false, 1);
+        assert!(expired.is_expired());
+        assert!(!expired.matches(&url("http://example.com/")));
+        assert!(expired.origin().is_none());
+
+        // 0はセッションCookieで期限切れにならない
+        assert!(!cookie("example.com", false, "/", false, 0).is_expired());
+        assert!(!cookie("example.com", false, "/", false, FUTURE).is_expired());
+    }
+
+    #[test]
+    fn cookie_header_combines_explicit_cookies_and_matching_file_entries() {
+        let dir = TempDir::new();
+        let path = dir.path().join("cookies.txt");
+        std::fs::write(
+            &path,
+            "# Netscape HTTP Cookie File\n\
+             .example.com\tTRUE\t/\tFALSE\t0\tfile\t1\n\
+             other.com\tFALSE\t/\tFALSE\t0\tother\t2\n\
+             example.com\tFALSE\t/\tFALSE\t1\texpired\t3\n",
+        )
+        .unwrap();
+        let request = RequestContext {
+            cookies: vec![("explicit".to_string(), "0".to_string())],
+            cookie_file: Some(path),
+            ..RequestContext::default()
+        };
+
+        assert_eq!(
+            request.cookie_header(&url("https://www.example.com/watch")).unwrap().as_deref(),
+            Some("explicit=0; file=1")
+        );
+        assert!(RequestContext::default().cookie_header(&url("https://example.com/")).unwrap().is_none());
+
+        let missing = RequestContext {
+            cookie_file: Some(dir.path().join("missing.txt")),
+            ..RequestContext::default()
+        };
+        assert!(missing.cookie_header(&url("https://example.com/")).is_err());
+    }
+}
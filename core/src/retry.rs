@@ -0,0 +1,333 @@
+//! 失敗時の再試行とダウンロード方法の切り替え
+//!
+//! 一時的なエラーは指数バックオフで同じ方法を再試行し、それでも失敗した場合は
+//! 優先度の順に次のダウンロード方法（バックエンド）に切り替える。
+//! 判断の経過は [`DecisionLog`] に記録し、タスクに残す。
+
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::types::{DownloadError, DownloadOptions, ErrorCode};
+
+/// 再試行の方針
+///
+/// 待機時間は `initial_wait` から再試行ごとに `multiplier` 倍になり、`max_wait` で頭打ちになる。
+/// 同時に再試行が集中しないよう、待機時間には `jitter` の割合でゆらぎを加える。
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// 一時的なエラーで再試行する最大回数（ダウンロード方法の内部での再試行）
+    pub max_retries: u32,
+    /// 同じダウンロード方法をやり直す最大回数（使い切ると次の方法に切り替える）
+    pub backend_retries: u32,
+    /// 最初の再試行までの待機時間
+    pub initial_wait: Duration,
+    /// 待機時間の上限
+    pub max_wait: Duration,
+    /// 再試行ごとに待機時間に掛ける倍率
+    pub multiplier: f64,
+    /// 待機時間のゆらぎの割合（0.0〜1.0）
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::from_options(&DownloadOptions::default())
+    }
+}
+
+impl RetryPolicy {
+    /// ダウンロードオプションの `max_retries` と `retry_wait` から作成
+    pub fn from_options(options: &DownloadOptions) -> Self {
+        Self {
+            max_retries: options.max_retries,
+            backend_retries: 1,
+            initial_wait: Duration::from_secs(options.retry_wait as u64),
+            max_wait: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+
+    /// 再試行しない方針
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            backend_retries: 0,
+            initial_wait: Duration::ZERO,
+            ..Self::default()
+        }
+    }
+
+    /// `attempt` 回目（1始まり）の再試行までの待機時間
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.delay_with(attempt, fastrand::f64())
+    }
+
+    /// 0以上1未満の乱数 `random` でゆらぎを決めた待機時間
+    ///
+    /// `random` が0.5のときゆらぎのない待機時間になる。
+    pub fn delay_with(&self, attempt: u32, random: f64) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let base = (self.initial_wait.as_secs_f64() * self.multiplier.max(1.0).powi(exponent))
+            .min(self.max_wait.as_secs_f64());
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = 1.0 + jitter * (2.0 * random.clamp(0.0, 1.0) - 1.0);
+
+        Duration::from_secs_f64((base * factor).clamp(0.0, self.max_wait.as_secs_f64()))
+    }
+
+    /// 処理を実行し、再試行可能なエラーの場合は待機時間を延ばしながら再試行する
+    ///
+    /// `operation` はエラーと再試行可能かどうかの組を返す。
+    pub(crate) async fn run<T, F, Fut>(&self, target: &str, mut operation: F) -> Result<T, DownloadError>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T, (DownloadError, bool)>>,
+    {
+        let mut attempt = 0;
+
+        loop {
+            match operation().await {
+                Ok(value) => return Ok(value),
+                Err((err, retryable)) if retryable && attempt < self.max_retries => {
+                    attempt += 1;
+                    let wait = self.delay(attempt);
+                    log::warn!(
+                        "{} の取得に失敗しました（{}回目の再試行、{:.1}秒後）: {}",
+                        target, attempt, wait.as_secs_f64(), err
+                    );
+                    tokio::time::sleep(wait).await;
+                }
+                Err((err, _)) => return Err(err),
+            }
+        }
+    }
+}
+
+/// 試行の結果として選んだ行動
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DecisionAction {
+    /// 利用できないため試さなかった
+    Skipped,
+    /// 一時的なエラーのため同じ方法で再試行する
+    Retry,
+    /// 次の方法に切り替える
+    Fallback,
+    /// 他の方法でも成功しないため中止する
+    Abort,
+    /// 成功した
+    Succeeded,
+}
+
+/// ダウンロード方法ごとの試行と、その結果の判断
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Decision {
+    /// ダウンロード方法（バックエンドの名前）
+    pub backend: String,
+    /// この方法での試行回数（試さなかった場合は0）
+    pub attempt: u32,
+    /// 判断
+    pub action: DecisionAction,
+    /// 理由（エラーメッセージなど）
+    pub reason: String,
+    /// 失敗した場合のエラーの種類
+    #[serde(default)]
+    pub error_code: Option<ErrorCode>,
+    /// 再試行までの待機時間（秒）
+    #[serde(default)]
+    pub wait_seconds: Option<f64>,
+}
+
+impl Decision {
+    /// 失敗した試行の判断を作成
+    pub fn failed(backend: &str, attempt: u32, action: DecisionAction, err: &DownloadError) -> Self {
+        Self {
+            backend: backend.to_string(),
+            attempt,
+            action,
+            reason: err.to_string(),
+            error_code: Some(ErrorCode::from(err)),
+            wait_seconds: None,
+        }
+    }
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.action {
+            DecisionAction::Skipped => write!(f, "{}: 使用できないため省略（{}）", self.backend, self.reason),
+            DecisionAction::Retry => write!(
+                f,
+                "{}: {}回目が失敗、{:.1}秒後に再試行（{}）",
+                self.backend,
+                self.attempt,
+                self.wait_seconds.unwrap_or_default(),
+                self.reason
+            ),
+            DecisionAction::Fallback => write!(
+                f,
+                "{}: {}回目が失敗、次の方法に切り替え（{}）",
+                self.backend, self.attempt, self.reason
+            ),
+            DecisionAction::Abort => write!(
+                f,
+                "{}: {}回目が失敗、他の方法でも解決できないため中止（{}）",
+                self.backend, self.attempt, self.reason
+            ),
+            DecisionAction::Succeeded => write!(f, "{}: {}回目で成功", self.backend, self.attempt),
+        }
+    }
+}
+
+/// 判断の記録
+///
+/// ダウンロードが途中で破棄された場合も、それまでの記録を参照できるよう
+/// 呼び出し側が所有する。
+#[derive(Debug, Default)]
+pub struct DecisionLog {
+    entries: Mutex<Vec<Decision>>,
+}
+
+impl DecisionLog {
+    /// 新しい空の記録を作成
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 判断を記録
+    pub fn record(&self, decision: Decision) {
+        match decision.action {
+            DecisionAction::Succeeded | DecisionAction::Skipped => log::info!("{}", decision),
+            _ => log::warn!("{}", decision),
+        }
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.push(decision);
+        }
+    }
+
+    /// 記録された判断の一覧
+    pub fn entries(&self) -> Vec<Decision> {
+        self.entries.lock().map(|entries| entries.clone()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn policy(max_retries: u32, jitter: f64) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            backend_retries: 1,
+            initial_wait: Duration::from_secs(1),
+            max_wait: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter,
+        }
+    }
+
+    fn secs(policy: &RetryPolicy, attempt: u32, random: f64) -> f64 {
+        policy.delay_with(attempt, random).as_secs_f64()
+    }
+
+    #[test]
+    fn delay_grows_exponentially_up_to_the_cap() {
+        let policy = policy(5, 0.0);
+
+        let delays: Vec<f64> = (1..=6).map(|attempt| policy.delay(attempt).as_secs_f64()).collect();
+
+        assert_eq!(delays, vec![1.0, 2.0, 4.0, 8.0, 10.0, 10.0]);
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(10));
+        // 0回目は最初の待機時間として扱う
+        assert_eq!(policy.delay(0), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn multiplier_below_one_keeps_the_initial_wait() {
+        let policy = RetryPolicy { multiplier: 0.5, ..policy(5, 0.0) };
+        assert_eq!(policy.delay(4), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn jitter_spreads_the_delay_within_its_ratio() {
+        let policy = policy(5, 0.2);
+
+        assert!((secs(&policy, 2, 0.0) - 1.6).abs() < 1e-9);
+        assert!((secs(&policy, 2, 0.5) - 2.0).abs() < 1e-9);
+        assert!((secs(&policy, 2, 0.999_999) - 2.4).abs() < 1e-5);
+        // ゆらぎを加えても上限は超えない
+        assert_eq!(secs(&policy, 10, 0.999_999), 10.0);
+
+        for _ in 0..1000 {
+            let delay = policy.delay(2).as_secs_f64();
+            assert!((1.6..=2.4).contains(&delay), "{}", delay);
+        }
+    }
+
+    #[test]
+    fn jitter_ratio_is_clamped() {
+        let policy = policy(5, 3.0);
+        assert_eq!(secs(&policy, 1, 0.0), 0.0);
+        assert_eq!(secs(&policy, 1, 0.999_999).round(), 2.0);
+    }
+
+    fn quick(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            initial_wait: Duration::from_millis(1),
+            max_wait: Duration::from_millis(5),
+            ..policy(max_retries, 0.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn run_retries_retryable_errors_until_success() {
+        let calls = AtomicU32::new(0);
+
+        let result = quick(3)
+            .run("test", || async {
+                match calls.fetch_add(1, Ordering::SeqCst) {
+                    0 | 1 => Err((DownloadError::Timeout("test".to_string()), true)),
+                    _ => Ok("done"),
+                }
+            })
+            .await;
+
+        assert_eq!(result.unwrap(), "done");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn run_gives_up_after_max_retries() {
+        let calls = AtomicU32::new(0);
+
+        let result: Result<(), _> = quick(2)
+            .run("test", || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err((DownloadError::Timeout("test".to_string()), true))
+            })
+            .await;
+
+        assert!(matches!(result, Err(DownloadError::Timeout(_))));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn run_does_not_retry_permanent_errors() {
+        let calls = AtomicU32::new(0);
+
+        let result: Result<(), _> = quick(5)
+            .run("test", || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err((DownloadError::Cancelled, false))
+            })
+            .await;
+
+        assert!(matches!(result, Err(DownloadError::Cancelled)));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(RetryPolicy::none().max_retries, 0);
+    }
+}
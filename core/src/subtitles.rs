@@ -0,0 +1,768 @@
+//! 字幕・自動生成字幕の取得と形式の変換
+//!
+//! 字幕のトラックはyt-dlpの動画情報（`subtitles`、`automatic_captions`）と、HLSの
+//! マスタープレイリストの字幕レンディション（`EXT-X-MEDIA:TYPE=SUBTITLES`。WebVTTの
+//! セグメントに分かれている）から取得する。WebVTT・SRT・ASSを読み書きでき、取得した字幕は
+//! 指定された形式に変換して動画の隣に保存するか、ffmpegで動画に埋め込む。
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use url::Url;
+use crate::retry::RetryPolicy;
+use crate::tools::fetcher::SegmentFetcher;
+use crate::tools::hls::{MasterPlaylist, Playlist};
+use crate::types::{DownloadError, SubtitleTrack, VideoInfo};
+
+/// 字幕の形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubtitleFormat {
+    /// WebVTT
+    Vtt,
+    /// SubRip
+    Srt,
+    /// Advanced SubStation Alpha
+    Ass,
+}
+
+impl SubtitleFormat {
+    /// 拡張子
+    pub fn extension(self) -> &'static str {
+        match self {
+            SubtitleFormat::Vtt => "vtt",
+            SubtitleFormat::Srt => "srt",
+            SubtitleFormat::Ass => "ass",
+        }
+    }
+
+    /// 拡張子から判定する（対応していない形式はNone）
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.trim_start_matches('.').to_lowercase().as_str() {
+            "vtt" | "webvtt" => Some(SubtitleFormat::Vtt),
+            "srt" => Some(SubtitleFormat::Srt),
+            "ass" | "ssa" => Some(SubtitleFormat::Ass),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for SubtitleFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::from_extension(value).ok_or_else(|| format!("対応していない字幕の形式: {}（vtt、srt、assのいずれか）", value))
+    }
+}
+
+impl fmt::Display for SubtitleFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+/// 字幕の取得・保存の設定
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SubtitleOptions {
+    /// 取得する言語（`ja`、`en-*`、`all`。空の場合は字幕を取得しない）
+    pub languages: Vec<String>,
+    /// 字幕がない言語は自動生成字幕を使う
+    pub auto_captions: bool,
+    /// 保存する形式（Noneの場合は取得した形式のまま）
+    pub format: Option<SubtitleFormat>,
+    /// 動画に埋め込む（MP4・MKVの場合。埋め込んだ字幕のファイルは削除する）
+    pub embed: bool,
+}
+
+impl SubtitleOptions {
+    /// 字幕を取得するかどうか
+    pub fn is_enabled(&self) -> bool {
+        !self.languages.is_empty()
+    }
+
+    /// 言語が指定に一致するか
+    ///
+    /// 大文字小文字は区別せず、末尾の `*` は前方一致（`en*` は `en`、`en-US` などに一致）。
+    pub fn matches(&self, language: &str) -> bool {
+        let language = language.to_lowercase();
+        self.languages.iter().map(|pattern| pattern.trim().to_lowercase()).any(|pattern| {
+            if pattern == "all" {
+                return true;
+            }
+            match pattern.strip_suffix('*') {
+                Some(prefix) => language.starts_with(prefix.trim_end_matches(['-', '.', '_'])),
+                None => language == pattern,
+            }
+        })
+    }
+}
+
+/// 字幕のトラック
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SubtitleSource {
+    /// 言語
+    pub language: String,
+    /// 表示名
+    pub name: Option<String>,
+    /// 字幕（またはHLSのメディアプレイリスト）のURL
+    pub url: String,
+    /// 形式
+    pub format: SubtitleFormat,
+    /// 自動生成字幕か
+    pub automatic: bool,
+    /// HLSのメディアプレイリスト（WebVTTのセグメントに分かれている）か
+    pub segmented: bool,
+}
+
+impl SubtitleSource {
+    /// 字幕を取得する
+    ///
+    /// HLSの場合はセグメントをすべて取得し、重複するキューを除いて1つにまとめる。
+    pub async fn fetch(&self, client: reqwest::Client, retry: RetryPolicy) -> Result<Subtitle, DownloadError> {
+        let fetcher = SegmentFetcher::new(client, retry);
+        let url = Url::parse(&self.url)
+            .map_err(|err| DownloadError::InvalidManifest(format!("不正なURL {}: {}", self.url, err)))?;
+        if !self.segmented {
+            return Subtitle::parse(&fetcher.fetch_text(&url).await?, self.format);
+        }
+
+        let media = match Playlist::parse(&fetcher.fetch_text(&url).await?, &url)? {
+            Playlist::Media(media) => media,
+            Playlist::Master(_) => {
+                return Err(DownloadError::InvalidManifest(format!("{} はメディアプレイリストではありません", url)));
+            }
+        };
+        let mut subtitle = Subtitle::default();
+        for segment in &media.segments {
+            let text = fetcher.fetch_text(&segment.uri).await?;
+            subtitle.append(Subtitle::parse(&text, SubtitleFormat::Vtt)?);
+        }
+        Ok(subtitle)
+    }
+}
+
+/// 動画情報の字幕のトラック（言語ごとに、読み込める形式のうちWebVTT・SRT・ASSの順に1つ選ぶ）
+pub fn available_tracks(info: &VideoInfo) -> Vec<SubtitleSource> {
+    let manual = info.subtitles.iter().flatten().map(|(language, tracks)| (language, tracks, false));
+    let automatic = info.automatic_captions.iter().flatten().map(|(language, tracks)| (language, tracks, true));
+
+    manual
+        .chain(automatic)
+        .filter_map(|(language, tracks, automatic)| {
+            let (track, format) = [SubtitleFormat::Vtt, SubtitleFormat::Srt, SubtitleFormat::Ass]
+                .into_iter()
+                .find_map(|format| {
+                    tracks
+                        .iter()
+                        .find(|track| track.url.is_some() && track_format(track) == Some(format))
+                        .map(|track| (track, format))
+                })?;
+            Some(SubtitleSource {
+                language: language.clone(),
+                name: track.name.clone(),
+                url: track.url.clone()?,
+                format,
+                automatic,
+                segmented: track.protocol.as_deref().is_some_and(|protocol| protocol.starts_with("m3u8")),
+            })
+        })
+        .collect()
+}
+
+/// yt-dlpの字幕のトラックの形式
+fn track_format(track: &SubtitleTrack) -> Option<SubtitleFormat> {
+    SubtitleFormat::from_extension(track.ext.as_deref()?)
+}
+
+/// HLSのマスタープレイリストの字幕レンディション
+pub fn hls_tracks(master: &MasterPlaylist) -> Vec<SubtitleSource> {
+    master
+        .renditions
+        .iter()
+        .filter(|rendition| rendition.media_type == "SUBTITLES")
+        .filter_map(|rendition| {
+            Some(SubtitleSource {
+                language: rendition.language.clone().unwrap_or_else(|| rendition.name.clone()),
+                name: Some(rendition.name.clone()).filter(|name| !name.is_empty()),
+                url: rendition.uri.as_ref()?.to_string(),
+                format: SubtitleFormat::Vtt,
+                automatic: false,
+                segmented: true,
+            })
+        })
+        .collect()
+}
+
+/// 設定に従って取得するトラックを選ぶ
+///
+/// 言語ごとに1つ選び、字幕がない言語は自動生成字幕を使う設定の場合のみ自動生成字幕を選ぶ。
+pub fn select_tracks(tracks: &[SubtitleSource], options: &SubtitleOptions) -> Vec<SubtitleSource> {
+    let mut selected: Vec<SubtitleSource> = Vec::new();
+    let candidates = tracks
+        .iter()
+        .filter(|track| !track.automatic)
+        .chain(tracks.iter().filter(|track| track.automatic && options.auto_captions));
+    for track in candidates {
+        if options.matches(&track.language) && !selected.iter().any(|chosen| chosen.language == track.language) {
+            selected.push(track.clone());
+        }
+    }
+    selected
+}
+
+/// ISO 639-2の3文字の言語コード（MP4の言語タグ用。わからない場合はNone）
+pub fn iso639_2(language: &str) -> Option<&'static str> {
+    let primary = language.split(['-', '_', '.']).next().unwrap_or(language).to_lowercase();
+    let code = match primary.as_str() {
+        "ja" | "jpn" => "jpn",
+        "en" | "eng" => "eng",
+        "zh" | "zho" | "chi" => "zho",
+        "ko" | "kor" => "kor",
+        "fr" | "fra" | "fre" => "fra",
+        "de" | "deu" | "ger" => "deu",
+        "es" | "spa" => "spa",
+        "pt" | "por" => "por",
+        "it" | "ita" => "ita",
+        "ru" | "rus" => "rus",
+        "ar" | "ara" => "ara",
+        "hi" | "hin" => "hin",
+        "id" | "ind" => "ind",
+        "th" | "tha" => "tha",
+        "vi" | "vie" => "vie",
+        "tr" | "tur" => "tur",
+        "nl" | "nld" | "dut" => "nld",
+        "pl" | "pol" => "pol",
+        "sv" | "swe" => "swe",
+        "uk" | "ukr" => "ukr",
+        _ => return None,
+    };
+    Some(code)
+}
+
+/// 字幕の1つのキュー
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cue {
+    /// 表示開始
+    pub start: Duration,
+    /// 表示終了
+    pub end: Duration,
+    /// テキスト（改行を含む。`<b>`、`<i>`、`<u>` 以外の書式は除く）
+    pub text: String,
+}
+
+/// 字幕（キューの一覧）
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Subtitle {
+    /// キュー（表示開始の順）
+    pub cues: Vec<Cue>,
+}
+
+impl Subtitle {
+    /// テキストを解析する
+    pub fn parse(text: &str, format: SubtitleFormat) -> Result<Self, DownloadError> {
+        let text = text.trim_start_matches('\u{feff}').replace("\r\n", "\n").replace('\r', "\n");
+        let mut cues = match format {
+            SubtitleFormat::Vtt => parse_vtt(&text)?,
+            SubtitleFormat::Srt => parse_srt(&text),
+            SubtitleFormat::Ass => parse_ass(&text),
+        };
+        cues.sort_by_key(|cue| cue.start);
+        Ok(Self { cues })
+    }
+
+    /// 指定した形式のテキストにする
+    pub fn render(&self, format: SubtitleFormat) -> String {
+        match format {
+            SubtitleFormat::Vtt => render_vtt(&self.cues),
+            SubtitleFormat::Srt => render_srt(&self.cues),
+            SubtitleFormat::Ass => render_ass(&self.cues),
+        }
+    }
+
+    /// 後ろにキューを追加する（セグメントの境界で重複するキューは除く）
+    pub fn append(&mut self, other: Subtitle) {
+        for cue in other.cues {
+            if !self.cues.contains(&cue) {
+                self.cues.push(cue);
+            }
+        }
+        self.cues.sort_by_key(|cue| cue.start);
+    }
+}
+
+/// WebVTTを解析する（NOTE・STYLE・REGIONのブロックとキューの設定は読み飛ばす）
+fn parse_vtt(text: &str) -> Result<Vec<Cue>, DownloadError> {
+    if !text.trim_start().starts_with("WEBVTT") {
+        return Err(DownloadError::InvalidManifest("WEBVTTヘッダーがありません".to_string()));
+    }
+
+    let mut cues = Vec::new();
+    for block in text.split("\n\n").skip(1) {
+        let mut lines = block.lines().skip_while(|line| line.trim().is_empty());
+        let Some(mut line) = lines.next() else {
+            continue;
+        };
+        // キューのIDがある場合は次の行がタイミング
+        if !line.contains("-->") {
+            match lines.next() {
+                Some(next) if next.contains("-->") => line = next,
+                _ => continue,
+            }
+        }
+        let Some((start, end)) = parse_timing(line) else {
+            continue;
+        };
+        let text: Vec<String> = lines.map(|line| unescape_html(&strip_tags(line))).collect();
+        push_cue(&mut cues, start, end, &text.join("\n"));
+    }
+    Ok(cues)
+}
+
+/// SRTを解析する（番号は読み飛ばす）
+fn parse_srt(text: &str) -> Vec<Cue> {
+    let mut cues = Vec::new();
+    for block in text.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        let Some((start, end)) = lines.next().and_then(parse_timing) else {
+            continue;
+        };
+        let text: Vec<String> = lines.map(strip_tags).collect();
+        push_cue(&mut cues, start, end, &text.join("\n"));
+    }
+    cues
+}
+
+/// ASSの `[Events]` の `Dialogue` を解析する（書式の指定は除く）
+fn parse_ass(text: &str) -> Vec<Cue> {
+    let mut fields: Vec<String> = ["Layer", "Start", "End", "Style", "Name", "MarginL", "MarginR", "MarginV", "Effect", "Text"]
+        .map(str::to_lowercase)
+        .to_vec();
+    let mut in_events = false;
+    let mut cues = Vec::new();
+
+    for line in text.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_events = line.eq_ignore_ascii_case("[events]");
+            continue;
+        }
+        if !in_events {
+            continue;
+        }
+        if let Some(format) = line.strip_prefix("Format:") {
+            fields = format.split(',').map(|field| field.trim().to_lowercase()).collect();
+        } else if let Some(dialogue) = line.strip_prefix("Dialogue:") {
+            // Textは最後の項目で、カンマを含むことがある
+            let values: Vec<&str> = dialogue.splitn(fields.len(), ',').collect();
+            let value = |name: &str| fields.iter().position(|field| field == name).and_then(|index| values.get(index));
+            let (Some(start), Some(end), Some(text)) = (value("start"), value("end"), value("text")) else {
+                continue;
+            };
+            let (Some(start), Some(end)) = (parse_timestamp(start), parse_timestamp(end)) else {
+                continue;
+            };
+            let mut plain = String::new();
+            let mut in_override = false;
+            for c in text.chars() {
+                match c {
+                    '{' => in_override = true,
+                    '}' if in_override => in_override = false,
+                    c if !in_override => plain.push(c),
+                    _ => {}
+                }
+            }
+            let plain = plain.replace("\\N", "\n").replace("\\n", "\n").replace("\\h", " ");
+            push_cue(&mut cues, start, end, &plain);
+        }
+    }
+    cues
+}
+
+fn push_cue(cues: &mut Vec<Cue>, start: Duration, end: Duration, text: &str) {
+    let text = text.trim_matches('\n');
+    if !text.trim().is_empty() && end >= start {
+        cues.push(Cue { start, end, text: text.to_string() });
+    }
+}
+
+/// `00:00:01.000 --> 00:00:02.000 position:10%` のようなタイミングの行を解析する
+fn parse_timing(line: &str) -> Option<(Duration, Duration)> {
+    let (start, rest) = line.split_once("-->")?;
+    let end = rest.split_whitespace().next()?;
+    Some((parse_timestamp(start)?, parse_timestamp(end)?))
+}
+
+/// `hh:mm:ss.mmm`、`mm:ss.mmm`、`hh:mm:ss,mmm`、`h:mm:ss.cc` 形式の時刻を解析する
+fn parse_timestamp(value: &str) -> Option<Duration> {
+    let value = value.trim().replace(',', ".");
+    let (clock, fraction) = value.split_once('.').unwrap_or((&value, "0"));
+    let mut seconds = 0u64;
+    for part in clock.split(':') {
+        seconds = seconds.checked_mul(60)?.checked_add(part.parse().ok()?)?;
+    }
+    let digits = fraction.chars().take_while(char::is_ascii_digit).take(3).collect::<String>();
+    let millis = format!("{:0<3}", digits).parse::<u64>().ok()?;
+    Some(Duration::from_secs(seconds) + Duration::from_millis(millis))
+}
+
+/// `<b>`、`<i>`、`<u>` 以外のタグ（`<c.class>`、`<v 話者>`、時刻など）を除く
+fn strip_tags(line: &str) -> String {
+    let mut text = String::new();
+    let mut rest = line;
+    while let Some(open) = rest.find('<') {
+        text.push_str(&rest[..open]);
+        let Some(close) = rest[open..].find('>') else {
+            text.push_str(&rest[open..]);
+            return text;
+        };
+        let tag = &rest[open..open + close + 1];
+        if matches!(tag, "<b>" | "</b>" | "<i>" | "</i>" | "<u>" | "</u>") {
+            text.push_str(tag);
+        }
+        rest = &rest[open + close + 1..];
+    }
+    text.push_str(rest);
+    text
+}
+
+fn unescape_html(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", "\u{a0}")
+        .replace("&lrm;", "\u{200e}")
+        .replace("&rlm;", "\u{200f}")
+        .replace("&amp;", "&")
+}
+
+/// WebVTTのテキストのエスケープ（`<b>`、`<i>`、`<u>` のタグはそのまま残す）
+fn escape_vtt(text: &str) -> String {
+    let escaped = text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    ["b", "i", "u"].iter().fold(escaped, |text, tag| {
+        text.replace(&format!("&lt;{}&gt;", tag), &format!("<{}>", tag))
+            .replace(&format!("&lt;/{}&gt;", tag), &format!("</{}>", tag))
+    })
+}
+
+/// 時刻を `hh:mm:ss<区切り>mmm` の形にする
+fn format_timestamp(time: Duration, separator: char) -> String {
+    let millis = time.as_millis();
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        separator,
+        millis % 1000
+    )
+}
+
+fn render_vtt(cues: &[Cue]) -> String {
+    let mut text = String::from("WEBVTT\n");
+    for cue in cues {
+        text.push_str(&format!(
+            "\n{} --> {}\n{}\n",
+            format_timestamp(cue.start, '.'),
+            format_timestamp(cue.end, '.'),
+            escape_vtt(&cue.text)
+        ));
+    }
+    text
+}
+
+fn render_srt(cues: &[Cue]) -> String {
+    let mut text = String::new();
+    for (index, cue) in cues.iter().enumerate() {
+        text.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            index + 1,
+            format_timestamp(cue.start, ','),
+            format_timestamp(cue.end, ','),
+            cue.text
+        ));
+    }
+    text
+}
+
+/// ASSの既定のスタイルとイベントの書式
+const ASS_HEADER: &str = "[Script Info]
+ScriptType: v4.00+
+PlayResX: 384
+PlayResY: 288
+ScaledBorderAndShadow: yes
+
+[V4+ Styles]
+Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding
+Style: Default,Arial,16,&H00FFFFFF,&H00FFFFFF,&H00000000,&H00000000,0,0,0,0,100,100,0,0,1,1,0,2,10,10,10,1
+
+[Events]
+Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+";
+
+fn render_ass(cues: &[Cue]) -> String {
+    // ASSの時刻は `h:mm:ss.cc`（1/100秒）
+    let timestamp = |time: Duration| {
+        let centis = time.as_millis() / 10;
+        format!("{}:{:02}:{:02}.{:02}", centis / 360_000, centis / 6000 % 60, centis / 100 % 60, centis % 100)
+    };
+    let mut text = String::from(ASS_HEADER);
+    for cue in cues {
+        let body = [("b", "\\b"), ("i", "\\i"), ("u", "\\u")]
+            .iter()
+            .fold(cue.text.replace('\n', "\\N"), |body, (tag, code)| {
+                body.replace(&format!("<{}>", tag), &format!("{{{}1}}", code))
+                    .replace(&format!("</{}>", tag), &format!("{{{}0}}", code))
+            });
+        text.push_str(&format!(
+            "Dialogue: 0,{},{},Default,,0,0,0,,{}\n",
+            timestamp(cue.start),
+            timestamp(cue.end),
+            body
+        ));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestServer;
+    use crate::tools::fetcher;
+
+    fn cue(start_ms: u64, end_ms: u64, text: &str) -> Cue {
+        Cue { start: Duration::from_millis(start_ms), end: Duration::from_millis(end_ms), text: text.to_string() }
+    }
+
+    #[test]
+    fn parses_vtt_with_ids_settings_and_markup() {
+        let text = "\u{feff}WEBVTT - 字幕\r\nKind: captions\r\n\r\n\
+                    NOTE 作成者のメモ\r\n--> を含んでもキューではない\r\n\r\n\
+                    STYLE\r\n::cue { color: yellow }\r\n\r\n\
+                    intro\r\n00:01.500 --> 00:03.000 align:start position:10%\r\n<v 話者><c.yellow>こんにちは</c></v>\r\n<b>Tom &amp; Jerry</b> &lt;3\r\n\r\n\
+                    00:00:00.000 --> 00:00:01.000\r\n<00:00:00.500>最初\r\n";
+
+        let subtitle = Subtitle::parse(text, SubtitleFormat::Vtt).unwrap();
+
+        assert_eq!(subtitle.cues, vec![cue(0, 1000, "最初"), cue(1500, 3000, "こんにちは\n<b>Tom & Jerry</b> <3")]);
+    }
+
+    #[test]
+    fn rejects_vtt_without_header() {
+        let error = Subtitle::parse("00:00.000 --> 00:01.000\nテキスト\n", SubtitleFormat::Vtt).unwrap_err();
+        assert!(matches!(error, DownloadError::InvalidManifest(_)));
+    }
+
+    #[test]
+    fn parses_srt_and_skips_broken_blocks() {
+        let text = "1\n00:00:01,000 --> 00:00:02,500\n<font color=\"red\">一行目</font>\n<i>二行目</i>\n\n\
+                    2\n壊れたタイミング\nテキスト\n\n\
+                    3\n01:00:00,040 --> 01:00:01,000\n最後\n";
+
+        let subtitle = Subtitle::parse(text, SubtitleFormat::Srt).unwrap();
+
+        assert_eq!(subtitle.cues, vec![cue(1000, 2500, "一行目\n<i>二行目</i>"), cue(3_600_040, 3_601_000, "最後")]);
+    }
+
+    #[test]
+    fn parses_ass_dialogue_using_the_format_line() {
+        let text = "[Script Info]\nTitle: test\n\n\
+                    [V4+ Styles]\nFormat: Name, Fontname\nStyle: Default,Arial\n\n\
+                    [Events]\nFormat: Layer, Start, End, Style, Text\n\
+                    Comment: 0,0:00:00.00,0:00:01.00,Default,コメント\n\
+                    Dialogue: 0,0:00:02.50,0:00:04.00,Default,{\\an8\\b1}上に,カンマ{\\b0}\\Nnbsp\\hあり\n\
+                    Dialogue: 0,0:00:00.00,0:00:01.20,Default,{\\pos(10,10)}\n\
+                    Dialogue: 0,1:02:03.04,1:02:04.00,Default,後\n";
+
+        let subtitle = Subtitle::parse(text, SubtitleFormat::Ass).unwrap();
+
+        assert_eq!(
+            subtitle.cues,
+            vec![cue(2500, 4000, "上に,カンマ\nnbsp あり"), cue(3_723_040, 3_724_000, "後")]
+        );
+    }
+
+    #[test]
+    fn renders_each_format() {
+        let subtitle = Subtitle { cues: vec![cue(1500, 3000, "<b>A & B</b>\n1 < 2"), cue(3_723_040, 3_724_000, "<i>後</i>")] };
+
+        assert_eq!(
+            subtitle.render(SubtitleFormat::Vtt),
+            "WEBVTT\n\n00:00:01.500 --> 00:00:03.000\n<b>A &amp; B</b>\n1 &lt; 2\n\n01:02:03.040 --> 01:02:04.000\n<i>後</i>\n"
+        );
+        assert_eq!(
+            subtitle.render(SubtitleFormat::Srt),
+            "1\n00:00:01,500 --> 00:00:03,000\n<b>A & B</b>\n1 < 2\n\n2\n01:02:03,040 --> 01:02:04,000\n<i>後</i>\n\n"
+        );
+        let ass = subtitle.render(SubtitleFormat::Ass);
+        assert!(ass.starts_with(ASS_HEADER));
+        assert_eq!(
+            &ass[ASS_HEADER.len()..],
+            "Dialogue: 0,0:00:01.50,0:00:03.00,Default,,0,0,0,,{\\b1}A & B{\\b0}\\N1 < 2\n\
+             Dialogue: 0,1:02:03.04,1:02:04.00,Default,,0,0,0,,{\\i1}後{\\i0}\n"
+        );
+    }
+
+    #[test]
+    fn converts_between_formats_without_losing_cues() {
+        let vtt = "WEBVTT\n\n00:00:01.000 --> 00:00:02.000\n<b>太字</b> &amp; 普通\n\n00:00:02.000 --> 00:00:04.560\n二行\n目\n";
+        let subtitle = Subtitle::parse(vtt, SubtitleFormat::Vtt).unwrap();
+
+        for format in [SubtitleFormat::Vtt, SubtitleFormat::Srt, SubtitleFormat::Ass] {
+            let converted = Subtitle::parse(&subtitle.render(format), format).unwrap();
+            let expected = match format {
+                // ASSの書式は読み込み時に除く
+                SubtitleFormat::Ass => vec![cue(1000, 2000, "太字 & 普通"), cue(2000, 4560, "二行\n目")],
+                _ => subtitle.cues.clone(),
+            };
+            assert_eq!(converted.cues, expected, "{}", format);
+        }
+    }
+
+    #[test]
+    fn append_skips_cues_repeated_at_segment_boundaries() {
+        let mut subtitle = Subtitle { cues: vec![cue(0, 1000, "a"), cue(1000, 6000, "b")] };
+        subtitle.append(Subtitle { cues: vec![cue(1000, 6000, "b"), cue(6000, 7000, "c"), cue(500, 800, "途中")] });
+
+        assert_eq!(subtitle.cues, vec![cue(0, 1000, "a"), cue(500, 800, "途中"), cue(1000, 6000, "b"), cue(6000, 7000, "c")]);
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(parse_timestamp("01:02:03.456"), Some(Duration::from_millis(3_723_456)));
+        assert_eq!(parse_timestamp("02:03,4"), Some(Duration::from_millis(123_400)));
+        assert_eq!(parse_timestamp("0:00:01.25"), Some(Duration::from_millis(1250)));
+        assert_eq!(parse_timestamp(" 00:05 "), Some(Duration::from_secs(5)));
+        assert_eq!(parse_timestamp("00:xx.000"), None);
+    }
+
+    #[test]
+    fn parses_formats() {
+        assert_eq!(SubtitleFormat::from_extension(".VTT"), Some(SubtitleFormat::Vtt));
+        assert_eq!(SubtitleFormat::from_extension("ssa"), Some(SubtitleFormat::Ass));
+        assert_eq!(SubtitleFormat::from_extension("json3"), None);
+        assert_eq!("srt".parse::<SubtitleFormat>(), Ok(SubtitleFormat::Srt));
+        assert!("ttml".parse::<SubtitleFormat>().is_err());
+    }
+
+    #[test]
+    fn matches_language_patterns() {
+        let options = SubtitleOptions { languages: vec!["JA".to_string(), "en-*".to_string()], ..Default::default() };
+        assert!(options.matches("ja"));
+        assert!(options.matches("en"));
+        assert!(options.matches("en-US"));
+        assert!(!options.matches("ja-JP"));
+        assert!(!options.matches("es"));
+
+        let all = SubtitleOptions { languages: vec!["all".to_string()], ..Default::default() };
+        assert!(all.matches("zh-Hans"));
+        assert!(!SubtitleOptions::default().is_enabled());
+    }
+
+    #[test]
+    fn lists_and_selects_tracks_from_video_info() {
+        let info: VideoInfo = serde_json::from_value(serde_json::json!({
+            "subtitles": {
+                "en": [
+                    {"ext": "json3", "url": "https://example.com/en.json3"},
+                    {"ext": "srt", "url": "https://example.com/en.srt", "name": "English"},
+                    {"ext": "vtt", "url": "https://example.com/en.vtt", "name": "English"}
+                ],
+                "fr": [{"ext": "ttml", "url": "https://example.com/fr.ttml"}],
+                "ja": [{"ext": "vtt", "url": "https://example.com/ja.m3u8", "protocol": "m3u8_native"}]
+            },
+            "automatic_captions": {
+                "en": [{"ext": "vtt", "url": "https://example.com/auto-en.vtt"}],
+                "de": [{"ext": "ass", "url": "https://example.com/auto-de.ass"}]
+            }
+        }))
+        .unwrap();
+
+        let tracks = available_tracks(&info);
+        let summary: Vec<(&str, &str, SubtitleFormat, bool, bool)> = tracks
+            .iter()
+            .map(|track| (track.language.as_str(), track.url.as_str(), track.format, track.automatic, track.segmented))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("en", "https://example.com/en.vtt", SubtitleFormat::Vtt, false, false),
+                ("ja", "https://example.com/ja.m3u8", SubtitleFormat::Vtt, false, true),
+                ("de", "https://example.com/auto-de.ass", SubtitleFormat::Ass, true, false),
+                ("en", "https://example.com/auto-en.vtt", SubtitleFormat::Vtt, true, false),
+            ]
+        );
+        assert_eq!(tracks[0].name.as_deref(), Some("English"));
+
+        let options = SubtitleOptions { languages: vec!["en".to_string(), "de".to_string()], ..Default::default() };
+        let selected: Vec<String> = select_tracks(&tracks, &options).into_iter().map(|track| track.url).collect();
+        assert_eq!(selected, vec!["https://example.com/en.vtt"]);
+
+        let options = SubtitleOptions { auto_captions: true, ..options };
+        let selected: Vec<String> = select_tracks(&tracks, &options).into_iter().map(|track| track.url).collect();
+        assert_eq!(selected, vec!["https://example.com/en.vtt", "https://example.com/auto-de.ass"]);
+    }
+
+    #[test]
+    fn lists_hls_subtitle_renditions() {
+        let url = Url::parse("https://example.com/video/master.m3u8").unwrap();
+        let master = "#EXTM3U\n\
+                      #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aud\",NAME=\"Main\",URI=\"audio.m3u8\"\n\
+                      #EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"subs\",NAME=\"日本語\",LANGUAGE=\"ja\",URI=\"subs/ja.m3u8\"\n\
+                      #EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"subs\",NAME=\"Forced\"\n\
+                      #EXT-X-STREAM-INF:BANDWIDTH=800000,SUBTITLES=\"subs\"\nvideo.m3u8\n";
+        let Playlist::Master(master) = Playlist::parse(master, &url).unwrap() else {
+            panic!("マスタープレイリストとして解析されない");
+        };
+
+        assert_eq!(
+            hls_tracks(&master),
+            vec![SubtitleSource {
+                language: "ja".to_string(),
+                name: Some("日本語".to_string()),
+                url: "https://example.com/video/subs/ja.m3u8".to_string(),
+                format: SubtitleFormat::Vtt,
+                automatic: false,
+                segmented: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn maps_languages_to_iso639_2() {
+        assert_eq!(iso639_2("ja"), Some("jpn"));
+        assert_eq!(iso639_2("en-US"), Some("eng"));
+        assert_eq!(iso639_2("zh_Hans"), Some("zho"));
+        assert_eq!(iso639_2("ger"), Some("deu"));
+        assert_eq!(iso639_2("xx"), None);
+    }
+
+    #[tokio::test]
+    async fn fetches_and_merges_segmented_vtt() {
+        let server = TestServer::files(vec![
+            (
+                "/subs/ja.m3u8",
+                b"#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXTINF:6,\nseg0.vtt\n#EXTINF:6,\nseg1.vtt\n#EXT-X-ENDLIST\n".to_vec(),
+            ),
+            (
+                "/subs/seg0.vtt",
+                "WEBVTT\nX-TIMESTAMP-MAP=MPEGTS:900000,LOCAL:00:00:00.000\n\n00:00:01.000 --> 00:00:02.000\n一\n\n00:00:05.000 --> 00:00:07.000\n境界\n"
+                    .as_bytes()
+                    .to_vec(),
+            ),
+            ("/subs/seg1.vtt", "WEBVTT\n\n00:00:05.000 --> 00:00:07.000\n境界\n\n00:00:08.000 --> 00:00:09.000\n二\n".as_bytes().to_vec()),
+        ])
+        .await;
+        let source = SubtitleSource {
+            language: "ja".to_string(),
+            name: None,
+            url: server.url("/subs/ja.m3u8"),
+            format: SubtitleFormat::Vtt,
+            automatic: false,
+            segmented: true,
+        };
+
+        let subtitle = source.fetch(fetcher::http_client(), RetryPolicy::none()).await.unwrap();
+
+        assert_eq!(subtitle.cues, vec![cue(1000, 2000, "一"), cue(5000, 7000, "境界"), cue(8000, 9000, "二")]);
+    }
+}
@@ -0,0 +1,223 @@
+//! テスト用のローカルHTTPサーバーと一時ディレクトリ
+//!
+//! サーバーは1つの接続で1つのリクエストだけを処理し、応答後に接続を閉じる。
+//! 受け取ったリクエストは記録し、テストから内容を確認できる。
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// 受け取ったリクエスト
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// ヘッダーの値（名前の大文字・小文字は区別しない）
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// `Range: bytes=<start>-[<end>]` の範囲
+    pub fn range(&self) -> Option<(u64, Option<u64>)> {
+        let (start, end) = self.header("Range")?.strip_prefix("bytes=")?.split_once('-')?;
+        Some((start.parse().ok()?, end.parse().ok()))
+    }
+}
+
+/// 返す応答
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// 200 OK
+    pub fn ok(body: impl Into<Vec<u8>>) -> Self {
+        Self { status: 200, headers: Vec::new(), body: body.into() }
+    }
+
+    /// 本文のない応答
+    pub fn status(status: u16) -> Self {
+        Self { status, headers: Vec::new(), body: Vec::new() }
+    }
+
+    /// Rangeヘッダーに従って一部（206）または全体を返す
+    pub fn ranged(body: &[u8], request: &Request) -> Self {
+        let Some((start, end)) = request.range() else {
+            return Self::ok(body.to_vec()).with_header("Accept-Ranges", "bytes");
+        };
+        let length = body.len() as u64;
+        let end = end.unwrap_or(length - 1).min(length - 1);
+        Self {
+            status: 206,
+            headers: vec![
+                ("Accept-Ranges".to_string(), "bytes".to_string()),
+                ("Content-Range".to_string(), format!("bytes {}-{}/{}", start, end, length)),
+            ],
+            body: body[start as usize..=end as usize].to_vec(),
+        }
+    }
+
+    /// ヘッダーを追加
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+type Handler = dyn Fn(&Request) -> Response + Send + Sync;
+
+/// ローカルのHTTPサーバー（破棄すると停止する）
+pub struct TestServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<Request>>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl TestServer {
+    /// 空いているポートでサーバーを起動
+    pub async fn start(handler: impl Fn(&Request) -> Response + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("local_addr");
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let recorded = Arc::clone(&requests);
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handler = Arc::clone(&handler);
+                let recorded = Arc::clone(&recorded);
+                tokio::spawn(async move {
+                    let _ = serve(stream, handler, recorded).await;
+                });
+            }
+        });
+
+        Self { addr, requests, task }
+    }
+
+    /// パスとその内容を返すサーバーを起動（Rangeに対応し、ないパスは404）
+    pub async fn files(files: Vec<(&str, Vec<u8>)>) -> Self {
+        let files: Vec<(String, Vec<u8>)> = files
+            .into_iter()
+            .map(|(path, body)| (path.to_string(), body))
+            .collect();
+        Self::start(move |request| {
+            let path = request.path.split('?').next().unwrap_or_default();
+            match files.iter().find(|(name, _)| name == path) {
+                Some((_, body)) => Response::ranged(body, request),
+                None => Response::status(404),
+            }
+        })
+        .await
+    }
+
+    /// パスのURL
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// これまでに受け取ったリクエスト
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(
+    mut stream: TcpStream,
+    handler: Arc<Handler>,
+    recorded: Arc<Mutex<Vec<Request>>>
+) -> std::io::Result<()> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let header_end = loop {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        if let Some(position) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default().to_string();
+    let path = request_line.next().unwrap_or_default().to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+
+    // Content-Lengthの分だけ本文を読む
+    let mut body = buffer.split_off(header_end + 4);
+    let length = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(0);
+    while body.len() < length {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+
+    let request = Request { method, path, headers, body };
+    let response = handler(&request);
+    let is_head = request.method == "HEAD";
+    recorded.lock().unwrap().push(request);
+
+    let mut head = format!("HTTP/1.1 {} Test\r\n", response.status);
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", response.body.len()));
+    stream.write_all(head.as_bytes()).await?;
+    if !is_head {
+        stream.write_all(&response.body).await?;
+    }
+    stream.shutdown().await
+}
+
+/// テスト用の一時ディレクトリ（破棄すると削除する）
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        let path = std::env::temp_dir().join(format!("nextdownloader-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&path).expect("create temp dir");
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
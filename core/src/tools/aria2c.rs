@@ -1,39 +1,107 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use serde_json::{Map, Value};
 use tokio::process::Command;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::sync::OnceCell;
 use regex::Regex;
-use crate::types::{DownloadError, ProgressInfo, ProgressCallback, DownloadOptions};
+use crate::bandwidth::{self, Throttle};
+use crate::tools::discovery::{ExternalTool, ToolPaths};
+use crate::types::{DownloadError, ProgressInfo, ProgressPhase, ProgressCallback, DownloadOptions};
+use crate::request::RequestContext;
+use crate::tools::aria2c_rpc::{Aria2Status, Aria2cDaemon, RpcDownload};
+use crate::tools::diagnostics;
+
+/// RPCモードで状態を問い合わせる間隔
+const RPC_POLL_INTERVAL: Duration = Duration::from_millis(500);
 
 /// aria2c外部ツールを扱うための構造体
 pub struct Aria2cTool {
     /// 実行ファイルのパス
     executable_path: PathBuf,
+    /// RPCモードの常駐プロセス（Noneの場合はダウンロードごとにプロセスを起動する）
+    daemon: Option<OnceCell<Aria2cDaemon>>,
+    /// 全体の速度制限（バイト/秒、ダウンロードごとの全体の制限とは厳しい方を使う）
+    speed_limit: Mutex<Option<u64>>,
+}
+
+impl Default for Aria2cTool {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Aria2cTool {
-    /// 新しいAria2cToolを作成（デフォルトパス使用）
+    /// 新しいAria2cToolを作成（環境変数、PATH、同梱ツールの順に探す）
     pub fn new() -> Self {
-        #[cfg(target_os = "windows")]
-        let default_path = PathBuf::from(".\\bin\\aria2c.exe");
-        
-        #[cfg(target_os = "macos")]
-        let default_path = PathBuf::from("/usr/local/bin/aria2c");
-        
-        #[cfg(target_os = "linux")]
-        let default_path = PathBuf::from("/usr/bin/aria2c");
-        
-        Self {
-            executable_path: default_path
-        }
+        Self::with_path(ToolPaths::default().path(ExternalTool::Aria2c))
     }
     
     /// 指定したパスでAria2cToolを作成
     pub fn with_path(path: PathBuf) -> Self {
         Self {
-            executable_path: path
+            executable_path: path,
+            daemon: None,
+            speed_limit: Mutex::new(None),
         }
     }
     
+    /// RPCモードに切り替える
+    ///
+    /// 最初のダウンロード時にaria2cを `--enable-rpc` で1つだけ起動し、
+    /// 以降のダウンロードは `aria2.addUri` で追加する。進捗は `aria2.tellStatus` で取得するため、
+    /// 表示を解析する（[`parse_readout`]）通常のモードより正確になる。
+    pub fn with_rpc(mut self) -> Self {
+        self.daemon = Some(OnceCell::new());
+        self
+    }
+    
+    /// RPCモードか
+    pub fn is_rpc(&self) -> bool {
+        self.daemon.is_some()
+    }
+    
+    /// 全体の速度制限（バイト/秒、Noneで無制限）を設定
+    ///
+    /// RPCモードで起動済みの場合は `aria2.changeGlobalOption` で即座に反映する。
+    /// それ以外の場合は次に起動するプロセスから適用される。
+    pub async fn set_speed_limit(&self, limit: Option<u64>) -> Result<(), DownloadError> {
+        *self.speed_limit.lock().unwrap() = limit;
+        
+        if let Some(daemon) = self.daemon.as_ref().and_then(OnceCell::get) {
+            let mut options = Map::new();
+            options.insert(
+                "max-overall-download-limit".to_string(),
+                Value::String(limit.unwrap_or(0).to_string())
+            );
+            daemon.client().change_global_option(options).await?;
+        }
+        
+        Ok(())
+    }
+    
+    /// 全体の速度制限（このaria2cの設定とダウンロードの全体の制限の厳しい方）
+    fn overall_limit(&self, throttle: &Throttle) -> Option<u64> {
+        let limit = *self.speed_limit.lock().unwrap();
+        match (limit, throttle.global_limit()) {
+            (Some(limit), Some(global)) => Some(limit.min(global)),
+            (limit, global) => limit.or(global),
+        }
+    }
+    
+    /// 速度制限の引数
+    fn speed_limit_args(&self, throttle: &Throttle) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(limit) = self.overall_limit(throttle) {
+            args.push(format!("--max-overall-download-limit={}", limit));
+        }
+        if let Some(limit) = throttle.task_limit() {
+            args.push(format!("--max-download-limit={}", limit));
+        }
+        args
+    }
+    
     /// aria2cが利用可能かチェック
     pub async fn is_available(&self) -> bool {
         if !self.executable_path.exists() {
@@ -76,7 +144,7 @@ impl Aria2cTool {
     pub async fn download(
         &self,
         url: &str,
-        output_path: &PathBuf,
+        output_path: &Path,
         filename: &str,
         options: &DownloadOptions,
         progress_callback: Option<ProgressCallback>
@@ -85,6 +153,18 @@ impl Aria2cTool {
         let output_filename = format!("{}.{}", filename, options.format.to_string().to_lowercase());
         let output_file_path = output_path.join(&output_filename);
         
+        if let Some(daemon) = &self.daemon {
+            let daemon_args: Vec<String> = self
+                .overall_limit(&options.throttle)
+                .map(|limit| format!("--max-overall-download-limit={}", limit))
+                .into_iter()
+                .collect();
+            let daemon = daemon
+                .get_or_try_init(|| Aria2cDaemon::start(&self.executable_path, &daemon_args))
+                .await?;
+            return self.download_rpc(daemon, url, output_path, &output_filename, options, progress_callback).await;
+        }
+        
         // 引数構築
         let mut args = vec![
             format!("-x{}", options.connections),
@@ -95,8 +175,16 @@ impl Aria2cTool {
             format!("--dir={}", output_path.to_string_lossy()),
             format!("--out={}", output_filename),
             "--summary-interval=1".to_string(),
+            // 進捗表示のサイズと速度をバイト単位で出力させる
+            "--human-readable=false".to_string(),
             "--download-result=full".to_string(),
             "--file-allocation=none".to_string(),
+            // 一時停止後の再開のため、制御ファイル（.aria2）を頻繁に保存して続きから取得する
+            "--continue=true".to_string(),
+            "--auto-save-interval=1".to_string(),
+            // 出力ファイル名はダウンロードマネージャーが決めるため、aria2c側では変えない
+            "--auto-file-renaming=false".to_string(),
+            "--allow-overwrite=true".to_string(),
         ];
         
         // HTTP/2サポート
@@ -115,78 +203,181 @@ impl Aria2cTool {
             args.push("--enable-http-keep-alive=true".to_string());
         }
         
-        // URLを追加
-        args.push(url.to_string());
+        args.extend(request_args(&options.request));
         
-        // プロセス起動
-        let mut child = Command::new(&self.executable_path)
-            .args(&args)
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped())
-            .spawn()?;
-            
-        // 進捗処理
-        if let Some(callback) = progress_callback {
-            let stdout = child.stdout.take().expect("Failed to get stdout");
-            let reader = BufReader::new(stdout);
-            let mut lines = reader.lines();
+        let progress_callback: Option<Arc<dyn Fn(ProgressInfo) + Send + Sync>> = progress_callback.map(Arc::from);
+        let (status, stderr_reader) = loop {
+            // プロセス起動（速度制限とURLは起動ごとに指定する。進捗を通知しない場合は標準出力を読まないため捨てる）
+            let stdout = if progress_callback.is_some() {
+                std::process::Stdio::piped()
+            } else {
+                std::process::Stdio::null()
+            };
+            let mut child = Command::new(&self.executable_path)
+                .args(&args)
+                .args(self.speed_limit_args(&options.throttle))
+                .arg(url)
+                .stdout(stdout)
+                .stderr(std::process::Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()?;
             
-            // 正規表現コンパイル
-            let progress_re = Regex::new(r"\d+%").unwrap();
-            let speed_re = Regex::new(r"DL:(\S+)").unwrap();
-            let eta_re = Regex::new(r"ETA:(\S+)").unwrap();
-            
-            tokio::spawn(async move {
-                while let Ok(Some(line)) = lines.next_line().await {
-                    let mut progress_info = ProgressInfo {
-                        progress: 0.0,
-                        speed: String::new(),
-                        eta: String::new(),
-                    };
-                    
-                    // 進捗抽出
-                    if let Some(progress_match) = progress_re.find(&line) {
-                        let progress_str = progress_match.as_str().trim_end_matches('%');
-                        if let Ok(progress) = progress_str.parse::<f64>() {
-                            progress_info.progress = progress / 100.0;
-                        }
-                    }
-                    
-                    // 速度抽出
-                    if let Some(caps) = speed_re.captures(&line) {
-                        if let Some(speed) = caps.get(1) {
-                            progress_info.speed = speed.as_str().to_string();
-                        }
-                    }
-                    
-                    // ETA抽出
-                    if let Some(caps) = eta_re.captures(&line) {
-                        if let Some(eta) = caps.get(1) {
-                            progress_info.eta = eta.as_str().to_string();
+            // エラー出力は終了を待つ間も読み続ける（パイプが詰まるとaria2cが書き込みで止まるため）
+            let mut stderr = child.stderr.take().expect("Failed to get stderr");
+            let stderr_reader = tokio::spawn(async move {
+                let mut bytes = Vec::new();
+                let _ = stderr.read_to_end(&mut bytes).await;
+                String::from_utf8_lossy(&bytes).to_string()
+            });
+                
+            // 進捗処理（RPCモードでない場合は表示の解析による推定にとどまる）
+            if let Some(callback) = progress_callback.clone() {
+                log::debug!("aria2cの進捗は表示から推定します（正確な値はRPCモードで取得できます）");
+                let stdout = child.stdout.take().expect("Failed to get stdout");
+                let reader = BufReader::new(stdout);
+                let mut lines = reader.lines();
+                
+                tokio::spawn(async move {
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        if let Some(progress_info) = parse_readout(&line) {
+                            callback(progress_info);
                         }
                     }
-                    
-                    // コールバック実行
-                    if progress_info.progress > 0.0 || !progress_info.speed.is_empty() {
-                        callback(progress_info);
-                    }
+                });
+            }
+            
+            // 実行終了を待機（速度制限が変わった場合は起動し直し、制御ファイルから続きを取得する）
+            tokio::select! {
+                status = child.wait() => break (status?, stderr_reader),
+                _ = options.throttle.changed() => {
+                    log::info!(
+                        "速度制限が {} に変わったため、aria2cを起動し直します",
+                        bandwidth::describe_limit(options.throttle.limit())
+                    );
+                    child.kill().await?;
                 }
-            });
+            }
+        };
+        
+        if !status.success() {
+            let error_message = stderr_reader.await.unwrap_or_default();
+            return Err(classify_error(status.code(), &error_message, url));
+        }
+        
+        // 出力ファイルが存在するか確認
+        if !output_file_path.exists() {
+            return Err(DownloadError::FileNotFound);
         }
         
-        // 実行終了を待機
-        let status = child.wait().await?;
+        Ok(output_file_path)
+    }
+    
+    /// 常駐しているaria2cにダウンロードを追加し、完了まで状態を問い合わせる
+    ///
+    /// 問い合わせのたびに速度制限の変更を確認し、変わっていれば反映する。
+    async fn download_rpc(
+        &self,
+        daemon: &Aria2cDaemon,
+        url: &str,
+        output_path: &Path,
+        output_filename: &str,
+        options: &DownloadOptions,
+        progress_callback: Option<ProgressCallback>
+    ) -> Result<PathBuf, DownloadError> {
+        let client = daemon.client();
         
-        if !status.success() {
-            let mut stderr = child.stderr.take().expect("Failed to get stderr");
-            let mut error_message = String::new();
-            use tokio::io::AsyncReadExt;
-            stderr.read_to_string(&mut error_message).await?;
+        let mut rpc_options = Map::new();
+        for (name, value) in [
+            ("dir", output_path.to_string_lossy().to_string()),
+            ("out", output_filename.to_string()),
+            ("max-connection-per-server", options.connections.to_string()),
+            ("split", options.splits.to_string()),
+            ("min-split-size", format!("{}M", options.chunk_size)),
+            ("retry-wait", options.retry_wait.to_string()),
+            ("max-tries", options.max_retries.to_string()),
+            ("enable-http-keep-alive", options.use_keep_alive.to_string()),
+            ("auto-file-renaming", "false".to_string()),
+            ("allow-overwrite", "true".to_string()),
+        ] {
+            rpc_options.insert(name.to_string(), Value::String(value));
+        }
+        
+        // Cookieファイルは常駐プロセス全体の設定になるため、該当するCookieをヘッダーで渡す
+        let request = &options.request;
+        let mut headers: Vec<String> = request
+            .headers
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect();
+        if let Ok(parsed_url) = url::Url::parse(url) {
+            if let Some(cookie) = request.cookie_header(&parsed_url)? {
+                headers.push(format!("Cookie: {}", cookie));
+            }
+        }
+        if !headers.is_empty() {
+            rpc_options.insert("header".to_string(), Value::from(headers));
+        }
+        if let Some(user_agent) = &request.user_agent {
+            rpc_options.insert("user-agent".to_string(), Value::String(user_agent.clone()));
+        }
+        if let Some(referer) = &request.referer {
+            rpc_options.insert("referer".to_string(), Value::String(referer.clone()));
+        }
+        
+        if let Some(limit) = options.throttle.task_limit() {
+            rpc_options.insert("max-download-limit".to_string(), limit_option(Some(limit)));
+        }
+        
+        let gid = client.add_uri(&[url], rpc_options).await?;
+        let download = RpcDownload::new(client, gid);
+        let mut applied_limits = (self.overall_limit(&options.throttle), options.throttle.task_limit());
+        
+        loop {
+            // 実行中に変わった速度制限を反映する
+            let limits = (self.overall_limit(&options.throttle), options.throttle.task_limit());
+            if limits.0 != applied_limits.0 {
+                let mut global_options = Map::new();
+                global_options.insert("max-overall-download-limit".to_string(), limit_option(limits.0));
+                client.change_global_option(global_options).await?;
+            }
+            if limits.1 != applied_limits.1 {
+                let mut task_options = Map::new();
+                task_options.insert("max-download-limit".to_string(), limit_option(limits.1));
+                client.change_option(download.gid(), task_options).await?;
+            }
+            applied_limits = limits;
             
-            return Err(DownloadError::ProcessFailed(error_message));
+            let status = client.tell_status(download.gid()).await?;
+            if let Some(callback) = &progress_callback {
+                callback(status_progress(&status));
+            }
+        
+            match status.status.as_str() {
+                "complete" => break,
+                "error" => {
+                    download.finish().await;
+                    let exit_code = status.error_code.as_deref().and_then(|code| code.parse().ok());
+                    let message = format!(
+                        "aria2cのダウンロードに失敗しました（コード {}）: {}",
+                        status.error_code.unwrap_or_default(),
+                        status.error_message.unwrap_or_default()
+                    );
+                    return Err(classify_error(exit_code, &message, url));
+                }
+                // 他のクライアントから削除された
+                "removed" => {
+                    download.finish().await;
+                    return Err(DownloadError::Cancelled);
+                }
+                // active, waiting, paused（他のクライアントからの一時停止）は継続して待機する
+                _ => tokio::time::sleep(RPC_POLL_INTERVAL).await,
+            }
         }
         
-        // 出力ファイルが存在するか確認
+        let output_file_path = download
+            .finish()
+            .await
+            .unwrap_or_else(|| output_path.join(output_filename));
         if !output_file_path.exists() {
             return Err(DownloadError::FileNotFound);
         }
@@ -194,3 +385,238 @@ impl Aria2cTool {
         Ok(output_file_path)
     }
 }
+
+/// aria2cのオプションの値に変換した速度制限（0は無制限）
+fn limit_option(limit: Option<u64>) -> Value {
+    Value::String(limit.unwrap_or(0).to_string())
+}
+
+/// aria2cの終了コード（RPCではerrorCode）からエラーの種類を判定
+///
+/// 終了コードの意味はaria2cのマニュアルの「EXIT STATUS」を参照。
+fn classify_error(exit_code: Option<i32>, message: &str, url: &str) -> DownloadError {
+    match exit_code {
+        // シグナルで終了した、または中断されて未完了のダウンロードが残った
+        None | Some(7) => diagnostics::terminated("aria2c", message),
+        // タイムアウト、または速度が下限を下回った
+        Some(2) | Some(5) => DownloadError::Timeout(diagnostics::summary(message)),
+        Some(3) => DownloadError::HttpStatus { status: 404, url: url.to_string() },
+        // ネットワークの問題、名前解決の失敗
+        Some(6) | Some(19) => DownloadError::NetworkUnreachable(diagnostics::summary(message)),
+        Some(9) => DownloadError::DiskFull(diagnostics::summary(message)),
+        Some(24) => DownloadError::LoginRequired(diagnostics::summary(message)),
+        // 不明なオプション（古いバージョンが新しいオプションを知らない）
+        Some(28) => DownloadError::ToolTooOld {
+            tool: "aria2c".to_string(),
+            message: diagnostics::summary(message),
+        },
+        _ => diagnostics::classify_common(message, url)
+            .unwrap_or_else(|| DownloadError::ProcessFailed(message.to_string())),
+    }
+}
+
+/// ヘッダー・Cookie・User-Agent・リファラーの引数
+fn request_args(request: &RequestContext) -> Vec<String> {
+    let mut args: Vec<String> = request
+        .extra_headers()
+        .iter()
+        .map(|(name, value)| format!("--header={}: {}", name, value))
+        .collect();
+    if let Some(cookie_file) = &request.cookie_file {
+        args.push(format!("--load-cookies={}", cookie_file.to_string_lossy()));
+    }
+    if let Some(user_agent) = &request.user_agent {
+        args.push(format!("--user-agent={}", user_agent));
+    }
+    if let Some(referer) = &request.referer {
+        args.push(format!("--referer={}", referer));
+    }
+    args
+}
+
+/// `aria2.tellStatus` の結果を進捗情報に変換
+fn status_progress(status: &Aria2Status) -> ProgressInfo {
+    let total_bytes = Some(status.total_length).filter(|total| *total > 0);
+    let remaining = status.total_length.saturating_sub(status.completed_length);
+    
+    ProgressInfo {
+        phase: ProgressPhase::DownloadingVideo,
+        progress: total_bytes
+            .map(|total| status.completed_length as f64 / total as f64)
+            .unwrap_or(0.0),
+        downloaded_bytes: status.completed_length,
+        total_bytes,
+        speed: Some(status.download_speed as f64),
+        eta: (total_bytes.is_some() && status.download_speed > 0)
+            .then(|| Duration::from_secs(remaining / status.download_speed)),
+    }
+}
+
+/// aria2cの進捗表示の1行を解析
+///
+/// RPCを使えない場合（RPCモードでないaria2cや、yt-dlpから起動したaria2c）の代わりの手段で、
+/// 表示の形式が変わると進捗を取得できなくなる。
+/// `[#2089b0 409600B/34812345B(1%) CN:1 DL:118476B ETA:4m51s]` の形式のみを受け付け、
+/// 要約の見出しやファイル名、結果の表などの行は無視する。
+/// `--human-readable` が有効な場合の `KiB` などの単位にも対応する。
+pub(crate) fn parse_readout(line: &str) -> Option<ProgressInfo> {
+    static READOUT_RE: OnceLock<Regex> = OnceLock::new();
+    let readout_re = READOUT_RE.get_or_init(|| {
+        Regex::new(concat!(
+            r"^\[#[0-9a-f]+ ",
+            r"(?P<done>[\d.]+(?:[KMGT]i)?B)/(?P<total>[\d.]+(?:[KMGT]i)?B)(?:\(\d+%\))?",
+            r"(?: CN:\d+)?(?: SD:\d+)?",
+            r"(?: DL:(?P<speed>[\d.]+(?:[KMGT]i)?B))?(?: UL:\S+)?",
+            r"(?: ETA:(?P<eta>(?:\d+h)?(?:\d+m)?(?:\d+s)?))?\]$"
+        ))
+        .unwrap()
+    });
+
+    // 端末向けの出力では行頭に復帰文字が含まれる
+    let line = line.rsplit('\r').next()?.trim();
+    let caps = readout_re.captures(line)?;
+
+    let downloaded_bytes = parse_size(&caps["done"])?;
+    let total_bytes = parse_size(&caps["total"]).filter(|total| *total > 0);
+    let progress = match total_bytes {
+        Some(total) => (downloaded_bytes as f64 / total as f64).min(1.0),
+        None => 0.0,
+    };
+
+    Some(ProgressInfo {
+        phase: ProgressPhase::DownloadingVideo,
+        progress,
+        downloaded_bytes,
+        total_bytes,
+        speed: caps.name("speed").and_then(|speed| parse_size(speed.as_str())).map(|speed| speed as f64),
+        eta: caps.name("eta").and_then(|eta| parse_eta(eta.as_str())),
+    })
+}
+
+/// `409600B` や `1.2MiB` をバイト数に変換
+fn parse_size(value: &str) -> Option<u64> {
+    let value = value.strip_suffix('B')?;
+    let (number, unit) = match value.strip_suffix('i') {
+        Some(rest) => rest.split_at(rest.len().checked_sub(1)?),
+        None => (value, ""),
+    };
+    let multiplier: u64 = match unit {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return None,
+    };
+    let number: f64 = number.parse().ok()?;
+    Some((number * multiplier as f64).round() as u64)
+}
+
+/// `1h2m3s` 形式の残り時間を変換
+fn parse_eta(value: &str) -> Option<Duration> {
+    let mut seconds = 0;
+    let mut digits = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let number: u64 = digits.parse().ok()?;
+        digits.clear();
+        seconds += number * match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+    }
+    if !digits.is_empty() || value.is_empty() {
+        return None;
+    }
+    Some(Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://example.com/video.mp4";
+
+    #[test]
+    fn parses_readouts_from_the_console_output() {
+        let output = concat!(
+            "\n",
+            "01/31 12:00:00 [NOTICE] Downloading 1 item(s)\n",
+            "\r[#2089b0 0B/0B CN:1 DL:0B]\n",
+            "\n",
+            " *** Download Progress Summary as of Wed Jan 31 12:00:01 2024 ***\n",
+            "===============================================================================\n",
+            "[#2089b0 409600B/34812345B(1%) CN:1 DL:118476B ETA:4m51s]\n",
+            "FILE: /tmp/video.mp4\n",
+            "-------------------------------------------------------------------------------\n",
+            "\n",
+            "\r[#2089b0 1.0MiB/33.2MiB(3%) CN:4 SD:2 DL:512KiB ETA:1m4s]\n",
+            "\r[#2089b0 34812345B/34812345B(100%) CN:1 DL:2516582B]\n",
+            "01/31 12:00:15 [NOTICE] Download complete: /tmp/video.mp4\n",
+            "\n",
+            "Download Results:\n",
+            "gid   |stat|avg speed  |path/URI\n",
+            "======+====+===========+=======================================================\n",
+            "2089b0|OK  |   2.4MiB/s|/tmp/video.mp4\n",
+            "\n",
+            "Status Legend:\n",
+            "(OK):download completed.\n",
+        );
+
+        let readouts: Vec<ProgressInfo> = output.lines().filter_map(parse_readout).collect();
+
+        assert_eq!(readouts.len(), 4);
+        // 合計がわからない間は進捗を0とする
+        assert_eq!(readouts[0].total_bytes, None);
+        assert_eq!(readouts[0].progress, 0.0);
+
+        assert_eq!(readouts[1].downloaded_bytes, 409600);
+        assert_eq!(readouts[1].total_bytes, Some(34812345));
+        assert_eq!(readouts[1].speed, Some(118476.0));
+        assert_eq!(readouts[1].eta, Some(Duration::from_secs(291)));
+
+        assert_eq!(readouts[2].downloaded_bytes, 1 << 20);
+        assert_eq!(readouts[2].speed, Some(512.0 * 1024.0));
+        assert_eq!(readouts[2].eta, Some(Duration::from_secs(64)));
+
+        assert_eq!(readouts[3].progress, 1.0);
+        assert_eq!(readouts[3].eta, None);
+    }
+
+    #[test]
+    fn classifies_exit_codes() {
+        let classify = |exit_code| classify_error(Some(exit_code), "errorCode=1 failed\n", URL);
+
+        assert!(matches!(classify(2), DownloadError::Timeout(_)));
+        assert!(matches!(classify(5), DownloadError::Timeout(_)));
+        assert!(matches!(classify(3), DownloadError::HttpStatus { status: 404, .. }));
+        assert!(matches!(classify(6), DownloadError::NetworkUnreachable(_)));
+        assert!(matches!(classify(19), DownloadError::NetworkUnreachable(_)));
+        assert!(matches!(classify(9), DownloadError::DiskFull(_)));
+        assert!(matches!(classify(24), DownloadError::LoginRequired(_)));
+        assert!(matches!(classify(28), DownloadError::ToolTooOld { ref tool, .. } if tool == "aria2c"));
+    }
+
+    #[test]
+    fn interrupted_downloads_are_not_fatal() {
+        for exit_code in [None, Some(7)] {
+            let err = classify_error(exit_code, "Shutdown sequence commencing...\n", URL);
+            assert!(matches!(err, DownloadError::ProcessFailed(_)), "{:?}", err);
+            assert!(!err.is_fatal());
+        }
+    }
+
+    #[test]
+    fn other_exit_codes_fall_back_to_the_message() {
+        let err = classify_error(Some(22), "errorCode=22 status=503 Service Unavailable\n", URL);
+        assert!(matches!(err, DownloadError::HttpStatus { status: 503, url: ref failed } if failed == URL), "{:?}", err);
+
+        let err = classify_error(Some(1), "Exception caught\n", URL);
+        assert!(matches!(err, DownloadError::ProcessFailed(ref message) if message == "Exception caught\n"), "{:?}", err);
+    }
+}
@@ -0,0 +1,425 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use serde::{Deserialize, Deserializer};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+use tokio::process::{Child, Command};
+use crate::types::DownloadError;
+
+/// 常駐したaria2cの起動を待つ最大時間
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// aria2cのJSON-RPCクライアント
+pub struct Aria2RpcClient {
+    client: reqwest::Client,
+    endpoint: String,
+    secret: Option<String>,
+    next_id: AtomicU64,
+}
+
+impl Aria2RpcClient {
+    /// 新しいAria2RpcClientを作成
+    ///
+    /// `endpoint` は `http://127.0.0.1:6800/jsonrpc` の形式。
+    pub fn new(endpoint: &str, secret: Option<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            endpoint: endpoint.to_string(),
+            secret,
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// RPCメソッドを呼び出す
+    pub async fn call<T: DeserializeOwned>(&self, method: &str, params: Vec<Value>) -> Result<T, DownloadError> {
+        // シークレットトークンは先頭の引数として渡す
+        let params: Vec<Value> = self
+            .secret
+            .iter()
+            .map(|secret| Value::String(format!("token:{}", secret)))
+            .chain(params)
+            .collect();
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": self.next_id.fetch_add(1, Ordering::Relaxed).to_string(),
+            "method": method,
+            "params": params,
+        });
+
+        let response: Value = self
+            .client
+            .post(&self.endpoint)
+            .json(&request)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if let Some(error) = response.get("error") {
+            let message = error.get("message").and_then(Value::as_str).unwrap_or("不明なエラー");
+            return Err(DownloadError::ProcessFailed(format!("aria2cのRPC呼び出し（{}）に失敗: {}", method, message)));
+        }
+        let result = response.get("result").cloned().unwrap_or(Value::Null);
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// バージョン情報を取得
+    pub async fn get_version(&self) -> Result<String, DownloadError> {
+        let version: Value = self.call("aria2.getVersion", Vec::new()).await?;
+        Ok(version.get("version").and_then(Value::as_str).unwrap_or("不明なバージョン").to_string())
+    }
+
+    /// ダウンロードを追加し、GIDを返す
+    pub async fn add_uri(&self, uris: &[&str], options: Map<String, Value>) -> Result<String, DownloadError> {
+        self.call("aria2.addUri", vec![json!(uris), Value::Object(options)]).await
+    }
+
+    /// ダウンロードの状態を取得
+    pub async fn tell_status(&self, gid: &str) -> Result<Aria2Status, DownloadError> {
+        self.call("aria2.tellStatus", vec![json!(gid)]).await
+    }
+
+    /// ダウンロードを一時停止
+    pub async fn pause(&self, gid: &str) -> Result<(), DownloadError> {
+        self.call::<String>("aria2.pause", vec![json!(gid)]).await.map(|_| ())
+    }
+
+    /// 一時停止したダウンロードを再開
+    pub async fn unpause(&self, gid: &str) -> Result<(), DownloadError> {
+        self.call::<String>("aria2.unpause", vec![json!(gid)]).await.map(|_| ())
+    }
+
+    /// ダウンロードを中止（途中までのデータと制御ファイルは残る）
+    pub async fn remove(&self, gid: &str) -> Result<(), DownloadError> {
+        self.call::<String>("aria2.forceRemove", vec![json!(gid)]).await.map(|_| ())
+    }
+
+    /// 終了したダウンロードの結果をメモリから削除
+    pub async fn remove_download_result(&self, gid: &str) -> Result<(), DownloadError> {
+        self.call::<String>("aria2.removeDownloadResult", vec![json!(gid)]).await.map(|_| ())
+    }
+
+    /// ダウンロードのオプションを変更
+    pub async fn change_option(&self, gid: &str, options: Map<String, Value>) -> Result<(), DownloadError> {
+        self.call::<String>("aria2.changeOption", vec![json!(gid), Value::Object(options)]).await.map(|_| ())
+    }
+
+    /// 全体のオプションを変更
+    pub async fn change_global_option(&self, options: Map<String, Value>) -> Result<(), DownloadError> {
+        self.call::<String>("aria2.changeGlobalOption", vec![Value::Object(options)]).await.map(|_| ())
+    }
+
+    /// aria2cを終了
+    pub async fn shutdown(&self) -> Result<(), DownloadError> {
+        self.call::<String>("aria2.forceShutdown", Vec::new()).await.map(|_| ())
+    }
+}
+
+/// `aria2.tellStatus` で取得したダウンロードの状態
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Aria2Status {
+    /// GID
+    pub gid: String,
+    /// 状態（active, waiting, paused, error, complete, removed）
+    pub status: String,
+    /// 合計バイト数（不明な場合は0）
+    #[serde(deserialize_with = "number_string")]
+    pub total_length: u64,
+    /// ダウンロード済みのバイト数
+    #[serde(deserialize_with = "number_string")]
+    pub completed_length: u64,
+    /// ダウンロード速度（バイト/秒）
+    #[serde(deserialize_with = "number_string")]
+    pub download_speed: u64,
+    /// エラーコード
+    pub error_code: Option<String>,
+    /// エラーメッセージ
+    pub error_message: Option<String>,
+    /// ダウンロード対象のファイル
+    #[serde(default)]
+    pub files: Vec<Aria2File>,
+}
+
+/// ダウンロード対象のファイル
+#[derive(Debug, Clone, Deserialize)]
+pub struct Aria2File {
+    /// 保存先のパス
+    pub path: String,
+}
+
+/// aria2cは数値を文字列で返すため変換する
+fn number_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    value.parse().map_err(serde::de::Error::custom)
+}
+
+/// `--enable-rpc` で常駐させたaria2cプロセス
+///
+/// ローカルのみで待ち受け、起動ごとに生成したシークレットトークンで保護する。
+/// 破棄するとプロセスも終了する。
+pub struct Aria2cDaemon {
+    client: Aria2RpcClient,
+    _child: Child,
+}
+
+impl Aria2cDaemon {
+    /// aria2cを起動し、RPCに応答するまで待機
+    pub async fn start(executable_path: &Path, extra_args: &[String]) -> Result<Self, DownloadError> {
+        // 空いているポートを確保してからaria2cに渡す
+        let port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+        let secret = uuid::Uuid::new_v4().simple().to_string();
+
+        let mut child = Command::new(executable_path)
+            .args([
+                "--enable-rpc=true".to_string(),
+                "--rpc-listen-all=false".to_string(),
+                format!("--rpc-listen-port={}", port),
+                format!("--rpc-secret={}", secret),
+                // 一時停止後の再開のため、制御ファイル（.aria2）を頻繁に保存して続きから取得する
+                "--continue=true".to_string(),
+                "--auto-save-interval=1".to_string(),
+                "--file-allocation=none".to_string(),
+            ])
+            .args(extra_args)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let client = Aria2RpcClient::new(&format!("http://127.0.0.1:{}/jsonrpc", port), Some(secret));
+        let started_at = std::time::Instant::now();
+        loop {
+            match client.get_version().await {
+                Ok(version) => {
+                    log::info!("aria2c {} をRPCモードで起動しました（ポート {}）", version, port);
+                    break;
+                }
+                Err(_) if started_at.elapsed() < STARTUP_TIMEOUT => {
+                    if let Some(status) = child.try_wait()? {
+                        return Err(DownloadError::ProcessFailed(format!(
+                            "aria2cが起動直後に終了しました: {}",
+                            status
+                        )));
+                    }
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(Self { client, _child: child })
+    }
+
+    /// RPCクライアント
+    pub fn client(&self) -> &Aria2RpcClient {
+        &self.client
+    }
+}
+
+/// RPCで追加したダウンロード
+///
+/// 完了前に破棄された場合（キャンセル・一時停止）はaria2c側のダウンロードも中止する。
+/// 途中までのデータと制御ファイルは残るため、同じ出力先で追加し直すと続きから取得する。
+pub(crate) struct RpcDownload<'a> {
+    client: &'a Aria2RpcClient,
+    gid: String,
+    finished: bool,
+}
+
+impl<'a> RpcDownload<'a> {
+    pub(crate) fn new(client: &'a Aria2RpcClient, gid: String) -> Self {
+        Self { client, gid, finished: false }
+    }
+
+    pub(crate) fn gid(&self) -> &str {
+        &self.gid
+    }
+
+    /// 終了したダウンロードの結果を片付ける
+    pub(crate) async fn finish(mut self) -> Option<PathBuf> {
+        self.finished = true;
+        let path = self
+            .client
+            .tell_status(&self.gid)
+            .await
+            .ok()
+            .and_then(|status| status.files.into_iter().next())
+            .map(|file| PathBuf::from(file.path));
+        if let Err(err) = self.client.remove_download_result(&self.gid).await {
+            log::warn!("aria2cのダウンロード結果の削除に失敗しました: {}", err);
+        }
+        path
+    }
+}
+
+impl Drop for RpcDownload<'_> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        // 破棄時には待機できないため、同じ接続先の別クライアントで中止を要求する
+        let client = Aria2RpcClient::new(&self.client.endpoint, self.client.secret.clone());
+        let gid = self.gid.clone();
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                if let Err(err) = client.remove(&gid).await {
+                    log::warn!("aria2cのダウンロード {} の中止に失敗しました: {}", gid, err);
+                }
+                let _ = client.remove_download_result(&gid).await;
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{Request, Response, TestServer};
+
+    /// メソッドごとに決めた結果を返すJSON-RPCサーバー
+    async fn rpc_server() -> TestServer {
+        TestServer::start(|request: &Request| {
+            let body: Value = serde_json::from_slice(&request.body).expect("JSON-RPCのリクエスト");
+            let result = match body["method"].as_str().unwrap_or_default() {
+                "aria2.addUri" => json!("2089b05ecca3d829"),
+                "aria2.tellStatus" => json!({
+                    "gid": "2089b05ecca3d829",
+                    "status": "active",
+                    "totalLength": "34896138",
+                    "completedLength": "1048576",
+                    "downloadSpeed": "524288",
+                    "files": [{ "path": "/downloads/video.mp4", "length": "34896138" }],
+                }),
+                "aria2.getVersion" => json!({ "version": "1.37.0", "enabledFeatures": [] }),
+                "aria2.pause" | "aria2.unpause" | "aria2.forceRemove" | "aria2.changeGlobalOption" => json!("OK"),
+                _ => {
+                    return Response::ok(json!({
+                        "jsonrpc": "2.0",
+                        "id": body["id"],
+                        "error": { "code": 1, "message": "Unauthorized" },
+                    }).to_string());
+                }
+            };
+            Response::ok(json!({ "jsonrpc": "2.0", "id": body["id"], "result": result }).to_string())
+                .with_header("Content-Type", "application/json")
+        })
+        .await
+    }
+
+    fn bodies(server: &TestServer) -> Vec<Value> {
+        server
+            .requests()
+            .iter()
+            .map(|request| {
+                assert_eq!(request.method, "POST");
+                assert_eq!(request.path, "/jsonrpc");
+                serde_json::from_slice(&request.body).unwrap()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn add_uri_sends_the_secret_uris_and_options() {
+        let server = rpc_server().await;
+        let client = Aria2RpcClient::new(&server.url("/jsonrpc"), Some("s3cret".to_string()));
+
+        let mut options = Map::new();
+        options.insert("dir".to_string(), json!("/downloads"));
+        options.insert("out".to_string(), json!("video.mp4"));
+        let gid = client
+            .add_uri(&["https://example.com/video.mp4", "https://mirror.example.com/video.mp4"], options)
+            .await
+            .unwrap();
+
+        assert_eq!(gid, "2089b05ecca3d829");
+        assert_eq!(bodies(&server), vec![json!({
+            "jsonrpc": "2.0",
+            "id": "1",
+            "method": "aria2.addUri",
+            "params": [
+                "token:s3cret",
+                ["https://example.com/video.mp4", "https://mirror.example.com/video.mp4"],
+                { "dir": "/downloads", "out": "video.mp4" },
+            ],
+        })]);
+    }
+
+    #[tokio::test]
+    async fn tell_status_parses_numbers_sent_as_strings() {
+        let server = rpc_server().await;
+        let client = Aria2RpcClient::new(&server.url("/jsonrpc"), None);
+
+        let status = client.tell_status("2089b05ecca3d829").await.unwrap();
+
+        assert_eq!(status.gid, "2089b05ecca3d829");
+        assert_eq!(status.status, "active");
+        assert_eq!(status.total_length, 34_896_138);
+        assert_eq!(status.completed_length, 1_048_576);
+        assert_eq!(status.download_speed, 524_288);
+        assert_eq!(status.error_code, None);
+        assert_eq!(status.files.len(), 1);
+        assert_eq!(status.files[0].path, "/downloads/video.mp4");
+        assert_eq!(bodies(&server)[0]["params"], json!(["2089b05ecca3d829"]));
+    }
+
+    #[tokio::test]
+    async fn control_methods_send_the_gid_with_increasing_ids() {
+        let server = rpc_server().await;
+        let client = Aria2RpcClient::new(&server.url("/jsonrpc"), Some("s3cret".to_string()));
+
+        client.pause("2089b05ecca3d829").await.unwrap();
+        client.unpause("2089b05ecca3d829").await.unwrap();
+        client.remove("2089b05ecca3d829").await.unwrap();
+        let mut options = Map::new();
+        options.insert("max-overall-download-limit".to_string(), json!("1048576"));
+        client.change_global_option(options).await.unwrap();
+
+        let calls: Vec<(String, String, Value)> = bodies(&server)
+            .into_iter()
+            .map(|body| {
+                (
+                    body["id"].as_str().unwrap().to_string(),
+                    body["method"].as_str().unwrap().to_string(),
+                    body["params"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(calls, vec![
+            ("1".to_string(), "aria2.pause".to_string(), json!(["token:s3cret", "2089b05ecca3d829"])),
+            ("2".to_string(), "aria2.unpause".to_string(), json!(["token:s3cret", "2089b05ecca3d829"])),
+            ("3".to_string(), "aria2.forceRemove".to_string(), json!(["token:s3cret", "2089b05ecca3d829"])),
+            (
+                "4".to_string(),
+                "aria2.changeGlobalOption".to_string(),
+                json!(["token:s3cret", { "max-overall-download-limit": "1048576" }]),
+            ),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn error_response_becomes_a_process_error() {
+        let server = rpc_server().await;
+        let client = Aria2RpcClient::new(&server.url("/jsonrpc"), Some("wrong".to_string()));
+
+        let result = client.shutdown().await;
+
+        match result {
+            Err(DownloadError::ProcessFailed(message)) => {
+                assert!(message.contains("aria2.forceShutdown"), "{}", message);
+                assert!(message.contains("Unauthorized"), "{}", message);
+            }
+            other => panic!("ProcessFailedになるはず: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn get_version_reads_the_version_field() {
+        let server = rpc_server().await;
+        let client = Aria2RpcClient::new(&server.url("/jsonrpc"), None);
+
+        assert_eq!(client.get_version().await.unwrap(), "1.37.0");
+        assert_eq!(bodies(&server)[0]["params"], json!([]));
+    }
+}
@@ -0,0 +1,355 @@
+use std::path::{Path, PathBuf};
+use futures::StreamExt;
+use url::Url;
+use crate::types::{DownloadError, ProgressCallback, ProgressPhase, DownloadOptions, VideoFormat, FormatInfo, FormatSelection};
+use crate::retry::RetryPolicy;
+use crate::tools::FFmpegTool;
+use crate::tools::fetcher::{self, SegmentFetcher, SegmentProgress};
+
+pub mod mpd;
+pub mod sidx;
+
+pub use self::mpd::{Mpd, Period, AdaptationSet, Representation, TrackKind, SegmentAddressing};
+
+use self::mpd::SegmentUrl;
+
+/// 同時に取得するセグメント数の上限（トラックごと）
+const MAX_CONCURRENT_SEGMENTS: u32 = 16;
+
+/// MPEG-DASHダウンロードを扱うための構造体
+///
+/// MPDを自前で解析して映像と音声のレプリゼンテーションを選び、
+/// 両方のトラックを並行して取得してからFFmpegToolで多重化する。
+pub struct DashDownloadTool {
+    ffmpeg: FFmpegTool,
+    client: reqwest::Client,
+}
+
+impl Default for DashDownloadTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DashDownloadTool {
+    /// 新しいDashDownloadToolを作成
+    pub fn new() -> Self {
+        Self::with_tools(FFmpegTool::new(), fetcher::http_client())
+    }
+
+    /// 使用するFFmpegToolとHTTPクライアントを指定してDashDownloadToolを作成
+    pub fn with_tools(ffmpeg: FFmpegTool, client: reqwest::Client) -> Self {
+        Self { ffmpeg, client }
+    }
+
+    /// MPDを取得して解析
+    pub async fn load_manifest(&self, url: &str) -> Result<Mpd, DownloadError> {
+        let fetcher = SegmentFetcher::new(self.client.clone(), RetryPolicy::none());
+        load_mpd(&fetcher, url).await
+    }
+
+    /// 選択可能なフォーマット（最初の期間のレプリゼンテーション）の一覧
+    pub async fn list_formats(&self, url: &str) -> Result<Vec<FormatInfo>, DownloadError> {
+        let mpd = self.load_manifest(url).await?;
+        let Some(period) = mpd.periods.first() else {
+            return Ok(Vec::new());
+        };
+
+        Ok(period
+            .adaptation_sets
+            .iter()
+            .filter(|set| matches!(set.kind, TrackKind::Video | TrackKind::Audio))
+            .flat_map(|set| set.representations.iter().map(move |rep| representation_format(set, rep)))
+            .collect())
+    }
+
+    /// DASHストリームをダウンロード
+    pub async fn download(
+        &self,
+        url: &str,
+        output_path: &Path,
+        filename: &str,
+        options: &DownloadOptions,
+        progress_callback: Option<ProgressCallback>
+    ) -> Result<PathBuf, DownloadError> {
+        let fetcher = SegmentFetcher::new(
+            fetcher::request_client(&self.client, &options.request, &parse_url(url)?)?,
+            RetryPolicy::from_options(options)
+        )
+        .with_throttle(options.throttle.clone());
+        let progress = SegmentProgress::new(progress_callback);
+        progress.phase(ProgressPhase::Probing);
+
+        let mpd = load_mpd(&fetcher, url).await?;
+        if mpd.is_dynamic {
+            return Err(DownloadError::InvalidManifest(
+                "ライブ配信（type=\"dynamic\"）のMPDには対応していません".to_string()
+            ));
+        }
+
+        // 期間ごとに映像と音声のセグメントを決定する
+        let mut video_periods = Vec::new();
+        let mut audio_periods = Vec::new();
+        for period in &mpd.periods {
+            if !matches!(options.format, VideoFormat::Mp3) {
+                if let Some(rep) = select_representation(period, TrackKind::Video, &options.selection)? {
+                    video_periods.push(plan_segments(&fetcher, rep, period.duration).await?);
+                }
+            }
+            if let Some(rep) = select_representation(period, TrackKind::Audio, &options.selection)? {
+                audio_periods.push(plan_segments(&fetcher, rep, period.duration).await?);
+            }
+        }
+
+        if video_periods.is_empty() && audio_periods.is_empty() {
+            return Err(DownloadError::InvalidManifest("ダウンロードできるトラックがありません".to_string()));
+        }
+
+        // 取得済みのセグメントは一時停止・中断後の再開時に再利用する
+        let work_dir = output_path.join(format!("{}.dash.part", filename));
+        tokio::fs::create_dir_all(&work_dir).await?;
+
+        let total_segments = video_periods
+            .iter()
+            .chain(&audio_periods)
+            .map(|plan| plan.segments.len() as u64)
+            .sum();
+        progress.set_total_segments(total_segments);
+        let concurrency = options.connections.clamp(1, MAX_CONCURRENT_SEGMENTS) as usize;
+
+        let (video_dir, audio_dir) = (work_dir.join("video"), work_dir.join("audio"));
+        let (video_files, audio_files) = tokio::try_join!(
+            download_track(&fetcher, &video_periods, &video_dir, concurrency, &progress, ProgressPhase::DownloadingVideo),
+            download_track(&fetcher, &audio_periods, &audio_dir, concurrency, &progress, ProgressPhase::DownloadingAudio)
+        )?;
+
+        let tracks: Vec<Vec<PathBuf>> = [video_files, audio_files]
+            .into_iter()
+            .filter(|files| !files.is_empty())
+            .collect();
+
+        let output_file = if self.ffmpeg.is_available().await {
+            progress.phase(ProgressPhase::Merging);
+            self.ffmpeg
+                .merge_tracks(&tracks, output_path, filename, &options.format)
+                .await?
+        } else {
+            match tracks.as_slice() {
+                // 単一のファイルになる場合はffmpegなしでもそのまま出力できる
+                [files] if files.len() == 1 => {
+                    log::warn!("ffmpegが見つからないため、多重化せずに保存します");
+                    let extension = files[0].extension().unwrap_or_default().to_string_lossy().to_string();
+                    let output_file = output_path.join(format!("{}.{}", filename, extension));
+                    tokio::fs::rename(&files[0], &output_file).await?;
+                    output_file
+                }
+                _ => {
+                    return Err(DownloadError::ProcessFailed(
+                        "映像と音声の多重化にはffmpegが必要です".to_string()
+                    ));
+                }
+            }
+        };
+
+        tokio::fs::remove_dir_all(&work_dir).await?;
+        Ok(output_file)
+    }
+}
+
+async fn load_mpd(fetcher: &SegmentFetcher, url: &str) -> Result<Mpd, DownloadError> {
+    let url = parse_url(url)?;
+    Mpd::parse(&fetcher.fetch_text(&url).await?, &url)
+}
+
+fn parse_url(url: &str) -> Result<Url, DownloadError> {
+    Url::parse(url).map_err(|err| DownloadError::InvalidManifest(format!("不正なURL {}: {}", url, err)))
+}
+
+/// 指定した種類のトラックから選択条件に最も合うレプリゼンテーションを選ぶ
+///
+/// DRMで保護されたものしかない場合はエラーにする。
+fn select_representation<'a>(
+    period: &'a Period,
+    kind: TrackKind,
+    selection: &FormatSelection
+) -> Result<Option<&'a Representation>, DownloadError> {
+    let candidates: Vec<(&AdaptationSet, &Representation)> = period
+        .adaptation_sets
+        .iter()
+        .filter(|set| set.kind == kind)
+        .flat_map(|set| set.representations.iter().map(move |rep| (set, rep)))
+        .collect();
+
+    let available: Vec<&(&AdaptationSet, &Representation)> = candidates.iter().filter(|(_, rep)| !rep.protected).collect();
+    if available.is_empty() && !candidates.is_empty() {
+        return Err(DownloadError::DrmProtected("ContentProtection (MPEG-DASH)".to_string()));
+    }
+
+    let formats: Vec<FormatInfo> = available.iter().map(|(set, rep)| representation_format(set, rep)).collect();
+    let selected = match kind {
+        TrackKind::Video => selection.select_video(&formats),
+        _ => selection.select_audio(&formats),
+    };
+
+    match selected {
+        Some(format) => {
+            let index = formats.iter().position(|f| std::ptr::eq(f, format)).unwrap_or(0);
+            Ok(Some(available[index].1))
+        }
+        // 映像でフォーマットIDが一致しない場合は指定ミスとして扱う
+        None if kind == TrackKind::Video && selection.format_id.is_some() && !formats.is_empty() => {
+            Err(DownloadError::InvalidManifest(format!(
+                "指定されたフォーマットが見つかりません: {}",
+                selection.format_id.as_deref().unwrap_or_default()
+            )))
+        }
+        None => Ok(None),
+    }
+}
+
+/// レプリゼンテーションのフォーマット情報
+fn representation_format(set: &AdaptationSet, rep: &Representation) -> FormatInfo {
+    let (vcodec, acodec) = match set.kind {
+        TrackKind::Video => (rep.codecs.clone().or(Some("unknown".to_string())), Some("none".to_string())),
+        _ => (Some("none".to_string()), rep.codecs.clone()),
+    };
+
+    FormatInfo {
+        format_id: Some(rep.id.clone()),
+        url: Some(rep.base_url.to_string()),
+        width: rep.width,
+        height: rep.height,
+        ext: Some(
+            match rep.mime_type.as_deref() {
+                Some(mime_type) if mime_type.ends_with("webm") => "webm",
+                _ => "mp4",
+            }
+            .to_string()
+        ),
+        vcodec,
+        acodec,
+        tbr: Some(rep.bandwidth as f64 / 1000.0),
+        fps: rep.frame_rate,
+        language: set.lang.clone(),
+        ..Default::default()
+    }
+}
+
+/// 1つの期間・レプリゼンテーションで取得するセグメント
+struct SegmentPlan {
+    initialization: Option<SegmentUrl>,
+    segments: Vec<SegmentUrl>,
+    extension: &'static str,
+}
+
+/// レプリゼンテーションのセグメント一覧を決定する
+///
+/// SegmentBaseの場合はindexRangeのsidxを取得してサブセグメントに分割する。
+async fn plan_segments(
+    fetcher: &SegmentFetcher,
+    rep: &Representation,
+    period_duration: Option<f64>
+) -> Result<SegmentPlan, DownloadError> {
+    let extension = match rep.mime_type.as_deref() {
+        Some(mime_type) if mime_type.ends_with("webm") => "webm",
+        _ => "mp4",
+    };
+    let whole_file = || (None, vec![SegmentUrl { url: rep.base_url.clone(), byte_range: None }]);
+
+    let (initialization, segments) = match &rep.addressing {
+        SegmentAddressing::Template(template) => template.expand(rep, period_duration)?,
+        SegmentAddressing::List(list) => (list.initialization.clone(), list.segments.clone()),
+        SegmentAddressing::Base(base) => match base.index_range {
+            Some(index_range) => {
+                let index = fetcher.fetch(&rep.base_url, Some(index_range)).await?;
+                match sidx::parse_sidx(&index, index_range)? {
+                    Some(ranges) => {
+                        // 初期化セグメントの範囲が省略されている場合はインデックスまでを含める
+                        let init_range = base.initialization.unwrap_or(fetcher::ByteRange {
+                            length: index_range.offset + index_range.length,
+                            offset: 0,
+                        });
+                        let initialization = SegmentUrl { url: rep.base_url.clone(), byte_range: Some(init_range) };
+                        let segments = ranges
+                            .into_iter()
+                            .map(|range| SegmentUrl { url: rep.base_url.clone(), byte_range: Some(range) })
+                            .collect();
+                        (Some(initialization), segments)
+                    }
+                    None => whole_file(),
+                }
+            }
+            None => whole_file(),
+        },
+        SegmentAddressing::Single => whole_file(),
+    };
+
+    if segments.is_empty() {
+        return Err(DownloadError::InvalidManifest(format!("レプリゼンテーション {} にセグメントがありません", rep.id)));
+    }
+
+    Ok(SegmentPlan { initialization, segments, extension })
+}
+
+/// 1つのトラックのセグメントを取得し、期間ごとに連結したファイルを返す
+async fn download_track(
+    fetcher: &SegmentFetcher,
+    periods: &[SegmentPlan],
+    track_dir: &Path,
+    concurrency: usize,
+    progress: &SegmentProgress,
+    phase: ProgressPhase
+) -> Result<Vec<PathBuf>, DownloadError> {
+    if periods.is_empty() {
+        return Ok(Vec::new());
+    }
+    tokio::fs::create_dir_all(track_dir).await?;
+
+    let init_path = |period: usize| track_dir.join(format!("init{}", period));
+    let segment_path = |period: usize, index: usize| track_dir.join(format!("segment{}_{:05}", period, index));
+
+    // （URL, 保存先, メディアセグメントか）
+    let mut resources: Vec<(&SegmentUrl, PathBuf, bool)> = Vec::new();
+    for (period, plan) in periods.iter().enumerate() {
+        if let Some(initialization) = &plan.initialization {
+            resources.push((initialization, init_path(period), false));
+        }
+        for (index, segment) in plan.segments.iter().enumerate() {
+            resources.push((segment, segment_path(period, index), true));
+        }
+    }
+
+    let fetches: Vec<_> = resources
+        .iter()
+        .map(|(segment, path, is_segment)| async move {
+            let size = fetcher.fetch_to_file(&segment.url, segment.byte_range, path, Ok).await?;
+            Ok::<_, DownloadError>((*is_segment, size))
+        })
+        .collect();
+    let mut results = futures::stream::iter(fetches).buffer_unordered(concurrency);
+
+    while let Some(result) = results.next().await {
+        let (is_segment, size) = result?;
+        progress.add(phase, is_segment, size);
+    }
+
+    // 期間ごとに初期化セグメントとメディアセグメントを連結する
+    let mut files = Vec::new();
+    for (period, plan) in periods.iter().enumerate() {
+        let path = track_dir.join(format!("part{}.{}", period, plan.extension));
+        let mut file = tokio::fs::File::create(&path).await?;
+
+        if plan.initialization.is_some() {
+            let mut input = tokio::fs::File::open(init_path(period)).await?;
+            tokio::io::copy(&mut input, &mut file).await?;
+        }
+        for index in 0..plan.segments.len() {
+            let mut input = tokio::fs::File::open(segment_path(period, index)).await?;
+            tokio::io::copy(&mut input, &mut file).await?;
+        }
+
+        files.push(path);
+    }
+
+    Ok(files)
+}
@@ -0,0 +1,729 @@
+use roxmltree::Node;
+use url::Url;
+use crate::types::DownloadError;
+use crate::tools::fetcher::ByteRange;
+
+/// MPEG-DASHのマニフェスト（MPD）
+#[derive(Debug, Clone)]
+pub struct Mpd {
+    /// ライブ配信（type="dynamic"）か
+    pub is_dynamic: bool,
+    /// 全体の長さ（秒）
+    pub duration: Option<f64>,
+    /// 期間
+    pub periods: Vec<Period>,
+}
+
+/// 期間（Period）
+#[derive(Debug, Clone)]
+pub struct Period {
+    /// 期間ID
+    pub id: Option<String>,
+    /// 開始時刻（秒）
+    pub start: f64,
+    /// 長さ（秒）
+    pub duration: Option<f64>,
+    /// アダプテーションセット
+    pub adaptation_sets: Vec<AdaptationSet>,
+}
+
+/// アダプテーションセット（AdaptationSet）
+#[derive(Debug, Clone)]
+pub struct AdaptationSet {
+    /// トラックの種類
+    pub kind: TrackKind,
+    /// 言語
+    pub lang: Option<String>,
+    /// ContentProtection（DRM）が指定されているか
+    pub protected: bool,
+    /// レプリゼンテーション
+    pub representations: Vec<Representation>,
+}
+
+/// トラックの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackKind {
+    /// 映像
+    Video,
+    /// 音声
+    Audio,
+    /// 字幕
+    Text,
+    /// その他
+    Other,
+}
+
+/// レプリゼンテーション（Representation）
+#[derive(Debug, Clone)]
+pub struct Representation {
+    /// レプリゼンテーションID
+    pub id: String,
+    /// ビットレート (bps)
+    pub bandwidth: u64,
+    /// 幅
+    pub width: Option<u32>,
+    /// 高さ
+    pub height: Option<u32>,
+    /// フレームレート
+    pub frame_rate: Option<f64>,
+    /// コーデック
+    pub codecs: Option<String>,
+    /// MIMEタイプ
+    pub mime_type: Option<String>,
+    /// ContentProtection（DRM）が指定されているか
+    pub protected: bool,
+    /// セグメントの基準URL（BaseURLを解決したもの）
+    pub base_url: Url,
+    /// セグメントの指定方法
+    pub addressing: SegmentAddressing,
+}
+
+/// セグメントの指定方法
+#[derive(Debug, Clone)]
+pub enum SegmentAddressing {
+    /// SegmentTemplate
+    Template(SegmentTemplate),
+    /// SegmentList
+    List(SegmentList),
+    /// SegmentBase（単一ファイルとインデックス）
+    Base(SegmentBase),
+    /// BaseURLのファイル全体
+    Single,
+}
+
+/// SegmentTemplate
+#[derive(Debug, Clone, Default)]
+pub struct SegmentTemplate {
+    /// メディアセグメントのURLテンプレート
+    pub media: Option<String>,
+    /// 初期化セグメントのURLテンプレート
+    pub initialization: Option<String>,
+    /// タイムスケール
+    pub timescale: u64,
+    /// セグメント長（タイムスケール単位）
+    pub duration: Option<u64>,
+    /// 最初のセグメント番号
+    pub start_number: u64,
+    /// SegmentTimeline
+    pub timeline: Vec<TimelineEntry>,
+}
+
+/// SegmentTimelineの要素（S）
+#[derive(Debug, Clone, Copy)]
+pub struct TimelineEntry {
+    /// 開始時刻
+    pub t: Option<u64>,
+    /// 長さ
+    pub d: u64,
+    /// 繰り返し回数（-1は次の要素または期間の終わりまで）
+    pub r: i64,
+}
+
+/// SegmentList
+#[derive(Debug, Clone, Default)]
+pub struct SegmentList {
+    /// 初期化セグメント
+    pub initialization: Option<SegmentUrl>,
+    /// メディアセグメント
+    pub segments: Vec<SegmentUrl>,
+}
+
+/// SegmentBase
+#[derive(Debug, Clone, Default)]
+pub struct SegmentBase {
+    /// 初期化セグメントのバイト範囲
+    pub initialization: Option<ByteRange>,
+    /// インデックス（sidx）のバイト範囲
+    pub index_range: Option<ByteRange>,
+}
+
+/// セグメントのURLとバイト範囲
+#[derive(Debug, Clone)]
+pub struct SegmentUrl {
+    /// URL
+    pub url: Url,
+    /// バイト範囲
+    pub byte_range: Option<ByteRange>,
+}
+
+impl Mpd {
+    /// MPDのXMLを解析する
+    ///
+    /// 相対URLは `base_url` を基準に解決する。
+    pub fn parse(text: &str, base_url: &Url) -> Result<Self, DownloadError> {
+        let doc = roxmltree::Document::parse(text)
+            .map_err(|err| DownloadError::InvalidManifest(format!("MPDのXMLが不正です: {}", err)))?;
+        let root = doc.root_element();
+        if !root.has_tag_name("MPD") {
+            return Err(DownloadError::InvalidManifest("MPD要素がありません".to_string()));
+        }
+
+        let base_url = child_base_url(root, base_url)?;
+        let duration = root.attribute("mediaPresentationDuration").and_then(parse_duration);
+
+        let mut periods = Vec::new();
+        let mut next_start = 0.0;
+        for node in children(root, "Period") {
+            let start = node.attribute("start").and_then(parse_duration).unwrap_or(next_start);
+            let period_duration = node.attribute("duration").and_then(parse_duration);
+            next_start = start + period_duration.unwrap_or(0.0);
+
+            periods.push(Period {
+                id: node.attribute("id").map(str::to_string),
+                start,
+                duration: period_duration,
+                adaptation_sets: parse_adaptation_sets(node, &base_url)?,
+            });
+        }
+
+        // 長さが省略された期間は、次の期間の開始時刻または全体の長さから求める
+        for index in 0..periods.len() {
+            if periods[index].duration.is_none() {
+                let end = periods.get(index + 1).map(|next| next.start).or(duration);
+                periods[index].duration = end.map(|end| end - periods[index].start);
+            }
+        }
+
+        if periods.is_empty() {
+            return Err(DownloadError::InvalidManifest("Period要素がありません".to_string()));
+        }
+
+        Ok(Self {
+            is_dynamic: root.attribute("type") == Some("dynamic"),
+            duration,
+            periods,
+        })
+    }
+}
+
+fn parse_adaptation_sets(period: Node, base_url: &Url) -> Result<Vec<AdaptationSet>, DownloadError> {
+    let base_url = child_base_url(period, base_url)?;
+    let mut sets = Vec::new();
+
+    for node in children(period, "AdaptationSet") {
+        let set_base_url = child_base_url(node, &base_url)?;
+        let protected = children(node, "ContentProtection").next().is_some();
+
+        let mut representations = Vec::new();
+        for rep in children(node, "Representation") {
+            // 属性はAdaptationSetから継承する
+            let attribute = |name: &str| rep.attribute(name).or_else(|| node.attribute(name));
+            let addressing = parse_addressing(&[rep, node, period], &set_base_url)?;
+
+            representations.push(Representation {
+                id: rep.attribute("id").unwrap_or_default().to_string(),
+                bandwidth: rep.attribute("bandwidth").and_then(|v| v.parse().ok()).unwrap_or(0),
+                width: attribute("width").and_then(|v| v.parse().ok()),
+                height: attribute("height").and_then(|v| v.parse().ok()),
+                frame_rate: attribute("frameRate").and_then(parse_frame_rate),
+                codecs: attribute("codecs").map(str::to_string),
+                mime_type: attribute("mimeType").map(str::to_string),
+                protected: protected || children(rep, "ContentProtection").next().is_some(),
+                base_url: child_base_url(rep, &set_base_url)?,
+                addressing,
+            });
+        }
+
+        sets.push(AdaptationSet {
+            kind: track_kind(node, representations.first()),
+            lang: node.attribute("lang").map(str::to_string),
+            protected,
+            representations,
+        });
+    }
+
+    Ok(sets)
+}
+
+/// セグメントの指定方法を、Representation、AdaptationSet、Periodの順に探す
+fn parse_addressing(levels: &[Node], set_base_url: &Url) -> Result<SegmentAddressing, DownloadError> {
+    let rep_base_url = child_base_url(levels[0], set_base_url)?;
+
+    // SegmentTemplateは上位の要素の属性を継承する
+    let templates: Vec<Node> = levels.iter().filter_map(|node| children(*node, "SegmentTemplate").next()).collect();
+    if !templates.is_empty() {
+        let attribute = |name: &str| templates.iter().find_map(|node| node.attribute(name));
+        let timeline = templates
+            .iter()
+            .find_map(|node| children(*node, "SegmentTimeline").next())
+            .map(|timeline| {
+                children(timeline, "S")
+                    .map(|s| TimelineEntry {
+                        t: s.attribute("t").and_then(|v| v.parse().ok()),
+                        d: s.attribute("d").and_then(|v| v.parse().ok()).unwrap_or(0),
+                        r: s.attribute("r").and_then(|v| v.parse().ok()).unwrap_or(0),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        return Ok(SegmentAddressing::Template(SegmentTemplate {
+            media: attribute("media").map(str::to_string),
+            initialization: attribute("initialization").map(str::to_string),
+            timescale: attribute("timescale").and_then(|v| v.parse().ok()).unwrap_or(1).max(1),
+            duration: attribute("duration").and_then(|v| v.parse().ok()),
+            start_number: attribute("startNumber").and_then(|v| v.parse().ok()).unwrap_or(1),
+            timeline,
+        }));
+    }
+
+    if let Some(list) = levels.iter().find_map(|node| children(*node, "SegmentList").next()) {
+        let initialization = match children(list, "Initialization").next() {
+            Some(node) => Some(segment_url(node, "sourceURL", "range", &rep_base_url)?),
+            None => None,
+        };
+        let segments = children(list, "SegmentURL")
+            .map(|node| segment_url(node, "media", "mediaRange", &rep_base_url))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        return Ok(SegmentAddressing::List(SegmentList { initialization, segments }));
+    }
+
+    if let Some(base) = levels.iter().find_map(|node| children(*node, "SegmentBase").next()) {
+        let initialization = children(base, "Initialization")
+            .next()
+            .and_then(|node| node.attribute("range"))
+            .map(|range| parse_range(range).ok_or_else(|| invalid_range(range)))
+            .transpose()?;
+        let index_range = base
+            .attribute("indexRange")
+            .map(|range| parse_range(range).ok_or_else(|| invalid_range(range)))
+            .transpose()?;
+
+        return Ok(SegmentAddressing::Base(SegmentBase { initialization, index_range }));
+    }
+
+    Ok(SegmentAddressing::Single)
+}
+
+impl SegmentTemplate {
+    /// 初期化セグメントとメディアセグメントのURLを展開する
+    pub fn expand(
+        &self,
+        representation: &Representation,
+        period_duration: Option<f64>
+    ) -> Result<(Option<SegmentUrl>, Vec<SegmentUrl>), DownloadError> {
+        let resolve = |template: &str, number: u64, time: u64| {
+            let path = substitute(template, &representation.id, representation.bandwidth, number, time);
+            representation
+                .base_url
+                .join(&path)
+                .map(|url| SegmentUrl { url, byte_range: None })
+                .map_err(|err| DownloadError::InvalidManifest(format!("不正なURL {}: {}", path, err)))
+        };
+
+        let initialization = self
+            .initialization
+            .as_deref()
+            .map(|template| resolve(template, self.start_number, 0))
+            .transpose()?;
+
+        let media = self
+            .media
+            .as_deref()
+            .ok_or_else(|| DownloadError::InvalidManifest("SegmentTemplateにmediaがありません".to_string()))?;
+
+        // 各セグメントの（番号, 開始時刻）
+        let mut numbers: Vec<(u64, u64)> = Vec::new();
+        if !self.timeline.is_empty() {
+            let period_end = period_duration.map(|duration| (duration * self.timescale as f64) as u64);
+            let mut time = 0;
+            let mut number = self.start_number;
+
+            for (index, entry) in self.timeline.iter().enumerate() {
+                time = entry.t.unwrap_or(time);
+                let repeat = if entry.r >= 0 {
+                    entry.r as u64
+                } else {
+                    let end = self.timeline.get(index + 1).and_then(|next| next.t).or(period_end).ok_or_else(|| {
+                        DownloadError::InvalidManifest("SegmentTimelineの終わりが不明です".to_string())
+                    })?;
+                    end.saturating_sub(time).div_ceil(entry.d.max(1)).saturating_sub(1)
+                };
+
+                for _ in 0..=repeat {
+                    numbers.push((number, time));
+                    number += 1;
+                    time += entry.d;
+                }
+            }
+        } else {
+            let duration = self.duration.filter(|duration| *duration > 0).ok_or_else(|| {
+                DownloadError::InvalidManifest("SegmentTemplateにdurationがありません".to_string())
+            })?;
+            let period_duration = period_duration
+                .ok_or_else(|| DownloadError::InvalidManifest("期間の長さが不明です".to_string()))?;
+            let count = (period_duration * self.timescale as f64 / duration as f64).ceil() as u64;
+
+            for index in 0..count {
+                numbers.push((self.start_number + index, index * duration));
+            }
+        }
+
+        let segments = numbers
+            .into_iter()
+            .map(|(number, time)| resolve(media, number, time))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok((initialization, segments))
+    }
+}
+
+/// テンプレートの識別子（$RepresentationID$、$Number%05d$など）を置換する
+fn substitute(template: &str, representation_id: &str, bandwidth: u64, number: u64, time: u64) -> String {
+    let mut result = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('$') else {
+            result.push_str(&rest[start..]);
+            return result;
+        };
+
+        let identifier = &after[..end];
+        let (name, format) = identifier.split_once('%').unwrap_or((identifier, ""));
+        let width: usize = format
+            .strip_prefix('0')
+            .and_then(|format| format.strip_suffix('d'))
+            .and_then(|width| width.parse().ok())
+            .unwrap_or(0);
+
+        match name {
+            "" => result.push('$'),
+            "RepresentationID" => result.push_str(representation_id),
+            "Number" => result.push_str(&format!("{:0width$}", number, width = width)),
+            "Bandwidth" => result.push_str(&format!("{:0width$}", bandwidth, width = width)),
+            "Time" => result.push_str(&format!("{:0width$}", time, width = width)),
+            _ => {
+                result.push('$');
+                result.push_str(identifier);
+                result.push('$');
+            }
+        }
+        rest = &after[end + 1..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// 要素の種類からトラックの種類を判定
+fn track_kind(node: Node, first: Option<&Representation>) -> TrackKind {
+    let content_type = node
+        .attribute("contentType")
+        .or_else(|| node.attribute("mimeType"))
+        .or_else(|| first.and_then(|rep| rep.mime_type.as_deref()))
+        .unwrap_or_default();
+
+    if content_type.starts_with("video") {
+        TrackKind::Video
+    } else if content_type.starts_with("audio") {
+        TrackKind::Audio
+    } else if content_type.starts_with("text") || content_type.starts_with("application/ttml") {
+        TrackKind::Text
+    } else if first.is_some_and(|rep| rep.width.is_some()) {
+        TrackKind::Video
+    } else {
+        TrackKind::Other
+    }
+}
+
+fn children<'a, 'input>(node: Node<'a, 'input>, name: &'static str) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(move |child| child.has_tag_name(name))
+}
+
+/// 子要素のBaseURLを解決する（ない場合は親の基準URL）
+fn child_base_url(node: Node, parent: &Url) -> Result<Url, DownloadError> {
+    match children(node, "BaseURL").next().and_then(|base| base.text()) {
+        Some(text) => parent
+            .join(text.trim())
+            .map_err(|err| DownloadError::InvalidManifest(format!("不正なBaseURL {}: {}", text, err))),
+        None => Ok(parent.clone()),
+    }
+}
+
+fn segment_url(node: Node, url_attribute: &str, range_attribute: &str, base_url: &Url) -> Result<SegmentUrl, DownloadError> {
+    let url = match node.attribute(url_attribute) {
+        Some(path) => base_url
+            .join(path)
+            .map_err(|err| DownloadError::InvalidManifest(format!("不正なURL {}: {}", path, err)))?,
+        None => base_url.clone(),
+    };
+    let byte_range = node
+        .attribute(range_attribute)
+        .map(|range| parse_range(range).ok_or_else(|| invalid_range(range)))
+        .transpose()?;
+
+    Ok(SegmentUrl { url, byte_range })
+}
+
+/// `first-last` 形式のバイト範囲を解析
+pub(crate) fn parse_range(value: &str) -> Option<ByteRange> {
+    let (first, last) = value.split_once('-')?;
+    let first: u64 = first.trim().parse().ok()?;
+    let last: u64 = last.trim().parse().ok()?;
+    (last >= first).then(|| ByteRange { length: last - first + 1, offset: first })
+}
+
+fn invalid_range(value: &str) -> DownloadError {
+    DownloadError::InvalidManifest(format!("不正なバイト範囲: {}", value))
+}
+
+/// `30000/1001` 形式のフレームレートを解析
+fn parse_frame_rate(value: &str) -> Option<f64> {
+    match value.split_once('/') {
+        Some((numerator, denominator)) => {
+            let denominator: f64 = denominator.parse().ok()?;
+            (denominator != 0.0).then_some(numerator.parse::<f64>().ok()? / denominator)
+        }
+        None => value.parse().ok(),
+    }
+}
+
+/// ISO 8601の期間（`PT1H2M3.5S`など）を秒に変換
+pub(crate) fn parse_duration(value: &str) -> Option<f64> {
+    let value = value.trim().strip_prefix('P')?;
+    let (date, time) = value.split_once('T').unwrap_or((value, ""));
+
+    let mut seconds = 0.0;
+    for (part, is_time) in [(date, false), (time, true)] {
+        let mut number = String::new();
+        for c in part.chars() {
+            if c.is_ascii_digit() || c == '.' {
+                number.push(c);
+                continue;
+            }
+            let amount: f64 = number.parse().ok()?;
+            number.clear();
+            seconds += amount * match (c, is_time) {
+                ('Y', false) => 365.0 * 86400.0,
+                ('M', false) => 30.0 * 86400.0,
+                ('W', false) => 7.0 * 86400.0,
+                ('D', false) => 86400.0,
+                ('H', true) => 3600.0,
+                ('M', true) => 60.0,
+                ('S', true) => 1.0,
+                _ => return None,
+            };
+        }
+        if !number.is_empty() {
+            return None;
+        }
+    }
+
+    Some(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Url {
+        Url::parse("https://cdn.example.com/vod/manifest.mpd").unwrap()
+    }
+
+    fn urls(segments: &[SegmentUrl]) -> Vec<&str> {
+        segments.iter().map(|segment| segment.url.as_str()).collect()
+    }
+
+    fn template(representation: &Representation) -> &SegmentTemplate {
+        match &representation.addressing {
+            SegmentAddressing::Template(template) => template,
+            other => panic!("SegmentTemplateのはず: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn expands_number_template_inherited_from_adaptation_set() {
+        let mpd = Mpd::parse(
+            r#"<?xml version="1.0"?>
+            <MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT9.5S">
+              <BaseURL>media/</BaseURL>
+              <Period>
+                <AdaptationSet mimeType="video/mp4" frameRate="30000/1001" codecs="avc1.64001f">
+                  <SegmentTemplate timescale="1000" duration="4000" startNumber="0"
+                      initialization="$RepresentationID$/init.mp4" media="$RepresentationID$/seg-$Number%05d$.m4s"/>
+                  <Representation id="720p" bandwidth="2400000" width="1280" height="720"/>
+                  <Representation id="1080p" bandwidth="4800000" width="1920" height="1080" codecs="avc1.640028"/>
+                </AdaptationSet>
+              </Period>
+            </MPD>"#,
+            &base(),
+        )
+        .unwrap();
+
+        let period = &mpd.periods[0];
+        assert!(!mpd.is_dynamic);
+        assert_eq!(period.duration, Some(9.5));
+        let set = &period.adaptation_sets[0];
+        assert_eq!(set.kind, TrackKind::Video);
+        let high = &set.representations[1];
+        assert_eq!((high.width, high.height, high.bandwidth), (Some(1920), Some(1080), 4_800_000));
+        assert_eq!(high.codecs.as_deref(), Some("avc1.640028"));
+        assert_eq!(set.representations[0].codecs.as_deref(), Some("avc1.64001f"));
+        assert!((high.frame_rate.unwrap() - 29.97).abs() < 0.01);
+
+        let (init, segments) = template(high).expand(high, period.duration).unwrap();
+        assert_eq!(init.unwrap().url.as_str(), "https://cdn.example.com/vod/media/1080p/init.mp4");
+        // 9.5秒を4秒ずつに分けると3セグメント（最後は短い）
+        assert_eq!(urls(&segments), vec![
+            "https://cdn.example.com/vod/media/1080p/seg-00000.m4s",
+            "https://cdn.example.com/vod/media/1080p/seg-00001.m4s",
+            "https://cdn.example.com/vod/media/1080p/seg-00002.m4s",
+        ]);
+    }
+
+    #[test]
+    fn expands_segment_timeline_with_repeats_and_time() {
+        let mpd = Mpd::parse(
+            r#"<MPD mediaPresentationDuration="PT26S">
+              <Period id="main">
+                <AdaptationSet contentType="audio" lang="ja">
+                  <Representation id="aac" bandwidth="128000" mimeType="audio/mp4">
+                    <SegmentTemplate timescale="10" media="a/$Time$.m4s" startNumber="5">
+                      <SegmentTimeline>
+                        <S t="100" d="40" r="1"/>
+                        <S d="20"/>
+                        <S t="200" d="30" r="-1"/>
+                      </SegmentTimeline>
+                    </SegmentTemplate>
+                  </Representation>
+                </AdaptationSet>
+              </Period>
+            </MPD>"#,
+            &base(),
+        )
+        .unwrap();
+
+        let set = &mpd.periods[0].adaptation_sets[0];
+        assert_eq!(set.kind, TrackKind::Audio);
+        assert_eq!(set.lang.as_deref(), Some("ja"));
+        let audio = &set.representations[0];
+        let (init, segments) = template(audio).expand(audio, mpd.periods[0].duration).unwrap();
+
+        assert!(init.is_none());
+        // r="-1" は期間の終わり（26秒 = 260単位）まで繰り返す
+        assert_eq!(urls(&segments), vec![
+            "https://cdn.example.com/vod/a/100.m4s",
+            "https://cdn.example.com/vod/a/140.m4s",
+            "https://cdn.example.com/vod/a/180.m4s",
+            "https://cdn.example.com/vod/a/200.m4s",
+            "https://cdn.example.com/vod/a/230.m4s",
+        ]);
+    }
+
+    #[test]
+    fn open_ended_timeline_without_period_end_is_an_error() {
+        let mpd = Mpd::parse(
+            r#"<MPD type="dynamic"><Period><AdaptationSet mimeType="video/mp4">
+              <Representation id="v" bandwidth="1">
+                <SegmentTemplate media="$Number$.m4s"><SegmentTimeline><S d="2" r="-1"/></SegmentTimeline></SegmentTemplate>
+              </Representation>
+            </AdaptationSet></Period></MPD>"#,
+            &base(),
+        )
+        .unwrap();
+
+        assert!(mpd.is_dynamic);
+        let video = &mpd.periods[0].adaptation_sets[0].representations[0];
+        assert!(matches!(template(video).expand(video, None), Err(DownloadError::InvalidManifest(_))));
+    }
+
+    #[test]
+    fn parses_segment_list_with_ranges() {
+        let mpd = Mpd::parse(
+            r#"<MPD mediaPresentationDuration="PT8S"><Period><AdaptationSet mimeType="video/mp4">
+              <Representation id="v" bandwidth="800000" width="640" height="360">
+                <BaseURL>https://other.example.com/files/video.mp4</BaseURL>
+                <SegmentList duration="4">
+                  <Initialization sourceURL="init.mp4" range="0-799"/>
+                  <SegmentURL mediaRange="800-1999"/>
+                  <SegmentURL media="part2.m4s"/>
+                </SegmentList>
+              </Representation>
+            </AdaptationSet></Period></MPD>"#,
+            &base(),
+        )
+        .unwrap();
+
+        let SegmentAddressing::List(list) = &mpd.periods[0].adaptation_sets[0].representations[0].addressing else {
+            panic!("SegmentListのはず");
+        };
+        let init = list.initialization.as_ref().unwrap();
+        assert_eq!(init.url.as_str(), "https://other.example.com/files/init.mp4");
+        assert_eq!(init.byte_range, Some(ByteRange { length: 800, offset: 0 }));
+        // media属性がない場合はBaseURLのファイルの一部
+        assert_eq!(urls(&list.segments), vec![
+            "https://other.example.com/files/video.mp4",
+            "https://other.example.com/files/part2.m4s",
+        ]);
+        assert_eq!(list.segments[0].byte_range, Some(ByteRange { length: 1200, offset: 800 }));
+        assert_eq!(list.segments[1].byte_range, None);
+    }
+
+    #[test]
+    fn parses_segment_base_and_content_protection() {
+        let mpd = Mpd::parse(
+            r#"<MPD><Period duration="PT1M"><AdaptationSet mimeType="audio/mp4">
+              <ContentProtection schemeIdUri="urn:mpeg:dash:mp4protection:2011" value="cenc"/>
+              <Representation id="a" bandwidth="96000">
+                <BaseURL>audio.mp4</BaseURL>
+                <SegmentBase indexRange="820-1023"><Initialization range="0-819"/></SegmentBase>
+              </Representation>
+            </AdaptationSet></Period></MPD>"#,
+            &base(),
+        )
+        .unwrap();
+
+        let set = &mpd.periods[0].adaptation_sets[0];
+        assert!(set.protected);
+        let audio = &set.representations[0];
+        assert!(audio.protected);
+        assert_eq!(audio.base_url.as_str(), "https://cdn.example.com/vod/audio.mp4");
+        let SegmentAddressing::Base(segment_base) = &audio.addressing else {
+            panic!("SegmentBaseのはず");
+        };
+        assert_eq!(segment_base.initialization, Some(ByteRange { length: 820, offset: 0 }));
+        assert_eq!(segment_base.index_range, Some(ByteRange { length: 204, offset: 820 }));
+    }
+
+    #[test]
+    fn period_durations_come_from_the_next_start() {
+        let mpd = Mpd::parse(
+            r#"<MPD mediaPresentationDuration="PT1M30S">
+              <Period id="ad"><AdaptationSet/></Period>
+              <Period id="main" start="PT15S"><AdaptationSet/></Period>
+            </MPD>"#,
+            &base(),
+        )
+        .unwrap();
+
+        let spans: Vec<(f64, Option<f64>)> = mpd.periods.iter().map(|period| (period.start, period.duration)).collect();
+        assert_eq!(spans, vec![(0.0, Some(15.0)), (15.0, Some(75.0))]);
+    }
+
+    #[test]
+    fn rejects_documents_that_are_not_mpd() {
+        assert!(matches!(Mpd::parse("<html></html>", &base()), Err(DownloadError::InvalidManifest(_))));
+        assert!(matches!(Mpd::parse("<MPD>", &base()), Err(DownloadError::InvalidManifest(_))));
+        assert!(matches!(Mpd::parse("<MPD/>", &base()), Err(DownloadError::InvalidManifest(_))));
+    }
+
+    #[test]
+    fn substitutes_template_identifiers() {
+        assert_eq!(substitute("$RepresentationID$_$Bandwidth$_$Number%03d$.m4s", "v1", 500, 7, 0), "v1_500_007.m4s");
+        assert_eq!(substitute("t$Time%08d$-$$-$Unknown$", "v", 0, 1, 9000), "t00009000-$-$Unknown$");
+        assert_eq!(substitute("broken$Number", "v", 0, 1, 0), "broken$Number");
+    }
+
+    #[test]
+    fn parses_iso8601_durations() {
+        assert_eq!(parse_duration("PT1H2M3.5S"), Some(3723.5));
+        assert_eq!(parse_duration("P1DT30M"), Some(88_200.0));
+        assert_eq!(parse_duration("PT0S"), Some(0.0));
+        assert_eq!(parse_duration("1H"), None);
+        assert_eq!(parse_duration("PT5"), None);
+    }
+}
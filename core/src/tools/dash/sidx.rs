@@ -0,0 +1,164 @@
+use crate::types::DownloadError;
+use crate::tools::fetcher::ByteRange;
+
+/// Segment Index Box（sidx）を解析して各サブセグメントのバイト範囲を返す
+///
+/// `data` は `index_range` の位置から取得したバイト列。参照先が別のsidx
+/// （階層化されたインデックス）の場合は `None` を返す。
+pub fn parse_sidx(data: &[u8], index_range: ByteRange) -> Result<Option<Vec<ByteRange>>, DownloadError> {
+    let mut position = 0;
+
+    // indexRangeの先頭にsidx以外のボックスが含まれている場合は読み飛ばす
+    loop {
+        let size = read_u32(data, position)? as usize;
+        let box_type = data.get(position + 4..position + 8).ok_or_else(truncated)?;
+        if box_type == b"sidx" {
+            break;
+        }
+        if size < 8 {
+            return Err(invalid("ボックスサイズが不正です"));
+        }
+        position += size;
+    }
+
+    let box_size = read_u32(data, position)? as u64;
+    let version = *data.get(position + 8).ok_or_else(truncated)?;
+    // size, type, version/flags, reference_ID, timescale
+    let mut cursor = position + 20;
+
+    let first_offset = if version == 0 {
+        let offset = read_u32(data, cursor + 4)? as u64;
+        cursor += 8;
+        offset
+    } else {
+        let offset = read_u64(data, cursor + 8)?;
+        cursor += 16;
+        offset
+    };
+
+    // reserved(16bit)
+    let reference_count = u16::from_be_bytes([
+        *data.get(cursor + 2).ok_or_else(truncated)?,
+        *data.get(cursor + 3).ok_or_else(truncated)?,
+    ]);
+    cursor += 4;
+
+    // 参照先のオフセットはsidxボックスの直後が基準
+    let mut offset = index_range.offset + position as u64 + box_size + first_offset;
+    let mut ranges = Vec::with_capacity(reference_count as usize);
+
+    for _ in 0..reference_count {
+        let reference = read_u32(data, cursor)?;
+        if reference & 0x8000_0000 != 0 {
+            return Ok(None);
+        }
+
+        let length = (reference & 0x7fff_ffff) as u64;
+        ranges.push(ByteRange { length, offset });
+        offset += length;
+        // referenced_size, subsegment_duration, SAP情報
+        cursor += 12;
+    }
+
+    Ok(Some(ranges))
+}
+
+fn read_u32(data: &[u8], position: usize) -> Result<u32, DownloadError> {
+    let bytes = data.get(position..position + 4).ok_or_else(truncated)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_u64(data: &[u8], position: usize) -> Result<u64, DownloadError> {
+    let high = read_u32(data, position)? as u64;
+    let low = read_u32(data, position + 4)? as u64;
+    Ok((high << 32) | low)
+}
+
+fn truncated() -> DownloadError {
+    invalid("データが途中で終わっています")
+}
+
+fn invalid(message: &str) -> DownloadError {
+    DownloadError::InvalidManifest(format!("sidxの解析に失敗: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// サブセグメントのサイズからsidxボックスを作成（`hierarchical` の参照は別のsidxを指す）
+    fn sidx(version: u8, first_offset: u64, references: &[(u32, bool)]) -> Vec<u8> {
+        let mut body = vec![version, 0, 0, 0];
+        body.extend(1u32.to_be_bytes()); // reference_ID
+        body.extend(90_000u32.to_be_bytes()); // timescale
+        if version == 0 {
+            body.extend(0u32.to_be_bytes());
+            body.extend((first_offset as u32).to_be_bytes());
+        } else {
+            body.extend(0u64.to_be_bytes());
+            body.extend(first_offset.to_be_bytes());
+        }
+        body.extend(0u16.to_be_bytes());
+        body.extend((references.len() as u16).to_be_bytes());
+        for (size, hierarchical) in references {
+            body.extend((size | if *hierarchical { 0x8000_0000 } else { 0 }).to_be_bytes());
+            body.extend(180_000u32.to_be_bytes()); // subsegment_duration
+            body.extend(0x9000_0000u32.to_be_bytes()); // SAP
+        }
+
+        let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend(b"sidx");
+        data.extend(body);
+        data
+    }
+
+    #[test]
+    fn parses_version_0_references_after_the_box() {
+        let data = sidx(0, 0, &[(1000, false), (1500, false), (700, false)]);
+        let index_range = ByteRange { length: data.len() as u64, offset: 820 };
+
+        let ranges = parse_sidx(&data, index_range).unwrap().unwrap();
+
+        let start = 820 + data.len() as u64;
+        assert_eq!(ranges, vec![
+            ByteRange { length: 1000, offset: start },
+            ByteRange { length: 1500, offset: start + 1000 },
+            ByteRange { length: 700, offset: start + 2500 },
+        ]);
+    }
+
+    #[test]
+    fn parses_version_1_with_first_offset_and_skips_leading_boxes() {
+        let mut data = vec![0, 0, 0, 16];
+        data.extend(b"styp");
+        data.extend(b"msdhmsdh");
+        let box_start = data.len() as u64;
+        let sidx = sidx(1, 64, &[(2048, false)]);
+        let box_size = sidx.len() as u64;
+        data.extend(sidx);
+
+        let ranges = parse_sidx(&data, ByteRange { length: data.len() as u64, offset: 1000 }).unwrap().unwrap();
+
+        assert_eq!(ranges, vec![ByteRange { length: 2048, offset: 1000 + box_start + box_size + 64 }]);
+    }
+
+    #[test]
+    fn hierarchical_index_is_not_supported() {
+        let data = sidx(0, 0, &[(1000, false), (5000, true)]);
+        assert_eq!(parse_sidx(&data, ByteRange { length: data.len() as u64, offset: 0 }).unwrap(), None);
+    }
+
+    #[test]
+    fn truncated_or_missing_box_is_an_error() {
+        let data = sidx(0, 0, &[(1000, false), (1500, false)]);
+        let truncated = &data[..data.len() - 10];
+        assert!(matches!(
+            parse_sidx(truncated, ByteRange { length: truncated.len() as u64, offset: 0 }),
+            Err(DownloadError::InvalidManifest(_))
+        ));
+
+        let mut no_sidx = vec![0, 0, 0, 4];
+        no_sidx.extend(b"moof");
+        assert!(parse_sidx(&no_sidx, ByteRange { length: 8, offset: 0 }).is_err());
+    }
+}
@@ -0,0 +1,129 @@
+//! 外部ツールの出力からエラーの種類を判定するための共通処理
+//!
+//! ツール固有の判定は各ツールのモジュールで行い、ここではどのツールでも
+//! 同じ表現になるネットワーク・ディスク・HTTPステータスのエラーを扱う。
+
+use std::sync::OnceLock;
+use regex::Regex;
+use crate::types::DownloadError;
+
+/// いずれかの語句を含むか（大文字小文字は区別しない）
+pub(crate) fn contains_any(output: &str, patterns: &[&str]) -> bool {
+    let output = output.to_lowercase();
+    patterns.iter().any(|pattern| output.contains(pattern))
+}
+
+/// エラーの要約（`ERROR:` で始まる行、なければ最後の空でない行）
+pub(crate) fn summary(output: &str) -> String {
+    let mut lines = output.lines().map(str::trim).filter(|line| !line.is_empty());
+    lines
+        .clone()
+        .find(|line| line.starts_with("ERROR:"))
+        .or_else(|| lines.next_back())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// 出力に含まれるHTTPエラーステータス
+///
+/// yt-dlp（`HTTP Error 403`）、aria2c（`status=404`）、ffmpeg（`Server returned 403`）の表記に対応する。
+pub(crate) fn http_status(output: &str) -> Option<u16> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| {
+        Regex::new(r"(?i)(?:http error|status=|server returned) ?([45][0-9]{2})\b").unwrap()
+    });
+    pattern.captures(output)?.get(1)?.as_str().parse().ok()
+}
+
+/// シグナルによる終了
+///
+/// ダウンロードマネージャーの停止要求では処理自体を破棄するため、ここで扱うのは
+/// 外部からプロセスが終了された場合。他の方法で取得し直せるよう致命的なエラーにはしない。
+pub(crate) fn terminated(tool: &str, output: &str) -> DownloadError {
+    DownloadError::ProcessFailed(format!("{} がシグナルで終了しました: {}", tool, summary(output)))
+}
+
+/// ツールに依存しないエラーを判定
+pub(crate) fn classify_common(output: &str, url: &str) -> Option<DownloadError> {
+    let message = summary(output);
+
+    if contains_any(output, &["no space left on device", "errno 28", "enospc", "disk full"]) {
+        return Some(DownloadError::DiskFull(message));
+    }
+    if let Some(status) = http_status(output) {
+        return Some(DownloadError::HttpStatus { status, url: url.to_string() });
+    }
+    if contains_any(output, &["timed out", "timeout was reached", "etimedout"]) {
+        return Some(DownloadError::Timeout(message));
+    }
+    if contains_any(output, &[
+        "name or service not known",
+        "temporary failure in name resolution",
+        "nodename nor servname",
+        "getaddrinfo failed",
+        "failed to resolve",
+        "could not resolve host",
+        "network is unreachable",
+        "no route to host",
+        "connection refused",
+    ]) {
+        return Some(DownloadError::NetworkUnreachable(message));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_prefers_the_error_line() {
+        assert_eq!(
+            summary("WARNING: retrying\nERROR: [youtube] abc: Video unavailable\n  more details  \n"),
+            "ERROR: [youtube] abc: Video unavailable"
+        );
+        assert_eq!(summary("first\n  last line  \n\n"), "last line");
+        assert_eq!(summary(" \n"), "");
+    }
+
+    #[test]
+    fn contains_any_ignores_case() {
+        assert!(contains_any("ERROR: No Space Left On Device", &["no space left on device"]));
+        assert!(!contains_any("ERROR: Video unavailable", &["private video", "login required"]));
+    }
+
+    #[test]
+    fn finds_http_statuses_in_each_tool_format() {
+        assert_eq!(http_status("ERROR: unable to download video data: HTTP Error 403: Forbidden"), Some(403));
+        assert_eq!(http_status("errorCode=22 status=503"), Some(503));
+        assert_eq!(http_status("Server returned 404 Not Found"), Some(404));
+        assert_eq!(http_status("status=200 OK"), None);
+        assert_eq!(http_status("HTTP Error 4040"), None);
+    }
+
+    #[test]
+    fn classifies_errors_common_to_all_tools() {
+        let url = "https://example.com/video.mp4";
+
+        assert!(matches!(classify_common("OSError: [Errno 28] No space left on device", url), Some(DownloadError::DiskFull(_))));
+        assert!(matches!(
+            classify_common("HTTP Error 429: Too Many Requests", url),
+            Some(DownloadError::HttpStatus { status: 429, url: ref failed }) if failed == url
+        ));
+        assert!(matches!(classify_common("Connection timed out", url), Some(DownloadError::Timeout(_))));
+        assert!(matches!(
+            classify_common("Temporary failure in name resolution", url),
+            Some(DownloadError::NetworkUnreachable(_))
+        ));
+        assert!(classify_common("Invalid data found when processing input", url).is_none());
+    }
+
+    #[test]
+    fn signal_exits_can_fall_back_to_other_backends() {
+        let err = terminated("yt-dlp", "[download]  50.0%\n");
+
+        assert!(matches!(err, DownloadError::ProcessFailed(ref message) if message.contains("yt-dlp")), "{:?}", err);
+        assert!(!err.is_fatal());
+    }
+}
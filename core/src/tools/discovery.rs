@@ -0,0 +1,274 @@
+//! 外部ツール（yt-dlp、aria2c、ffmpeg）の場所の解決
+//!
+//! 次の順に探し、最初に見つかったものを使う。
+//!
+//! 1. 明示的に指定されたパス
+//! 2. 環境変数（`NEXTDOWNLOADER_YTDLP`、`NEXTDOWNLOADER_ARIA2C`、`NEXTDOWNLOADER_FFMPEG`）
+//! 3. 環境変数PATH
+//! 4. `tools install` でインストールしたツール（[`ToolManager`]）
+//! 5. 同梱ツールのディレクトリ（既定は実行ファイルと同じ場所の `bin/`）
+
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use crate::tools::manager::{self, ToolManager};
+use crate::utils;
+
+/// 外部ツールの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExternalTool {
+    /// yt-dlp
+    YtDlp,
+    /// aria2c
+    Aria2c,
+    /// ffmpeg
+    Ffmpeg,
+}
+
+impl ExternalTool {
+    /// すべての外部ツール
+    pub const ALL: [ExternalTool; 3] = [ExternalTool::YtDlp, ExternalTool::Aria2c, ExternalTool::Ffmpeg];
+
+    /// 実行ファイル名（拡張子なし）
+    pub fn executable_name(&self) -> &'static str {
+        match self {
+            ExternalTool::YtDlp => "yt-dlp",
+            ExternalTool::Aria2c => "aria2c",
+            ExternalTool::Ffmpeg => "ffmpeg",
+        }
+    }
+
+    /// 場所を指定する環境変数
+    pub fn env_var(&self) -> &'static str {
+        match self {
+            ExternalTool::YtDlp => "NEXTDOWNLOADER_YTDLP",
+            ExternalTool::Aria2c => "NEXTDOWNLOADER_ARIA2C",
+            ExternalTool::Ffmpeg => "NEXTDOWNLOADER_FFMPEG",
+        }
+    }
+}
+
+impl fmt::Display for ExternalTool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.executable_name())
+    }
+}
+
+impl FromStr for ExternalTool {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "yt-dlp" | "ytdlp" => Ok(ExternalTool::YtDlp),
+            "aria2c" | "aria2" => Ok(ExternalTool::Aria2c),
+            "ffmpeg" => Ok(ExternalTool::Ffmpeg),
+            _ => Err(format!("不明なツール: {}（yt-dlp, aria2c, ffmpeg）", value)),
+        }
+    }
+}
+
+/// ツールの場所をどこから決めたか
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolSource {
+    /// 明示的に指定されたパス
+    Explicit,
+    /// 環境変数
+    Environment,
+    /// 環境変数PATH
+    Path,
+    /// `tools install` でインストールしたツール
+    Managed,
+    /// 同梱ツールのディレクトリ
+    Bundled,
+}
+
+impl ToolSource {
+    /// 表示用の説明
+    pub fn description(&self) -> &'static str {
+        match self {
+            ToolSource::Explicit => "指定されたパス",
+            ToolSource::Environment => "環境変数",
+            ToolSource::Path => "PATH",
+            ToolSource::Managed => "インストール済み",
+            ToolSource::Bundled => "同梱",
+        }
+    }
+}
+
+/// 解決した外部ツールの場所
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolLocation {
+    /// ツールの種類
+    pub tool: ExternalTool,
+    /// 実行ファイルのパス
+    pub path: PathBuf,
+    /// どこから決めたか
+    pub source: ToolSource,
+}
+
+/// 外部ツールの場所の設定
+///
+/// 指定しなかったツールは環境変数、PATH、インストールしたツール、同梱ツールのディレクトリの順に探す。
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToolPaths {
+    /// yt-dlpの実行ファイル
+    pub ytdlp: Option<PathBuf>,
+    /// aria2cの実行ファイル
+    pub aria2c: Option<PathBuf>,
+    /// ffmpegの実行ファイル
+    pub ffmpeg: Option<PathBuf>,
+    /// `tools install` のインストール先（省略時はデータディレクトリの `NextDownloader/tools`）
+    pub managed_dir: Option<PathBuf>,
+    /// 同梱ツールのディレクトリ（省略時は実行ファイルと同じ場所の `bin/`）
+    pub bundled_dir: Option<PathBuf>,
+}
+
+impl ToolPaths {
+    /// 明示的に指定されたパス
+    pub fn explicit(&self, tool: ExternalTool) -> Option<&Path> {
+        match tool {
+            ExternalTool::YtDlp => self.ytdlp.as_deref(),
+            ExternalTool::Aria2c => self.aria2c.as_deref(),
+            ExternalTool::Ffmpeg => self.ffmpeg.as_deref(),
+        }
+    }
+
+    /// ツールの場所を解決する
+    ///
+    /// 明示的に指定されたパスと環境変数のパスは、存在しなくてもそのまま使う
+    /// （指定の誤りを他の場所のツールで隠さないため）。
+    pub fn locate(&self, tool: ExternalTool) -> Option<ToolLocation> {
+        self.locate_with(tool, |name| std::env::var_os(name))
+    }
+
+    /// 環境変数（`PATH` を含む）を `env` から読んでツールの場所を解決する
+    fn locate_with(&self, tool: ExternalTool, env: impl Fn(&str) -> Option<OsString>) -> Option<ToolLocation> {
+        let location = |path: PathBuf, source| ToolLocation { tool, path, source };
+        let name = tool.executable_name();
+
+        if let Some(path) = self.explicit(tool) {
+            return Some(location(path.to_path_buf(), ToolSource::Explicit));
+        }
+        if let Some(path) = env(tool.env_var()).filter(|value| !value.is_empty()) {
+            return Some(location(PathBuf::from(path), ToolSource::Environment));
+        }
+        if let Some(path) = env("PATH").and_then(|paths| utils::find_in_paths(&paths, name)) {
+            return Some(location(path, ToolSource::Path));
+        }
+        if let Some(path) = self.managed_path(tool) {
+            return Some(location(path, ToolSource::Managed));
+        }
+        self.bundled_dir
+            .clone()
+            .or_else(utils::bundled_dir)
+            .and_then(|dir| utils::find_in_dir(&dir, name))
+            .map(|path| location(path, ToolSource::Bundled))
+    }
+
+    /// `tools install` のインストール先
+    pub fn managed_dir(&self) -> Option<PathBuf> {
+        self.managed_dir.clone().or_else(ToolManager::default_dir)
+    }
+
+    /// `tools install` でインストールしたツールの実行ファイル
+    fn managed_path(&self, tool: ExternalTool) -> Option<PathBuf> {
+        let installed = manager::read_installed(&self.managed_dir()?).ok()?;
+        installed
+            .into_iter()
+            .find(|installed| installed.tool == tool)
+            .map(|installed| installed.path)
+            .filter(|path| path.is_file())
+    }
+
+    /// ツールの実行ファイルのパス
+    ///
+    /// 見つからない場合は実行ファイル名のみを返す（利用できないものとして扱われる）。
+    pub fn path(&self, tool: ExternalTool) -> PathBuf {
+        self.locate(tool)
+            .map(|location| location.path)
+            .unwrap_or_else(|| PathBuf::from(tool.executable_name()))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+    use std::os::unix::fs::PermissionsExt;
+    use crate::test_util::TempDir;
+
+    /// 実行可能な空のファイルを作る
+    fn executable(dir: &Path, name: &str) -> PathBuf {
+        std::fs::create_dir_all(dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, b"").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    fn located(paths: &ToolPaths, env: &[(&str, &OsStr)]) -> Option<(PathBuf, ToolSource)> {
+        paths
+            .locate_with(ExternalTool::YtDlp, |name| {
+                env.iter().find(|(key, _)| *key == name).map(|(_, value)| value.to_os_string())
+            })
+            .map(|location| (location.path, location.source))
+    }
+
+    #[test]
+    fn resolves_explicit_then_env_then_path_then_bundled() {
+        let dir = TempDir::new();
+        let explicit = dir.path().join("explicit").join("yt-dlp");
+        let from_env = dir.path().join("env").join("yt-dlp");
+        let path_dir = dir.path().join("path");
+        let in_path = executable(&path_dir, "yt-dlp");
+        let bin_dir = dir.path().join("bin");
+        let bundled = executable(&bin_dir, "yt-dlp");
+        let search_path = std::env::join_paths([dir.path().join("empty"), path_dir.clone()]).unwrap();
+
+        let mut paths = ToolPaths {
+            ytdlp: Some(explicit.clone()),
+            managed_dir: Some(dir.path().join("managed")),
+            bundled_dir: Some(bin_dir),
+            ..ToolPaths::default()
+        };
+        let env = [("NEXTDOWNLOADER_YTDLP", from_env.as_os_str()), ("PATH", search_path.as_os_str())];
+
+        // 指定されたパスと環境変数のパスは存在しなくても使う
+        assert_eq!(located(&paths, &env), Some((explicit, ToolSource::Explicit)));
+        paths.ytdlp = None;
+        assert_eq!(located(&paths, &env), Some((from_env, ToolSource::Environment)));
+        // 空の環境変数は指定なしとして扱う
+        let empty_env = [("NEXTDOWNLOADER_YTDLP", OsStr::new("")), ("PATH", search_path.as_os_str())];
+        assert_eq!(located(&paths, &empty_env), Some((in_path, ToolSource::Path)));
+        assert_eq!(located(&paths, &[]), Some((bundled, ToolSource::Bundled)));
+
+        paths.bundled_dir = Some(dir.path().join("missing"));
+        assert_eq!(located(&paths, &[]), None);
+    }
+
+    #[test]
+    fn files_without_execute_permission_are_skipped() {
+        let dir = TempDir::new();
+        let path_dir = dir.path().join("path");
+        executable(&path_dir, "yt-dlp");
+        std::fs::set_permissions(path_dir.join("yt-dlp"), std::fs::Permissions::from_mode(0o644)).unwrap();
+        let paths = ToolPaths {
+            managed_dir: Some(dir.path().join("managed")),
+            bundled_dir: Some(dir.path().join("bin")),
+            ..ToolPaths::default()
+        };
+
+        assert_eq!(located(&paths, &[("PATH", path_dir.as_os_str())]), None);
+    }
+
+    #[test]
+    fn bundled_tools_default_to_bin_next_to_the_executable() {
+        let exe = std::env::current_exe().unwrap();
+        assert_eq!(utils::bundled_dir(), Some(exe.parent().unwrap().join("bin")));
+    }
+}
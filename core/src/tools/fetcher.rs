@@ -0,0 +1,219 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use url::Url;
+use crate::bandwidth::Throttle;
+use crate::types::{DownloadError, ProgressCallback, ProgressInfo, ProgressPhase};
+use crate::request::RequestContext;
+use crate::retry::RetryPolicy;
+
+/// セグメントの取得に使うHTTPクライアントを作成
+///
+/// マニフェスト取得時に設定されたCookieを、キーやセグメントの取得でも送る。
+pub fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .cookie_store(true)
+        .build()
+        .unwrap_or_default()
+}
+
+/// リクエスト情報が指定されている場合はそれを送るクライアントを作成し、
+/// そうでなければ既定のクライアントを使う
+pub fn request_client(
+    default: &reqwest::Client,
+    request: &RequestContext,
+    url: &Url
+) -> Result<reqwest::Client, DownloadError> {
+    if request.is_empty() {
+        Ok(default.clone())
+    } else {
+        request.http_client(url)
+    }
+}
+
+/// HTTPでセグメントを取得するための構造体
+///
+/// 一時的なエラー（通信エラー、5xx、429）の場合は再試行の方針に従って
+/// 待機時間を延ばしながら再試行する。
+#[derive(Clone)]
+pub struct SegmentFetcher {
+    client: reqwest::Client,
+    retry: RetryPolicy,
+    throttle: Throttle,
+}
+
+impl SegmentFetcher {
+    /// 新しいSegmentFetcherを作成
+    pub fn new(client: reqwest::Client, retry: RetryPolicy) -> Self {
+        Self {
+            client,
+            retry,
+            throttle: Throttle::default(),
+        }
+    }
+
+    /// 受信速度を制限する
+    pub fn with_throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    /// テキスト（プレイリストなど）を取得
+    pub async fn fetch_text(&self, url: &Url) -> Result<String, DownloadError> {
+        let bytes = self.fetch(url, None).await?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// リソースを取得（バイト範囲指定可）
+    pub async fn fetch(&self, url: &Url, byte_range: Option<ByteRange>) -> Result<Vec<u8>, DownloadError> {
+        self.retry.run(url.as_str(), || self.fetch_once(url, byte_range)).await
+    }
+
+    /// リソースをファイルに保存し、サイズを返す
+    ///
+    /// ファイルが既にある場合は取得済みとみなす（中断後の再開用）。
+    /// `transform` は保存前のデータの変換（復号など）に使う。
+    pub async fn fetch_to_file(
+        &self,
+        url: &Url,
+        byte_range: Option<ByteRange>,
+        path: &Path,
+        transform: impl FnOnce(Vec<u8>) -> Result<Vec<u8>, DownloadError>
+    ) -> Result<u64, DownloadError> {
+        if let Ok(metadata) = tokio::fs::metadata(path).await {
+            return Ok(metadata.len());
+        }
+
+        let bytes = transform(self.fetch(url, byte_range).await?)?;
+
+        // 書き込み途中のファイルを取得済みと誤認しないよう一時ファイル経由で保存する
+        let tmp_path = path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, &bytes).await?;
+        tokio::fs::rename(&tmp_path, path).await?;
+
+        Ok(bytes.len() as u64)
+    }
+
+    /// 1回だけ取得を試みる（エラーと再試行可能かどうかを返す）
+    async fn fetch_once(
+        &self,
+        url: &Url,
+        byte_range: Option<ByteRange>
+    ) -> Result<Vec<u8>, (DownloadError, bool)> {
+        let mut request = self.client.get(url.clone());
+        if let Some(range) = byte_range {
+            request = request.header(reqwest::header::RANGE, range.header_value());
+        }
+
+        let mut response = request.send().await.map_err(|err| (err.into(), true))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let err = DownloadError::HttpStatus { status: status.as_u16(), url: url.to_string() };
+            let retryable = err.is_retryable();
+            return Err((err, retryable));
+        }
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|err| (err.into(), true))? {
+            self.throttle.acquire(chunk.len() as u64).await;
+            bytes.extend_from_slice(&chunk);
+        }
+
+        // Rangeを無視して全体を返すサーバーに対応する
+        if let Some(range) = byte_range {
+            if status != reqwest::StatusCode::PARTIAL_CONTENT {
+                let start = range.offset as usize;
+                let end = start + range.length as usize;
+                return bytes
+                    .get(start..end)
+                    .map(|slice| slice.to_vec())
+                    .ok_or_else(|| {
+                        let err = DownloadError::ProcessFailed(format!("{} のバイト範囲が不正です", url));
+                        (err, false)
+                    });
+            }
+        }
+
+        Ok(bytes)
+    }
+}
+
+/// バイト範囲
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    /// 長さ
+    pub length: u64,
+    /// 開始位置
+    pub offset: u64,
+}
+
+impl ByteRange {
+    /// HTTPのRangeヘッダー値
+    pub fn header_value(&self) -> String {
+        format!("bytes={}-{}", self.offset, self.offset + self.length.saturating_sub(1))
+    }
+}
+
+/// セグメント単位の進捗集計
+///
+/// 合計バイト数はセグメント数と取得済みのバイト数から推定する。
+pub(crate) struct SegmentProgress {
+    total_segments: AtomicU64,
+    done_segments: AtomicU64,
+    downloaded_bytes: AtomicU64,
+    started_at: Instant,
+    callback: Option<ProgressCallback>,
+}
+
+impl SegmentProgress {
+    pub(crate) fn new(callback: Option<ProgressCallback>) -> Self {
+        Self {
+            total_segments: AtomicU64::new(0),
+            done_segments: AtomicU64::new(0),
+            downloaded_bytes: AtomicU64::new(0),
+            started_at: Instant::now(),
+            callback,
+        }
+    }
+
+    /// ダウンロードするセグメント数を設定
+    pub(crate) fn set_total_segments(&self, total_segments: u64) {
+        self.total_segments.store(total_segments, Ordering::Relaxed);
+    }
+
+    /// バイト数を伴わない段階（情報取得・結合）に移ったことを通知
+    pub(crate) fn phase(&self, phase: ProgressPhase) {
+        if let Some(callback) = &self.callback {
+            callback(ProgressInfo {
+                downloaded_bytes: self.downloaded_bytes.load(Ordering::Relaxed),
+                ..ProgressInfo::new(phase)
+            });
+        }
+    }
+
+    pub(crate) fn add(&self, phase: ProgressPhase, is_segment: bool, bytes: u64) {
+        let downloaded = self.downloaded_bytes.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        if !is_segment {
+            return;
+        }
+        let done = self.done_segments.fetch_add(1, Ordering::Relaxed) + 1;
+
+        let Some(callback) = &self.callback else {
+            return;
+        };
+
+        let total = self.total_segments.load(Ordering::Relaxed).max(done);
+        let elapsed = self.started_at.elapsed().as_secs_f64().max(0.001);
+        let remaining = total.saturating_sub(done) as f64;
+
+        callback(ProgressInfo {
+            phase,
+            progress: done as f64 / total as f64,
+            downloaded_bytes: downloaded,
+            total_bytes: Some((downloaded as f64 / done as f64 * total as f64).round() as u64),
+            speed: Some(downloaded as f64 / elapsed),
+            eta: Some(Duration::from_secs_f64(elapsed / done as f64 * remaining)),
+        });
+    }
+}
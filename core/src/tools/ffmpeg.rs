@@ -1,28 +1,28 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tokio::process::Command;
+use crate::tools::discovery::{ExternalTool, ToolPaths};
 use crate::types::{DownloadError, VideoFormat};
+use crate::tools::diagnostics;
+use crate::metadata::MediaMetadata;
+use crate::subtitles::iso639_2;
 
 /// FFmpeg外部ツールを扱うための構造体
+#[derive(Clone)]
 pub struct FFmpegTool {
     /// 実行ファイルのパス
     executable_path: PathBuf,
 }
 
+impl Default for FFmpegTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl FFmpegTool {
-    /// 新しいFFmpegToolを作成（デフォルトパス使用）
+    /// 新しいFFmpegToolを作成（環境変数、PATH、同梱ツールの順に探す）
     pub fn new() -> Self {
-        #[cfg(target_os = "windows")]
-        let default_path = PathBuf::from(".\\bin\\ffmpeg.exe");
-        
-        #[cfg(target_os = "macos")]
-        let default_path = PathBuf::from("/usr/local/bin/ffmpeg");
-        
-        #[cfg(target_os = "linux")]
-        let default_path = PathBuf::from("/usr/bin/ffmpeg");
-        
-        Self {
-            executable_path: default_path
-        }
+        Self::with_path(ToolPaths::default().path(ExternalTool::Ffmpeg))
     }
     
     /// 指定したパスでFFmpegToolを作成
@@ -49,8 +49,8 @@ impl FFmpegTool {
     /// 動画を処理する
     pub async fn process_video(
         &self,
-        input_url: &PathBuf,
-        output_path: &PathBuf,
+        input_url: &Path,
+        output_path: &Path,
         filename: &str,
         format: &VideoFormat
     ) -> Result<PathBuf, DownloadError> {
@@ -90,6 +90,7 @@ impl FFmpegTool {
         let mut child = Command::new(&self.executable_path)
             .args(&args)
             .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
             .spawn()?;
             
         // 実行終了を待機
@@ -101,7 +102,7 @@ impl FFmpegTool {
             use tokio::io::AsyncReadExt;
             stderr.read_to_string(&mut error_message).await?;
             
-            return Err(DownloadError::ProcessFailed(error_message));
+            return Err(classify_error(&error_message, status.code(), &input_url.to_string_lossy()));
         }
         
         // 出力ファイルが存在するか確認
@@ -112,17 +113,330 @@ impl FFmpegTool {
         Ok(output_file_path)
     }
     
+    /// 複数のトラックを結合・多重化する
+    ///
+    /// 各トラックは1つ以上のファイルからなり、複数ある場合はconcatデマルチプレクサで
+    /// 連結する。2つ目以降のトラックからは音声のみを使用する。
+    pub async fn merge_tracks(
+        &self,
+        tracks: &[Vec<PathBuf>],
+        output_path: &Path,
+        filename: &str,
+        format: &VideoFormat
+    ) -> Result<PathBuf, DownloadError> {
+        let output_filename = format!("{}.{}", filename, format.to_string().to_lowercase());
+        let output_file_path = output_path.join(&output_filename);
+
+        let mut args = vec!["-y".to_string()];
+        for (index, files) in tracks.iter().enumerate() {
+            match files.as_slice() {
+                [] => return Err(DownloadError::FileNotFound),
+                [file] => {
+                    if is_playlist(file) {
+                        // SAMPLE-AESのローカルのプレイリストは、キー（key{n}.bin）の拡張子と
+                        // 復号に使うcryptoプロトコルを許可しないとhlsデマルチプレクサが読み込めない
+                        args.extend(["-allowed_extensions", "ALL", "-protocol_whitelist", "file,crypto"].map(String::from));
+                    }
+                    args.push("-i".to_string());
+                    args.push(file.to_string_lossy().to_string());
+                }
+                [first, ..] => {
+                    // concatデマルチプレクサ用のリストを入力ファイルと同じ場所に作成
+                    // （リスト内の相対パスはリストファイルの場所を基準に解決される）
+                    let list_path = first.with_file_name(format!("concat{}.txt", index));
+                    let list = files
+                        .iter()
+                        .map(|file| {
+                            let name = file.file_name().unwrap_or_default().to_string_lossy();
+                            format!("file '{}'\n", name.replace('\'', "'\\''"))
+                        })
+                        .collect::<String>();
+                    tokio::fs::write(&list_path, list).await?;
+
+                    args.extend([
+                        "-f".to_string(),
+                        "concat".to_string(),
+                        "-safe".to_string(),
+                        "0".to_string(),
+                        "-i".to_string(),
+                        list_path.to_string_lossy().to_string(),
+                    ]);
+                }
+            }
+        }
+
+        if tracks.len() == 1 {
+            args.extend(["-map", "0:v?", "-map", "0:a?"].map(String::from));
+        } else {
+            args.extend(["-map".to_string(), "0:v?".to_string()]);
+            for index in 1..tracks.len() {
+                args.extend(["-map".to_string(), format!("{}:a?", index)]);
+            }
+        }
+
+        match format {
+            VideoFormat::Mp4 => {
+                args.extend(["-c", "copy", "-movflags", "+faststart"].map(String::from));
+            }
+            VideoFormat::Mkv => {
+                args.extend(["-c", "copy"].map(String::from));
+            }
+            VideoFormat::Mp3 => {
+                // HLSの音声は通常AACのため再エンコードが必要
+                args.extend(["-vn", "-c:a", "libmp3lame", "-q:a", "2"].map(String::from));
+            }
+        }
+        args.push(output_file_path.to_string_lossy().to_string());
+
+        let output = Command::new(&self.executable_path)
+            .args(&args)
+            .kill_on_drop(true)
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            let error_message = String::from_utf8_lossy(&output.stderr);
+            return Err(classify_error(&error_message, output.status.code(), ""));
+        }
+
+        if !output_file_path.exists() {
+            return Err(DownloadError::FileNotFound);
+        }
+
+        Ok(output_file_path)
+    }
+
+    /// 字幕のファイルを動画に埋め込み、元のファイルを置き換える
+    ///
+    /// `subtitles` は字幕のファイルと言語の組。MP4はmov_textに変換し、MKVは字幕の形式のまま
+    /// 多重化する。映像・音声は再エンコードしない。
+    pub async fn embed_subtitles(
+        &self,
+        video: &Path,
+        subtitles: &[(PathBuf, String)],
+        format: &VideoFormat
+    ) -> Result<PathBuf, DownloadError> {
+        let extension = format.to_string().to_lowercase();
+        let temp_path = video.with_extension(format!("subtitles.{}", extension));
+
+        let mut args = vec!["-y".to_string(), "-i".to_string(), video.to_string_lossy().to_string()];
+        for (path, _) in subtitles {
+            args.push("-i".to_string());
+            args.push(path.to_string_lossy().to_string());
+        }
+        args.extend(["-map", "0:v?", "-map", "0:a?"].map(String::from));
+        for index in 1..=subtitles.len() {
+            args.extend(["-map".to_string(), format!("{}:0", index)]);
+        }
+        args.extend(["-c", "copy"].map(String::from));
+
+        match format {
+            VideoFormat::Mp4 => {
+                args.extend(["-c:s", "mov_text", "-movflags", "+faststart"].map(String::from));
+            }
+            VideoFormat::Mkv => {}
+            VideoFormat::Mp3 => {
+                return Err(DownloadError::ProcessFailed("MP3には字幕を埋め込めません".to_string()));
+            }
+        }
+        for (index, (_, language)) in subtitles.iter().enumerate() {
+            // MP4の言語タグはISO 639-2の3文字のみ
+            let language = match format {
+                VideoFormat::Mp4 => iso639_2(language).unwrap_or("und").to_string(),
+                _ => language.clone(),
+            };
+            args.push(format!("-metadata:s:s:{}", index));
+            args.push(format!("language={}", language));
+        }
+
+        self.replace_file(video, &temp_path, args).await
+    }
+
+    /// メタデータとカバーアートを書き込み、元のファイルを置き換える
+    ///
+    /// カバーアートはMP4・MP3では添付画像（attached_pic）、MKVでは添付ファイルにする。
+    /// JPEG・PNG以外の画像（WebPなど）はJPEGに変換してから添付する。
+    pub async fn embed_metadata(
+        &self,
+        file: &Path,
+        metadata: &MediaMetadata,
+        cover: Option<&Path>,
+        format: &VideoFormat
+    ) -> Result<PathBuf, DownloadError> {
+        let extension = format.to_string().to_lowercase();
+        let temp_path = file.with_extension(format!("metadata.{}", extension));
+
+        let converted = match cover {
+            Some(cover) => Some(self.cover_image(cover).await?),
+            None => None,
+        };
+        let cover = converted.as_ref().map(|(path, _)| path.as_path());
+
+        // MP4の添付画像は既存のストリームの後ろに追加する
+        let streams = match (format, cover) {
+            (VideoFormat::Mp4, Some(_)) => self.stream_count(file).await?,
+            _ => 0,
+        };
+        let args = metadata_args(file, metadata, cover, format, streams);
+
+        let result = self.replace_file(file, &temp_path, args).await;
+        if let Some((path, true)) = &converted {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+        result
+    }
+
+    /// 添付できる画像（JPEG・PNG）にする
+    ///
+    /// 変換した場合は変換後のファイルと `true` を返す。
+    async fn cover_image(&self, cover: &Path) -> Result<(PathBuf, bool), DownloadError> {
+        let extension = cover.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+        if matches!(extension, "jpg" | "jpeg" | "png") {
+            return Ok((cover.to_path_buf(), false));
+        }
+
+        let converted = cover.with_extension("jpg");
+        let output = Command::new(&self.executable_path)
+            .arg("-y")
+            .arg("-i")
+            .arg(cover)
+            .args(["-frames:v", "1"])
+            .arg(&converted)
+            .kill_on_drop(true)
+            .output()
+            .await?;
+        if !output.status.success() {
+            let error_message = String::from_utf8_lossy(&output.stderr);
+            return Err(classify_error(&error_message, output.status.code(), ""));
+        }
+        Ok((converted, true))
+    }
+
+    /// ファイルのストリーム数（ffmpegの入力情報の `Stream #0:N` の行数）
+    async fn stream_count(&self, file: &Path) -> Result<usize, DownloadError> {
+        // 出力を指定しないため終了コードは常に失敗になる
+        let output = Command::new(&self.executable_path)
+            .arg("-hide_banner")
+            .arg("-i")
+            .arg(file)
+            .kill_on_drop(true)
+            .output()
+            .await?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Ok(stderr
+            .lines()
+            .filter(|line| line.trim_start().starts_with("Stream #0:"))
+            .count())
+    }
+
+    /// `args` の末尾に一時ファイルを出力先として実行し、成功したら元のファイルを置き換える
+    async fn replace_file(&self, file: &Path, temp_path: &Path, mut args: Vec<String>) -> Result<PathBuf, DownloadError> {
+        args.push(temp_path.to_string_lossy().to_string());
+
+        let output = Command::new(&self.executable_path)
+            .args(&args)
+            .kill_on_drop(true)
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            let _ = tokio::fs::remove_file(temp_path).await;
+            let error_message = String::from_utf8_lossy(&output.stderr);
+            return Err(classify_error(&error_message, output.status.code(), ""));
+        }
+
+        tokio::fs::rename(temp_path, file).await?;
+        Ok(file.to_path_buf())
+    }
+
     /// 音声を抽出
     pub async fn extract_audio(
         &self,
-        input_url: &PathBuf,
-        output_path: &PathBuf,
+        input_url: &Path,
+        output_path: &Path,
         filename: &str
     ) -> Result<PathBuf, DownloadError> {
         self.process_video(input_url, output_path, filename, &VideoFormat::Mp3).await
     }
 }
 
+/// メタデータとカバーアートを書き込むffmpegの引数（出力ファイルを除く）
+///
+/// `streams` は `file` のストリーム数（MP4に添付画像を追加する場合だけ使う）。
+fn metadata_args(
+    file: &Path,
+    metadata: &MediaMetadata,
+    cover: Option<&Path>,
+    format: &VideoFormat,
+    streams: usize
+) -> Vec<String> {
+    let mut args = vec!["-y".to_string(), "-i".to_string(), file.to_string_lossy().to_string()];
+    match (format, cover) {
+        (VideoFormat::Mp4, Some(cover)) => {
+            args.extend(["-i".to_string(), cover.to_string_lossy().to_string()]);
+            args.extend(["-map", "0", "-map", "1", "-c", "copy"].map(String::from));
+            args.extend([format!("-disposition:{}", streams), "attached_pic".to_string()]);
+        }
+        (VideoFormat::Mp3, Some(cover)) => {
+            args.extend(["-i".to_string(), cover.to_string_lossy().to_string()]);
+            args.extend(["-map", "0:a", "-map", "1", "-c", "copy", "-disposition:v:0", "attached_pic"].map(String::from));
+            args.extend(["-metadata:s:v", "title=Album cover", "-metadata:s:v", "comment=Cover (front)"].map(String::from));
+        }
+        (VideoFormat::Mkv, Some(cover)) => {
+            let (mimetype, filename) = match cover.extension().and_then(|extension| extension.to_str()) {
+                Some("png") => ("image/png", "cover.png"),
+                _ => ("image/jpeg", "cover.jpg"),
+            };
+            args.extend(["-map", "0", "-c", "copy"].map(String::from));
+            args.extend(["-attach".to_string(), cover.to_string_lossy().to_string()]);
+            args.extend([
+                "-metadata:s:t".to_string(),
+                format!("mimetype={}", mimetype),
+                "-metadata:s:t".to_string(),
+                format!("filename={}", filename),
+            ]);
+        }
+        (_, None) => {
+            args.extend(["-map", "0", "-c", "copy"].map(String::from));
+        }
+    }
+    for (name, value) in metadata.tags() {
+        args.extend(["-metadata".to_string(), format!("{}={}", name, value)]);
+    }
+    match format {
+        VideoFormat::Mp4 => args.extend(["-movflags", "+faststart"].map(String::from)),
+        // ID3v2.4に対応していないプレーヤーもあるため、ID3v2.3で書き込む
+        VideoFormat::Mp3 => args.extend(["-id3v2_version", "3"].map(String::from)),
+        VideoFormat::Mkv => {}
+    }
+    args
+}
+
+/// ffmpegにプレイリストとして渡すローカルのファイル（SAMPLE-AESの `local.m3u8`）か
+pub(crate) fn is_playlist(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "m3u8")
+}
+
+/// ffmpegの終了コードとエラー出力からエラーの種類を判定
+///
+/// `input` は入力のURL（HTTPエラーの表示に使う）。
+fn classify_error(stderr: &str, exit_code: Option<i32>, input: &str) -> DownloadError {
+    // シグナルで終了した（ffmpegはシグナルを受け取ると255で終了する）
+    if exit_code.is_none() || diagnostics::contains_any(stderr, &["received signal"]) {
+        return diagnostics::terminated("ffmpeg", stderr);
+    }
+    // 新しいオプションを知らない古いバージョン
+    if diagnostics::contains_any(stderr, &["unrecognized option", "option not found"]) {
+        return DownloadError::ToolTooOld {
+            tool: "ffmpeg".to_string(),
+            message: diagnostics::summary(stderr),
+        };
+    }
+
+    diagnostics::classify_common(stderr, input)
+        .unwrap_or_else(|| DownloadError::ProcessFailed(stderr.to_string()))
+}
+
 impl std::fmt::Display for VideoFormat {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -132,3 +446,158 @@ impl std::fmt::Display for VideoFormat {
         }
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use crate::test_util::TempDir;
+
+    /// 引数を `args.txt` に1行ずつ書き出し、最後の引数（出力ファイル）を作成する偽のffmpeg
+    fn fake_ffmpeg(dir: &Path) -> FFmpegTool {
+        let path = dir.join("ffmpeg");
+        std::fs::write(
+            &path,
+            format!(
+                "#!/bin/sh\nfor arg in \"$@\"; do echo \"$arg\"; done > '{}'\nfor arg in \"$@\"; do out=\"$arg\"; done\n: > \"$out\"\n",
+                dir.join("args.txt").display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        FFmpegTool::with_path(path)
+    }
+
+    fn recorded_args(dir: &Path) -> Vec<String> {
+        std::fs::read_to_string(dir.join("args.txt")).unwrap().lines().map(str::to_string).collect()
+    }
+
+    #[tokio::test]
+    async fn local_playlist_input_allows_key_files_and_crypto() {
+        let dir = TempDir::new();
+        let ffmpeg = fake_ffmpeg(dir.path());
+        let playlist = dir.path().join("track0").join("local.m3u8");
+
+        let output = ffmpeg
+            .merge_tracks(&[vec![playlist.clone()]], dir.path(), "video", &VideoFormat::Mkv)
+            .await
+            .unwrap();
+
+        assert_eq!(output, dir.path().join("video.mkv"));
+        let args = recorded_args(dir.path());
+        assert_eq!(args[..7], [
+            "-y",
+            "-allowed_extensions",
+            "ALL",
+            "-protocol_whitelist",
+            "file,crypto",
+            "-i",
+            playlist.to_str().unwrap(),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn segment_inputs_are_passed_without_playlist_options() {
+        let dir = TempDir::new();
+        let ffmpeg = fake_ffmpeg(dir.path());
+        let video = dir.path().join("track0").join("part0.ts");
+        let audio = dir.path().join("track1").join("part0.ts");
+
+        ffmpeg
+            .merge_tracks(&[vec![video.clone()], vec![audio.clone()]], dir.path(), "video", &VideoFormat::Mp4)
+            .await
+            .unwrap();
+
+        let args = recorded_args(dir.path());
+        assert!(!args.iter().any(|arg| arg == "-allowed_extensions"));
+        assert_eq!(args[..5], ["-y", "-i", video.to_str().unwrap(), "-i", audio.to_str().unwrap()]);
+    }
+
+    fn metadata() -> MediaMetadata {
+        MediaMetadata {
+            title: Some("タイトル".to_string()),
+            artist: Some("投稿者".to_string()),
+            source_url: Some("https://example.com/watch?v=abc".to_string()),
+            ..MediaMetadata::default()
+        }
+    }
+
+    #[test]
+    fn mp4_metadata_adds_the_cover_after_existing_streams() {
+        let args = metadata_args(Path::new("video.mp4"), &metadata(), Some(Path::new("cover.jpg")), &VideoFormat::Mp4, 2);
+
+        assert_eq!(args, [
+            "-y", "-i", "video.mp4", "-i", "cover.jpg",
+            "-map", "0", "-map", "1", "-c", "copy", "-disposition:2", "attached_pic",
+            "-metadata", "title=タイトル",
+            "-metadata", "artist=投稿者",
+            "-metadata", "comment=https://example.com/watch?v=abc",
+            "-movflags", "+faststart",
+        ]);
+    }
+
+    #[test]
+    fn mkv_metadata_attaches_the_cover_as_a_file() {
+        let args = metadata_args(Path::new("video.mkv"), &metadata(), Some(Path::new("cover.png")), &VideoFormat::Mkv, 0);
+
+        assert_eq!(args, [
+            "-y", "-i", "video.mkv",
+            "-map", "0", "-c", "copy",
+            "-attach", "cover.png", "-metadata:s:t", "mimetype=image/png", "-metadata:s:t", "filename=cover.png",
+            "-metadata", "title=タイトル",
+            "-metadata", "artist=投稿者",
+            "-metadata", "comment=https://example.com/watch?v=abc",
+        ]);
+        let args = metadata_args(Path::new("video.mkv"), &MediaMetadata::default(), Some(Path::new("cover.jpg")), &VideoFormat::Mkv, 0);
+        assert!(args.windows(2).any(|pair| pair == ["-metadata:s:t", "mimetype=image/jpeg"]));
+    }
+
+    #[test]
+    fn mp3_metadata_writes_id3v2_with_an_attached_cover() {
+        let args = metadata_args(Path::new("audio.mp3"), &metadata(), Some(Path::new("cover.jpg")), &VideoFormat::Mp3, 0);
+
+        assert_eq!(args, [
+            "-y", "-i", "audio.mp3", "-i", "cover.jpg",
+            "-map", "0:a", "-map", "1", "-c", "copy", "-disposition:v:0", "attached_pic",
+            "-metadata:s:v", "title=Album cover", "-metadata:s:v", "comment=Cover (front)",
+            "-metadata", "title=タイトル",
+            "-metadata", "artist=投稿者",
+            "-metadata", "comment=https://example.com/watch?v=abc",
+            "-id3v2_version", "3",
+        ]);
+    }
+
+    #[test]
+    fn metadata_without_a_cover_copies_all_streams() {
+        for format in [VideoFormat::Mp4, VideoFormat::Mkv, VideoFormat::Mp3] {
+            let args = metadata_args(Path::new("input"), &metadata(), None, &format, 0);
+            assert_eq!(args[..7], ["-y", "-i", "input", "-map", "0", "-c", "copy"], "{}", format);
+            assert!(!args.iter().any(|arg| arg.contains("attach")), "{}", format);
+        }
+    }
+
+    #[test]
+    fn signal_exits_are_not_fatal() {
+        for (stderr, exit_code) in [
+            ("frame= 100 fps=25 q=-1.0 size=1024kB\n", None),
+            ("frame= 100 fps=25 q=-1.0 size=1024kB\nExiting normally, received signal 15.\n", Some(255)),
+        ] {
+            let err = classify_error(stderr, exit_code, "https://example.com/index.m3u8");
+            assert!(matches!(err, DownloadError::ProcessFailed(_)), "{:?}", err);
+            assert!(!err.is_fatal());
+        }
+    }
+
+    #[test]
+    fn classifies_old_versions_and_http_errors() {
+        let err = classify_error("Unrecognized option 'http_persistent'.\nError splitting the argument list: Option not found\n", Some(1), "");
+        assert!(matches!(err, DownloadError::ToolTooOld { ref tool, .. } if tool == "ffmpeg"), "{:?}", err);
+
+        let url = "https://example.com/index.m3u8";
+        let err = classify_error("[https @ 0x55d] HTTP error 404 Not Found\nServer returned 404 Not Found\n", Some(1), url);
+        assert!(matches!(err, DownloadError::HttpStatus { status: 404, url: ref failed } if failed == url), "{:?}", err);
+
+        let err = classify_error("Invalid data found when processing input\n", Some(1), url);
+        assert!(matches!(err, DownloadError::ProcessFailed(_)), "{:?}", err);
+    }
+}
@@ -0,0 +1,82 @@
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+use crate::types::DownloadError;
+
+type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+
+/// AES-128のキー長（バイト）
+pub const KEY_LENGTH: usize = 16;
+
+/// 取得したキーを検証して固定長に変換
+pub fn parse_key(bytes: &[u8]) -> Result<[u8; KEY_LENGTH], DownloadError> {
+    bytes.try_into().map_err(|_| {
+        DownloadError::InvalidManifest(format!(
+            "AES-128のキー長が不正です（{}バイト）",
+            bytes.len()
+        ))
+    })
+}
+
+/// AES-128-CBC（PKCS#7パディング）で暗号化されたセグメントを復号
+pub fn decrypt_aes128(
+    data: &[u8],
+    key: &[u8; KEY_LENGTH],
+    iv: &[u8; 16]
+) -> Result<Vec<u8>, DownloadError> {
+    Aes128CbcDec::new(key.into(), iv.into())
+        .decrypt_padded_vec_mut::<Pkcs7>(data)
+        .map_err(|_| DownloadError::ProcessFailed("セグメントの復号に失敗しました".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// NIST SP 800-38A F.2.1（CBC-AES128）のキー・初期化ベクトル・最初のブロック
+    const KEY: [u8; 16] = [
+        0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f, 0x3c,
+    ];
+    const IV: [u8; 16] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+    ];
+    const PLAINTEXT: [u8; 16] = [
+        0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17, 0x2a,
+    ];
+    const CIPHERTEXT: [u8; 16] = [
+        0x76, 0x49, 0xab, 0xac, 0x81, 0x19, 0xb2, 0x46, 0xce, 0xe9, 0x8e, 0x9b, 0x12, 0xe9, 0x19, 0x7d,
+    ];
+    /// 上の平文・暗号文に続く、PKCS#7パディングだけのブロックを暗号化したもの
+    const PADDING_BLOCK: [u8; 16] = [
+        0x89, 0x64, 0xe0, 0xb1, 0x49, 0xc1, 0x0b, 0x7b, 0x68, 0x2e, 0x6e, 0x39, 0xaa, 0xeb, 0x73, 0x1c,
+    ];
+
+    #[test]
+    fn decrypts_known_ciphertext() {
+        let data = [CIPHERTEXT, PADDING_BLOCK].concat();
+        assert_eq!(decrypt_aes128(&data, &KEY, &IV).unwrap(), PLAINTEXT);
+    }
+
+    #[test]
+    fn wrong_iv_or_key_does_not_decrypt() {
+        let data = [CIPHERTEXT, PADDING_BLOCK].concat();
+        // CBCでは初期化ベクトルが違うと最初のブロックだけが変わる
+        let mut iv = IV;
+        iv[15] ^= 1;
+        assert_ne!(decrypt_aes128(&data, &KEY, &iv).unwrap(), PLAINTEXT);
+
+        let mut key = KEY;
+        key[0] ^= 1;
+        assert!(decrypt_aes128(&data, &key, &IV).is_err());
+    }
+
+    #[test]
+    fn rejects_ciphertext_that_is_not_block_aligned() {
+        assert!(decrypt_aes128(&CIPHERTEXT[..15], &KEY, &IV).is_err());
+    }
+
+    #[test]
+    fn parses_only_sixteen_byte_keys() {
+        assert_eq!(parse_key(&KEY).unwrap(), KEY);
+        assert!(matches!(parse_key(&KEY[..15]), Err(DownloadError::InvalidManifest(_))));
+        assert!(parse_key(&[KEY, KEY].concat()).is_err());
+    }
+}
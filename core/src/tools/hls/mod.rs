@@ -0,0 +1,623 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use futures::StreamExt;
+use url::Url;
+use crate::types::{DownloadError, ProgressCallback, ProgressPhase, DownloadOptions, FormatInfo, FormatSelection};
+use crate::retry::RetryPolicy;
+use crate::tools::FFmpegTool;
+use crate::tools::ffmpeg::is_playlist;
+use crate::tools::fetcher::{self, ByteRange, SegmentFetcher, SegmentProgress};
+
+pub mod playlist;
+pub mod crypto;
+
+pub use self::playlist::{
+    Playlist, MasterPlaylist, MediaPlaylist, VariantStream, Rendition, Segment, SegmentKey, KeyMethod
+};
+use self::playlist::InitSection;
+use self::crypto::KEY_LENGTH;
+
+/// 同時に取得するセグメント数の上限
+const MAX_CONCURRENT_SEGMENTS: u32 = 16;
+
+/// HLSダウンロードを扱うための構造体
+///
+/// M3U8プレイリストを自前で解析してセグメントを並列に取得し、
+/// FFmpegToolで結合・リマックスする。yt-dlpには依存しない。
+///
+/// AES-128で暗号化されたセグメントは取得時に復号する。SAMPLE-AESは
+/// セグメント内のサンプル単位で暗号化されているため、キーとともに
+/// ローカルのプレイリストとしてffmpegに渡して復号する。
+pub struct HlsDownloadTool {
+    ffmpeg: FFmpegTool,
+    client: reqwest::Client,
+}
+
+impl Default for HlsDownloadTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HlsDownloadTool {
+    /// 新しいHlsDownloadToolを作成
+    pub fn new() -> Self {
+        Self::with_tools(FFmpegTool::new(), fetcher::http_client())
+    }
+
+    /// 使用するFFmpegToolとHTTPクライアントを指定してHlsDownloadToolを作成
+    pub fn with_tools(ffmpeg: FFmpegTool, client: reqwest::Client) -> Self {
+        Self { ffmpeg, client }
+    }
+
+    /// プレイリストを取得して解析
+    pub async fn load_playlist(&self, url: &str) -> Result<Playlist, DownloadError> {
+        let fetcher = SegmentFetcher::new(self.client.clone(), RetryPolicy::none());
+        let url = parse_url(url)?;
+        Playlist::parse(&fetcher.fetch_text(&url).await?, &url)
+    }
+
+    /// 選択可能なフォーマット（バリアントと別トラックの音声）の一覧
+    pub async fn list_formats(&self, url: &str) -> Result<Vec<FormatInfo>, DownloadError> {
+        match self.load_playlist(url).await? {
+            Playlist::Master(master) => Ok(master_formats(&master)),
+            Playlist::Media(_) => Ok(vec![FormatInfo {
+                format_id: Some("hls".to_string()),
+                url: Some(url.to_string()),
+                manifest_url: Some(url.to_string()),
+                ext: Some("mp4".to_string()),
+                ..Default::default()
+            }]),
+        }
+    }
+
+    /// HLSマニフェストを解析してセグメントURLを取得
+    ///
+    /// マスタープレイリストの場合は最も高いビットレートのバリアントを使用する。
+    pub async fn parse_manifest(&self, url: &str) -> Result<Vec<String>, DownloadError> {
+        let fetcher = SegmentFetcher::new(self.client.clone(), RetryPolicy::none());
+        let selection = FormatSelection::default();
+        let (video, _) = self.resolve_tracks(&fetcher, &parse_url(url)?, &selection).await?;
+
+        Ok(video.segments.iter().map(|segment| segment.uri.to_string()).collect())
+    }
+
+    /// HLSストリームをダウンロード
+    pub async fn download(
+        &self,
+        url: &str,
+        output_path: &Path,
+        filename: &str,
+        options: &DownloadOptions,
+        progress_callback: Option<ProgressCallback>
+    ) -> Result<PathBuf, DownloadError> {
+        let url = parse_url(url)?;
+        let fetcher = SegmentFetcher::new(
+            fetcher::request_client(&self.client, &options.request, &url)?,
+            RetryPolicy::from_options(options)
+        )
+        .with_throttle(options.throttle.clone());
+        let progress = SegmentProgress::new(progress_callback);
+        progress.phase(ProgressPhase::Probing);
+
+        let (video, audio) = self.resolve_tracks(&fetcher, &url, &options.selection).await?;
+        let tracks: Vec<MediaPlaylist> = std::iter::once(video).chain(audio).collect();
+
+        // 取得済みのセグメントは一時停止・中断後の再開時に再利用する
+        let work_dir = output_path.join(format!("{}.hls.part", filename));
+        tokio::fs::create_dir_all(&work_dir).await?;
+
+        progress.set_total_segments(tracks.iter().map(|track| track.segments.len() as u64).sum());
+        let concurrency = options.connections.clamp(1, MAX_CONCURRENT_SEGMENTS) as usize;
+
+        let mut track_files = Vec::new();
+        for (index, track) in tracks.iter().enumerate() {
+            let track_dir = work_dir.join(format!("track{}", index));
+            // 2番目以降のトラックは別の音声レンディション
+            let phase = if index == 0 { ProgressPhase::DownloadingVideo } else { ProgressPhase::DownloadingAudio };
+            let files = download_track(&fetcher, track, &track_dir, concurrency, &progress, phase).await?;
+            track_files.push(files);
+        }
+
+        let output_file = if self.ffmpeg.is_available().await {
+            progress.phase(ProgressPhase::Merging);
+            self.ffmpeg
+                .merge_tracks(&track_files, output_path, filename, &options.format)
+                .await?
+        } else {
+            match track_files.as_slice() {
+                // 単一のファイルになる場合はffmpegなしでもそのまま出力できる
+                [files] if files.len() == 1 && !is_playlist(&files[0]) => {
+                    log::warn!("ffmpegが見つからないため、リマックスせずに保存します");
+                    let extension = files[0].extension().unwrap_or_default().to_string_lossy().to_string();
+                    let output_file = output_path.join(format!("{}.{}", filename, extension));
+                    tokio::fs::rename(&files[0], &output_file).await?;
+                    output_file
+                }
+                _ => {
+                    return Err(DownloadError::ProcessFailed(
+                        "このストリームの結合にはffmpegが必要です".to_string()
+                    ));
+                }
+            }
+        };
+
+        tokio::fs::remove_dir_all(&work_dir).await?;
+        Ok(output_file)
+    }
+
+    /// ダウンロードするメディアプレイリスト（映像と、別トラックの音声）を決定
+    async fn resolve_tracks(
+        &self,
+        fetcher: &SegmentFetcher,
+        url: &Url,
+        selection: &FormatSelection
+    ) -> Result<(MediaPlaylist, Option<MediaPlaylist>), DownloadError> {
+        let master = match Playlist::parse(&fetcher.fetch_text(url).await?, url)? {
+            Playlist::Media(media) => return Ok((checked(media)?, None)),
+            Playlist::Master(master) => master,
+        };
+
+        // フォーマット一覧の先頭はバリアントと同じ順序
+        let formats = master_formats(&master);
+        let variant_formats = &formats[..master.variants.len()];
+        let selected = selection.select_video(variant_formats).ok_or_else(|| {
+            DownloadError::InvalidManifest(format!(
+                "指定されたフォーマットが見つかりません: {}",
+                selection.format_id.as_deref().unwrap_or_default()
+            ))
+        })?;
+        let variant = &master.variants[index_of(variant_formats, selected)];
+
+        let video = load_media(fetcher, &variant.uri).await?;
+
+        // 音声が別のレンディションとして配信されている場合は合わせて取得する
+        let audio_uri = variant.audio.as_ref().and_then(|group_id| {
+            let renditions: Vec<&Rendition> = master
+                .renditions
+                .iter()
+                .filter(|rendition| rendition.media_type == "AUDIO" && &rendition.group_id == group_id)
+                .filter(|rendition| rendition.uri.is_some())
+                .collect();
+
+            let by_selection = selection.audio_language.is_some() || selection.format_id.is_some();
+            let rendition_formats: Vec<FormatInfo> = renditions.iter().map(|rendition| rendition_format(rendition)).collect();
+            let selected = match selection.select_audio(&rendition_formats) {
+                Some(format) if by_selection => Some(renditions[index_of(&rendition_formats, format)]),
+                _ => renditions.iter().find(|rendition| rendition.default).or(renditions.first()).copied(),
+            };
+            selected.and_then(|rendition| rendition.uri.clone())
+        });
+
+        let audio = match audio_uri {
+            Some(uri) if uri != variant.uri => Some(load_media(fetcher, &uri).await?),
+            _ => None,
+        };
+
+        Ok((video, audio))
+    }
+}
+
+/// マスタープレイリストのフォーマット一覧（バリアント、音声レンディションの順）
+fn master_formats(master: &MasterPlaylist) -> Vec<FormatInfo> {
+    let variants = master.variants.iter().map(|variant| {
+        let codecs: Vec<&str> = variant.codecs.as_deref().unwrap_or_default().split(',').map(str::trim).collect();
+        let (audio_codecs, video_codecs): (Vec<&str>, Vec<&str>) = codecs
+            .into_iter()
+            .filter(|codec| !codec.is_empty())
+            .partition(|codec| is_audio_codec(codec));
+
+        FormatInfo {
+            format_id: Some(format!("hls-{}", variant.bandwidth / 1000)),
+            url: Some(variant.uri.to_string()),
+            width: variant.resolution.map(|(width, _)| width),
+            height: variant.resolution.map(|(_, height)| height),
+            ext: Some("mp4".to_string()),
+            // CODECSがない場合も映像を含むバリアントとして扱う
+            vcodec: Some(video_codecs.first().copied().unwrap_or("unknown").to_string()),
+            acodec: audio_codecs.first().map(|codec| codec.to_string()),
+            tbr: Some(variant.average_bandwidth.unwrap_or(variant.bandwidth) as f64 / 1000.0),
+            fps: variant.frame_rate,
+            ..Default::default()
+        }
+    });
+
+    let renditions = master
+        .renditions
+        .iter()
+        .filter(|rendition| rendition.media_type == "AUDIO" && rendition.uri.is_some())
+        .map(rendition_format);
+
+    variants.chain(renditions).collect()
+}
+
+/// 音声レンディションのフォーマット情報
+fn rendition_format(rendition: &Rendition) -> FormatInfo {
+    let name = rendition.language.as_deref().unwrap_or(&rendition.name);
+    FormatInfo {
+        format_id: Some(format!("hls-audio-{}-{}", rendition.group_id, name)),
+        url: rendition.uri.as_ref().map(Url::to_string),
+        ext: Some("mp4".to_string()),
+        vcodec: Some("none".to_string()),
+        language: rendition.language.clone(),
+        ..Default::default()
+    }
+}
+
+fn is_audio_codec(codec: &str) -> bool {
+    ["mp4a", "ac-3", "ec-3", "opus", "flac", "alac"].iter().any(|prefix| codec.starts_with(prefix))
+}
+
+/// 一覧内での位置（選択結果は一覧の要素への参照）
+fn index_of(formats: &[FormatInfo], selected: &FormatInfo) -> usize {
+    formats.iter().position(|format| std::ptr::eq(format, selected)).unwrap_or(0)
+}
+
+/// メディアプレイリストを取得
+async fn load_media(fetcher: &SegmentFetcher, url: &Url) -> Result<MediaPlaylist, DownloadError> {
+    match Playlist::parse(&fetcher.fetch_text(url).await?, url)? {
+        Playlist::Media(media) => checked(media),
+        Playlist::Master(_) => Err(DownloadError::InvalidManifest(format!(
+            "{} はメディアプレイリストではありません",
+            url
+        ))),
+    }
+}
+
+fn checked(media: MediaPlaylist) -> Result<MediaPlaylist, DownloadError> {
+    if media.segments.is_empty() {
+        return Err(DownloadError::InvalidManifest("セグメントがありません".to_string()));
+    }
+    if !media.end_list {
+        log::warn!("ライブ配信のプレイリストです。現在公開されているセグメントのみを取得します");
+    }
+    Ok(media)
+}
+
+fn parse_url(url: &str) -> Result<Url, DownloadError> {
+    Url::parse(url).map_err(|err| DownloadError::InvalidManifest(format!("不正なURL {}: {}", url, err)))
+}
+
+/// 取得対象のリソース（初期化セクションまたはセグメント）
+struct Resource<'a> {
+    uri: &'a Url,
+    byte_range: Option<ByteRange>,
+    path: PathBuf,
+    is_segment: bool,
+    /// AES-128で復号する場合のキーと初期化ベクトル
+    decryption: Option<([u8; KEY_LENGTH], [u8; 16])>,
+}
+
+/// 1つのメディアプレイリストのセグメントを取得し、連結したファイルを返す
+///
+/// 不連続点や初期化セクションの切り替わりごとに別のファイルに分ける。
+/// SAMPLE-AESの場合は連結せず、ffmpegに渡すローカルのプレイリストを返す。
+async fn download_track(
+    fetcher: &SegmentFetcher,
+    playlist: &MediaPlaylist,
+    track_dir: &Path,
+    concurrency: usize,
+    progress: &SegmentProgress,
+    phase: ProgressPhase
+) -> Result<Vec<PathBuf>, DownloadError> {
+    tokio::fs::create_dir_all(track_dir).await?;
+
+    // 初期化セクションと、それを最初に使うセグメントのシーケンス番号
+    let mut maps: Vec<(&InitSection, u64)> = Vec::new();
+    for segment in &playlist.segments {
+        if let Some(map) = &segment.map {
+            if !maps.iter().any(|(m, _)| *m == map) {
+                maps.push((map, segment.sequence));
+            }
+        }
+    }
+
+    let sample_aes = playlist
+        .segments
+        .iter()
+        .any(|segment| segment.key.as_ref().is_some_and(|key| key.method == KeyMethod::SampleAes));
+    if sample_aes && !maps.is_empty() {
+        return Err(DownloadError::DrmProtected("fMP4のSAMPLE-AES".to_string()));
+    }
+
+    // キーを取得（同じURLのキーは一度だけ取得する）
+    let mut keys: HashMap<&Url, [u8; KEY_LENGTH]> = HashMap::new();
+    let segment_keys = playlist.segments.iter().filter_map(|segment| segment.key.as_ref());
+    let map_keys = maps.iter().filter_map(|(map, _)| map.key.as_ref());
+    for key in segment_keys.chain(map_keys) {
+        if !keys.contains_key(&key.uri) {
+            let bytes = fetcher.fetch(&key.uri, None).await?;
+            keys.insert(&key.uri, crypto::parse_key(&bytes)?);
+        }
+    }
+
+    let decryption = |key: &Option<SegmentKey>, sequence: u64| {
+        key.as_ref()
+            .filter(|key| key.method == KeyMethod::Aes128)
+            .and_then(|key| Some((*keys.get(&key.uri)?, key.iv_for(sequence))))
+    };
+
+    let map_path = |map: &InitSection| {
+        let index = maps.iter().position(|(m, _)| *m == map).unwrap_or(0);
+        track_dir.join(format!("init{}.mp4", index))
+    };
+    let segment_path = |index: usize| {
+        let extension = if maps.is_empty() { "ts" } else { "m4s" };
+        track_dir.join(format!("segment{:05}.{}", index, extension))
+    };
+
+    let resources: Vec<Resource> = maps
+        .iter()
+        .map(|(map, sequence)| Resource {
+            uri: &map.uri,
+            byte_range: map.byte_range,
+            path: map_path(map),
+            is_segment: false,
+            decryption: decryption(&map.key, *sequence),
+        })
+        .chain(playlist.segments.iter().enumerate().map(|(index, segment)| Resource {
+            uri: &segment.uri,
+            byte_range: segment.byte_range,
+            path: segment_path(index),
+            is_segment: true,
+            decryption: decryption(&segment.key, segment.sequence),
+        }))
+        .collect();
+
+    let fetches: Vec<_> = resources
+        .iter()
+        .map(|resource| fetch_to_file(fetcher, resource))
+        .collect();
+    let mut results = futures::stream::iter(fetches).buffer_unordered(concurrency);
+
+    while let Some(result) = results.next().await {
+        let (is_segment, size) = result?;
+        progress.add(phase, is_segment, size);
+    }
+
+    if sample_aes {
+        let playlist_path = write_local_playlist(playlist, track_dir, &keys, segment_path).await?;
+        return Ok(vec![playlist_path]);
+    }
+
+    // 取得したセグメントを順番に連結する
+    let mut files: Vec<PathBuf> = Vec::new();
+    let mut current: Option<tokio::fs::File> = None;
+    let mut current_map: Option<&InitSection> = None;
+
+    for (index, segment) in playlist.segments.iter().enumerate() {
+        let starts_part = current.is_none() || segment.discontinuity || segment.map.as_ref() != current_map;
+        if starts_part {
+            let extension = if segment.map.is_some() { "mp4" } else { "ts" };
+            let path = track_dir.join(format!("part{}.{}", files.len(), extension));
+            let mut file = tokio::fs::File::create(&path).await?;
+            if let Some(map) = &segment.map {
+                let mut init = tokio::fs::File::open(map_path(map)).await?;
+                tokio::io::copy(&mut init, &mut file).await?;
+            }
+            files.push(path);
+            current = Some(file);
+            current_map = segment.map.as_ref();
+        }
+
+        if let Some(file) = current.as_mut() {
+            let mut input = tokio::fs::File::open(segment_path(index)).await?;
+            tokio::io::copy(&mut input, file).await?;
+        }
+    }
+
+    Ok(files)
+}
+
+/// リソースを取得して保存し、種類とサイズを返す
+async fn fetch_to_file(
+    fetcher: &SegmentFetcher,
+    resource: &Resource<'_>
+) -> Result<(bool, u64), DownloadError> {
+    let size = fetcher
+        .fetch_to_file(resource.uri, resource.byte_range, &resource.path, |bytes| {
+            match &resource.decryption {
+                Some((key, iv)) => crypto::decrypt_aes128(&bytes, key, iv),
+                None => Ok(bytes),
+            }
+        })
+        .await?;
+
+    Ok((resource.is_segment, size))
+}
+
+/// 取得済みのセグメントとキーを参照するローカルのプレイリストを作成
+///
+/// AES-128のセグメントは取得時に復号済みのため、暗号化なしとして記述する。
+async fn write_local_playlist(
+    playlist: &MediaPlaylist,
+    track_dir: &Path,
+    keys: &HashMap<&Url, [u8; KEY_LENGTH]>,
+    segment_path: impl Fn(usize) -> PathBuf
+) -> Result<PathBuf, DownloadError> {
+    let mut text = String::new();
+    let _ = writeln!(text, "#EXTM3U");
+    let _ = writeln!(text, "#EXT-X-VERSION:3");
+    let _ = writeln!(text, "#EXT-X-TARGETDURATION:{}", playlist.target_duration.ceil() as u64);
+    let _ = writeln!(text, "#EXT-X-MEDIA-SEQUENCE:{}", playlist.media_sequence);
+
+    let mut key_files: Vec<&Url> = Vec::new();
+    let mut current_key: Option<&SegmentKey> = None;
+
+    for (index, segment) in playlist.segments.iter().enumerate() {
+        if segment.discontinuity {
+            let _ = writeln!(text, "#EXT-X-DISCONTINUITY");
+        }
+
+        let key = segment.key.as_ref().filter(|key| key.method == KeyMethod::SampleAes);
+        if index == 0 || key != current_key {
+            match key {
+                Some(key) => {
+                    let key_index = match key_files.iter().position(|uri| *uri == &key.uri) {
+                        Some(key_index) => key_index,
+                        None => {
+                            let bytes = keys.get(&key.uri).ok_or(DownloadError::FileNotFound)?;
+                            let key_path = track_dir.join(format!("key{}.bin", key_files.len()));
+                            tokio::fs::write(&key_path, bytes).await?;
+                            key_files.push(&key.uri);
+                            key_files.len() - 1
+                        }
+                    };
+                    let _ = write!(text, "#EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"key{}.bin\"", key_index);
+                    if let Some(iv) = key.iv {
+                        let hex: String = iv.iter().map(|byte| format!("{:02x}", byte)).collect();
+                        let _ = write!(text, ",IV=0x{}", hex);
+                    }
+                    let _ = writeln!(text);
+                }
+                None => {
+                    let _ = writeln!(text, "#EXT-X-KEY:METHOD=NONE");
+                }
+            }
+            current_key = key;
+        }
+
+        let path = segment_path(index);
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let _ = writeln!(text, "#EXTINF:{:.3},", segment.duration);
+        let _ = writeln!(text, "{}", name);
+    }
+    let _ = writeln!(text, "#EXT-X-ENDLIST");
+
+    let playlist_path = track_dir.join("local.m3u8");
+    tokio::fs::write(&playlist_path, text).await?;
+    Ok(playlist_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{TempDir, TestServer};
+
+    /// ffmpegなしのツール（単一のファイルになるストリームのみ保存できる）
+    fn tool_without_ffmpeg() -> HlsDownloadTool {
+        HlsDownloadTool::with_tools(FFmpegTool::with_path(PathBuf::from("/nonexistent/ffmpeg")), fetcher::http_client())
+    }
+
+    #[tokio::test]
+    async fn downloads_master_playlist_segments_in_order() {
+        let server = TestServer::files(vec![
+            (
+                "/master.m3u8",
+                b"#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=200000\nlow/index.m3u8\n#EXT-X-STREAM-INF:BANDWIDTH=800000\nhigh/index.m3u8\n".to_vec(),
+            ),
+            ("/low/index.m3u8", b"#EXTM3U\n#EXTINF:4,\nlow.ts\n#EXT-X-ENDLIST\n".to_vec()),
+            (
+                "/high/index.m3u8",
+                b"#EXTM3U\n#EXT-X-TARGETDURATION:4\n\
+                  #EXTINF:4,\nseg0.ts\n\
+                  #EXTINF:4,\n#EXT-X-BYTERANGE:4@2\npacked.ts\n\
+                  #EXTINF:4,\n#EXT-X-BYTERANGE:3\npacked.ts\n\
+                  #EXT-X-ENDLIST\n".to_vec(),
+            ),
+            ("/high/seg0.ts", b"AAAA".to_vec()),
+            ("/high/packed.ts", b"xxBBBBCCCyy".to_vec()),
+            ("/low/low.ts", b"LOW".to_vec()),
+        ])
+        .await;
+        let dir = TempDir::new();
+
+        let output = tool_without_ffmpeg()
+            .download(&server.url("/master.m3u8"), dir.path(), "video", &DownloadOptions::default(), None)
+            .await
+            .unwrap();
+
+        assert_eq!(output, dir.path().join("video.ts"));
+        assert_eq!(std::fs::read(&output).unwrap(), b"AAAABBBBCCC");
+        // 作業ディレクトリは完了後に削除する
+        assert!(!dir.path().join("video.hls.part").exists());
+        assert!(server.requests().iter().all(|request| !request.path.starts_with("/low/")));
+    }
+
+    #[tokio::test]
+    async fn discontinuity_requires_ffmpeg_to_merge() {
+        let server = TestServer::files(vec![
+            (
+                "/index.m3u8",
+                b"#EXTM3U\n#EXTINF:4,\na.ts\n#EXT-X-DISCONTINUITY\n#EXTINF:4,\nb.ts\n#EXT-X-ENDLIST\n".to_vec(),
+            ),
+            ("/a.ts", b"A".to_vec()),
+            ("/b.ts", b"B".to_vec()),
+        ])
+        .await;
+        let dir = TempDir::new();
+
+        let result = tool_without_ffmpeg()
+            .download(&server.url("/index.m3u8"), dir.path(), "video", &DownloadOptions::default(), None)
+            .await;
+
+        assert!(matches!(result, Err(DownloadError::ProcessFailed(_))));
+        // 取得済みのセグメントは再開用に残す
+        let part = dir.path().join("video.hls.part").join("track0");
+        assert_eq!(std::fs::read(part.join("part0.ts")).unwrap(), b"A");
+        assert_eq!(std::fs::read(part.join("part1.ts")).unwrap(), b"B");
+    }
+
+    /// AES-128-CBC（PKCS#7パディング）で暗号化
+    fn encrypt(data: &[u8], key: &[u8; KEY_LENGTH], iv: &[u8; 16]) -> Vec<u8> {
+        use aes::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+        cbc::Encryptor::<aes::Aes128>::new(key.into(), iv.into()).encrypt_padded_vec_mut::<Pkcs7>(data)
+    }
+
+    #[tokio::test]
+    async fn decrypts_aes128_segments_with_explicit_and_sequence_ivs() {
+        let key = *b"0123456789abcdef";
+        let explicit_iv = *b"fedcba9876543210";
+        // IVの指定がない場合はシーケンス番号（ここでは8）をビッグエンディアンで使う
+        let mut sequence_iv = [0u8; 16];
+        sequence_iv[15] = 8;
+
+        let server = TestServer::files(vec![
+            (
+                "/index.m3u8",
+                b"#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXT-X-MEDIA-SEQUENCE:7\n\
+                  #EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\",IV=0x66656463626139383736353433323130\n\
+                  #EXTINF:4,\nseg7.ts\n\
+                  #EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\"\n\
+                  #EXTINF:4,\nseg8.ts\n\
+                  #EXT-X-KEY:METHOD=NONE\n\
+                  #EXTINF:4,\nseg9.ts\n\
+                  #EXT-X-ENDLIST\n".to_vec(),
+            ),
+            ("/key.bin", key.to_vec()),
+            ("/seg7.ts", encrypt(b"explicit iv|", &key, &explicit_iv)),
+            ("/seg8.ts", encrypt(b"sequence iv|", &key, &sequence_iv)),
+            ("/seg9.ts", b"clear".to_vec()),
+        ])
+        .await;
+        let dir = TempDir::new();
+
+        let output = tool_without_ffmpeg()
+            .download(&server.url("/index.m3u8"), dir.path(), "video", &DownloadOptions::default(), None)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&output).unwrap(), b"explicit iv|sequence iv|clear");
+        // 同じキーは一度だけ取得する
+        let key_requests = server.requests().iter().filter(|request| request.path == "/key.bin").count();
+        assert_eq!(key_requests, 1);
+    }
+
+    #[tokio::test]
+    async fn missing_segment_fails_the_download() {
+        let server = TestServer::files(vec![(
+            "/index.m3u8",
+            b"#EXTM3U\n#EXTINF:4,\nmissing.ts\n#EXT-X-ENDLIST\n".to_vec(),
+        )])
+        .await;
+        let dir = TempDir::new();
+        let options = DownloadOptions { max_retries: 0, ..Default::default() };
+
+        let result = tool_without_ffmpeg()
+            .download(&server.url("/index.m3u8"), dir.path(), "video", &options, None)
+            .await;
+
+        assert!(matches!(result, Err(DownloadError::HttpStatus { status: 404, .. })), "{:?}", result);
+    }
+}
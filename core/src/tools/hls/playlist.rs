@@ -0,0 +1,607 @@
+use std::collections::HashMap;
+use url::Url;
+use crate::types::DownloadError;
+use crate::tools::fetcher::ByteRange;
+
+/// M3U8プレイリスト
+#[derive(Debug, Clone)]
+pub enum Playlist {
+    /// マスタープレイリスト（バリアントの一覧）
+    Master(MasterPlaylist),
+    /// メディアプレイリスト（セグメントの一覧）
+    Media(MediaPlaylist),
+}
+
+/// マスタープレイリスト
+#[derive(Debug, Clone, Default)]
+pub struct MasterPlaylist {
+    /// バリアントストリーム
+    pub variants: Vec<VariantStream>,
+    /// 代替レンディション（EXT-X-MEDIA）
+    pub renditions: Vec<Rendition>,
+}
+
+/// バリアントストリーム（EXT-X-STREAM-INF）
+#[derive(Debug, Clone)]
+pub struct VariantStream {
+    /// メディアプレイリストのURL
+    pub uri: Url,
+    /// ピークビットレート (bps)
+    pub bandwidth: u64,
+    /// 平均ビットレート (bps)
+    pub average_bandwidth: Option<u64>,
+    /// 解像度（幅, 高さ）
+    pub resolution: Option<(u32, u32)>,
+    /// コーデック
+    pub codecs: Option<String>,
+    /// フレームレート
+    pub frame_rate: Option<f64>,
+    /// 音声レンディションのグループID
+    pub audio: Option<String>,
+}
+
+/// 代替レンディション（EXT-X-MEDIA）
+#[derive(Debug, Clone)]
+pub struct Rendition {
+    /// 種別（AUDIO, VIDEO, SUBTITLES, CLOSED-CAPTIONS）
+    pub media_type: String,
+    /// グループID
+    pub group_id: String,
+    /// 名前
+    pub name: String,
+    /// 言語
+    pub language: Option<String>,
+    /// メディアプレイリストのURL（バリアントに多重化されている場合はNone）
+    pub uri: Option<Url>,
+    /// デフォルトのレンディションか
+    pub default: bool,
+}
+
+/// メディアプレイリスト
+#[derive(Debug, Clone, Default)]
+pub struct MediaPlaylist {
+    /// 最大セグメント長（秒）
+    pub target_duration: f64,
+    /// 先頭セグメントのシーケンス番号
+    pub media_sequence: u64,
+    /// セグメント
+    pub segments: Vec<Segment>,
+    /// EXT-X-ENDLISTがあるか（ライブ配信ではない）
+    pub end_list: bool,
+}
+
+impl MediaPlaylist {
+    /// 全セグメントの長さの合計（秒）
+    pub fn duration(&self) -> f64 {
+        self.segments.iter().map(|segment| segment.duration).sum()
+    }
+}
+
+/// メディアセグメント
+#[derive(Debug, Clone)]
+pub struct Segment {
+    /// セグメントのURL
+    pub uri: Url,
+    /// 長さ（秒）
+    pub duration: f64,
+    /// シーケンス番号
+    pub sequence: u64,
+    /// 取得するバイト範囲（EXT-X-BYTERANGE）
+    pub byte_range: Option<ByteRange>,
+    /// 直前にEXT-X-DISCONTINUITYがあるか
+    pub discontinuity: bool,
+    /// 初期化セクション（EXT-X-MAP）
+    pub map: Option<InitSection>,
+    /// 暗号化キー（EXT-X-KEY）
+    pub key: Option<SegmentKey>,
+}
+
+/// 初期化セクション（EXT-X-MAP）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitSection {
+    /// 初期化セクションのURL
+    pub uri: Url,
+    /// 取得するバイト範囲
+    pub byte_range: Option<ByteRange>,
+    /// 暗号化キー（EXT-X-MAPの時点で有効なEXT-X-KEY）
+    pub key: Option<SegmentKey>,
+}
+
+/// 暗号化方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyMethod {
+    /// セグメント全体をAES-128-CBCで暗号化
+    Aes128,
+    /// サンプル単位の暗号化（MPEG-TSのみ対応）
+    SampleAes,
+}
+
+/// セグメントの暗号化キー（EXT-X-KEY）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentKey {
+    /// 暗号化方式
+    pub method: KeyMethod,
+    /// キーのURL
+    pub uri: Url,
+    /// 明示的に指定された初期化ベクトル
+    pub iv: Option<[u8; 16]>,
+}
+
+impl SegmentKey {
+    /// 初期化ベクトル（未指定の場合はシーケンス番号から導出する）
+    pub fn iv_for(&self, sequence: u64) -> [u8; 16] {
+        self.iv.unwrap_or_else(|| {
+            let mut iv = [0u8; 16];
+            iv[8..].copy_from_slice(&sequence.to_be_bytes());
+            iv
+        })
+    }
+}
+
+impl Playlist {
+    /// M3U8テキストを解析する
+    ///
+    /// 相対URLは `base_url` を基準に解決する。
+    pub fn parse(text: &str, base_url: &Url) -> Result<Self, DownloadError> {
+        let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+
+        if lines.next() != Some("#EXTM3U") {
+            return Err(DownloadError::InvalidManifest("#EXTM3Uヘッダーがありません".to_string()));
+        }
+
+        let is_master = text.lines().any(|line| line.trim_start().starts_with("#EXT-X-STREAM-INF"));
+        if is_master {
+            parse_master(lines, base_url).map(Self::Master)
+        } else {
+            parse_media(lines, base_url).map(Self::Media)
+        }
+    }
+}
+
+fn parse_master<'a>(
+    lines: impl Iterator<Item = &'a str>,
+    base_url: &Url
+) -> Result<MasterPlaylist, DownloadError> {
+    let mut playlist = MasterPlaylist::default();
+    let mut pending: Option<VariantStream> = None;
+
+    for line in lines {
+        if let Some(value) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+            let attrs = parse_attributes(value);
+            // URIは次の行で確定する
+            pending = Some(VariantStream {
+                uri: base_url.clone(),
+                bandwidth: attrs.get("BANDWIDTH").and_then(|v| v.parse().ok()).unwrap_or(0),
+                average_bandwidth: attrs.get("AVERAGE-BANDWIDTH").and_then(|v| v.parse().ok()),
+                resolution: attrs.get("RESOLUTION").and_then(|v| {
+                    let (width, height) = v.split_once('x')?;
+                    Some((width.parse().ok()?, height.parse().ok()?))
+                }),
+                codecs: attrs.get("CODECS").cloned(),
+                frame_rate: attrs.get("FRAME-RATE").and_then(|v| v.parse().ok()),
+                audio: attrs.get("AUDIO").cloned(),
+            });
+        } else if let Some(value) = line.strip_prefix("#EXT-X-MEDIA:") {
+            let attrs = parse_attributes(value);
+            let uri = match attrs.get("URI") {
+                Some(uri) => Some(resolve(base_url, uri)?),
+                None => None,
+            };
+            playlist.renditions.push(Rendition {
+                media_type: attrs.get("TYPE").cloned().unwrap_or_default(),
+                group_id: attrs.get("GROUP-ID").cloned().unwrap_or_default(),
+                name: attrs.get("NAME").cloned().unwrap_or_default(),
+                language: attrs.get("LANGUAGE").cloned(),
+                uri,
+                default: attrs.get("DEFAULT").is_some_and(|v| v == "YES"),
+            });
+        } else if !line.starts_with('#') {
+            if let Some(mut variant) = pending.take() {
+                variant.uri = resolve(base_url, line)?;
+                playlist.variants.push(variant);
+            }
+        }
+    }
+
+    if playlist.variants.is_empty() {
+        return Err(DownloadError::InvalidManifest("バリアントストリームがありません".to_string()));
+    }
+
+    Ok(playlist)
+}
+
+fn parse_media<'a>(
+    lines: impl Iterator<Item = &'a str>,
+    base_url: &Url
+) -> Result<MediaPlaylist, DownloadError> {
+    let mut playlist = MediaPlaylist::default();
+    let mut sequence = 0;
+
+    // 次のセグメントに適用するタグの状態
+    let mut duration: Option<f64> = None;
+    let mut byte_range: Option<(u64, Option<u64>)> = None;
+    let mut discontinuity = false;
+    let mut map: Option<InitSection> = None;
+    let mut key = KeyState::None;
+    // 連続するEXT-X-KEYは同じセグメントに対する別形式のキー
+    let mut in_key_group = false;
+    // オフセット省略時のEXT-X-BYTERANGEは直前のサブレンジの続きから始まる
+    let mut last_range_end: Option<(Url, u64)> = None;
+
+    for line in lines {
+        if let Some(value) = line.strip_prefix("#EXT-X-TARGETDURATION:") {
+            playlist.target_duration = value.parse().unwrap_or(0.0);
+        } else if let Some(value) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+            playlist.media_sequence = value.parse().unwrap_or(0);
+            sequence = playlist.media_sequence;
+        } else if let Some(value) = line.strip_prefix("#EXTINF:") {
+            let value = value.split(',').next().unwrap_or_default();
+            duration = Some(value.trim().parse().map_err(|_| {
+                DownloadError::InvalidManifest(format!("不正なEXTINF: {}", line))
+            })?);
+        } else if let Some(value) = line.strip_prefix("#EXT-X-BYTERANGE:") {
+            byte_range = Some(parse_byte_range(value).ok_or_else(|| {
+                DownloadError::InvalidManifest(format!("不正なEXT-X-BYTERANGE: {}", line))
+            })?);
+        } else if line == "#EXT-X-DISCONTINUITY" {
+            discontinuity = true;
+        } else if let Some(value) = line.strip_prefix("#EXT-X-MAP:") {
+            let attrs = parse_attributes(value);
+            let uri = attrs.get("URI").ok_or_else(|| {
+                DownloadError::InvalidManifest("EXT-X-MAPにURIがありません".to_string())
+            })?;
+            let byte_range = match attrs.get("BYTERANGE") {
+                Some(value) => {
+                    let (length, offset) = parse_byte_range(value).ok_or_else(|| {
+                        DownloadError::InvalidManifest(format!("不正なEXT-X-MAP: {}", line))
+                    })?;
+                    Some(ByteRange { length, offset: offset.unwrap_or(0) })
+                }
+                None => None,
+            };
+            map = Some(InitSection {
+                uri: resolve(base_url, uri)?,
+                byte_range,
+                key: key.segment_key(line)?,
+            });
+        } else if let Some(value) = line.strip_prefix("#EXT-X-KEY:") {
+            let parsed = parse_key(&parse_attributes(value), base_url)?;
+            // 複数の形式が併記されている場合は対応できるキーを優先する
+            if !(in_key_group && matches!(key, KeyState::Key(_)) && matches!(parsed, KeyState::Drm(_))) {
+                key = parsed;
+            }
+            in_key_group = true;
+            continue;
+        } else if line == "#EXT-X-ENDLIST" {
+            playlist.end_list = true;
+        } else if !line.starts_with('#') {
+            let uri = resolve(base_url, line)?;
+            let byte_range = match byte_range.take() {
+                Some((length, offset)) => {
+                    let offset = match (offset, &last_range_end) {
+                        (Some(offset), _) => offset,
+                        (None, Some((last_uri, end))) if *last_uri == uri => *end,
+                        (None, _) => {
+                            return Err(DownloadError::InvalidManifest(format!(
+                                "EXT-X-BYTERANGEの開始位置が不明です: {}",
+                                line
+                            )));
+                        }
+                    };
+                    last_range_end = Some((uri.clone(), offset + length));
+                    Some(ByteRange { length, offset })
+                }
+                None => None,
+            };
+
+            playlist.segments.push(Segment {
+                uri,
+                duration: duration.take().unwrap_or(playlist.target_duration),
+                sequence,
+                byte_range,
+                discontinuity: std::mem::take(&mut discontinuity),
+                map: map.clone(),
+                key: key.segment_key(line)?,
+            });
+            sequence += 1;
+        }
+        in_key_group = false;
+    }
+
+    Ok(playlist)
+}
+
+/// 解析中のEXT-X-KEYの状態
+enum KeyState {
+    /// 暗号化なし
+    None,
+    /// 対応している暗号化
+    Key(SegmentKey),
+    /// 対応していないDRM（方式またはKEYFORMAT）
+    Drm(String),
+}
+
+impl KeyState {
+    /// セグメントに適用するキー（DRMの場合はエラー）
+    fn segment_key(&self, line: &str) -> Result<Option<SegmentKey>, DownloadError> {
+        match self {
+            Self::None => Ok(None),
+            Self::Key(key) => Ok(Some(key.clone())),
+            Self::Drm(format) => Err(DownloadError::DrmProtected(format!("{} ({})", format, line))),
+        }
+    }
+}
+
+/// EXT-X-KEYの属性を解析
+fn parse_key(attrs: &HashMap<String, String>, base_url: &Url) -> Result<KeyState, DownloadError> {
+    let method = match attrs.get("METHOD").map(String::as_str).unwrap_or("NONE") {
+        "NONE" => return Ok(KeyState::None),
+        "AES-128" => KeyMethod::Aes128,
+        "SAMPLE-AES" => KeyMethod::SampleAes,
+        other => return Ok(KeyState::Drm(other.to_string())),
+    };
+
+    // FairPlayやWidevineなどのKEYFORMATは鍵の取得にライセンスサーバーが必要
+    match attrs.get("KEYFORMAT").map(String::as_str) {
+        None | Some("identity") => {}
+        Some(format) => return Ok(KeyState::Drm(format.to_string())),
+    }
+
+    let uri = attrs
+        .get("URI")
+        .ok_or_else(|| DownloadError::InvalidManifest("EXT-X-KEYにURIがありません".to_string()))?;
+    let iv = match attrs.get("IV") {
+        Some(value) => Some(parse_iv(value).ok_or_else(|| {
+            DownloadError::InvalidManifest(format!("不正なIV: {}", value))
+        })?),
+        None => None,
+    };
+
+    Ok(KeyState::Key(SegmentKey {
+        method,
+        uri: resolve(base_url, uri)?,
+        iv,
+    }))
+}
+
+/// `0x` で始まる16進数の初期化ベクトルを解析
+fn parse_iv(value: &str) -> Option<[u8; 16]> {
+    let hex = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X"))?;
+    if hex.is_empty() || hex.len() > 32 {
+        return None;
+    }
+
+    // 32桁未満の場合は上位を0で埋める
+    let hex = format!("{:0>32}", hex);
+    let mut iv = [0u8; 16];
+    for (index, byte) in iv.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(iv)
+}
+
+/// `<n>[@<o>]` 形式のバイト範囲を解析
+fn parse_byte_range(value: &str) -> Option<(u64, Option<u64>)> {
+    match value.split_once('@') {
+        Some((length, offset)) => Some((length.trim().parse().ok()?, Some(offset.trim().parse().ok()?))),
+        None => Some((value.trim().parse().ok()?, None)),
+    }
+}
+
+/// `KEY=VALUE,KEY="QUOTED,VALUE"` 形式の属性リストを解析
+fn parse_attributes(value: &str) -> HashMap<String, String> {
+    let mut attrs = HashMap::new();
+    let mut rest = value.trim();
+
+    while !rest.is_empty() {
+        let Some((key, after)) = rest.split_once('=') else {
+            break;
+        };
+
+        let (value, after) = if let Some(quoted) = after.strip_prefix('"') {
+            match quoted.split_once('"') {
+                Some((value, after)) => (value, after),
+                None => (quoted, ""),
+            }
+        } else {
+            match after.split_once(',') {
+                Some((value, after)) => (value, after),
+                None => (after, ""),
+            }
+        };
+
+        attrs.insert(key.trim().to_string(), value.to_string());
+        rest = after.trim_start_matches(',').trim_start();
+    }
+
+    attrs
+}
+
+fn resolve(base_url: &Url, uri: &str) -> Result<Url, DownloadError> {
+    base_url
+        .join(uri)
+        .map_err(|err| DownloadError::InvalidManifest(format!("不正なURL {}: {}", uri, err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Url {
+        Url::parse("https://cdn.example.com/live/index.m3u8").unwrap()
+    }
+
+    fn media(text: &str) -> MediaPlaylist {
+        match Playlist::parse(text, &base()).unwrap() {
+            Playlist::Media(media) => media,
+            Playlist::Master(_) => panic!("メディアプレイリストとして解析されませんでした"),
+        }
+    }
+
+    #[test]
+    fn parses_master_playlist() {
+        let text = "#EXTM3U\n\
+            #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aud\",NAME=\"English\",LANGUAGE=\"en\",DEFAULT=YES,URI=\"audio/en.m3u8\"\n\
+            #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aud\",NAME=\"Main\"\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=1280000,AVERAGE-BANDWIDTH=1000000,RESOLUTION=1280x720,CODECS=\"avc1.4d401f,mp4a.40.2\",FRAME-RATE=29.970,AUDIO=\"aud\"\n\
+            720p/index.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=640000\n\
+            https://other.example.com/low.m3u8\n";
+
+        let Playlist::Master(master) = Playlist::parse(text, &base()).unwrap() else {
+            panic!("マスタープレイリストとして解析されませんでした");
+        };
+        assert_eq!(master.variants.len(), 2);
+        let high = &master.variants[0];
+        assert_eq!(high.uri.as_str(), "https://cdn.example.com/live/720p/index.m3u8");
+        assert_eq!(high.bandwidth, 1_280_000);
+        assert_eq!(high.average_bandwidth, Some(1_000_000));
+        assert_eq!(high.resolution, Some((1280, 720)));
+        assert_eq!(high.codecs.as_deref(), Some("avc1.4d401f,mp4a.40.2"));
+        assert_eq!(high.frame_rate, Some(29.97));
+        assert_eq!(high.audio.as_deref(), Some("aud"));
+        assert_eq!(master.variants[1].uri.as_str(), "https://other.example.com/low.m3u8");
+        assert_eq!(master.variants[1].resolution, None);
+
+        assert_eq!(master.renditions.len(), 2);
+        let english = &master.renditions[0];
+        assert_eq!(english.media_type, "AUDIO");
+        assert_eq!(english.group_id, "aud");
+        assert_eq!(english.language.as_deref(), Some("en"));
+        assert!(english.default);
+        assert_eq!(english.uri.as_ref().unwrap().as_str(), "https://cdn.example.com/live/audio/en.m3u8");
+        // URIのないレンディションはバリアントに多重化されている
+        assert_eq!(master.renditions[1].uri, None);
+        assert!(!master.renditions[1].default);
+    }
+
+    #[test]
+    fn parses_media_playlist() {
+        let playlist = media(
+            "#EXTM3U\n\
+             #EXT-X-TARGETDURATION:10\n\
+             #EXT-X-MEDIA-SEQUENCE:42\n\
+             #EXTINF:9.009,\n\
+             seg42.ts\n\
+             #EXTINF:4.5,title\n\
+             /abs/seg43.ts\n\
+             #EXT-X-ENDLIST\n",
+        );
+
+        assert_eq!(playlist.target_duration, 10.0);
+        assert_eq!(playlist.media_sequence, 42);
+        assert!(playlist.end_list);
+        assert_eq!(playlist.segments.len(), 2);
+        assert_eq!(playlist.segments[0].uri.as_str(), "https://cdn.example.com/live/seg42.ts");
+        assert_eq!(playlist.segments[0].sequence, 42);
+        assert_eq!(playlist.segments[0].duration, 9.009);
+        assert_eq!(playlist.segments[1].uri.as_str(), "https://cdn.example.com/abs/seg43.ts");
+        assert_eq!(playlist.segments[1].sequence, 43);
+        assert!((playlist.duration() - 13.509).abs() < 1e-9);
+    }
+
+    #[test]
+    fn live_playlist_has_no_end_list() {
+        let playlist = media("#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXTINF:6,\na.ts\n");
+        assert!(!playlist.end_list);
+        assert_eq!(playlist.media_sequence, 0);
+    }
+
+    #[test]
+    fn rejects_text_without_header() {
+        let result = Playlist::parse("#EXTINF:6,\na.ts\n", &base());
+        assert!(matches!(result, Err(DownloadError::InvalidManifest(_))));
+    }
+
+    #[test]
+    fn applies_map_to_following_segments() {
+        let playlist = media(
+            "#EXTM3U\n\
+             #EXT-X-TARGETDURATION:4\n\
+             #EXT-X-MAP:URI=\"init.mp4\",BYTERANGE=\"720@0\"\n\
+             #EXTINF:4,\n\
+             a.m4s\n\
+             #EXTINF:4,\n\
+             b.m4s\n\
+             #EXT-X-MAP:URI=\"init2.mp4\"\n\
+             #EXTINF:4,\n\
+             c.m4s\n",
+        );
+
+        let first = playlist.segments[0].map.as_ref().unwrap();
+        assert_eq!(first.uri.as_str(), "https://cdn.example.com/live/init.mp4");
+        assert_eq!(first.byte_range, Some(ByteRange { length: 720, offset: 0 }));
+        assert_eq!(playlist.segments[1].map.as_ref(), Some(first));
+
+        let second = playlist.segments[2].map.as_ref().unwrap();
+        assert_eq!(second.uri.as_str(), "https://cdn.example.com/live/init2.mp4");
+        assert_eq!(second.byte_range, None);
+    }
+
+    #[test]
+    fn byte_range_without_offset_continues_previous_range() {
+        let playlist = media(
+            "#EXTM3U\n\
+             #EXT-X-TARGETDURATION:4\n\
+             #EXTINF:4,\n\
+             #EXT-X-BYTERANGE:1000@500\n\
+             all.ts\n\
+             #EXTINF:4,\n\
+             #EXT-X-BYTERANGE:2000\n\
+             all.ts\n\
+             #EXTINF:4,\n\
+             #EXT-X-BYTERANGE:300\n\
+             all.ts\n\
+             #EXTINF:4,\n\
+             whole.ts\n",
+        );
+
+        let ranges: Vec<Option<ByteRange>> = playlist.segments.iter().map(|segment| segment.byte_range).collect();
+        assert_eq!(
+            ranges,
+            vec![
+                Some(ByteRange { length: 1000, offset: 500 }),
+                Some(ByteRange { length: 2000, offset: 1500 }),
+                Some(ByteRange { length: 300, offset: 3500 }),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn byte_range_without_offset_for_new_uri_is_an_error() {
+        let result = Playlist::parse(
+            "#EXTM3U\n#EXTINF:4,\n#EXT-X-BYTERANGE:1000@0\na.ts\n#EXTINF:4,\n#EXT-X-BYTERANGE:1000\nb.ts\n",
+            &base(),
+        );
+        assert!(matches!(result, Err(DownloadError::InvalidManifest(_))));
+    }
+
+    #[test]
+    fn marks_only_the_segment_after_discontinuity() {
+        let playlist = media(
+            "#EXTM3U\n\
+             #EXTINF:4,\n\
+             a.ts\n\
+             #EXT-X-DISCONTINUITY\n\
+             #EXTINF:4,\n\
+             ad.ts\n\
+             #EXTINF:4,\n\
+             ad2.ts\n\
+             #EXT-X-DISCONTINUITY\n\
+             #EXTINF:4,\n\
+             b.ts\n",
+        );
+
+        let flags: Vec<bool> = playlist.segments.iter().map(|segment| segment.discontinuity).collect();
+        assert_eq!(flags, vec![false, true, false, true]);
+    }
+
+    #[test]
+    fn parses_attribute_lists_with_quoted_commas() {
+        let attrs = parse_attributes("BANDWIDTH=1000,CODECS=\"avc1.64001f,mp4a.40.2\", NAME=\"a=b\"");
+        assert_eq!(attrs.get("BANDWIDTH").map(String::as_str), Some("1000"));
+        assert_eq!(attrs.get("CODECS").map(String::as_str), Some("avc1.64001f,mp4a.40.2"));
+        assert_eq!(attrs.get("NAME").map(String::as_str), Some("a=b"));
+    }
+}
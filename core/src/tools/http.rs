@@ -0,0 +1,532 @@
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use crate::bandwidth::Throttle;
+use crate::types::{DownloadError, ProgressCallback, ProgressInfo, ProgressPhase, DownloadOptions};
+use crate::retry::RetryPolicy;
+use crate::tools::fetcher::{self, ByteRange};
+
+/// 進捗を通知する最短の間隔
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// 外部ツールを使わずにHTTPでファイルをダウンロードするための構造体
+///
+/// サーバーがRangeリクエストに対応している場合はファイルを分割し、複数の
+/// コネクションで並行して取得する。取得済みの範囲は状態ファイルに記録し、
+/// 中断後は続きから再開する。
+pub struct HttpDownloadTool {
+    client: reqwest::Client,
+}
+
+impl Default for HttpDownloadTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpDownloadTool {
+    /// 新しいHttpDownloadToolを作成
+    pub fn new() -> Self {
+        Self::with_client(fetcher::http_client())
+    }
+
+    /// 指定したHTTPクライアントでHttpDownloadToolを作成
+    pub fn with_client(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+
+    /// ファイルをダウンロード
+    pub async fn download(
+        &self,
+        url: &str,
+        output_path: &Path,
+        filename: &str,
+        options: &DownloadOptions,
+        progress_callback: Option<ProgressCallback>
+    ) -> Result<PathBuf, DownloadError> {
+        let output_filename = format!("{}.{}", filename, options.format.to_string().to_lowercase());
+        let output_file_path = output_path.join(&output_filename);
+        let part_path = output_path.join(format!("{}.part", output_filename));
+        let state_path = output_path.join(format!("{}.part.json", output_filename));
+
+        let parsed_url = url::Url::parse(url)
+            .map_err(|err| DownloadError::ProcessFailed(format!("不正なURL {}: {}", url, err)))?;
+        let client = fetcher::request_client(&self.client, &options.request, &parsed_url)?;
+        let client = &client;
+
+        let retry = RetryPolicy::from_options(options);
+        let throttle = &options.throttle;
+        let probe = retry.run(url, || probe(client, url)).await?;
+
+        match probe {
+            Probe { total_length: Some(total_length), accept_ranges: true } if total_length > 0 => {
+                // 分割数で割った大きさを基本とし、チャンクサイズより細かくは分けない
+                let piece_size = total_length
+                    .div_ceil(options.splits.max(1) as u64)
+                    .max(options.chunk_size.max(1) as u64 * 1024 * 1024);
+                let concurrency = options.splits.min(options.connections).max(1) as usize;
+
+                let mut state = match DownloadState::load(&state_path, &part_path).await {
+                    Some(state) if state.matches(url, total_length, piece_size) => state,
+                    _ => DownloadState::new(url, total_length, piece_size),
+                };
+                if state.completed_length() == 0 {
+                    let file = tokio::fs::File::create(&part_path).await?;
+                    file.set_len(total_length).await?;
+                }
+
+                let progress = TransferProgress::new(Some(total_length), state.completed_length(), progress_callback);
+                let pending: Vec<(usize, ByteRange)> = state
+                    .pending_pieces()
+                    .map(|index| (index, state.piece_range(index)))
+                    .collect();
+                let fetches: Vec<_> = pending
+                    .into_iter()
+                    .map(|(index, range)| {
+                        let (retry, progress, part_path) = (&retry, &progress, &part_path);
+                        async move {
+                            let written = retry.run(url, || fetch_piece(client, url, range, part_path, progress, throttle)).await?;
+                            Ok::<_, DownloadError>((index, written))
+                        }
+                    })
+                    .collect();
+                let mut results = futures::stream::iter(fetches).buffer_unordered(concurrency);
+
+                // 一時ファイルは最初に全体の大きさを確保するため、実際に書き込んだ量で確かめる
+                let mut written_length = state.completed_length();
+                while let Some(result) = results.next().await {
+                    let (index, written) = result?;
+                    let expected = state.piece_range(index).length;
+                    if written != expected {
+                        return Err(DownloadError::ProcessFailed(format!(
+                            "{} の範囲{}から受信したサイズ（{}バイト）が要求した長さ（{}バイト）と一致しません",
+                            url, index, written, expected
+                        )));
+                    }
+                    written_length += written;
+                    state.completed[index] = true;
+                    state.save(&state_path).await?;
+                }
+                drop(results);
+
+                if written_length != total_length {
+                    return Err(DownloadError::ProcessFailed(format!(
+                        "ダウンロードしたサイズ（{}バイト）がContent-Length（{}バイト）と一致しません",
+                        written_length, total_length
+                    )));
+                }
+                progress.emit(true);
+                tokio::fs::remove_file(&state_path).await?;
+            }
+            // Rangeに対応していないサーバーは1本のコネクションで最初から取得する
+            Probe { total_length, .. } => {
+                let _ = tokio::fs::remove_file(&state_path).await;
+                let progress = TransferProgress::new(total_length, 0, progress_callback);
+                retry.run(url, || fetch_whole(client, url, total_length, &part_path, &progress, throttle)).await?;
+                progress.emit(true);
+            }
+        }
+
+        tokio::fs::rename(&part_path, &output_file_path).await?;
+        Ok(output_file_path)
+    }
+}
+
+/// ファイルサイズとRangeリクエストへの対応を確認
+async fn probe(client: &reqwest::Client, url: &str) -> Result<Probe, (DownloadError, bool)> {
+    let response = client
+        .get(url)
+        .header(reqwest::header::RANGE, "bytes=0-0")
+        .send()
+        .await
+        .map_err(|err| (err.into(), true))?;
+    let response = check_status(url, response)?;
+
+    if response.status() == reqwest::StatusCode::PARTIAL_CONTENT {
+        let total_length = content_range(&response).and_then(|(_, _, total)| total);
+        return Ok(Probe { total_length, accept_ranges: total_length.is_some() });
+    }
+
+    Ok(Probe { total_length: response.content_length(), accept_ranges: false })
+}
+
+/// 指定したバイト範囲を取得し、一時ファイルの該当位置に書き込む（書き込んだバイト数を返す）
+async fn fetch_piece(
+    client: &reqwest::Client,
+    url: &str,
+    range: ByteRange,
+    part_path: &Path,
+    progress: &TransferProgress,
+    throttle: &Throttle
+) -> Result<u64, (DownloadError, bool)> {
+    let response = client
+        .get(url)
+        .header(reqwest::header::RANGE, range.header_value())
+        .send()
+        .await
+        .map_err(|err| (err.into(), true))?;
+    let response = check_status(url, response)?;
+
+    let expected = Some((range.offset, range.offset + range.length - 1));
+    if response.status() != reqwest::StatusCode::PARTIAL_CONTENT
+        || content_range(&response).map(|(start, end, _)| (start, end)) != expected
+    {
+        let err = DownloadError::ProcessFailed(format!("{} が指定したバイト範囲を返しませんでした", url));
+        return Err((err, false));
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(part_path)
+        .await
+        .map_err(|err| (err.into(), false))?;
+    file.seek(SeekFrom::Start(range.offset)).await.map_err(|err| (err.into(), false))?;
+
+    write_body(url, response, &mut file, Some(range.length), progress, throttle).await
+}
+
+/// 1本のコネクションでファイル全体を取得する
+async fn fetch_whole(
+    client: &reqwest::Client,
+    url: &str,
+    total_length: Option<u64>,
+    part_path: &Path,
+    progress: &TransferProgress,
+    throttle: &Throttle
+) -> Result<(), (DownloadError, bool)> {
+    let response = client.get(url).send().await.map_err(|err| (err.into(), true))?;
+    let response = check_status(url, response)?;
+
+    let mut file = tokio::fs::File::create(part_path).await.map_err(|err| (err.into(), false))?;
+    write_body(url, response, &mut file, total_length, progress, throttle).await?;
+    Ok(())
+}
+
+/// ファイルサイズの確認結果
+struct Probe {
+    total_length: Option<u64>,
+    accept_ranges: bool,
+}
+
+/// 取得済みの範囲を記録する状態ファイル
+#[derive(Debug, Serialize, Deserialize)]
+struct DownloadState {
+    url: String,
+    total_length: u64,
+    piece_size: u64,
+    completed: Vec<bool>,
+}
+
+impl DownloadState {
+    fn new(url: &str, total_length: u64, piece_size: u64) -> Self {
+        Self {
+            url: url.to_string(),
+            total_length,
+            piece_size,
+            completed: vec![false; total_length.div_ceil(piece_size) as usize],
+        }
+    }
+
+    /// 状態ファイルを読み込む（一時ファイルがない場合は最初から取得する）
+    async fn load(state_path: &Path, part_path: &Path) -> Option<Self> {
+        let length = tokio::fs::metadata(part_path).await.ok()?.len();
+        let json = tokio::fs::read(state_path).await.ok()?;
+        let state: Self = serde_json::from_slice(&json).ok()?;
+        (state.total_length == length).then_some(state)
+    }
+
+    /// 書き込み途中の状態を読まないよう一時ファイル経由で保存する
+    async fn save(&self, state_path: &Path) -> Result<(), DownloadError> {
+        let tmp_path = state_path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, serde_json::to_vec(self)?).await?;
+        tokio::fs::rename(&tmp_path, state_path).await?;
+        Ok(())
+    }
+
+    /// 同じファイルの同じ分割方法か
+    fn matches(&self, url: &str, total_length: u64, piece_size: u64) -> bool {
+        self.url == url
+            && self.total_length == total_length
+            && self.piece_size == piece_size
+            && self.completed.len() as u64 == total_length.div_ceil(piece_size)
+    }
+
+    fn piece_range(&self, index: usize) -> ByteRange {
+        let offset = index as u64 * self.piece_size;
+        ByteRange {
+            length: self.piece_size.min(self.total_length - offset),
+            offset,
+        }
+    }
+
+    fn pending_pieces(&self) -> impl Iterator<Item = usize> + '_ {
+        self.completed.iter().enumerate().filter(|(_, done)| !**done).map(|(index, _)| index)
+    }
+
+    fn completed_length(&self) -> u64 {
+        (0..self.completed.len())
+            .filter(|index| self.completed[*index])
+            .map(|index| self.piece_range(index).length)
+            .sum()
+    }
+}
+
+/// 転送量の集計
+struct TransferProgress {
+    total_length: Option<u64>,
+    downloaded: AtomicU64,
+    /// 再開時点で取得済みだったバイト数（速度の計算から除く）
+    resumed: u64,
+    started_at: Instant,
+    last_emit: Mutex<Option<Instant>>,
+    callback: Option<ProgressCallback>,
+}
+
+impl TransferProgress {
+    fn new(total_length: Option<u64>, resumed: u64, callback: Option<ProgressCallback>) -> Self {
+        Self {
+            total_length,
+            downloaded: AtomicU64::new(resumed),
+            resumed,
+            started_at: Instant::now(),
+            last_emit: Mutex::new(None),
+            callback,
+        }
+    }
+
+    fn add(&self, bytes: u64) {
+        self.downloaded.fetch_add(bytes, Ordering::Relaxed);
+        self.emit(false);
+    }
+
+    /// 失敗した試行で書き込んだ分を取り消す
+    fn rollback(&self, bytes: u64) {
+        self.downloaded.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    fn emit(&self, force: bool) {
+        let Some(callback) = &self.callback else {
+            return;
+        };
+        {
+            let mut last_emit = self.last_emit.lock().unwrap();
+            if !force && last_emit.is_some_and(|last| last.elapsed() < PROGRESS_INTERVAL) {
+                return;
+            }
+            *last_emit = Some(Instant::now());
+        }
+
+        let downloaded = self.downloaded.load(Ordering::Relaxed);
+        let speed = downloaded.saturating_sub(self.resumed) as f64 / self.started_at.elapsed().as_secs_f64().max(0.001);
+        let eta = self
+            .total_length
+            .filter(|_| speed > 0.0)
+            .map(|total| Duration::from_secs_f64(total.saturating_sub(downloaded) as f64 / speed));
+
+        callback(ProgressInfo {
+            phase: ProgressPhase::DownloadingVideo,
+            progress: self
+                .total_length
+                .map(|total| downloaded as f64 / total.max(1) as f64)
+                .unwrap_or(0.0),
+            downloaded_bytes: downloaded,
+            total_bytes: self.total_length,
+            speed: Some(speed),
+            eta,
+        });
+    }
+}
+
+/// エラーステータスを再試行可能かどうかと共に返す
+fn check_status(url: &str, response: reqwest::Response) -> Result<reqwest::Response, (DownloadError, bool)> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let err = DownloadError::HttpStatus { status: status.as_u16(), url: url.to_string() };
+    let retryable = err.is_retryable();
+    Err((err, retryable))
+}
+
+/// `Content-Range: bytes 0-99/1234` を（開始, 終了, 合計）に変換
+fn content_range(response: &reqwest::Response) -> Option<(u64, u64, Option<u64>)> {
+    let value = response.headers().get(reqwest::header::CONTENT_RANGE)?.to_str().ok()?;
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    Some((start.parse().ok()?, end.parse().ok()?, total.parse().ok()))
+}
+
+/// レスポンス本文を書き込み、Content-Lengthと一致するか検証する（書き込んだバイト数を返す）
+async fn write_body(
+    url: &str,
+    mut response: reqwest::Response,
+    file: &mut tokio::fs::File,
+    expected_length: Option<u64>,
+    progress: &TransferProgress,
+    throttle: &Throttle
+) -> Result<u64, (DownloadError, bool)> {
+    let expected_length = expected_length.or(response.content_length());
+    let mut written = 0;
+
+    let result = async {
+        while let Some(chunk) = response.chunk().await.map_err(|err| (err.into(), true))? {
+            throttle.acquire(chunk.len() as u64).await;
+            file.write_all(&chunk).await.map_err(|err| (err.into(), false))?;
+            written += chunk.len() as u64;
+            progress.add(chunk.len() as u64);
+        }
+        file.flush().await.map_err(|err| (err.into(), false))?;
+
+        match expected_length {
+            Some(expected) if written != expected => {
+                let err = DownloadError::ProcessFailed(format!(
+                    "{} から受信したサイズ（{}バイト）がContent-Length（{}バイト）と一致しません",
+                    url, written, expected
+                ));
+                Err((err, true))
+            }
+            _ => Ok(written),
+        }
+    }
+    .await;
+
+    if result.is_err() {
+        progress.rollback(written);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{Request, Response, TempDir, TestServer};
+
+    const MIB: usize = 1024 * 1024;
+
+    /// 位置ごとに異なる内容のデータ
+    fn body(length: usize) -> Vec<u8> {
+        (0..length).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn options(splits: u32) -> DownloadOptions {
+        DownloadOptions {
+            splits,
+            connections: splits,
+            chunk_size: 1,
+            max_retries: 0,
+            ..DownloadOptions::default()
+        }
+    }
+
+    /// 確認用（bytes=0-0）を除いたRangeリクエストの範囲
+    fn fetched_ranges(server: &TestServer) -> Vec<(u64, Option<u64>)> {
+        let mut ranges: Vec<_> = server
+            .requests()
+            .iter()
+            .filter_map(Request::range)
+            .filter(|range| *range != (0, Some(0)))
+            .collect();
+        ranges.sort();
+        ranges
+    }
+
+    #[tokio::test]
+    async fn downloads_ranges_over_multiple_connections() {
+        let data = body(3 * MIB + 100);
+        let server = TestServer::files(vec![("/video.mp4", data.clone())]).await;
+        let dir = TempDir::new();
+
+        let path = HttpDownloadTool::new()
+            .download(&server.url("/video.mp4"), dir.path(), "video", &options(4), None)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        let mib = MIB as u64;
+        assert_eq!(fetched_ranges(&server), vec![
+            (0, Some(mib - 1)),
+            (mib, Some(2 * mib - 1)),
+            (2 * mib, Some(3 * mib - 1)),
+            (3 * mib, Some(3 * mib + 99)),
+        ]);
+        assert!(!dir.path().join("video.mp4.part").exists());
+        assert!(!dir.path().join("video.mp4.part.json").exists());
+    }
+
+    #[tokio::test]
+    async fn resumes_from_the_state_file() {
+        let data = body(2 * MIB + 10);
+        let server = TestServer::files(vec![("/video.mp4", data.clone())]).await;
+        let url = server.url("/video.mp4");
+        let dir = TempDir::new();
+
+        // 前半だけ取得した状態で中断していた
+        let mut part = data[..MIB].to_vec();
+        part.resize(data.len(), 0);
+        std::fs::write(dir.path().join("video.mp4.part"), &part).unwrap();
+        let mut state = DownloadState::new(&url, data.len() as u64, MIB as u64);
+        state.completed[0] = true;
+        state.save(&dir.path().join("video.mp4.part.json")).await.unwrap();
+
+        let path = HttpDownloadTool::new()
+            .download(&url, dir.path(), "video", &options(3), None)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        let mib = MIB as u64;
+        assert_eq!(fetched_ranges(&server), vec![(mib, Some(2 * mib - 1)), (2 * mib, Some(2 * mib + 9))]);
+        assert!(!dir.path().join("video.mp4.part.json").exists());
+    }
+
+    #[tokio::test]
+    async fn downloads_whole_file_when_ranges_are_not_supported() {
+        let data = body(MIB + 10);
+        let served = data.clone();
+        let server = TestServer::start(move |_| Response::ok(served.clone())).await;
+        let dir = TempDir::new();
+
+        let path = HttpDownloadTool::new()
+            .download(&server.url("/video.mp4"), dir.path(), "video", &options(4), None)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        // 確認と本体の取得の2回だけ
+        assert_eq!(server.requests().len(), 2);
+        assert!(fetched_ranges(&server).is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_ranges_shorter_than_requested() {
+        let data = body(2 * MIB);
+        let server = TestServer::start(move |request| {
+            let mut response = Response::ranged(&data, request);
+            // 2つ目の範囲だけ途中で切れる
+            if request.range().is_some_and(|(start, _)| start > 0) {
+                response.body.truncate(response.body.len() / 2);
+            }
+            response
+        })
+        .await;
+        let dir = TempDir::new();
+
+        let result = HttpDownloadTool::new()
+            .download(&server.url("/video.mp4"), dir.path(), "video", &options(2), None)
+            .await;
+
+        assert!(matches!(result, Err(DownloadError::ProcessFailed(_))), "{:?}", result);
+        assert!(!dir.path().join("video.mp4").exists());
+        // 途中で切れた範囲は完了として記録しない
+        if let Ok(json) = std::fs::read(dir.path().join("video.mp4.part.json")) {
+            let state: DownloadState = serde_json::from_slice(&json).unwrap();
+            assert!(!state.completed[1]);
+        }
+    }
+}
@@ -0,0 +1,698 @@
+//! 外部ツールのインストールと更新
+//!
+//! マニフェストで版とSHA-256を固定したツールを、アプリ専用のディレクトリ
+//! （既定はデータディレクトリの `NextDownloader/tools`）にインストールする。
+//! ダウンロードしたファイルはチェックサムを確認してから展開し、
+//! インストールした版は `installed.json` に記録する。
+//!
+//! マニフェストはTOMLで、ツールごとに版とプラットフォームごとの取得元を書く。
+//! 相対的な `url` はマニフェストの場所を基準に解決するため、マニフェストと
+//! ファイルを同じディレクトリに置けばオフラインのミラーとして使える。
+//!
+//! ```toml
+//! [[tools]]
+//! tool = "ffmpeg"
+//! version = "7.0.2"
+//!
+//! [[tools.assets]]
+//! platform = "linux-x86_64"
+//! url = "ffmpeg-7.0.2-amd64-static.tar.xz"
+//! sha256 = "…"
+//! archive = "tar.xz"
+//! ```
+
+use std::io::{Cursor, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::Url;
+use crate::tools::discovery::ExternalTool;
+use crate::types::DownloadError;
+
+/// ミラーに置くマニフェストのファイル名
+pub const MANIFEST_FILE: &str = "manifest.toml";
+
+/// インストールした版を記録するファイル名
+const INSTALLED_FILE: &str = "installed.json";
+
+/// アーカイブの形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ArchiveFormat {
+    /// ZIP
+    #[serde(rename = "zip")]
+    Zip,
+    /// gzipで圧縮したtar
+    #[serde(rename = "tar.gz")]
+    TarGz,
+    /// xzで圧縮したtar
+    #[serde(rename = "tar.xz")]
+    TarXz,
+}
+
+/// プラットフォームごとの取得元
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolAsset {
+    /// プラットフォーム（例: `linux-x86_64`、`macos-aarch64`、`windows-x86_64`）
+    pub platform: String,
+    /// 取得元のURLまたはパス（相対的な場合はマニフェストの場所が基準）
+    pub url: String,
+    /// ファイルのSHA-256（16進数）
+    pub sha256: String,
+    /// アーカイブの形式（省略時は実行ファイルそのもの）
+    #[serde(default)]
+    pub archive: Option<ArchiveFormat>,
+    /// アーカイブ内の実行ファイルのパス（省略時はツール名のファイルを探す）
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+/// ツールの固定された版
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolRelease {
+    /// ツールの種類
+    pub tool: ExternalTool,
+    /// 版
+    pub version: String,
+    /// プラットフォームごとの取得元
+    #[serde(default)]
+    pub assets: Vec<ToolAsset>,
+}
+
+impl ToolRelease {
+    /// プラットフォームの取得元
+    pub fn asset(&self, platform: &str) -> Option<&ToolAsset> {
+        self.assets.iter().find(|asset| asset.platform == platform)
+    }
+}
+
+/// インストールするツールの版と取得元の一覧
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToolManifest {
+    /// ツールごとの版
+    pub tools: Vec<ToolRelease>,
+}
+
+impl ToolManifest {
+    /// TOMLのマニフェストを解析
+    pub fn parse(text: &str) -> Result<Self, DownloadError> {
+        toml::from_str(text).map_err(|err| DownloadError::InvalidManifest(err.to_string()))
+    }
+
+    /// ツールの版
+    pub fn release(&self, tool: ExternalTool) -> Option<&ToolRelease> {
+        self.tools.iter().find(|release| release.tool == tool)
+    }
+}
+
+/// 現在のプラットフォームの名前（例: `linux-x86_64`）
+pub fn current_platform() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/// マニフェストと取得元の設定
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InstallerConfig {
+    /// マニフェストのURLまたはパス（省略時はミラーの `manifest.toml`）
+    pub manifest: Option<String>,
+    /// ファイルの取得元を置き換えるミラーのURLまたはパス（`file://` も可）
+    pub mirror: Option<String>,
+}
+
+/// インストール済みのツール
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstalledTool {
+    /// ツールの種類
+    pub tool: ExternalTool,
+    /// 版
+    pub version: String,
+    /// 実行ファイルのパス
+    pub path: PathBuf,
+    /// 取得したファイルのSHA-256
+    pub sha256: String,
+    /// インストールした時刻（UNIX時間）
+    pub installed_at: u64,
+}
+
+/// インストールの結果
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstallOutcome {
+    /// 新しくインストールした
+    Installed(InstalledTool),
+    /// 別の版から入れ替えた
+    Updated {
+        /// 以前の版
+        previous: String,
+        /// インストールしたツール
+        installed: InstalledTool,
+    },
+    /// 既にマニフェストの版がインストールされている
+    UpToDate(InstalledTool),
+}
+
+/// 外部ツールのインストールを管理する
+pub struct ToolManager {
+    dir: PathBuf,
+    settings: InstallerConfig,
+    client: reqwest::Client,
+}
+
+impl ToolManager {
+    /// 指定したディレクトリにインストールするToolManagerを作成
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            settings: InstallerConfig::default(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// マニフェストと取得元を指定する
+    pub fn with_settings(mut self, settings: InstallerConfig) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// 既定のインストール先（OSのデータディレクトリ配下）
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("NextDownloader").join("tools"))
+    }
+
+    /// インストール先のディレクトリ
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// インストール済みのツールの一覧
+    pub fn installed(&self) -> Result<Vec<InstalledTool>, DownloadError> {
+        read_installed(&self.dir)
+    }
+
+    /// インストール済みのツール
+    pub fn installed_tool(&self, tool: ExternalTool) -> Result<Option<InstalledTool>, DownloadError> {
+        Ok(self.installed()?.into_iter().find(|installed| installed.tool == tool))
+    }
+
+    /// マニフェストを取得する
+    pub async fn manifest(&self) -> Result<ToolManifest, DownloadError> {
+        let location = self.manifest_location()?;
+        let bytes = self.fetch(&location).await?;
+        ToolManifest::parse(&String::from_utf8_lossy(&bytes))
+    }
+
+    /// マニフェストの版のツールをインストールする
+    ///
+    /// 同じ版がインストール済みの場合は `force` を指定しない限り何もしない。
+    /// 別の版がインストールされている場合は入れ替える。
+    pub async fn install(
+        &self,
+        manifest: &ToolManifest,
+        tool: ExternalTool,
+        force: bool
+    ) -> Result<InstallOutcome, DownloadError> {
+        let release = manifest
+            .release(tool)
+            .ok_or_else(|| DownloadError::InvalidManifest(format!("{} の版が記載されていません", tool)))?;
+        let platform = current_platform();
+        let asset = release.asset(&platform).ok_or_else(|| {
+            DownloadError::InvalidManifest(format!("{} の {} 向けのファイルが記載されていません", tool, platform))
+        })?;
+
+        let previous = self.installed_tool(tool)?;
+        if let Some(installed) = &previous {
+            if installed.version == release.version && installed.path.exists() && !force {
+                return Ok(InstallOutcome::UpToDate(installed.clone()));
+            }
+        }
+
+        // 取得してチェックサムを確認
+        let location = self.asset_location(asset)?;
+        log::info!("{} {} を取得しています: {}", tool, release.version, location);
+        let bytes = self.fetch(&location).await?;
+        let sha256 = sha256_hex(&bytes);
+        if !sha256.eq_ignore_ascii_case(asset.sha256.trim()) {
+            return Err(DownloadError::ChecksumMismatch {
+                url: location,
+                expected: asset.sha256.trim().to_lowercase(),
+                actual: sha256,
+            });
+        }
+
+        // 展開してから差し替える
+        let tool_dir = self.dir.join(tool.executable_name());
+        let version_dir = tool_dir.join(&release.version);
+        let staging_dir = tool_dir.join(format!("{}.partial", release.version));
+        let asset_for_extract = asset.clone();
+        let extract_dir = staging_dir.clone();
+        let relative_path = tokio::task::spawn_blocking(move || {
+            extract_asset(&asset_for_extract, tool, &bytes, &extract_dir)
+        })
+        .await
+        .map_err(|err| DownloadError::Internal(err.to_string()))??;
+
+        if version_dir.exists() {
+            std::fs::remove_dir_all(&version_dir)?;
+        }
+        std::fs::rename(&staging_dir, &version_dir)?;
+        remove_other_versions(&tool_dir, &release.version)?;
+
+        let installed = InstalledTool {
+            tool,
+            version: release.version.clone(),
+            path: version_dir.join(relative_path),
+            sha256,
+            installed_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default(),
+        };
+        self.record(Some(installed.clone()), tool)?;
+
+        Ok(match previous {
+            Some(previous) if previous.version != installed.version => InstallOutcome::Updated {
+                previous: previous.version,
+                installed,
+            },
+            Some(_) | None => InstallOutcome::Installed(installed),
+        })
+    }
+
+    /// インストールしたツールを削除する（インストールされていなかった場合は `false`）
+    pub fn remove(&self, tool: ExternalTool) -> Result<bool, DownloadError> {
+        let installed = self.installed_tool(tool)?;
+        let tool_dir = self.dir.join(tool.executable_name());
+        if tool_dir.exists() {
+            std::fs::remove_dir_all(&tool_dir)?;
+        }
+        self.record(None, tool)?;
+        Ok(installed.is_some())
+    }
+
+    /// インストールの記録を更新する（`None` の場合は記録を消す）
+    fn record(&self, installed: Option<InstalledTool>, tool: ExternalTool) -> Result<(), DownloadError> {
+        let mut entries = self.installed()?;
+        entries.retain(|entry| entry.tool != tool);
+        entries.extend(installed);
+
+        std::fs::create_dir_all(&self.dir)?;
+        let json = serde_json::to_string_pretty(&entries)?;
+        let path = self.dir.join(INSTALLED_FILE);
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, json)?;
+        std::fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    /// マニフェストの場所
+    fn manifest_location(&self) -> Result<String, DownloadError> {
+        match (&self.settings.manifest, &self.settings.mirror) {
+            (Some(manifest), _) => Ok(manifest.clone()),
+            (None, Some(mirror)) => Ok(join_location(mirror, MANIFEST_FILE)),
+            (None, None) => Err(DownloadError::Config(
+                "ツールのマニフェストが指定されていません（installer.manifest または installer.mirror を設定してください）"
+                    .to_string(),
+            )),
+        }
+    }
+
+    /// ファイルの取得元
+    ///
+    /// ミラーが指定されている場合は、ミラーの同じファイル名のものを使う。
+    fn asset_location(&self, asset: &ToolAsset) -> Result<String, DownloadError> {
+        if let Some(mirror) = &self.settings.mirror {
+            let name = asset.url.rsplit('/').next().unwrap_or(&asset.url);
+            return Ok(join_location(mirror, name));
+        }
+        if is_remote(&asset.url) || asset.url.starts_with("file://") || Path::new(&asset.url).is_absolute() {
+            return Ok(asset.url.clone());
+        }
+
+        let manifest = self.manifest_location()?;
+        let base = match manifest.rsplit_once('/') {
+            Some((base, _)) => base.to_string(),
+            None => ".".to_string(),
+        };
+        Ok(join_location(&base, &asset.url))
+    }
+
+    /// URL（http、https、file）またはパスからファイルを読み込む
+    async fn fetch(&self, location: &str) -> Result<Vec<u8>, DownloadError> {
+        if is_remote(location) {
+            let response = self.client.get(location).send().await?;
+            let status = response.status();
+            if !status.is_success() {
+                return Err(DownloadError::HttpStatus {
+                    status: status.as_u16(),
+                    url: location.to_string(),
+                });
+            }
+            return Ok(response.bytes().await?.to_vec());
+        }
+
+        let path = match location.strip_prefix("file://") {
+            Some(_) => Url::parse(location)
+                .ok()
+                .and_then(|url| url.to_file_path().ok())
+                .ok_or_else(|| DownloadError::Config(format!("不正なファイルURL: {}", location)))?,
+            None => PathBuf::from(location),
+        };
+        Ok(tokio::fs::read(path).await?)
+    }
+}
+
+/// インストール先のディレクトリの記録を読み込む（存在しない場合は空）
+pub fn read_installed(dir: &Path) -> Result<Vec<InstalledTool>, DownloadError> {
+    match std::fs::read_to_string(dir.join(INSTALLED_FILE)) {
+        Ok(json) => Ok(serde_json::from_str(&json)?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// HTTP(S)のURLか
+fn is_remote(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+/// URLまたはパスの後ろに相対パスをつなげる
+fn join_location(base: &str, relative: &str) -> String {
+    if is_remote(base) || base.starts_with("file://") {
+        format!("{}/{}", base.trim_end_matches('/'), relative)
+    } else {
+        Path::new(base).join(relative).to_string_lossy().to_string()
+    }
+}
+
+/// SHA-256の16進数表記
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// アーカイブ展開時のエラー
+fn extract_error(err: impl std::fmt::Display) -> DownloadError {
+    DownloadError::Internal(format!("アーカイブの展開に失敗しました: {}", err))
+}
+
+/// 取得したファイルを展開し、実行ファイルの（展開先からの）相対パスを返す
+///
+/// 実行権限は実行ファイルにだけ付ける。
+fn extract_asset(asset: &ToolAsset, tool: ExternalTool, bytes: &[u8], dest: &Path) -> Result<PathBuf, DownloadError> {
+    if dest.exists() {
+        std::fs::remove_dir_all(dest)?;
+    }
+    std::fs::create_dir_all(dest)?;
+
+    let executable = format!("{}{}", tool.executable_name(), std::env::consts::EXE_SUFFIX);
+    let relative = match asset.archive {
+        None => {
+            std::fs::write(dest.join(&executable), bytes)?;
+            PathBuf::from(&executable)
+        }
+        Some(format) => {
+            match format {
+                ArchiveFormat::Zip => extract_zip(bytes, dest)?,
+                ArchiveFormat::TarGz => {
+                    tar::Archive::new(flate2::read::GzDecoder::new(Cursor::new(bytes))).unpack(dest)?
+                }
+                ArchiveFormat::TarXz => {
+                    let mut tar = Vec::new();
+                    lzma_rs::xz_decompress(&mut std::io::BufReader::new(Cursor::new(bytes)), &mut tar)
+                        .map_err(extract_error)?;
+                    tar::Archive::new(Cursor::new(tar)).unpack(dest)?
+                }
+            }
+            match &asset.path {
+                // 展開先の外を指すパスは受け付けない
+                Some(path) if !Path::new(path).components().all(|component| matches!(component, Component::Normal(_))) => {
+                    return Err(extract_error(format!("不正なパス: {}", path)));
+                }
+                Some(path) => PathBuf::from(path),
+                None => find_file(dest, &executable)?
+                    .and_then(|path| path.strip_prefix(dest).ok().map(Path::to_path_buf))
+                    .ok_or_else(|| extract_error(format!("{} が含まれていません", executable)))?,
+            }
+        }
+    };
+
+    let path = dest.join(&relative);
+    if !path.is_file() {
+        return Err(extract_error(format!("{} が含まれていません", relative.display())));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    Ok(relative)
+}
+
+/// ZIPを展開する（展開先の外を指すエントリは無視する）
+fn extract_zip(bytes: &[u8], dest: &Path) -> Result<(), DownloadError> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).map_err(extract_error)?;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).map_err(extract_error)?;
+        let Some(relative) = entry.enclosed_name() else {
+            continue;
+        };
+        let path = dest.join(relative);
+        if entry.is_dir() {
+            std::fs::create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        std::fs::write(&path, data)?;
+    }
+    Ok(())
+}
+
+/// ディレクトリ以下から指定した名前のファイルを探す
+fn find_file(dir: &Path, name: &str) -> Result<Option<PathBuf>, DownloadError> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if let Some(found) = find_file(&path, name)? {
+                return Ok(Some(found));
+            }
+        } else if path.file_name().is_some_and(|file_name| file_name == name) {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// 指定した版以外の展開済みのディレクトリを削除する
+fn remove_other_versions(tool_dir: &Path, version: &str) -> Result<(), DownloadError> {
+    for entry in std::fs::read_dir(tool_dir)? {
+        let path = entry?.path();
+        if path.is_dir() && path.file_name().is_some_and(|name| name != version) {
+            std::fs::remove_dir_all(&path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use crate::test_util::TempDir;
+
+    const BINARY: &[u8] = b"#!/bin/sh\necho ffmpeg\n";
+
+    /// ミラーのディレクトリにファイルとマニフェストを置く
+    fn mirror(dir: &Path, version: &str, file: &str, bytes: &[u8], archive: Option<&str>, sha256: Option<&str>) -> PathBuf {
+        let mirror = dir.join("mirror");
+        std::fs::create_dir_all(&mirror).unwrap();
+        std::fs::write(mirror.join(file), bytes).unwrap();
+        let archive = archive.map(|archive| format!("archive = \"{}\"\n", archive)).unwrap_or_default();
+        let manifest = format!(
+            "[[tools]]\ntool = \"ffmpeg\"\nversion = \"{}\"\n\n[[tools.assets]]\nplatform = \"{}\"\nurl = \"https://example.com/{}\"\nsha256 = \"{}\"\n{}",
+            version,
+            current_platform(),
+            file,
+            sha256.map(str::to_string).unwrap_or_else(|| sha256_hex(bytes)),
+            archive
+        );
+        std::fs::write(mirror.join(MANIFEST_FILE), manifest).unwrap();
+        mirror
+    }
+
+    fn manager(dir: &Path, mirror: &Path) -> ToolManager {
+        ToolManager::new(dir.join("tools")).with_settings(InstallerConfig {
+            manifest: None,
+            mirror: Some(mirror.to_string_lossy().to_string()),
+        })
+    }
+
+    async fn install(manager: &ToolManager, force: bool) -> Result<InstallOutcome, DownloadError> {
+        let manifest = manager.manifest().await?;
+        manager.install(&manifest, ExternalTool::Ffmpeg, force).await
+    }
+
+    fn tar_gz(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default()));
+        for (name, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            // `set_path` は `..` を拒否するため名前を直接書き込む
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_cksum();
+            builder.append(&header, *data).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            writer.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[tokio::test]
+    async fn rejects_a_checksum_mismatch_without_installing() {
+        let dir = TempDir::new();
+        let mirror = mirror(dir.path(), "7.0", "ffmpeg", BINARY, None, Some(&"0".repeat(64)));
+        let manager = manager(dir.path(), &mirror);
+
+        let result = install(&manager, false).await;
+
+        assert!(matches!(result, Err(DownloadError::ChecksumMismatch { .. })), "{:?}", result);
+        assert!(manager.installed().unwrap().is_empty());
+        assert!(!manager.dir().join("ffmpeg").exists());
+    }
+
+    #[tokio::test]
+    async fn installs_from_an_offline_mirror_and_tracks_versions() {
+        let dir = TempDir::new();
+        let mirror_dir = mirror(dir.path(), "7.0", "ffmpeg", BINARY, None, None);
+        let manager = manager(dir.path(), &mirror_dir);
+
+        let InstallOutcome::Installed(installed) = install(&manager, false).await.unwrap() else {
+            panic!("インストールされていません");
+        };
+        assert_eq!(installed.version, "7.0");
+        assert_eq!(installed.sha256, sha256_hex(BINARY));
+        assert_eq!(std::fs::read(&installed.path).unwrap(), BINARY);
+        assert_eq!(manager.installed_tool(ExternalTool::Ffmpeg).unwrap(), Some(installed.clone()));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&installed.path).unwrap().permissions().mode() & 0o777, 0o755);
+        }
+
+        // 同じ版は強制しない限り入れ直さない
+        assert!(matches!(install(&manager, false).await.unwrap(), InstallOutcome::UpToDate(_)));
+        assert!(matches!(install(&manager, true).await.unwrap(), InstallOutcome::Installed(_)));
+
+        // 新しい版に入れ替え、古い版は削除する
+        mirror(dir.path(), "7.1", "ffmpeg", BINARY, None, None);
+        let InstallOutcome::Updated { previous, installed: updated } = install(&manager, false).await.unwrap() else {
+            panic!("更新されていません");
+        };
+        assert_eq!(previous, "7.0");
+        assert_eq!(updated.version, "7.1");
+        assert!(updated.path.is_file());
+        assert!(!installed.path.exists());
+        assert_eq!(manager.installed().unwrap(), vec![updated]);
+    }
+
+    #[tokio::test]
+    async fn remove_deletes_the_files_and_the_record() {
+        let dir = TempDir::new();
+        let mirror = mirror(dir.path(), "7.0", "ffmpeg", BINARY, None, None);
+        let manager = manager(dir.path(), &mirror);
+        install(&manager, false).await.unwrap();
+
+        assert!(manager.remove(ExternalTool::Ffmpeg).unwrap());
+        assert!(!manager.dir().join("ffmpeg").exists());
+        assert!(manager.installed().unwrap().is_empty());
+        assert!(!manager.remove(ExternalTool::Ffmpeg).unwrap());
+    }
+
+    #[test]
+    fn extracts_tar_gz_and_skips_entries_outside_the_destination() {
+        let dir = TempDir::new();
+        let dest = dir.path().join("extract").join("dest");
+        let bytes = tar_gz(&[("ffmpeg-7.0/bin/ffmpeg", BINARY), ("../escaped", b"x"), ("ffmpeg-7.0/README", b"readme")]);
+        let asset = ToolAsset {
+            platform: current_platform(),
+            url: "ffmpeg.tar.gz".to_string(),
+            sha256: sha256_hex(&bytes),
+            archive: Some(ArchiveFormat::TarGz),
+            path: None,
+        };
+
+        let relative = extract_asset(&asset, ExternalTool::Ffmpeg, &bytes, &dest).unwrap();
+
+        assert_eq!(relative, Path::new("ffmpeg-7.0/bin/ffmpeg"));
+        assert_eq!(std::fs::read(dest.join(&relative)).unwrap(), BINARY);
+        assert!(!dir.path().join("extract").join("escaped").exists());
+    }
+
+    #[test]
+    fn extracts_zip_and_skips_entries_outside_the_destination() {
+        let dir = TempDir::new();
+        let dest = dir.path().join("extract").join("dest");
+        let bytes = zip(&[("../escaped", b"x"), ("bin/ffmpeg", BINARY)]);
+        let asset = ToolAsset {
+            platform: current_platform(),
+            url: "ffmpeg.zip".to_string(),
+            sha256: sha256_hex(&bytes),
+            archive: Some(ArchiveFormat::Zip),
+            path: Some("bin/ffmpeg".to_string()),
+        };
+
+        let relative = extract_asset(&asset, ExternalTool::Ffmpeg, &bytes, &dest).unwrap();
+
+        assert_eq!(relative, Path::new("bin/ffmpeg"));
+        assert_eq!(std::fs::read(dest.join(&relative)).unwrap(), BINARY);
+        assert!(!dir.path().join("extract").join("escaped").exists());
+    }
+
+    #[test]
+    fn rejects_executable_paths_outside_the_destination() {
+        let dir = TempDir::new();
+        let dest = dir.path().join("dest");
+        let bytes = zip(&[("bin/ffmpeg", BINARY)]);
+        let asset = ToolAsset {
+            platform: current_platform(),
+            url: "ffmpeg.zip".to_string(),
+            sha256: sha256_hex(&bytes),
+            archive: Some(ArchiveFormat::Zip),
+            path: Some("../bin/ffmpeg".to_string()),
+        };
+        // 展開先の外に同じ名前のファイルがあっても使わない
+        std::fs::create_dir_all(dir.path().join("bin")).unwrap();
+        std::fs::write(dir.path().join("bin").join("ffmpeg"), b"other").unwrap();
+
+        assert!(extract_asset(&asset, ExternalTool::Ffmpeg, &bytes, &dest).is_err());
+    }
+
+    #[test]
+    fn missing_executables_are_reported() {
+        let dir = TempDir::new();
+        let bytes = tar_gz(&[("README", b"readme")]);
+        let asset = ToolAsset {
+            platform: current_platform(),
+            url: "ffmpeg.tar.gz".to_string(),
+            sha256: sha256_hex(&bytes),
+            archive: Some(ArchiveFormat::TarGz),
+            path: None,
+        };
+
+        assert!(extract_asset(&asset, ExternalTool::Ffmpeg, &bytes, &dir.path().join("dest")).is_err());
+    }
+}
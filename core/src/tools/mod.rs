@@ -1,10 +1,24 @@
 /// 外部ツールを扱うモジュール
 pub mod ytdlp;
 pub mod aria2c;
+pub mod aria2c_rpc;
 pub mod ffmpeg;
+pub mod fetcher;
+pub mod http;
 pub mod hls;
+pub mod dash;
+pub mod discovery;
+pub mod manager;
+pub(crate) mod diagnostics;
 
 pub use self::ytdlp::YtDlpTool;
 pub use self::aria2c::Aria2cTool;
+pub use self::aria2c_rpc::{Aria2RpcClient, Aria2Status, Aria2cDaemon};
 pub use self::ffmpeg::FFmpegTool;
+pub use self::http::HttpDownloadTool;
 pub use self::hls::HlsDownloadTool;
+pub use self::dash::DashDownloadTool;
+pub use self::discovery::{ExternalTool, ToolLocation, ToolPaths, ToolSource};
+pub use self::manager::{
+    ArchiveFormat, InstallOutcome, InstalledTool, InstallerConfig, ToolAsset, ToolManager, ToolManifest, ToolRelease,
+};
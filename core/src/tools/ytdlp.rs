@@ -1,8 +1,32 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::process::Command;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use regex::Regex;
-use crate::types::{DownloadError, VideoInfo, ProgressInfo, ProgressCallback, DownloadOptions, FormatInfo};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use serde::Deserialize;
+use uuid::Uuid;
+use crate::bandwidth;
+use crate::filename::CollisionPolicy;
+use crate::playlist::{Playlist, PlaylistEntry};
+use crate::tools::discovery::{ExternalTool, ToolPaths};
+use crate::types::{
+    DownloadError, VideoInfo, ProgressInfo, ProgressPhase, ProgressCallback, DownloadOptions, VideoFormat,
+    FormatSelection, codec_family
+};
+use crate::request::RequestContext;
+use crate::tools::aria2c;
+use crate::tools::diagnostics;
+
+/// ダウンロード進捗の出力形式（状態:取得済み:合計:推定合計:速度:残り秒数:映像コーデック）
+const DOWNLOAD_PROGRESS_TEMPLATE: &str = concat!(
+    "download:[nextdownloader] download:%(progress.status)s:%(progress.downloaded_bytes)s:",
+    "%(progress.total_bytes)s:%(progress.total_bytes_estimate)s:%(progress.speed)s:",
+    "%(progress.eta)s:%(info.vcodec)s"
+);
+
+/// 後処理の進捗の出力形式（後処理名:状態）
+const POSTPROCESS_PROGRESS_TEMPLATE: &str =
+    "postprocess:[nextdownloader] postprocess:%(progress.postprocessor)s:%(progress.status)s";
 
 /// YouTube-DLP外部ツールを扱うための構造体
 pub struct YtDlpTool {
@@ -10,21 +34,16 @@ pub struct YtDlpTool {
     executable_path: PathBuf,
 }
 
+impl Default for YtDlpTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl YtDlpTool {
-    /// 新しいYtDlpToolを作成（デフォルトパス使用）
+    /// 新しいYtDlpToolを作成（環境変数、PATH、同梱ツールの順に探す）
     pub fn new() -> Self {
-        #[cfg(target_os = "windows")]
-        let default_path = PathBuf::from(".\\bin\\yt-dlp.exe");
-        
-        #[cfg(target_os = "macos")]
-        let default_path = PathBuf::from("/usr/local/bin/yt-dlp");
-        
-        #[cfg(target_os = "linux")]
-        let default_path = PathBuf::from("/usr/bin/yt-dlp");
-        
-        Self {
-            executable_path: default_path
-        }
+        Self::with_path(ToolPaths::default().path(ExternalTool::YtDlp))
     }
     
     /// 指定したパスでYtDlpToolを作成
@@ -48,18 +67,19 @@ impl YtDlpTool {
         result.is_ok()
     }
     
-    /// 動画情報を取得
-    pub async fn get_video_info(&self, url: &str) -> Result<VideoInfo, DownloadError> {
+    /// ヘッダー・Cookieを指定して動画情報を取得
+    pub async fn get_video_info(&self, url: &str, request: &RequestContext) -> Result<VideoInfo, DownloadError> {
         let output = Command::new(&self.executable_path)
             .arg("-J")
             .arg("--no-warnings")
+            .args(request_args(request))
             .arg(url)
             .output()
             .await?;
             
         if !output.status.success() {
             let error_message = String::from_utf8_lossy(&output.stderr);
-            return Err(DownloadError::ProcessFailed(error_message.to_string()));
+            return Err(classify_error(&error_message, output.status.code(), url));
         }
         
         let json_str = String::from_utf8_lossy(&output.stdout);
@@ -68,156 +88,605 @@ impl YtDlpTool {
         Ok(video_info)
     }
     
-    /// 動画をダウンロード
+    /// ダウンロードオプション（ヘッダー・Cookie、画質の選択）を指定して動画情報を取得
+    ///
+    /// 幅・高さなどはダウンロード時に選ばれるフォーマットのものになる。
+    pub async fn get_download_info(&self, url: &str, options: &DownloadOptions) -> Result<VideoInfo, DownloadError> {
+        let output = Command::new(&self.executable_path)
+            .arg("-J")
+            .arg("--no-warnings")
+            .arg("--no-playlist")
+            .args(request_args(&options.request))
+            .args(selection_args(&options.selection, matches!(options.format, VideoFormat::Mp3)))
+            .arg(url)
+            .output()
+            .await?;
+            
+        if !output.status.success() {
+            let error_message = String::from_utf8_lossy(&output.stderr);
+            return Err(classify_error(&error_message, output.status.code(), url));
+        }
+        
+        Ok(serde_json::from_slice(&output.stdout)?)
+    }
+    
+    /// プレイリスト・チャンネルの項目の一覧を取得
+    ///
+    /// `--flat-playlist` で各項目の情報は取得しないため、項目数が多くてもすぐに終わる。
+    /// プレイリストでない場合（単一の動画）はNoneを返す。
+    pub async fn resolve_entries(&self, url: &str, request: &RequestContext) -> Result<Option<Playlist>, DownloadError> {
+        let output = Command::new(&self.executable_path)
+            .arg("--flat-playlist")
+            .arg("-J")
+            .arg("--no-warnings")
+            .args(request_args(request))
+            .arg(url)
+            .output()
+            .await?;
+            
+        if !output.status.success() {
+            let error_message = String::from_utf8_lossy(&output.stderr);
+            return Err(classify_error(&error_message, output.status.code(), url));
+        }
+        
+        let info: FlatPlaylist = serde_json::from_slice(&output.stdout)?;
+        Ok(info.into_playlist())
+    }
+    
+    /// 動画をダウンロード（外部ダウンローダーとしてaria2cを使う）
     pub async fn download(
         &self,
         url: &str,
-        output_path: &PathBuf,
+        output_path: &Path,
+        filename: &str,
+        options: &DownloadOptions,
+        progress_callback: Option<ProgressCallback>
+    ) -> Result<PathBuf, DownloadError> {
+        self.run_download(url, output_path, filename, options, progress_callback, true).await
+    }
+    
+    /// 動画をyt-dlp内蔵のダウンローダーでダウンロード
+    ///
+    /// aria2cが使えない場合や、aria2cでの取得に失敗した場合の代替として使う。
+    pub async fn download_native(
+        &self,
+        url: &str,
+        output_path: &Path,
         filename: &str,
         options: &DownloadOptions,
         progress_callback: Option<ProgressCallback>
+    ) -> Result<PathBuf, DownloadError> {
+        self.run_download(url, output_path, filename, options, progress_callback, false).await
+    }
+    
+    async fn run_download(
+        &self,
+        url: &str,
+        output_path: &Path,
+        filename: &str,
+        options: &DownloadOptions,
+        progress_callback: Option<ProgressCallback>,
+        use_aria2c: bool
     ) -> Result<PathBuf, DownloadError> {
         // 引数構築
         let mut args = vec![
             "--no-warnings".to_string(),
-            "--downloader".to_string(), 
-            "aria2c".to_string(),
+            // 進捗は機械可読な形式で1行ずつ出力させる
+            "--newline".to_string(),
+            "--progress-template".to_string(),
+            DOWNLOAD_PROGRESS_TEMPLATE.to_string(),
+            "--progress-template".to_string(),
+            POSTPROCESS_PROGRESS_TEMPLATE.to_string(),
+            // 一時停止後の再開時は.partファイルの続きから取得する
+            "--continue".to_string(),
+            // プレイリストは項目ごとのタスクに展開するため、再生リストを含む動画のURLでも動画のみを取得する
+            "--no-playlist".to_string(),
         ];
         
-        // aria2c引数
-        let mut aria2c_args = format!(
-            "-x{} -s{} -k{}M --retry-wait={} --max-tries={}",
-            options.connections,
-            options.splits,
-            options.chunk_size,
-            options.retry_wait,
-            options.max_retries
-        );
-        
-        if options.use_http2 {
-            aria2c_args.push_str(" --enable-http-pipelining=true --http2=true");
+        if !use_aria2c {
+            args.push("--retries".to_string());
+            args.push(options.max_retries.to_string());
+            args.push("--fragment-retries".to_string());
+            args.push(options.max_retries.to_string());
         }
         
-        if options.use_quic {
-            aria2c_args.push_str(" --enable-quic=true");
-        }
-        
-        if options.use_keep_alive {
-            aria2c_args.push_str(" --enable-http-keep-alive=true");
-        }
-        
-        args.push("--downloader-args".to_string());
-        args.push(format!("aria2c:{}", aria2c_args));
+        // 出力ファイル（`%` はyt-dlpのテンプレートとして解釈されないようにする）
+        let extension = options.format.to_string().to_lowercase();
+        let output_file = output_path.join(format!("{}.{}", filename, extension));
+        args.push("-o".to_string());
+        args.push(format!("{}.%(ext)s", output_path.join(filename).to_string_lossy().replace('%', "%%")));
         
-        // 出力フォーマット
-        let output_template = format!("{}/{}",
-            output_path.to_string_lossy(), 
-            filename
-        );
+        // 最終的なファイルのパスを書き出させる
+        let filepath_file = std::env::temp_dir().join(format!("nextdownloader-{}.path", Uuid::new_v4()));
+        args.push("--print-to-file".to_string());
+        args.push("after_move:filepath".to_string());
+        args.push(filepath_file.to_string_lossy().replace('%', "%%"));
         
-        args.push("-o".to_string());
-        args.push(format!("{}.%(ext)s", output_template));
+        // 同じ名前のファイルの扱いはダウンロードマネージャーが決める
+        if options.collision == CollisionPolicy::Overwrite {
+            args.push("--force-overwrites".to_string());
+        }
         
-        // フォーマット
+        // フォーマット（拡張子が出力フォーマットと必ず一致するよう、結合しない場合も変換する）
         match options.format {
-            crate::types::VideoFormat::Mp4 => {
+            VideoFormat::Mp4 | VideoFormat::Mkv => {
                 args.push("--merge-output-format".to_string());
-                args.push("mp4".to_string());
+                args.push(extension.clone());
+                args.push("--remux-video".to_string());
+                args.push(extension.clone());
             }
-            crate::types::VideoFormat::Mkv => {
-                args.push("--merge-output-format".to_string());
-                args.push("mkv".to_string());
-            }
-            crate::types::VideoFormat::Mp3 => {
+            VideoFormat::Mp3 => {
                 args.push("--extract-audio".to_string());
                 args.push("--audio-format".to_string());
                 args.push("mp3".to_string());
             }
         }
         
-        // URL追加
-        args.push(url.to_string());
+        // ヘッダー・Cookie
+        args.extend(request_args(&options.request));
+        
+        // 画質・トラックの選択
+        args.extend(selection_args(&options.selection, matches!(options.format, VideoFormat::Mp3)));
         
-        // プロセス起動
-        let mut child = Command::new(&self.executable_path)
-            .args(&args)
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped())
-            .spawn()?;
+        let progress_callback: Option<Arc<dyn Fn(ProgressInfo) + Send + Sync>> = progress_callback.map(Arc::from);
+        let (status, stderr_reader) = loop {
+            // 速度制限は起動ごとに指定する（外部ダウンローダーのaria2cにも渡す）
+            let limit = options.throttle.limit();
+            let mut run_args = args.clone();
+            if use_aria2c {
+                run_args.extend(aria2c_downloader_args(options, limit));
+            }
+            if let Some(limit) = limit {
+                run_args.push("--limit-rate".to_string());
+                run_args.push(limit.to_string());
+            }
             
-        // 進捗処理
-        if let Some(callback) = progress_callback {
-            let stdout = child.stdout.take().expect("Failed to get stdout");
-            let reader = BufReader::new(stdout);
-            let mut lines = reader.lines();
+            // URL追加
+            run_args.push(url.to_string());
             
-            // 正規表現コンパイル
-            let progress_re = Regex::new(r"(\d+\.\d+)%").unwrap();
-            let speed_re = Regex::new(r"at\s+(\S+/s)").unwrap();
-            let eta_re = Regex::new(r"ETA\s+(\S+)").unwrap();
+            // プロセス起動（進捗を通知しない場合は標準出力を読まないため捨てる）
+            let stdout = if progress_callback.is_some() {
+                std::process::Stdio::piped()
+            } else {
+                std::process::Stdio::null()
+            };
+            let mut child = Command::new(&self.executable_path)
+                .args(&run_args)
+                .stdout(stdout)
+                .stderr(std::process::Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()?;
             
-            tokio::spawn(async move {
-                while let Ok(Some(line)) = lines.next_line().await {
-                    let mut progress_info = ProgressInfo {
-                        progress: 0.0,
-                        speed: String::new(),
-                        eta: String::new(),
-                    };
-                    
-                    // 進捗抽出
-                    if let Some(caps) = progress_re.captures(&line) {
-                        if let Some(progress_str) = caps.get(1) {
-                            if let Ok(progress) = progress_str.as_str().parse::<f64>() {
-                                progress_info.progress = progress / 100.0;
-                            }
-                        }
-                    }
-                    
-                    // 速度抽出
-                    if let Some(caps) = speed_re.captures(&line) {
-                        if let Some(speed) = caps.get(1) {
-                            progress_info.speed = speed.as_str().to_string();
-                        }
-                    }
+            // エラー出力は終了を待つ間も読み続ける（パイプが詰まるとyt-dlpが書き込みで止まるため）
+            let mut stderr = child.stderr.take().expect("Failed to get stderr");
+            let stderr_reader = tokio::spawn(async move {
+                let mut bytes = Vec::new();
+                let _ = stderr.read_to_end(&mut bytes).await;
+                String::from_utf8_lossy(&bytes).to_string()
+            });
+                
+            // 進捗処理
+            if let Some(callback) = progress_callback.clone() {
+                let stdout = child.stdout.take().expect("Failed to get stdout");
+                let reader = BufReader::new(stdout);
+                let mut lines = reader.lines();
+                
+                tokio::spawn(async move {
+                    callback(ProgressInfo::new(ProgressPhase::Probing));
                     
-                    // ETA抽出
-                    if let Some(caps) = eta_re.captures(&line) {
-                        if let Some(eta) = caps.get(1) {
-                            progress_info.eta = eta.as_str().to_string();
+                    // aria2cの進捗表示には段階が含まれないため、直前の段階を引き継ぐ
+                    let mut phase = ProgressPhase::DownloadingVideo;
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let progress_info = parse_progress_line(&line)
+                            .or_else(|| aria2c::parse_readout(&line).map(|info| ProgressInfo { phase, ..info }));
+                        if let Some(progress_info) = progress_info {
+                            phase = progress_info.phase;
+                            callback(progress_info);
                         }
                     }
-                    
-                    // コールバック実行
-                    if progress_info.progress > 0.0 || !progress_info.speed.is_empty() {
-                        callback(progress_info);
-                    }
+                });
+            }
+            
+            // 実行終了を待機（速度制限が変わった場合は起動し直し、.partファイルの続きから取得する）
+            tokio::select! {
+                status = child.wait() => break (status?, stderr_reader),
+                _ = options.throttle.changed() => {
+                    log::info!(
+                        "速度制限が {} に変わったため、yt-dlpを起動し直します",
+                        bandwidth::describe_limit(options.throttle.limit())
+                    );
+                    child.kill().await?;
                 }
-            });
-        }
+            }
+        };
         
-        // 実行終了を待機
-        let status = child.wait().await?;
+        let printed = tokio::fs::read_to_string(&filepath_file).await.ok();
+        let _ = tokio::fs::remove_file(&filepath_file).await;
         
         if !status.success() {
-            let mut stderr = child.stderr.take().expect("Failed to get stderr");
-            let mut error_message = String::new();
-            use tokio::io::AsyncReadExt;
-            stderr.read_to_string(&mut error_message).await?;
-            
-            return Err(DownloadError::ProcessFailed(error_message));
+            let error_message = stderr_reader.await.unwrap_or_default();
+            return Err(classify_error(&error_message, status.code(), url));
         }
         
-        // 出力ファイルを探す
-        let mut dir_entries = tokio::fs::read_dir(output_path).await?;
-        while let Ok(Some(entry)) = dir_entries.next_entry().await {
-            let file_name = entry.file_name();
-            let file_name_str = file_name.to_string_lossy();
-            
-            if file_name_str.starts_with(filename) {
-                return Ok(entry.path());
+        // yt-dlpが書き出したパス（書き出されなかった場合は指定したパス）
+        let output_file = printed
+            .as_deref()
+            .and_then(|text| text.lines().rfind(|line| !line.trim().is_empty()))
+            .map(|line| PathBuf::from(line.trim()))
+            .unwrap_or(output_file);
+        if !output_file.exists() {
+            return Err(DownloadError::FileNotFound);
+        }
+        Ok(output_file)
+    }
+}
+
+/// `--flat-playlist -J` の出力
+#[derive(Deserialize)]
+struct FlatPlaylist {
+    #[serde(rename = "_type")]
+    kind: Option<String>,
+    id: Option<String>,
+    title: Option<String>,
+    entries: Option<Vec<Option<FlatEntry>>>,
+}
+
+/// `--flat-playlist -J` の出力の項目
+#[derive(Deserialize)]
+struct FlatEntry {
+    id: Option<String>,
+    ie_key: Option<String>,
+    title: Option<String>,
+    url: Option<String>,
+    webpage_url: Option<String>,
+    upload_date: Option<String>,
+    timestamp: Option<f64>,
+    duration: Option<f64>,
+}
+
+impl FlatPlaylist {
+    /// プレイリストの場合は項目の一覧に変換する
+    ///
+    /// 番号はプレイリスト内の位置（`--playlist-items` と同じ1始まり）。
+    /// 削除済みなどで情報のない項目も番号は数える。
+    fn into_playlist(self) -> Option<Playlist> {
+        if self.kind.as_deref() != Some("playlist") {
+            return None;
+        }
+        let entries = self
+            .entries
+            .unwrap_or_default()
+            .into_iter()
+            .enumerate()
+            .filter_map(|(position, entry)| {
+                let entry = entry?;
+                Some(PlaylistEntry {
+                    index: position + 1,
+                    url: entry.url.or(entry.webpage_url)?,
+                    id: entry.id,
+                    extractor: entry.ie_key,
+                    title: entry.title,
+                    upload_date: entry.upload_date,
+                    timestamp: entry.timestamp.map(|timestamp| timestamp as i64),
+                    duration: entry.duration,
+                })
+            })
+            .collect();
+        Some(Playlist {
+            id: self.id,
+            title: self.title,
+            entries,
+        })
+    }
+}
+
+/// yt-dlpの終了コードとエラー出力からエラーの種類を判定
+fn classify_error(stderr: &str, exit_code: Option<i32>, url: &str) -> DownloadError {
+    let message = diagnostics::summary(stderr);
+
+    // シグナルで終了した
+    if exit_code.is_none() {
+        return diagnostics::terminated("yt-dlp", stderr);
+    }
+    // 新しいオプションを知らない古いバージョン
+    if diagnostics::contains_any(stderr, &["no such option", "unrecognized arguments"]) {
+        return DownloadError::ToolTooOld { tool: "yt-dlp".to_string(), message };
+    }
+    if diagnostics::contains_any(stderr, &["drm protected", "drm-protected"]) {
+        return DownloadError::DrmProtected(message);
+    }
+    if diagnostics::contains_any(stderr, &[
+        "available in your country",
+        "not available in your location",
+        "geo restrict",
+        "geo-restrict",
+    ]) {
+        return DownloadError::GeoRestricted(message);
+    }
+    if diagnostics::contains_any(stderr, &[
+        "sign in to confirm",
+        "login required",
+        "requires authentication",
+        "this video is private",
+        "private video",
+        "members-only",
+        "use --cookies",
+        "--username",
+    ]) {
+        return DownloadError::LoginRequired(message);
+    }
+    if diagnostics::contains_any(stderr, &["unsupported url"]) {
+        return DownloadError::UnsupportedSite(message);
+    }
+    // 更新を促す文言はほとんどのエラーに付くため、バージョンが古い根拠にはしない
+    diagnostics::classify_common(stderr, url).unwrap_or_else(|| DownloadError::ProcessFailed(stderr.to_string()))
+}
+
+/// 外部ダウンローダーとしてaria2cを使う引数
+fn aria2c_downloader_args(options: &DownloadOptions, speed_limit: Option<u64>) -> Vec<String> {
+    let mut aria2c_args = format!(
+        "-x{} -s{} -k{}M --retry-wait={} --max-tries={} --human-readable=false",
+        options.connections,
+        options.splits,
+        options.chunk_size,
+        options.retry_wait,
+        options.max_retries
+    );
+    
+    if options.use_http2 {
+        aria2c_args.push_str(" --enable-http-pipelining=true --http2=true");
+    }
+    
+    if options.use_quic {
+        aria2c_args.push_str(" --enable-quic=true");
+    }
+    
+    if options.use_keep_alive {
+        aria2c_args.push_str(" --enable-http-keep-alive=true");
+    }
+    
+    if let Some(limit) = speed_limit {
+        aria2c_args.push_str(&format!(" --max-download-limit={}", limit));
+    }
+    
+    vec![
+        "--downloader".to_string(),
+        "aria2c".to_string(),
+        "--downloader-args".to_string(),
+        format!("aria2c:{}", aria2c_args),
+    ]
+}
+
+/// リクエスト情報をyt-dlpの引数に変換
+///
+/// 外部ダウンローダー（aria2c）にはyt-dlpが同じヘッダーとCookieを引き継ぐ。
+fn request_args(request: &RequestContext) -> Vec<String> {
+    let mut args = Vec::new();
+    for (name, value) in request.extra_headers() {
+        args.push("--add-header".to_string());
+        args.push(format!("{}:{}", name, value));
+    }
+    if let Some(cookie_file) = &request.cookie_file {
+        args.push("--cookies".to_string());
+        args.push(cookie_file.to_string_lossy().to_string());
+    }
+    if let Some(user_agent) = &request.user_agent {
+        args.push("--user-agent".to_string());
+        args.push(user_agent.clone());
+    }
+    if let Some(referer) = &request.referer {
+        args.push("--referer".to_string());
+        args.push(referer.clone());
+    }
+    args
+}
+
+/// 選択条件をyt-dlpの `-f` / `-S` 引数に変換
+fn selection_args(selection: &FormatSelection, audio_only: bool) -> Vec<String> {
+    if selection.is_default() {
+        return Vec::new();
+    }
+
+    let spec = if let Some(format_id) = &selection.format_id {
+        if audio_only {
+            format_id.clone()
+        } else {
+            // 映像のみのフォーマットが指定された場合は最良の音声と組み合わせる
+            format!("{}+ba/{}", format_id, format_id)
+        }
+    } else {
+        let height = selection
+            .max_height
+            .map(|height| format!("[height<={}]", height))
+            .unwrap_or_default();
+        let language = selection
+            .audio_language
+            .as_ref()
+            .map(|language| format!("[language^={}]", language))
+            .unwrap_or_default();
+
+        if audio_only {
+            format!("ba{}/ba/b", language)
+        } else {
+            format!("bv*{h}+ba{l}/bv*{h}+ba/b{h}/b", h = height, l = language)
+        }
+    };
+
+    let mut args = vec!["-f".to_string(), spec];
+
+    // yt-dlpの並び替えは最も優先するコーデックのみ指定できる
+    let codec = selection
+        .preferred_codecs
+        .first()
+        .and_then(|codec| codec_family(codec))
+        .map(|family| match family {
+            "av1" => "av01",
+            other => other,
+        });
+    if let Some(codec) = codec {
+        args.push("-S".to_string());
+        args.push(format!("res,vcodec:{}", codec));
+    }
+
+    args
+}
+
+/// `--progress-template` で出力させた進捗行を解析
+///
+/// 値がない項目はyt-dlpが `NA` と出力するため `None` として扱う。
+fn parse_progress_line(line: &str) -> Option<ProgressInfo> {
+    let line = line.trim().strip_prefix("[nextdownloader] ")?;
+
+    if let Some(rest) = line.strip_prefix("postprocess:") {
+        let (postprocessor, _status) = rest.split_once(':')?;
+        let phase = match postprocessor {
+            "Merger" => ProgressPhase::Merging,
+            _ => ProgressPhase::PostProcessing,
+        };
+        return Some(ProgressInfo::new(phase));
+    }
+
+    let fields: Vec<&str> = line.strip_prefix("download:")?.splitn(7, ':').collect();
+    let [status, downloaded, total, total_estimate, speed, eta, vcodec] = fields.as_slice() else {
+        return None;
+    };
+    let number = |value: &str| value.parse::<f64>().ok().filter(|number| number.is_finite() && *number >= 0.0);
+
+    let downloaded_bytes = number(downloaded).unwrap_or(0.0) as u64;
+    let total_bytes = number(total).or_else(|| number(total_estimate)).map(|total| total as u64);
+    let progress = match total_bytes {
+        _ if *status == "finished" => 1.0,
+        Some(total) if total > 0 => (downloaded_bytes as f64 / total as f64).min(1.0),
+        _ => 0.0,
+    };
+    // 音声のみのフォーマットは映像コーデックが "none" になる
+    let phase = if *vcodec == "none" {
+        ProgressPhase::DownloadingAudio
+    } else {
+        ProgressPhase::DownloadingVideo
+    };
+
+    Some(ProgressInfo {
+        phase,
+        progress,
+        downloaded_bytes,
+        total_bytes,
+        speed: number(speed),
+        eta: number(eta).map(Duration::from_secs_f64),
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use crate::test_util::TempDir;
+
+    /// 標準出力とエラー出力にパイプの容量（64KiB）を超える量を書いてから失敗する偽のyt-dlp
+    fn noisy_ytdlp(dir: &Path) -> YtDlpTool {
+        let path = dir.join("yt-dlp");
+        std::fs::write(
+            &path,
+            "#!/bin/sh\n\
+             i=0\n\
+             while [ $i -lt 2000 ]; do\n\
+               echo \"[download] fragment $i of 2000 ........................................\"\n\
+               echo \"WARNING: [generic] retrying fragment $i ...............................\" >&2\n\
+               i=$((i + 1))\n\
+             done\n\
+             echo 'ERROR: [youtube] abc: Private video. Sign in if you have been granted access' >&2\n\
+             exit 1\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        YtDlpTool::with_path(path)
+    }
+
+    async fn download(tool: &YtDlpTool, dir: &Path, callback: Option<ProgressCallback>) -> Result<PathBuf, DownloadError> {
+        let options = DownloadOptions::default();
+        let output_path = dir.to_path_buf();
+        let download = tool.download("https://example.com/watch?v=abc", &output_path, "video", &options, callback);
+        tokio::time::timeout(Duration::from_secs(10), download)
+            .await
+            .expect("yt-dlpの出力が読まれずに止まっている")
+    }
+
+    #[tokio::test]
+    async fn large_output_without_progress_callback_does_not_block() {
+        let dir = TempDir::new();
+        let result = download(&noisy_ytdlp(dir.path()), dir.path(), None).await;
+
+        assert!(matches!(result, Err(DownloadError::LoginRequired(_))), "{:?}", result);
+    }
+
+    #[tokio::test]
+    async fn large_output_with_progress_callback_does_not_block() {
+        let dir = TempDir::new();
+        let callback: ProgressCallback = Box::new(|_| {});
+        let result = download(&noisy_ytdlp(dir.path()), dir.path(), Some(callback)).await;
+
+        match result {
+            Err(DownloadError::LoginRequired(message)) => assert!(message.contains("Private video"), "{}", message),
+            other => panic!("LoginRequiredになるはず: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn update_hints_alone_do_not_mean_the_tool_is_too_old() {
+        // 抽出に失敗するとほぼ必ず更新を促す文言が付く
+        let stderr = "ERROR: [niconico] sm9: Unable to extract data; please report this issue on  \
+                      https://github.com/yt-dlp/yt-dlp/issues?q= , filling out the appropriate issue template. \
+                      Confirm you are on the latest version using  yt-dlp -U";
+        let err = classify_error(stderr, Some(1), "https://example.com/watch");
+
+        assert!(matches!(err, DownloadError::ProcessFailed(_)), "{:?}", err);
+        assert!(!err.is_fatal());
+    }
+
+    #[test]
+    fn unknown_options_mean_the_tool_is_too_old() {
+        let stderr = "Usage: yt-dlp [OPTIONS] URL [URL...]\n\nyt-dlp: error: no such option: --print-to-file\n";
+        let err = classify_error(stderr, Some(2), "https://example.com/watch");
+
+        match err {
+            DownloadError::ToolTooOld { tool, message } => {
+                assert_eq!(tool, "yt-dlp");
+                assert!(message.contains("--print-to-file"), "{}", message);
             }
+            other => panic!("ToolTooOldになるはず: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn signal_exits_are_not_fatal() {
+        let err = classify_error("[download]  12.5% of 10.00MiB\n", None, "https://example.com/watch");
+
+        assert!(matches!(err, DownloadError::ProcessFailed(_)), "{:?}", err);
+        assert!(!err.is_fatal());
+    }
+
+    #[test]
+    fn classifies_site_errors() {
+        let url = "https://example.com/watch";
+        let classify = |stderr: &str| classify_error(stderr, Some(1), url);
+
+        assert!(matches!(
+            classify("ERROR: [youtube] abc: Sign in to confirm your age. Use --cookies-from-browser or --cookies"),
+            DownloadError::LoginRequired(_)
+        ));
+        assert!(matches!(
+            classify("ERROR: [youtube] abc: The uploader has not made this video available in your country"),
+            DownloadError::GeoRestricted(_)
+        ));
+        assert!(matches!(classify("ERROR: [generic] abc: This video is DRM protected"), DownloadError::DrmProtected(_)));
+        assert!(matches!(classify("ERROR: Unsupported URL: https://example.com/watch"), DownloadError::UnsupportedSite(_)));
+        match classify("ERROR: unable to download video data: HTTP Error 403: Forbidden") {
+            DownloadError::HttpStatus { status, url: failed } => {
+                assert_eq!(status, 403);
+                assert_eq!(failed, url);
+            }
+            other => panic!("HttpStatusになるはず: {:?}", other),
         }
-        
-        Err(DownloadError::FileNotFound)
     }
 }
@@ -1,11 +1,18 @@
-use std::path::PathBuf;
 use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
 use thiserror::Error;
+use crate::request::RequestContext;
+use crate::bandwidth::Throttle;
+use crate::filename::CollisionPolicy;
+use crate::metadata::MetadataOptions;
+use crate::subtitles::SubtitleOptions;
 
 /// ダウンロードするコンテンツのタイプ
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum ContentType {
-    /// 通常のMP4ファイル
+    /// 直接ダウンロードできる動画ファイル（MP4・MPEG-TSなど）
     Mp4,
     /// HLSストリーミング (m3u8)
     Hls,
@@ -18,13 +25,16 @@ pub enum ContentType {
 }
 
 /// 動画フォーマット
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum VideoFormat {
     /// MP4フォーマット
+    #[serde(alias = "mp4")]
     Mp4,
     /// MKVフォーマット
+    #[serde(alias = "mkv")]
     Mkv,
     /// MP3フォーマット (音声のみ)
+    #[serde(alias = "mp3")]
     Mp3,
 }
 
@@ -49,6 +59,36 @@ pub struct DownloadOptions {
     pub use_keep_alive: bool,
     /// 出力フォーマット
     pub format: VideoFormat,
+    /// 画質・トラックの選択条件
+    #[serde(default)]
+    pub selection: FormatSelection,
+    /// ヘッダー・Cookieなどのリクエスト情報
+    #[serde(default)]
+    pub request: RequestContext,
+    /// 優先して使うバックエンドの名前（扱えない場合は通常の順に試す）
+    #[serde(default)]
+    pub backend: Option<String>,
+    /// このダウンロードの速度制限（バイト/秒、Noneで無制限）
+    #[serde(default)]
+    pub speed_limit: Option<u64>,
+    /// ダウンロード済みの記録（[`DownloadArchive`](crate::archive::DownloadArchive)）のファイル
+    #[serde(default)]
+    pub archive: Option<PathBuf>,
+    /// アーカイブに記録済みでもダウンロードし直す
+    #[serde(default)]
+    pub ignore_archive: bool,
+    /// 出力先に同じ名前のファイルがある場合の扱い
+    #[serde(default)]
+    pub collision: CollisionPolicy,
+    /// 字幕の言語・形式・埋め込み
+    #[serde(default)]
+    pub subtitles: SubtitleOptions,
+    /// メタデータ・カバーアートの書き込み
+    #[serde(default)]
+    pub metadata: MetadataOptions,
+    /// 実行中に変更できる速度制限（ダウンロードマネージャーが設定する）
+    #[serde(skip)]
+    pub throttle: Throttle,
 }
 
 impl Default for DownloadOptions {
@@ -63,10 +103,190 @@ impl Default for DownloadOptions {
             use_quic: false,
             use_keep_alive: true,
             format: VideoFormat::Mp4,
+            selection: FormatSelection::default(),
+            request: RequestContext::default(),
+            backend: None,
+            speed_limit: None,
+            archive: None,
+            ignore_archive: false,
+            collision: CollisionPolicy::default(),
+            subtitles: SubtitleOptions::default(),
+            metadata: MetadataOptions::default(),
+            throttle: Throttle::default(),
+        }
+    }
+}
+
+impl DownloadOptions {
+    /// コンテンツタイプに適したオプション
+    pub fn for_content_type(content_type: &ContentType) -> Self {
+        match content_type {
+            ContentType::Hls => Self {
+                chunk_size: 1,
+                retry_wait: 1,
+                max_retries: 10,
+                ..Default::default()
+            },
+            ContentType::Dash => Self {
+                connections: 8,
+                splits: 8,
+                chunk_size: 1,
+                use_quic: true,
+                ..Default::default()
+            },
+            _ => Self::default(),
+        }
+    }
+}
+
+/// 画質・トラックの選択条件
+///
+/// yt-dlp、HLS、DASHのいずれの経路でも同じ条件で選択する。
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FormatSelection {
+    /// 明示的に指定するフォーマットID
+    pub format_id: Option<String>,
+    /// 映像の高さの上限（例: 1080）
+    pub max_height: Option<u32>,
+    /// 優先する映像コーデック（優先度順。av1, h264, h265, vp9）
+    pub preferred_codecs: Vec<String>,
+    /// 音声の言語（例: ja）
+    pub audio_language: Option<String>,
+}
+
+impl FormatSelection {
+    /// 条件が指定されているか
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// 条件に最も合う映像フォーマットを選ぶ
+    ///
+    /// 高さの上限以下で最も高い解像度を選び、同じ解像度の中では
+    /// 優先コーデック、ビットレートの順に比べる。上限以下のものがない
+    /// 場合は最も低い解像度を選ぶ。
+    pub fn select_video<'a>(&self, formats: &'a [FormatInfo]) -> Option<&'a FormatInfo> {
+        let candidates: Vec<&FormatInfo> = formats.iter().filter(|format| format.has_video()).collect();
+
+        if let Some(format_id) = &self.format_id {
+            return candidates.into_iter().find(|format| format.format_id.as_ref() == Some(format_id));
+        }
+
+        let within: Vec<&FormatInfo> = candidates
+            .iter()
+            .copied()
+            .filter(|format| match (self.max_height, format.height) {
+                (Some(max_height), Some(height)) => height <= max_height,
+                _ => true,
+            })
+            .collect();
+
+        if within.is_empty() {
+            return candidates.into_iter().min_by_key(|format| format.height.unwrap_or(0));
+        }
+
+        within.into_iter().max_by_key(|format| {
+            (format.height.unwrap_or(0), self.codec_score(format), bitrate_key(format))
+        })
+    }
+
+    /// 条件に最も合う音声のみのフォーマットを選ぶ
+    ///
+    /// 言語が一致するものを優先し、その中で最もビットレートが高いものを選ぶ。
+    pub fn select_audio<'a>(&self, formats: &'a [FormatInfo]) -> Option<&'a FormatInfo> {
+        let candidates: Vec<&FormatInfo> = formats
+            .iter()
+            .filter(|format| format.has_audio() && !format.has_video())
+            .collect();
+
+        if let Some(format_id) = &self.format_id {
+            if let Some(format) = candidates.iter().find(|format| format.format_id.as_ref() == Some(format_id)) {
+                return Some(format);
+            }
+        }
+
+        let matching: Vec<&FormatInfo> = match &self.audio_language {
+            Some(language) => candidates
+                .iter()
+                .copied()
+                .filter(|format| format.language.as_deref().is_some_and(|lang| language_matches(lang, language)))
+                .collect(),
+            None => Vec::new(),
+        };
+        let candidates = if matching.is_empty() { candidates } else { matching };
+
+        candidates.into_iter().max_by_key(|format| bitrate_key(format))
+    }
+
+    /// 優先コーデックの順位（先頭ほど大きい、該当なしは0）
+    fn codec_score(&self, format: &FormatInfo) -> usize {
+        let Some(family) = format.vcodec.as_deref().and_then(codec_family) else {
+            return 0;
+        };
+        self.preferred_codecs
+            .iter()
+            .position(|codec| codec_family(codec) == Some(family))
+            .map(|index| self.preferred_codecs.len() - index)
+            .unwrap_or(0)
+    }
+}
+
+impl std::str::FromStr for FormatSelection {
+    type Err = String;
+
+    /// `1080p,av1,h264,lang=ja` や `id=137` のようなカンマ区切りの指定を解析
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut selection = Self::default();
+
+        for token in value.split(',').map(str::trim).filter(|token| !token.is_empty()) {
+            let lower = token.to_lowercase();
+            if lower == "best" {
+                continue;
+            } else if let Some(format_id) = token.strip_prefix("id=") {
+                selection.format_id = Some(format_id.to_string());
+            } else if let Some(language) = lower.strip_prefix("lang=") {
+                selection.audio_language = Some(language.to_string());
+            } else if let Some(height) = lower.trim_start_matches("<=").strip_suffix('p') {
+                let height = height.parse().map_err(|_| format!("不正な解像度: {}", token))?;
+                selection.max_height = Some(height);
+            } else if let Some(family) = codec_family(&lower) {
+                selection.preferred_codecs.push(family.to_string());
+            } else {
+                return Err(format!("不明な画質指定: {}", token));
+            }
         }
+
+        Ok(selection)
     }
 }
 
+/// コーデック文字列（`avc1.64001f` など）を系統名に正規化
+pub fn codec_family(codec: &str) -> Option<&'static str> {
+    let codec = codec.trim().to_lowercase();
+    if codec.starts_with("av01") || codec == "av1" {
+        Some("av1")
+    } else if codec.starts_with("avc") || codec == "h264" || codec == "h.264" {
+        Some("h264")
+    } else if codec.starts_with("hvc1") || codec.starts_with("hev1") || matches!(codec.as_str(), "h265" | "h.265" | "hevc") {
+        Some("h265")
+    } else if codec.starts_with("vp09") || codec.starts_with("vp9") {
+        Some("vp9")
+    } else {
+        None
+    }
+}
+
+fn bitrate_key(format: &FormatInfo) -> u64 {
+    format.tbr.map(|tbr| (tbr * 1000.0) as u64).unwrap_or(0)
+}
+
+/// 言語コードの一致（`ja` と `ja-JP` のような地域指定の違いは無視する）
+fn language_matches(language: &str, wanted: &str) -> bool {
+    let primary = |code: &str| code.split(['-', '_']).next().unwrap_or_default().to_lowercase();
+    primary(language) == primary(wanted)
+}
+
 /// ダウンロード関連のエラー
 #[derive(Debug, Error)]
 pub enum DownloadError {
@@ -78,28 +298,234 @@ pub enum DownloadError {
     #[error("コンテンツタイプが不明です")]
     UnknownContentType,
     
-    /// プロセス実行失敗
+    /// プロセス実行失敗（他のどの種類にも分類できない場合）
     #[error("プロセスの実行に失敗: {0}")]
     ProcessFailed(String),
     
     /// I/Oエラー
     #[error("I/Oエラー: {0}")]
-    Io(#[from] std::io::Error),
+    Io(std::io::Error),
     
     /// JSON解析エラー
     #[error("JSON解析エラー: {0}")]
     Json(#[from] serde_json::Error),
     
+    /// HTTP通信エラー
+    #[error("HTTP通信エラー: {0}")]
+    Http(reqwest::Error),
+    
+    /// サーバーがエラーステータスを返した
+    #[error("HTTPエラー {status}: {url}")]
+    HttpStatus { status: u16, url: String },
+    
+    /// ネットワークに接続できない（名前解決の失敗や接続の拒否など）
+    #[error("ネットワークに接続できません: {0}")]
+    NetworkUnreachable(String),
+    
+    /// タイムアウト
+    #[error("タイムアウトしました: {0}")]
+    Timeout(String),
+    
+    /// 地域制限で視聴できない
+    #[error("この地域では利用できないコンテンツです: {0}")]
+    GeoRestricted(String),
+    
+    /// ログインが必要
+    #[error("ログインが必要です（Cookieを指定してください）: {0}")]
+    LoginRequired(String),
+    
+    /// 対応していないサイト
+    #[error("対応していないサイトです: {0}")]
+    UnsupportedSite(String),
+    
+    /// マニフェスト（m3u8など）の解析エラー
+    #[error("マニフェストの解析に失敗: {0}")]
+    InvalidManifest(String),
+    
+    /// DRMで保護されていて復号できない
+    #[error("DRMで保護されたコンテンツには対応していません: {0}")]
+    DrmProtected(String),
+    
+    /// ディスクの空き容量が不足している
+    #[error("ディスクの空き容量が不足しています: {0}")]
+    DiskFull(String),
+    
+    /// 外部ツールのバージョンが古い
+    #[error("{tool} のバージョンが古いため実行できません。更新してください: {message}")]
+    ToolTooOld { tool: String, message: String },
+    
+    /// キャンセルされた
+    #[error("ダウンロードがキャンセルされました")]
+    Cancelled,
+    
+    /// 出力先に同じ名前のファイルがある（衝突時の扱いが `skip` の場合）
+    #[error("同じ名前のファイルがすでにあります: {}", .0.display())]
+    AlreadyExists(PathBuf),
+    
+    /// ダウンロードしたファイルのチェックサムが指定された値と一致しない
+    #[error("チェックサムが一致しません: {url}（期待値 {expected}、実際 {actual}）")]
+    ChecksumMismatch { url: String, expected: String, actual: String },
+    
+    /// 設定ファイルの読み込み・解析に失敗した、または設定の値が不正
+    #[error("設定ファイルのエラー: {0}")]
+    Config(String),
+    
     /// 内部エラー
     #[error("内部エラー: {0}")]
     Internal(String),
 }
 
+impl From<std::io::Error> for DownloadError {
+    fn from(err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::StorageFull => DownloadError::DiskFull(err.to_string()),
+            std::io::ErrorKind::TimedOut => DownloadError::Timeout(err.to_string()),
+            _ => DownloadError::Io(err),
+        }
+    }
+}
+
+impl From<reqwest::Error> for DownloadError {
+    fn from(err: reqwest::Error) -> Self {
+        if let Some(status) = err.status() {
+            DownloadError::HttpStatus {
+                status: status.as_u16(),
+                url: err.url().map(|url| url.to_string()).unwrap_or_default(),
+            }
+        } else if err.is_timeout() {
+            DownloadError::Timeout(err.to_string())
+        } else if err.is_connect() {
+            DownloadError::NetworkUnreachable(err.to_string())
+        } else {
+            DownloadError::Http(err)
+        }
+    }
+}
+
+impl DownloadError {
+    /// 時間をおいて同じ方法で再試行すれば成功する可能性があるか
+    ///
+    /// 通信の切断やタイムアウト、408・429・5xxのステータスが該当する。
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            DownloadError::Http(_)
+            | DownloadError::NetworkUnreachable(_)
+            | DownloadError::Timeout(_) => true,
+            DownloadError::HttpStatus { status, .. } => matches!(status, 408 | 429 | 500..=599),
+            _ => false,
+        }
+    }
+
+    /// 他のダウンロード方法に切り替えても成功しないか
+    ///
+    /// 配信元が拒否している場合や、保存先の問題、ユーザーによるキャンセルが該当する。
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            DownloadError::GeoRestricted(_)
+            | DownloadError::LoginRequired(_)
+            | DownloadError::UnsupportedSite(_)
+            | DownloadError::DrmProtected(_)
+            | DownloadError::DiskFull(_)
+            | DownloadError::AlreadyExists(_)
+            | DownloadError::Cancelled => true,
+            DownloadError::HttpStatus { status, .. } => matches!(status, 401 | 404 | 410),
+            _ => false,
+        }
+    }
+}
+
+/// エラーの種類を表すコード
+///
+/// FFIの戻り値、CLIの終了コード、GUIへのエラー通知で共通に使う。
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    Success = 0,
+    FileNotFound = 1,
+    ProcessFailed = 2,
+    IoError = 3,
+    JsonError = 4,
+    UnknownError = 5,
+    /// 引数が不正（NULLポインタや不正なUTF-8など）
+    InvalidArgument = 6,
+    /// キャンセルされた
+    Cancelled = 7,
+    /// HTTP 403
+    HttpForbidden = 8,
+    /// HTTP 404
+    HttpNotFound = 9,
+    /// HTTP 410
+    HttpGone = 10,
+    /// HTTP 429
+    HttpTooManyRequests = 11,
+    /// その他のHTTPエラー
+    HttpError = 12,
+    NetworkUnreachable = 13,
+    Timeout = 14,
+    GeoRestricted = 15,
+    /// ログインが必要（HTTP 401を含む）
+    LoginRequired = 16,
+    UnsupportedSite = 17,
+    DrmProtected = 18,
+    DiskFull = 19,
+    ToolTooOld = 20,
+    /// 同じ名前のファイルがすでにある
+    FileExists = 21,
+}
+
+impl From<&DownloadError> for ErrorCode {
+    fn from(err: &DownloadError) -> Self {
+        match err {
+            DownloadError::FileNotFound => ErrorCode::FileNotFound,
+            DownloadError::UnknownContentType => ErrorCode::UnsupportedSite,
+            DownloadError::ProcessFailed(_) => ErrorCode::ProcessFailed,
+            DownloadError::Io(_) => ErrorCode::IoError,
+            DownloadError::Json(_) => ErrorCode::JsonError,
+            DownloadError::Http(_) => ErrorCode::HttpError,
+            DownloadError::HttpStatus { status, .. } => match status {
+                401 => ErrorCode::LoginRequired,
+                403 => ErrorCode::HttpForbidden,
+                404 => ErrorCode::HttpNotFound,
+                410 => ErrorCode::HttpGone,
+                429 => ErrorCode::HttpTooManyRequests,
+                _ => ErrorCode::HttpError,
+            },
+            DownloadError::NetworkUnreachable(_) => ErrorCode::NetworkUnreachable,
+            DownloadError::Timeout(_) => ErrorCode::Timeout,
+            DownloadError::GeoRestricted(_) => ErrorCode::GeoRestricted,
+            DownloadError::LoginRequired(_) => ErrorCode::LoginRequired,
+            DownloadError::UnsupportedSite(_) => ErrorCode::UnsupportedSite,
+            DownloadError::DrmProtected(_) => ErrorCode::DrmProtected,
+            DownloadError::DiskFull(_) => ErrorCode::DiskFull,
+            DownloadError::ToolTooOld { .. } => ErrorCode::ToolTooOld,
+            DownloadError::Cancelled => ErrorCode::Cancelled,
+            DownloadError::AlreadyExists(_) => ErrorCode::FileExists,
+            DownloadError::Config(_) => ErrorCode::InvalidArgument,
+            DownloadError::InvalidManifest(_)
+            | DownloadError::ChecksumMismatch { .. }
+            | DownloadError::Internal(_) => ErrorCode::UnknownError,
+        }
+    }
+}
+
 /// 動画情報
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VideoInfo {
+    /// 動画ID
+    pub id: Option<String>,
+    /// 抽出器の名前（yt-dlpの `extractor_key`）
+    pub extractor_key: Option<String>,
     /// タイトル
     pub title: Option<String>,
+    /// 投稿者
+    pub uploader: Option<String>,
+    /// 投稿日（YYYYMMDD）
+    pub upload_date: Option<String>,
+    /// 選ばれたフォーマットの幅
+    pub width: Option<u32>,
+    /// 選ばれたフォーマットの高さ
+    pub height: Option<u32>,
     /// 利用可能なフォーマット
     pub formats: Option<Vec<FormatInfo>>,
     /// 説明
@@ -108,10 +534,31 @@ pub struct VideoInfo {
     pub duration: Option<f64>,
     /// URL
     pub url: Option<String>,
+    /// 動画ページのURL
+    pub webpage_url: Option<String>,
+    /// サムネイル画像のURL
+    pub thumbnail: Option<String>,
+    /// 字幕（言語ごとのトラック）
+    pub subtitles: Option<BTreeMap<String, Vec<SubtitleTrack>>>,
+    /// 自動生成字幕（言語ごとのトラック）
+    pub automatic_captions: Option<BTreeMap<String, Vec<SubtitleTrack>>>,
+}
+
+/// 字幕のトラック（yt-dlpの `subtitles` の各要素）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SubtitleTrack {
+    /// 拡張子（vtt, srt, ass, json3 など）
+    pub ext: Option<String>,
+    /// URL
+    pub url: Option<String>,
+    /// 表示名
+    pub name: Option<String>,
+    /// 取得方法（HLSの場合は `m3u8_native` など）
+    pub protocol: Option<String>,
 }
 
 /// フォーマット情報
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct FormatInfo {
     /// フォーマットID
     pub format_id: Option<String>,
@@ -125,17 +572,96 @@ pub struct FormatInfo {
     pub height: Option<u32>,
     /// 拡張子
     pub ext: Option<String>,
+    /// 映像コーデック（映像なしの場合は "none"）
+    pub vcodec: Option<String>,
+    /// 音声コーデック（音声なしの場合は "none"）
+    pub acodec: Option<String>,
+    /// 合計ビットレート (kbps)
+    pub tbr: Option<f64>,
+    /// フレームレート
+    pub fps: Option<f64>,
+    /// ファイルサイズ（バイト）
+    pub filesize: Option<u64>,
+    /// 音声の言語
+    pub language: Option<String>,
+}
+
+impl FormatInfo {
+    /// 映像を含むか
+    pub fn has_video(&self) -> bool {
+        match self.vcodec.as_deref() {
+            Some(codec) => codec != "none",
+            None => self.height.is_some(),
+        }
+    }
+
+    /// 音声を含むか（コーデック不明の場合は映像を含まなければ音声とみなす）
+    pub fn has_audio(&self) -> bool {
+        match self.acodec.as_deref() {
+            Some(codec) => codec != "none",
+            None => !self.has_video(),
+        }
+    }
+}
+
+/// ダウンロード処理の段階
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProgressPhase {
+    /// 動画情報・マニフェストの取得
+    Probing,
+    /// 映像（または映像と音声を含むファイル）のダウンロード
+    #[default]
+    DownloadingVideo,
+    /// 音声トラックのダウンロード
+    DownloadingAudio,
+    /// 映像と音声の結合
+    Merging,
+    /// 音声抽出などの後処理
+    PostProcessing,
+    /// 字幕のダウンロード・埋め込み
+    Subtitles,
+}
+
+impl ProgressPhase {
+    /// 表示用の名前
+    pub fn description(&self) -> &'static str {
+        match self {
+            ProgressPhase::Probing => "情報取得中",
+            ProgressPhase::DownloadingVideo => "映像をダウンロード中",
+            ProgressPhase::DownloadingAudio => "音声をダウンロード中",
+            ProgressPhase::Merging => "結合中",
+            ProgressPhase::PostProcessing => "後処理中",
+            ProgressPhase::Subtitles => "字幕を処理中",
+        }
+    }
 }
 
 /// 進捗情報
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct ProgressInfo {
-    /// 進捗（0.0〜1.0）
+    /// 処理の段階
+    pub phase: ProgressPhase,
+    /// 現在の段階の進捗（0.0〜1.0）
     pub progress: f64,
-    /// ダウンロード速度
-    pub speed: String,
+    /// ダウンロード済みのバイト数
+    pub downloaded_bytes: u64,
+    /// 合計バイト数（不明な場合は `None`、セグメント形式では推定値）
+    pub total_bytes: Option<u64>,
+    /// ダウンロード速度（バイト/秒）
+    pub speed: Option<f64>,
     /// 残り時間
-    pub eta: String,
+    pub eta: Option<Duration>,
+}
+
+impl ProgressInfo {
+    /// 指定した段階の空の進捗情報を作成
+    pub fn new(phase: ProgressPhase) -> Self {
+        Self {
+            phase,
+            ..Default::default()
+        }
+    }
 }
 
 /// プログレスコールバック型定義
@@ -180,3 +706,110 @@ impl SystemStatus {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn video(id: &str, height: u32, vcodec: &str, tbr: f64) -> FormatInfo {
+        FormatInfo {
+            format_id: Some(id.to_string()),
+            height: Some(height),
+            vcodec: Some(vcodec.to_string()),
+            acodec: Some("none".to_string()),
+            tbr: Some(tbr),
+            ..Default::default()
+        }
+    }
+
+    fn audio(id: &str, language: Option<&str>, tbr: f64) -> FormatInfo {
+        FormatInfo {
+            format_id: Some(id.to_string()),
+            vcodec: Some("none".to_string()),
+            acodec: Some("mp4a.40.2".to_string()),
+            language: language.map(str::to_string),
+            tbr: Some(tbr),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_height_codecs_and_language() {
+        let selection: FormatSelection = "1080p, AV1 ,avc1.64001f,lang=JA".parse().unwrap();
+
+        assert_eq!(selection, FormatSelection {
+            format_id: None,
+            max_height: Some(1080),
+            preferred_codecs: vec!["av1".to_string(), "h264".to_string()],
+            audio_language: Some("ja".to_string()),
+        });
+    }
+
+    #[test]
+    fn parses_format_id_without_changing_case() {
+        let selection: FormatSelection = "id=hls-AAC_2,<=720p".parse().unwrap();
+
+        assert_eq!(selection.format_id.as_deref(), Some("hls-AAC_2"));
+        assert_eq!(selection.max_height, Some(720));
+    }
+
+    #[test]
+    fn best_and_empty_mean_no_conditions() {
+        assert!("best".parse::<FormatSelection>().unwrap().is_default());
+        assert!("".parse::<FormatSelection>().unwrap().is_default());
+        assert!(" , BEST ,".parse::<FormatSelection>().unwrap().is_default());
+    }
+
+    #[test]
+    fn rejects_unknown_tokens() {
+        assert_eq!("mpeg2".parse::<FormatSelection>(), Err("不明な画質指定: mpeg2".to_string()));
+        assert_eq!("fullp".parse::<FormatSelection>(), Err("不正な解像度: fullp".to_string()));
+        assert!("1080".parse::<FormatSelection>().is_err());
+    }
+
+    #[test]
+    fn selects_highest_video_within_height_then_codec_then_bitrate() {
+        let formats = vec![
+            video("2160-vp9", 2160, "vp09.00.50.08", 16000.0),
+            video("1080-h264", 1080, "avc1.640028", 4500.0),
+            video("1080-av1", 1080, "av01.0.08M.08", 2500.0),
+            video("1080-vp9", 1080, "vp09.00.40.08", 3000.0),
+            video("720-h264", 720, "avc1.64001f", 2500.0),
+            audio("audio", None, 128.0),
+        ];
+
+        let pick = |spec: &str| {
+            let selection: FormatSelection = spec.parse().unwrap();
+            selection.select_video(&formats).and_then(|format| format.format_id.clone())
+        };
+        assert_eq!(pick("best").as_deref(), Some("2160-vp9"));
+        // 同じ解像度ではコーデックの指定がなければビットレートの高いものを選ぶ
+        assert_eq!(pick("1080p").as_deref(), Some("1080-h264"));
+        assert_eq!(pick("1080p,av1,h264").as_deref(), Some("1080-av1"));
+        assert_eq!(pick("1080p,h265,vp9").as_deref(), Some("1080-vp9"));
+        // 上限以下のものがなければ最も低い解像度
+        assert_eq!(pick("480p").as_deref(), Some("720-h264"));
+        assert_eq!(pick("id=720-h264").as_deref(), Some("720-h264"));
+        assert_eq!(pick("id=audio"), None);
+    }
+
+    #[test]
+    fn selects_audio_by_language_then_bitrate() {
+        let formats = vec![
+            video("1080", 1080, "avc1.640028", 4500.0),
+            audio("en-high", Some("en-US"), 256.0),
+            audio("ja-low", Some("ja"), 96.0),
+            audio("ja-high", Some("ja-JP"), 160.0),
+        ];
+
+        let pick = |spec: &str| {
+            let selection: FormatSelection = spec.parse().unwrap();
+            selection.select_audio(&formats).and_then(|format| format.format_id.clone())
+        };
+        assert_eq!(pick("best").as_deref(), Some("en-high"));
+        assert_eq!(pick("lang=ja").as_deref(), Some("ja-high"));
+        // 一致する言語がなければ全体から選ぶ
+        assert_eq!(pick("lang=fr").as_deref(), Some("en-high"));
+        assert_eq!(pick("id=ja-low").as_deref(), Some("ja-low"));
+    }
+}
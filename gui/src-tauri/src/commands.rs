@@ -1,25 +1,42 @@
 use nextdownloader_core::{
+    BandwidthConfig,
+    Config,
+    DownloadError,
     DownloadManager, 
     Downloader, 
-    DownloadOptions, 
+    DownloadTask,
+    FormatInfo,
     ContentType,
+    ErrorCode,
+    OptionOverrides,
+    RequestContext,
+    SpeedLimit,
     VideoFormat,
     SystemStatus,
-    ProgressInfo
+    ProgressInfo,
+    ProgressPhase,
+    DEFAULT_FILENAME_TEMPLATE,
+    content_type_from_url,
+    sanitize_filename
 };
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::sync::Arc;
 use tauri::State;
-use tauri::Manager;
+use tauri::Emitter;
 
 // 進捗情報をフロントエンドに送信するための型
 #[derive(Serialize, Clone)]
 struct ProgressEvent {
     task_id: String,
+    phase: ProgressPhase,
     progress: f64,
-    speed: String,
-    eta: String,
+    downloaded_bytes: u64,
+    total_bytes: Option<u64>,
+    /// バイト/秒
+    speed: Option<f64>,
+    /// 秒
+    eta: Option<f64>,
 }
 
 // システム状態情報
@@ -37,21 +54,85 @@ struct SystemStatusInfo {
 pub struct DownloadRequest {
     url: String,
     output_path: String,
+    /// ファイル名（テンプレートとしては解釈しない）
     filename: String,
+    /// ファイル名のテンプレート（`{title} [{id}]` など。指定した場合は `filename` より優先する）
+    filename_template: Option<String>,
     format: String,
     connections: Option<u32>,
     splits: Option<u32>,
     chunk_size: Option<u32>,
+    priority: Option<i32>,
+    quality: Option<String>,
+    #[serde(flatten)]
+    request: RequestFields,
+    /// 速度制限（バイト/秒）
+    speed_limit: Option<u64>,
 }
 
-// ダウンロード結果
-#[derive(Serialize)]
-pub struct DownloadResult {
-    success: bool,
-    file_path: String,
+// 認証が必要なURLに送るリクエストの情報
+#[derive(Deserialize, Default)]
+pub struct RequestFields {
+    /// 設定ファイルのプロファイル
+    profile: Option<String>,
+    /// ブラウザ拡張から受け取ったリファラー
+    referrer: Option<String>,
+    headers: Option<Vec<(String, String)>>,
+    cookies: Option<Vec<(String, String)>>,
+    cookie_file: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl RequestFields {
+    /// 設定ファイルより優先する値
+    fn overrides(&self) -> OptionOverrides {
+        OptionOverrides {
+            headers: self.headers.clone().unwrap_or_default().into_iter().collect(),
+            cookies: self.cookies.clone().unwrap_or_default().into_iter().collect(),
+            cookie_file: self.cookie_file.as_ref().map(PathBuf::from),
+            user_agent: self.user_agent.clone(),
+            referer: self.referrer.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// 設定ファイルと合わせて、URLに送るリクエストの情報を決める（`download_url` と同じ順で解決する）
+    fn resolve(&self, url: &str) -> Result<RequestContext, CommandError> {
+        let config = Config::load()?;
+        let content_type = content_type_from_url(url);
+        let resolved = config.resolve(url, content_type.as_ref(), self.profile.as_deref(), &self.overrides())?;
+        Ok(resolved.options.request)
+    }
+}
+
+/// フロントエンドに返すエラー
+///
+/// `code` でエラーの種類（`http_forbidden` や `login_required` など）を判別できる。
+#[derive(Debug, Serialize)]
+pub struct CommandError {
+    code: ErrorCode,
     message: String,
 }
 
+impl From<DownloadError> for CommandError {
+    fn from(err: DownloadError) -> Self {
+        Self {
+            code: ErrorCode::from(&err),
+            message: err.to_string(),
+        }
+    }
+}
+
+/// 引数の解析エラー
+impl From<String> for CommandError {
+    fn from(message: String) -> Self {
+        Self {
+            code: ErrorCode::InvalidArgument,
+            message,
+        }
+    }
+}
+
 // コンテンツタイプ検出結果
 #[derive(Serialize)]
 pub struct ContentTypeResult {
@@ -60,14 +141,18 @@ pub struct ContentTypeResult {
     message: String,
 }
 
-/// URLからダウンロード
+/// URLのダウンロードをキューに追加
+///
+/// `filename` はそのままファイル名として使い、`filename_template` を指定した場合は
+/// テンプレートとして展開する（どちらも空の場合は設定ファイルのテンプレート）。
+/// タスクIDを返す。進捗は`download-progress`、状態変化は
+/// `download-task-updated`イベントで通知される。
 #[tauri::command]
 pub async fn download_url(
     app: tauri::AppHandle,
+    downloader: State<'_, Arc<DownloadManager>>,
     request: DownloadRequest
-) -> Result<DownloadResult, String> {
-    let downloader = DownloadManager::new();
-    
+) -> Result<String, CommandError> {
     // フォーマット変換
     let format = match request.format.to_lowercase().as_str() {
         "mp4" => VideoFormat::Mp4,
@@ -76,76 +161,165 @@ pub async fn download_url(
         _ => VideoFormat::Mp4,
     };
     
-    // ダウンロードオプション
-    let options = DownloadOptions {
-        connections: request.connections.unwrap_or(16),
-        splits: request.splits.unwrap_or(16),
-        chunk_size: request.chunk_size.unwrap_or(4),
-        format,
-        ..Default::default()
+    // ダウンロードオプション（画面で指定された値を設定ファイルより優先する）
+    let flags = OptionOverrides {
+        output_dir: Some(PathBuf::from(&request.output_path)).filter(|path| !path.as_os_str().is_empty()),
+        format: Some(format),
+        quality: request.quality.clone(),
+        connections: request.connections,
+        splits: request.splits,
+        chunk_size: request.chunk_size,
+        speed_limit: request.speed_limit.map(SpeedLimit),
+        ..request.request.overrides()
     };
+    let config = Config::load()?;
+    let content_type = content_type_from_url(&request.url);
+    let resolved = config.resolve(&request.url, content_type.as_ref(), request.request.profile.as_deref(), &flags)?;
+    let options = resolved.options;
     
-    // タスクID生成
-    let task_id = uuid::Uuid::new_v4().to_string();
-    let task_id_clone = task_id.clone();
+    // ファイル名はテンプレートとして解釈されないよう1つのファイル名にして波括弧をエスケープする
+    // （どちらも空の場合は設定ファイルのテンプレート、なければ既定のテンプレート）
+    let template = request.filename_template.as_deref().map(str::trim).unwrap_or_default();
+    let filename = match (template, request.filename.trim()) {
+        ("", "") => resolved.filename_template.unwrap_or_else(|| DEFAULT_FILENAME_TEMPLATE.to_string()),
+        ("", filename) => sanitize_filename(filename).replace('{', "{{").replace('}', "}}"),
+        (template, _) => template.to_string(),
+    };
     
-    // 進捗コールバック
-    let app_handle = Arc::new(app);
-    let app_handle_clone = Arc::clone(&app_handle);
+    // 進捗コールバック（タスクIDは登録後に確定するため共有セルで受け渡す）
+    let task_id_cell = Arc::new(std::sync::OnceLock::<String>::new());
+    let callback_task_id = Arc::clone(&task_id_cell);
     let progress_callback = Box::new(move |info: ProgressInfo| {
         // フロントエンドにイベント送信
-        let _ = app_handle_clone.emit_all("download-progress", ProgressEvent {
-            task_id: task_id_clone.clone(),
+        let _ = app.emit("download-progress", ProgressEvent {
+            task_id: callback_task_id.get().cloned().unwrap_or_default(),
+            phase: info.phase,
             progress: info.progress,
-            speed: info.speed.clone(),
-            eta: info.eta.clone(),
+            downloaded_bytes: info.downloaded_bytes,
+            total_bytes: info.total_bytes,
+            speed: info.speed,
+            eta: info.eta.map(|eta| eta.as_secs_f64()),
         });
     });
     
-    // ダウンロード実行
-    let output_path = PathBuf::from(&request.output_path);
-    match downloader.download(
+    // キューに登録し、タスクIDを確定させてから開始する（進捗イベントに必ずタスクIDが入るように）
+    let output_path = resolved.output_dir.unwrap_or_else(|| PathBuf::from("."));
+    let task_id = downloader.add_task(
         &request.url,
         &output_path,
-        &request.filename,
+        &filename,
         Some(options),
+        request.priority.unwrap_or(0),
         Some(progress_callback)
-    ).await {
-        Ok(file_path) => {
-            // 完了イベント送信
-            let _ = app_handle.emit_all("download-finished", ProgressEvent {
-                task_id: task_id.clone(),
-                progress: 1.0,
-                speed: "完了".to_string(),
-                eta: "0s".to_string(),
-            });
-            
-            Ok(DownloadResult {
-                success: true,
-                file_path: file_path.to_string_lossy().to_string(),
-                message: "ダウンロードが完了しました".to_string(),
-            })
-        },
-        Err(err) => {
-            // エラーイベント送信
-            let _ = app_handle.emit_all("download-error", ProgressEvent {
-                task_id,
-                progress: 0.0,
-                speed: "エラー".to_string(),
-                eta: "失敗".to_string(),
-            });
-            
-            Err(err.to_string())
-        }
-    }
+    ).await?;
+    let _ = task_id_cell.set(task_id.clone());
+    downloader.start_pending();
+    
+    Ok(task_id)
+}
+
+/// URLで選択可能なフォーマットの一覧を取得
+///
+/// `request` のCookie・ヘッダーなどは `download_url` と同じく設定ファイルより優先する。
+#[tauri::command]
+pub async fn list_formats(
+    downloader: State<'_, Arc<DownloadManager>>,
+    url: String,
+    request: Option<RequestFields>
+) -> Result<Vec<FormatInfo>, CommandError> {
+    let context = request.unwrap_or_default().resolve(&url)?;
+    downloader
+        .list_formats(&url, &context)
+        .await
+        .map_err(CommandError::from)
+}
+
+/// キュー内のタスク一覧を取得
+#[tauri::command]
+pub async fn list_tasks(
+    downloader: State<'_, Arc<DownloadManager>>
+) -> Result<Vec<DownloadTask>, CommandError> {
+    downloader.list_tasks().await.map_err(CommandError::from)
+}
+
+/// タスクをキャンセル
+#[tauri::command]
+pub async fn cancel_task(
+    downloader: State<'_, Arc<DownloadManager>>,
+    task_id: String
+) -> Result<(), CommandError> {
+    downloader.cancel_download(&task_id).await.map_err(CommandError::from)
+}
+
+/// タスクを一時停止
+#[tauri::command]
+pub async fn pause_task(
+    downloader: State<'_, Arc<DownloadManager>>,
+    task_id: String
+) -> Result<(), CommandError> {
+    downloader.pause(&task_id).await.map_err(CommandError::from)
+}
+
+/// 一時停止したタスクを再開
+#[tauri::command]
+pub async fn resume_task(
+    downloader: State<'_, Arc<DownloadManager>>,
+    task_id: String
+) -> Result<(), CommandError> {
+    downloader.resume(&task_id).await?;
+    downloader.start_pending();
+    Ok(())
+}
+
+/// タスクの優先度を変更
+#[tauri::command]
+pub async fn set_task_priority(
+    downloader: State<'_, Arc<DownloadManager>>,
+    task_id: String,
+    priority: i32
+) -> Result<(), CommandError> {
+    downloader.set_priority(&task_id, priority).await.map_err(CommandError::from)
+}
+
+/// タスクの速度制限を変更（バイト/秒、nullで無制限）
+#[tauri::command]
+pub async fn set_task_speed_limit(
+    downloader: State<'_, Arc<DownloadManager>>,
+    task_id: String,
+    speed_limit: Option<u64>
+) -> Result<(), CommandError> {
+    downloader.set_task_speed_limit(&task_id, speed_limit).await.map_err(CommandError::from)
+}
+
+/// 全体の速度制限と時間帯ごとの制限を取得
+#[tauri::command]
+pub async fn get_bandwidth(
+    downloader: State<'_, Arc<DownloadManager>>
+) -> Result<BandwidthConfig, CommandError> {
+    Ok(downloader.bandwidth())
+}
+
+/// 全体の速度制限と時間帯ごとの制限を変更（実行中のダウンロードにも反映する）
+#[tauri::command]
+pub async fn set_bandwidth(
+    downloader: State<'_, Arc<DownloadManager>>,
+    bandwidth: BandwidthConfig
+) -> Result<(), CommandError> {
+    downloader.set_bandwidth(bandwidth);
+    Ok(())
 }
 
 /// コンテンツタイプを検出
+///
+/// `request` のCookie・ヘッダーなどは `download_url` と同じく設定ファイルより優先する。
 #[tauri::command]
-pub async fn detect_content_type(url: String) -> Result<ContentTypeResult, String> {
-    let downloader = DownloadManager::new();
-    
-    match downloader.detect_content_type(&url).await {
+pub async fn detect_content_type(
+    downloader: State<'_, Arc<DownloadManager>>,
+    url: String,
+    request: Option<RequestFields>
+) -> Result<ContentTypeResult, CommandError> {
+    let context = request.unwrap_or_default().resolve(&url)?;
+    match downloader.detect_content_type_with(&url, &context).await {
         Ok(content_type) => {
             let type_str = match content_type {
                 ContentType::Mp4 => "mp4",
@@ -162,15 +336,16 @@ pub async fn detect_content_type(url: String) -> Result<ContentTypeResult, Strin
             })
         },
         Err(err) => {
-            Err(err.to_string())
+            Err(err.into())
         }
     }
 }
 
 /// システム状態をチェック
 #[tauri::command]
-pub async fn check_system_status() -> Result<SystemStatusInfo, String> {
-    let downloader = DownloadManager::new();
+pub async fn check_system_status(
+    downloader: State<'_, Arc<DownloadManager>>
+) -> Result<SystemStatusInfo, CommandError> {
     let (ytdlp, aria2c, ffmpeg) = downloader.check_dependencies().await;
     
     let status = SystemStatus::MissingDependencies {
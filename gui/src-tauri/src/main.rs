@@ -3,9 +3,11 @@
 
 mod commands;
 
-use tauri::Manager;
+use std::sync::Arc;
+use tauri::{Emitter, Manager};
+use tokio::sync::broadcast;
 use tauri_plugin_dialog::DialogExt;
-use nextdownloader_core::{DownloadManager, Downloader};
+use nextdownloader_core::{Config, DownloadManager, QueueConfig, TaskStatus};
 
 // Tauriアプリケーションのエントリーポイント
 fn main() {
@@ -15,10 +17,45 @@ fn main() {
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_updater::Builder::new().build())
         .setup(|app| {
+            // 永続化されたダウンロードキューを読み込み、未完了のタスクを再開
+            let config = Config::load()?;
+            let tools = config.tools;
+            let downloader = Arc::new(
+                DownloadManager::with_queue_config(QueueConfig::persistent())?
+                    .with_tool_paths(tools.clone())
+                    .with_bandwidth(config.bandwidth)
+            );
+            app.manage(Arc::clone(&downloader));
+            
+            let event_handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                let mut events = downloader.subscribe();
+                downloader.start_pending();
+                
+                // タスクの状態変化をフロントエンドに転送
+                loop {
+                    let task = match events.recv().await {
+                        Ok(task) => task,
+                        // 取りこぼした通知は次の状態変化で追いつくため、転送を続ける
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+                    let event = match task.status {
+                        TaskStatus::Completed => Some("download-finished"),
+                        TaskStatus::Failed => Some("download-error"),
+                        _ => None,
+                    };
+                    if let Some(event) = event {
+                        let _ = event_handle.emit(event, &task);
+                    }
+                    let _ = event_handle.emit("download-task-updated", &task);
+                }
+            });
+            
             let app_handle = app.handle().clone();
             // バックグラウンドで依存関係チェック
             tokio::spawn(async move {
-                let downloader = DownloadManager::new();
+                let downloader = DownloadManager::new().with_tool_paths(tools);
                 let status = downloader.system_status().await;
                 if !status.is_ready() {
                     // 依存関係不足の警告ダイアログ
@@ -37,6 +74,15 @@ fn main() {
         // コマンドハンドラー登録
         .invoke_handler(tauri::generate_handler![
             commands::download_url,
+            commands::list_formats,
+            commands::list_tasks,
+            commands::cancel_task,
+            commands::pause_task,
+            commands::resume_task,
+            commands::set_task_priority,
+            commands::set_task_speed_limit,
+            commands::get_bandwidth,
+            commands::set_bandwidth,
             commands::detect_content_type,
             commands::check_system_status
         ])
@@ -21,6 +21,9 @@ public class DownloadService: ObservableObject {
     /// 進捗ハンドラ
     private var progressHandlers: [UUID: (Double, String, String) -> Void] = [:]
     
+    /// 実行中のダウンロード（キャンセルするとRustコアのダウンロードも中断する）
+    private var downloadTasks: [UUID: Task<Void, Never>] = [:]
+    
     /// 初期化
     private init() {
         // 初期化時にシステム状態をチェック
@@ -89,7 +92,13 @@ public class DownloadService: ObservableObject {
     
     /// ダウンロードを開始
     public func startDownload(item: DownloadItem) {
-        Task {
+        downloadTasks[item.id] = Task {
+            defer {
+                Task { @MainActor in
+                    self.downloadTasks[item.id] = nil
+                }
+            }
+            
             await updateDownloadItem(id: item.id) { item in
                 var updatedItem = item
                 updatedItem.status = .downloading
@@ -140,6 +149,14 @@ public class DownloadService: ObservableObject {
                 
                 print("ダウンロード完了: \(downloadedFilePath)")
                 
+            } catch DownloadError.cancelled {
+                await updateDownloadItem(id: item.id) { item in
+                    var updatedItem = item
+                    updatedItem.status = .cancelled
+                    updatedItem.speed = ""
+                    updatedItem.remainingTime = ""
+                    return updatedItem
+                }
             } catch {
                 print("ダウンロードエラー: \(error.localizedDescription)")
                 
@@ -171,6 +188,8 @@ public class DownloadService: ObservableObject {
     
     /// ダウンロードをキャンセル
     public func cancelDownload(item: DownloadItem) {
+        downloadTasks[item.id]?.cancel()
+        
         Task {
             await updateDownloadItem(id: item.id) { item in
                 var updatedItem = item
@@ -186,6 +186,8 @@ public enum DownloadError: Error {
     case jsonParsingFailed
     /// ビデオ情報取得失敗
     case videoInfoFetchFailed
+    /// キャンセルされた
+    case cancelled
     /// 不明なエラー
     case unknown
     
@@ -204,6 +206,8 @@ public enum DownloadError: Error {
             return "JSONの解析に失敗しました"
         case .videoInfoFetchFailed:
             return "ビデオ情報の取得に失敗しました"
+        case .cancelled:
+            return "ダウンロードがキャンセルされました"
         case .unknown:
             return "不明なエラーが発生しました"
         }
@@ -16,7 +16,7 @@ public class NextDownloaderCore {
         var result: [UInt8] = [0, 0, 0]
         
         let status = result.withUnsafeMutableBufferPointer { bufferPtr in
-            check_dependencies(bufferPtr.baseAddress)
+            nd_check_dependencies(bufferPtr.baseAddress)
         }
         
         if status == 0 {
@@ -38,7 +38,7 @@ public class NextDownloaderCore {
         var result: Int32 = 4 // デフォルトはunknown
         
         let status = url.withCString { urlPtr in
-            detect_content_type(urlPtr, &result)
+            nd_detect_content_type(urlPtr, &result)
         }
         
         if status == 0 {
@@ -67,13 +67,13 @@ public class NextDownloaderCore {
             var jsonResult: UnsafeMutablePointer<CChar>? = nil
             
             let status = url.withCString { urlPtr in
-                get_video_info(urlPtr, &jsonResult)
+                nd_get_video_info(urlPtr, &jsonResult)
             }
             
             if status == 0 && jsonResult != nil {
                 defer {
                     // メモリリーク防止のためCの文字列を解放
-                    free_string(jsonResult)
+                    nd_string_free(jsonResult)
                 }
                 
                 let jsonString = String(cString: jsonResult!)
@@ -99,6 +99,8 @@ public class NextDownloaderCore {
     ///   - options: ダウンロードオプション（nilの場合はデフォルト値を使用）
     ///   - progressCallback: 進捗コールバック
     /// - Returns: ダウンロードしたファイルのパス
+    ///
+    /// 呼び出し元のタスクがキャンセルされた場合はダウンロードを中断し、`DownloadError.cancelled` を投げる。
     public func downloadVideo(
         url: String,
         outputPath: String,
@@ -106,177 +108,274 @@ public class NextDownloaderCore {
         options: DownloadOptions? = nil,
         progressCallback: ((Double, String, String) -> Void)? = nil
     ) async throws -> String {
-        return try await withCheckedThrowingContinuation { continuation in
-            var resultPath: UnsafeMutablePointer<CChar>? = nil
-            
-            // オプションをJSON文字列に変換
-            let optionsJson: String?
-            if let options = options {
-                let encoder = JSONEncoder()
-                if let data = try? encoder.encode(options),
-                   let json = String(data: data, encoding: .utf8) {
-                    optionsJson = json
-                } else {
-                    optionsJson = nil
-                }
+        // オプションをJSON文字列に変換
+        let optionsJson: String?
+        if let options = options {
+            let encoder = JSONEncoder()
+            if let data = try? encoder.encode(options),
+               let json = String(data: data, encoding: .utf8) {
+                optionsJson = json
             } else {
                 optionsJson = nil
             }
+        } else {
+            optionsJson = nil
+        }
+        
+        let handle = TaskHandle()
+        return try await withTaskCancellationHandler {
+            try await withCheckedThrowingContinuation { continuation in
+                // コールバックに渡すコンテキスト（完了コールバックで解放する）
+                let context = Unmanaged.passRetained(
+                    DownloadContext(handle: handle, progressCallback: progressCallback, continuation: continuation)
+                ).toOpaque()
             
-            let status = url.withCString { urlPtr in
-                outputPath.withCString { outputPathPtr in
-                    filename.withCString { filenamePtr in
-                        if let optionsJson = optionsJson {
-                            optionsJson.withCString { optionsJsonPtr in
-                                download_video(urlPtr, outputPathPtr, filenamePtr, optionsJsonPtr, &resultPath)
+                let task = url.withCString { urlPtr in
+                    outputPath.withCString { outputPathPtr in
+                        filename.withCString { filenamePtr in
+                            if let optionsJson = optionsJson {
+                                return optionsJson.withCString { optionsJsonPtr in
+                                    nd_download_start(urlPtr, outputPathPtr, filenamePtr, optionsJsonPtr, downloadProgress, downloadCompletion, context)
+                                }
+                            } else {
+                                return nd_download_start(urlPtr, outputPathPtr, filenamePtr, nil, downloadProgress, downloadCompletion, context)
                             }
-                        } else {
-                            download_video(urlPtr, outputPathPtr, filenamePtr, nil, &resultPath)
                         }
                     }
                 }
-            }
             
-            if status == 0 && resultPath != nil {
-                defer {
-                    // メモリリーク防止のためCの文字列を解放
-                    free_string(resultPath)
-                }
-                
-                let path = String(cString: resultPath!)
-                continuation.resume(returning: path)
-            } else {
-                let error: DownloadError
-                switch status {
-                case 1:
-                    error = .fileNotFound
-                case 2:
-                    error = .processFailed
-                case 3:
-                    error = .ioError
-                case 4:
-                    error = .jsonParsingFailed
-                default:
-                    error = .unknown
+                if let task = task {
+                    // ハンドルはキャンセルできるよう完了コールバックまで保持する
+                    handle.attach(task)
+                } else {
+                    Unmanaged<DownloadContext>.fromOpaque(context).release()
+                    continuation.resume(throwing: DownloadError.unknown)
                 }
-                continuation.resume(throwing: error)
             }
+        } onCancel: {
+            handle.cancel()
         }
     }
 }
 
-// MARK: - FFI関数の宣言
+// MARK: - ダウンロードのコールバック
 
-/// システムの依存関係をチェック
-@_cdecl("check_dependencies")
-private func check_dependencies(_ result: UnsafeMutablePointer<UInt8>?) -> Int32 {
-    let libPath = Bundle.module.path(forResource: "libnextdownloader_core", ofType: "dylib")
+/// 進捗情報（Rust側の `NdProgress` と同じレイアウト）
+private struct NdProgress {
+    var phase: Int32
+    var progress: Double
+    var downloadedBytes: UInt64
+    var totalBytes: Int64
+    var speed: Double
+    var etaSeconds: Double
+}
+
+/// タスクのハンドル（キャンセルと完了が別のスレッドから届くためロックで守る）
+private final class TaskHandle {
+    private let lock = NSLock()
+    private var task: OpaquePointer?
+    private var cancelled = false
+    private var finished = false
     
-    guard let lib = dlopen(libPath, RTLD_LAZY) else {
-        return -1
+    /// 開始したタスクのハンドルを保持する（完了済みならすぐに解放する）
+    func attach(_ task: OpaquePointer) {
+        lock.lock()
+        defer { lock.unlock() }
+        if finished {
+            nd_task_free(task)
+            return
+        }
+        self.task = task
+        if cancelled {
+            _ = nd_cancel(task)
+        }
     }
-    defer { dlclose(lib) }
     
-    guard let fn = dlsym(lib, "check_dependencies") else {
-        return -1
+    /// ダウンロードをキャンセルする（開始前なら開始後にキャンセルする）
+    func cancel() {
+        lock.lock()
+        defer { lock.unlock() }
+        cancelled = true
+        if let task = task {
+            _ = nd_cancel(task)
+        }
     }
     
-    typealias CheckDependenciesFn = @convention(c) (UnsafeMutablePointer<UInt8>?) -> Int32
-    let function = unsafeBitCast(fn, to: CheckDependenciesFn.self)
-    
-    return function(result)
+    /// 完了したタスクのハンドルを解放する
+    func finish() {
+        lock.lock()
+        defer { lock.unlock() }
+        finished = true
+        if let task = task {
+            nd_task_free(task)
+            self.task = nil
+        }
+    }
 }
 
-/// URLからコンテンツタイプを検出
-@_cdecl("detect_content_type")
-private func detect_content_type(_ url: UnsafePointer<CChar>?, _ result: UnsafeMutablePointer<Int32>?) -> Int32 {
-    let libPath = Bundle.module.path(forResource: "libnextdownloader_core", ofType: "dylib")
+/// ダウンロード1件分のコールバックの状態
+private final class DownloadContext {
+    let handle: TaskHandle
+    let progressCallback: ((Double, String, String) -> Void)?
+    let continuation: CheckedContinuation<String, Error>
     
-    guard let lib = dlopen(libPath, RTLD_LAZY) else {
-        return -1
+    init(handle: TaskHandle, progressCallback: ((Double, String, String) -> Void)?, continuation: CheckedContinuation<String, Error>) {
+        self.handle = handle
+        self.progressCallback = progressCallback
+        self.continuation = continuation
     }
-    defer { dlclose(lib) }
+}
+
+/// 進捗コールバック
+private let downloadProgress: @convention(c) (UnsafeMutableRawPointer?, UnsafePointer<NdProgress>?) -> Void = { userData, progress in
+    guard let userData = userData, let progress = progress?.pointee else { return }
+    let context = Unmanaged<DownloadContext>.fromOpaque(userData).takeUnretainedValue()
     
-    guard let fn = dlsym(lib, "detect_content_type") else {
-        return -1
-    }
+    let formatter = ByteCountFormatter()
+    let speed = progress.speed >= 0 ? "\(formatter.string(fromByteCount: Int64(progress.speed)))/s" : ""
+    let eta = progress.etaSeconds >= 0 ? "\(Int(progress.etaSeconds))秒" : ""
+    context.progressCallback?(progress.progress, speed, eta)
+}
+
+/// 完了コールバック
+private let downloadCompletion: @convention(c) (UnsafeMutableRawPointer?, Int32, UnsafePointer<CChar>?) -> Void = { userData, status, result in
+    guard let userData = userData else { return }
+    let context = Unmanaged<DownloadContext>.fromOpaque(userData).takeRetainedValue()
+    context.handle.finish()
     
-    typealias DetectContentTypeFn = @convention(c) (UnsafePointer<CChar>?, UnsafeMutablePointer<Int32>?) -> Int32
-    let function = unsafeBitCast(fn, to: DetectContentTypeFn.self)
+    if status == 0, let result = result {
+        context.continuation.resume(returning: String(cString: result))
+        return
+    }
     
-    return function(url, result)
+    let error: DownloadError
+    switch status {
+    case 1:
+        error = .fileNotFound
+    case 2:
+        error = .processFailed
+    case 3:
+        error = .ioError
+    case 4:
+        error = .jsonParsingFailed
+    case 7:
+        error = .cancelled
+    default:
+        error = .unknown
+    }
+    context.continuation.resume(throwing: error)
 }
 
-/// URLからビデオ情報を取得
-@_cdecl("get_video_info")
-private func get_video_info(_ url: UnsafePointer<CChar>?, _ jsonResult: UnsafeMutablePointer<UnsafeMutablePointer<CChar>?>?) -> Int32 {
+// MARK: - FFI関数の宣言
+
+/// Rustコアの動的ライブラリ（最初の呼び出しで開き、プロセスの終了まで閉じない）
+///
+/// ダウンロード中はライブラリのスレッドからコールバックされるため、途中で閉じてはいけない。
+private let coreLibrary: UnsafeMutableRawPointer? = {
     let libPath = Bundle.module.path(forResource: "libnextdownloader_core", ofType: "dylib")
-    
-    guard let lib = dlopen(libPath, RTLD_LAZY) else {
+    return dlopen(libPath, RTLD_LAZY)
+}()
+
+/// ライブラリの関数を取得（見つからない場合はnil）
+private func coreFunction<T>(_ name: String, as type: T.Type) -> T? {
+    guard let lib = coreLibrary, let symbol = dlsym(lib, name) else {
+        return nil
+    }
+    return unsafeBitCast(symbol, to: type)
+}
+
+private typealias CheckDependenciesFn = @convention(c) (UnsafeMutablePointer<UInt8>?) -> Int32
+private let checkDependenciesFn = coreFunction("nd_check_dependencies", as: CheckDependenciesFn.self)
+
+/// システムの依存関係をチェック
+@_cdecl("nd_check_dependencies")
+private func nd_check_dependencies(_ result: UnsafeMutablePointer<UInt8>?) -> Int32 {
+    guard let function = checkDependenciesFn else {
         return -1
     }
-    defer { dlclose(lib) }
-    
-    guard let fn = dlsym(lib, "get_video_info") else {
+    return function(result)
+}
+
+private typealias DetectContentTypeFn = @convention(c) (UnsafePointer<CChar>?, UnsafeMutablePointer<Int32>?) -> Int32
+private let detectContentTypeFn = coreFunction("nd_detect_content_type", as: DetectContentTypeFn.self)
+
+/// URLからコンテンツタイプを検出
+@_cdecl("nd_detect_content_type")
+private func nd_detect_content_type(_ url: UnsafePointer<CChar>?, _ result: UnsafeMutablePointer<Int32>?) -> Int32 {
+    guard let function = detectContentTypeFn else {
+        return -1
+    }
+    return function(url, result)
+}
+
+private typealias GetVideoInfoFn = @convention(c) (UnsafePointer<CChar>?, UnsafeMutablePointer<UnsafeMutablePointer<CChar>?>?) -> Int32
+private let getVideoInfoFn = coreFunction("nd_get_video_info", as: GetVideoInfoFn.self)
+
+/// URLからビデオ情報を取得
+@_cdecl("nd_get_video_info")
+private func nd_get_video_info(_ url: UnsafePointer<CChar>?, _ jsonResult: UnsafeMutablePointer<UnsafeMutablePointer<CChar>?>?) -> Int32 {
+    guard let function = getVideoInfoFn else {
         return -1
     }
-    
-    typealias GetVideoInfoFn = @convention(c) (UnsafePointer<CChar>?, UnsafeMutablePointer<UnsafeMutablePointer<CChar>?>?) -> Int32
-    let function = unsafeBitCast(fn, to: GetVideoInfoFn.self)
-    
     return function(url, jsonResult)
 }
 
-/// URLからビデオをダウンロード
-@_cdecl("download_video")
-private func download_video(
+private typealias DownloadStartFn = @convention(c) (
+    UnsafePointer<CChar>?,
+    UnsafePointer<CChar>?,
+    UnsafePointer<CChar>?,
+    UnsafePointer<CChar>?,
+    (@convention(c) (UnsafeMutableRawPointer?, UnsafePointer<NdProgress>?) -> Void)?,
+    (@convention(c) (UnsafeMutableRawPointer?, Int32, UnsafePointer<CChar>?) -> Void)?,
+    UnsafeMutableRawPointer?
+) -> OpaquePointer?
+private let downloadStartFn = coreFunction("nd_download_start", as: DownloadStartFn.self)
+
+/// ダウンロードを開始（タスクのハンドルを返す）
+@_cdecl("nd_download_start")
+private func nd_download_start(
     _ url: UnsafePointer<CChar>?,
     _ outputPath: UnsafePointer<CChar>?,
     _ filename: UnsafePointer<CChar>?,
     _ optionsJson: UnsafePointer<CChar>?,
-    _ resultPath: UnsafeMutablePointer<UnsafeMutablePointer<CChar>?>?
-) -> Int32 {
-    let libPath = Bundle.module.path(forResource: "libnextdownloader_core", ofType: "dylib")
-    
-    guard let lib = dlopen(libPath, RTLD_LAZY) else {
-        return -1
+    _ progressCallback: (@convention(c) (UnsafeMutableRawPointer?, UnsafePointer<NdProgress>?) -> Void)?,
+    _ completionCallback: (@convention(c) (UnsafeMutableRawPointer?, Int32, UnsafePointer<CChar>?) -> Void)?,
+    _ userData: UnsafeMutableRawPointer?
+) -> OpaquePointer? {
+    guard let function = downloadStartFn else {
+        return nil
     }
-    defer { dlclose(lib) }
-    
-    guard let fn = dlsym(lib, "download_video") else {
+    return function(url, outputPath, filename, optionsJson, progressCallback, completionCallback, userData)
+}
+
+private typealias CancelFn = @convention(c) (OpaquePointer?) -> Int32
+private let cancelFn = coreFunction("nd_cancel", as: CancelFn.self)
+
+/// ダウンロードをキャンセル（完了コールバックはキャンセルのエラーで呼ばれる）
+@_cdecl("nd_cancel")
+private func nd_cancel(_ task: OpaquePointer?) -> Int32 {
+    guard let function = cancelFn else {
         return -1
     }
-    
-    typealias DownloadVideoFn = @convention(c) (
-        UnsafePointer<CChar>?,
-        UnsafePointer<CChar>?,
-        UnsafePointer<CChar>?,
-        UnsafePointer<CChar>?,
-        UnsafeMutablePointer<UnsafeMutablePointer<CChar>?>?
-    ) -> Int32
-    let function = unsafeBitCast(fn, to: DownloadVideoFn.self)
-    
-    return function(url, outputPath, filename, optionsJson, resultPath)
+    return function(task)
+}
+
+private typealias TaskFreeFn = @convention(c) (OpaquePointer?) -> Void
+private let taskFreeFn = coreFunction("nd_task_free", as: TaskFreeFn.self)
+
+/// タスクのハンドルを解放
+@_cdecl("nd_task_free")
+private func nd_task_free(_ task: OpaquePointer?) {
+    taskFreeFn?(task)
 }
 
+private typealias FreeStringFn = @convention(c) (UnsafeMutablePointer<CChar>?) -> Void
+private let freeStringFn = coreFunction("nd_string_free", as: FreeStringFn.self)
+
 /// C文字列を解放
-@_cdecl("free_string")
-private func free_string(_ ptr: UnsafeMutablePointer<CChar>?) {
-    let libPath = Bundle.module.path(forResource: "libnextdownloader_core", ofType: "dylib")
-    
-    guard let lib = dlopen(libPath, RTLD_LAZY) else {
-        return
-    }
-    defer { dlclose(lib) }
-    
-    guard let fn = dlsym(lib, "free_string") else {
-        return
-    }
-    
-    typealias FreeStringFn = @convention(c) (UnsafeMutablePointer<CChar>?) -> Void
-    let function = unsafeBitCast(fn, to: FreeStringFn.self)
-    
-    function(ptr)
+@_cdecl("nd_string_free")
+private func nd_string_free(_ ptr: UnsafeMutablePointer<CChar>?) {
+    freeStringFn?(ptr)
 }
 
 // MARK: - dlopenのためのインポート
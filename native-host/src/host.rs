@@ -0,0 +1,499 @@
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use anyhow::{anyhow, bail, Result};
+use nextdownloader_core::{
+    Config,
+    DownloadManager,
+    Downloader,
+    DownloadOptions,
+    OptionOverrides,
+    ProgressInfo,
+    QueueConfig,
+    TaskStatus,
+    VideoFormat,
+    DEFAULT_FILENAME_TEMPLATE,
+    content_type_from_url,
+    sanitize_filename
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, mpsc};
+use crate::manifest::Allowlist;
+use crate::protocol;
+
+/// 切断時に実行中のタスクが一時停止するまで待つ最大時間
+const HANDOFF_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `connectNative` のポートで受け付ける要求
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum Request {
+    /// ダウンロードを開始し、進捗を通知する
+    Download(DownloadRequest),
+    /// タスクをキャンセル
+    Cancel { task_id: String },
+    /// タスクを一時停止
+    Pause { task_id: String },
+    /// 一時停止したタスクを再開
+    Resume { task_id: String },
+    /// キュー内のタスク一覧
+    List,
+}
+
+/// ダウンロード要求
+///
+/// `action` のないメッセージ（`sendNativeMessage` による `{url, title, referrer}`）も
+/// この形式として受け付ける。
+#[derive(Deserialize)]
+struct DownloadRequest {
+    url: String,
+    title: Option<String>,
+    referrer: Option<String>,
+    format: Option<String>,
+    quality: Option<String>,
+    output_path: Option<PathBuf>,
+    #[serde(default)]
+    headers: Vec<(String, String)>,
+    #[serde(default)]
+    cookies: Vec<(String, String)>,
+    user_agent: Option<String>,
+}
+
+/// ブラウザから起動されたホストを実行
+///
+/// `origin` は呼び出し元の拡張機能（`chrome-extension://<ID>/`）。
+pub async fn run(origin: &str) -> Result<()> {
+    let (sender, mut outgoing) = mpsc::unbounded_channel::<Value>();
+
+    // 進捗コールバックなど複数の箇所から送るため、標準出力への書き込みは1か所にまとめる
+    let writer = tokio::spawn(async move {
+        let mut stdout = tokio::io::stdout();
+        while let Some(message) = outgoing.recv().await {
+            if let Err(err) = protocol::write_message(&mut stdout, &message).await {
+                eprintln!("メッセージの送信に失敗しました: {}", err);
+                break;
+            }
+        }
+    });
+
+    let result = serve(origin, sender.clone()).await;
+    if let Err(err) = &result {
+        let _ = sender.send(json!({ "success": false, "type": "error", "error": err.to_string() }));
+    }
+
+    drop(sender);
+    let _ = writer.await;
+    result
+}
+
+/// メッセージを読み続け、切断されたら残りのタスクをワーカーに引き継ぐ
+///
+/// ワーカーなど他のプロセスがタスクを実行中の場合、登録したタスクはそのプロセスが実行する
+/// （タスクの実行ロックを保持するプロセスだけがタスクを開始する）。
+async fn serve(origin: &str, sender: mpsc::UnboundedSender<Value>) -> Result<()> {
+    if !Allowlist::load()?.allows(origin) {
+        bail!("許可されていない拡張機能からの接続です: {}", origin);
+    }
+
+    let config = Config::load()?;
+    let downloader = Arc::new(
+        DownloadManager::with_queue_config(QueueConfig::persistent())?
+            .with_tool_paths(config.tools.clone())
+            .with_bandwidth(config.bandwidth.clone())
+    );
+
+    // タスクの状態変化を通知
+    let mut events = downloader.subscribe();
+    let event_sender = sender.clone();
+    let forwarder = tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(task) => {
+                    let _ = event_sender.send(json!({ "type": "task", "task": task }));
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
+    let mut session = Session {
+        downloader,
+        config,
+        sender,
+        queued: false,
+    };
+    let mut stdin = tokio::io::stdin();
+    while let Some(message) = protocol::read_message(&mut stdin).await? {
+        session.handle(message).await;
+    }
+
+    forwarder.abort();
+    session.hand_off().await
+}
+
+/// 1回の接続の状態
+struct Session {
+    downloader: Arc<DownloadManager>,
+    config: Config,
+    sender: mpsc::UnboundedSender<Value>,
+    /// ワーカーに実行を任せるタスクを登録したか
+    queued: bool,
+}
+
+impl Session {
+    /// メッセージを処理して応答する
+    ///
+    /// 要求に `id` が含まれる場合は応答にも同じ `id` を付ける。
+    async fn handle(&mut self, message: Value) {
+        let id = message.get("id").cloned();
+
+        let mut reply = if message.get("action").is_some() {
+            match serde_json::from_value::<Request>(message) {
+                Ok(request) => self.handle_request(request).await.unwrap_or_else(|err| {
+                    json!({ "type": "error", "message": err.to_string() })
+                }),
+                Err(err) => json!({ "type": "error", "message": format!("不正な要求です: {}", err) }),
+            }
+        } else {
+            // 単発のメッセージは応答後すぐに切断されるため、実行はワーカーに任せる
+            match serde_json::from_value::<DownloadRequest>(message) {
+                Ok(request) => match self.add_task(request).await {
+                    Ok(task_id) => {
+                        self.queued = true;
+                        json!({ "success": true, "task_id": task_id })
+                    }
+                    Err(err) => json!({ "success": false, "error": err.to_string() }),
+                },
+                Err(err) => json!({ "success": false, "error": format!("不正な要求です: {}", err) }),
+            }
+        };
+
+        if let (Some(id), Some(reply)) = (id, reply.as_object_mut()) {
+            reply.insert("id".to_string(), id);
+        }
+        let _ = self.sender.send(reply);
+    }
+
+    async fn handle_request(&mut self, request: Request) -> Result<Value> {
+        match request {
+            Request::Download(request) => {
+                let task_id = self.start_task(request).await?;
+                self.queued = true;
+                Ok(json!({ "type": "queued", "task_id": task_id }))
+            }
+            Request::Cancel { task_id } => {
+                self.downloader.cancel_download(&task_id).await?;
+                Ok(json!({ "type": "ok", "task_id": task_id }))
+            }
+            Request::Pause { task_id } => {
+                self.downloader.pause(&task_id).await?;
+                Ok(json!({ "type": "ok", "task_id": task_id }))
+            }
+            Request::Resume { task_id } => {
+                self.downloader.resume(&task_id).await?;
+                self.downloader.start_pending();
+                self.queued = true;
+                Ok(json!({ "type": "ok", "task_id": task_id }))
+            }
+            Request::List => {
+                let tasks = self.downloader.list_tasks().await?;
+                Ok(json!({ "type": "tasks", "tasks": tasks }))
+            }
+        }
+    }
+
+    /// キューに登録のみ行う
+    async fn add_task(&self, request: DownloadRequest) -> Result<String> {
+        let (output_path, filename, options) = task_parameters(&request, &self.config)?;
+        Ok(self
+            .downloader
+            .add_task(&request.url, &output_path, &filename, Some(options), 0, None)
+            .await?)
+    }
+
+    /// このプロセスで実行を開始し、進捗をブラウザに通知する
+    async fn start_task(&self, request: DownloadRequest) -> Result<String> {
+        let (output_path, filename, options) = task_parameters(&request, &self.config)?;
+
+        // タスクIDは登録後に確定するため共有セルで受け渡す
+        let task_id_cell = Arc::new(OnceLock::<String>::new());
+        let callback_task_id = Arc::clone(&task_id_cell);
+        let progress_sender = self.sender.clone();
+        let progress_callback = Box::new(move |info: ProgressInfo| {
+            let _ = progress_sender.send(progress_message(
+                callback_task_id.get().map(String::as_str).unwrap_or_default(),
+                &info
+            ));
+        });
+
+        let task_id = self
+            .downloader
+            .enqueue(&request.url, &output_path, &filename, Some(options), 0, Some(progress_callback))
+            .await?;
+        let _ = task_id_cell.set(task_id.clone());
+        Ok(task_id)
+    }
+
+    /// 切断後も残りのダウンロードが続くよう、ワーカープロセスに引き継ぐ
+    ///
+    /// このプロセスで実行中のタスクは一時停止してから待機中に戻す。
+    /// 途中までのデータは出力先に残るため、ワーカーが続きから取得する。
+    async fn hand_off(self) -> Result<()> {
+        let mut handed_off = false;
+
+        // 一時停止したタスクや待機中のタスクをこのプロセスで開始しないようにする
+        self.downloader.stop_scheduling().await;
+
+        let own_pid = Some(std::process::id());
+        let running: Vec<String> = self
+            .downloader
+            .list_tasks()
+            .await?
+            .into_iter()
+            .filter(|task| task.status == TaskStatus::Running && task.owner_pid == own_pid)
+            .map(|task| task.id)
+            .collect();
+        for task_id in &running {
+            self.downloader.pause(task_id).await?;
+            let paused = tokio::time::timeout(HANDOFF_TIMEOUT, async {
+                while let Some(task) = self.downloader.task(task_id).await {
+                    if task.status != TaskStatus::Running {
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            })
+            .await;
+            if paused.is_err() {
+                eprintln!("タスク {} の一時停止を確認できませんでした", task_id);
+            }
+            self.downloader.resume(task_id).await?;
+            handed_off = true;
+        }
+
+        if handed_off || self.queued {
+            spawn_worker()?;
+        }
+        Ok(())
+    }
+}
+
+/// 要求と設定ファイルから出力先・ファイル名・オプションを決める
+///
+/// 要求で指定された値を設定ファイルより優先する。
+fn task_parameters(request: &DownloadRequest, config: &Config) -> Result<(PathBuf, String, DownloadOptions)> {
+    let format = match request.format.as_deref().map(str::to_lowercase).as_deref() {
+        None => None,
+        Some("mp4") => Some(VideoFormat::Mp4),
+        Some("mkv") => Some(VideoFormat::Mkv),
+        Some("mp3") => Some(VideoFormat::Mp3),
+        Some(other) => bail!("サポートされていないフォーマット: {}", other),
+    };
+
+    let flags = OptionOverrides {
+        format,
+        quality: request.quality.clone(),
+        headers: request.headers.iter().cloned().collect(),
+        cookies: request.cookies.iter().cloned().collect(),
+        user_agent: request.user_agent.clone(),
+        referer: request.referrer.clone(),
+        ..Default::default()
+    };
+    let content_type = content_type_from_url(&request.url);
+    let resolved = config.resolve(&request.url, content_type.as_ref(), None, &flags)?;
+
+    let download_dir = match resolved.output_dir {
+        Some(path) => path,
+        None => dirs::download_dir()
+            .or_else(dirs::home_dir)
+            .ok_or_else(|| anyhow!("保存先のディレクトリが見つかりません"))?,
+    };
+    let output_path = match &request.output_path {
+        Some(requested) => restrict_output_path(&download_dir, requested)?,
+        None => download_dir,
+    };
+    // ページのタイトルは1つのファイル名にし、テンプレートとして解釈されないよう波括弧をエスケープする
+    let filename = match (&request.title, resolved.filename_template) {
+        (Some(title), _) if !title.trim().is_empty() => {
+            sanitize_filename(title).replace('{', "{{").replace('}', "}}")
+        }
+        (_, Some(template)) => template,
+        _ => DEFAULT_FILENAME_TEMPLATE.to_string(),
+    };
+
+    Ok((output_path, filename, resolved.options))
+}
+
+/// 進捗通知のメッセージ
+fn progress_message(task_id: &str, info: &ProgressInfo) -> Value {
+    json!({
+        "type": "progress",
+        "task_id": task_id,
+        "phase": info.phase,
+        "progress": info.progress,
+        "downloaded_bytes": info.downloaded_bytes,
+        "total_bytes": info.total_bytes,
+        "speed": info.speed,
+        "eta": info.eta.map(|eta| eta.as_secs_f64()),
+    })
+}
+
+/// キューを実行するワーカープロセスをブラウザから切り離して起動
+fn spawn_worker() -> Result<()> {
+    let mut command = std::process::Command::new(std::env::current_exe()?);
+    command
+        .arg("worker")
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+
+    // ブラウザがホストを終了させる際に巻き込まれないよう別のプロセスグループにする
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+
+    command.spawn()?;
+    Ok(())
+}
+
+/// 拡張機能から指定された保存先を、設定した保存先のディレクトリの中に制限する
+///
+/// 相対パスは設定した保存先からの位置とし、外に出るパス（`..` を含むもの、
+/// 保存先の外の絶対パス）は受け付けない。
+fn restrict_output_path(download_dir: &Path, requested: &Path) -> Result<PathBuf> {
+    let path = download_dir.join(requested);
+    if requested.components().any(|component| component == Component::ParentDir) || !path.starts_with(download_dir) {
+        bail!(
+            "保存先は {} の中を指定してください: {}",
+            download_dir.display(),
+            requested.display()
+        );
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nextdownloader_core::DomainRule;
+
+    fn request(message: Value) -> DownloadRequest {
+        serde_json::from_value(message).unwrap()
+    }
+
+    #[test]
+    fn request_values_take_precedence_over_the_config() {
+        let config = Config {
+            defaults: OptionOverrides {
+                output_dir: Some(PathBuf::from("/config/downloads")),
+                format: Some(VideoFormat::Mkv),
+                user_agent: Some("ConfigAgent".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let request = request(json!({
+            "url": "https://example.com/video.mp4",
+            "title": "Title",
+            "referrer": "https://example.com/watch",
+            "format": "MP3",
+            "output_path": "/config/downloads/request",
+            "headers": [["X-Token", "secret"]],
+            "user_agent": "BrowserAgent",
+        }));
+
+        let (output_path, filename, options) = task_parameters(&request, &config).unwrap();
+
+        assert_eq!(output_path, PathBuf::from("/config/downloads/request"));
+        assert_eq!(filename, "Title");
+        assert_eq!(options.format, VideoFormat::Mp3);
+        assert_eq!(options.request.user_agent.as_deref(), Some("BrowserAgent"));
+        assert_eq!(options.request.referer.as_deref(), Some("https://example.com/watch"));
+        assert_eq!(options.request.headers, vec![("X-Token".to_string(), "secret".to_string())]);
+    }
+
+    #[test]
+    fn config_fills_in_values_missing_from_the_request() {
+        let config = Config {
+            defaults: OptionOverrides {
+                output_dir: Some(PathBuf::from("/config/downloads")),
+                filename_template: Some("{title}-{id}".to_string()),
+                ..Default::default()
+            },
+            rules: vec![DomainRule {
+                host: "*.example.com".to_string(),
+                options: OptionOverrides {
+                    format: Some(VideoFormat::Mkv),
+                    ..Default::default()
+                },
+            }],
+            ..Default::default()
+        };
+        let request = request(json!({ "url": "https://media.example.com/video.mp4", "title": "  " }));
+
+        let (output_path, filename, options) = task_parameters(&request, &config).unwrap();
+
+        assert_eq!(output_path, PathBuf::from("/config/downloads"));
+        // 空白だけのタイトルは使わずテンプレートにする
+        assert_eq!(filename, "{title}-{id}");
+        assert_eq!(options.format, VideoFormat::Mkv);
+
+        let (_, filename, _) = task_parameters(&request, &Config::default()).unwrap();
+        assert_eq!(filename, DEFAULT_FILENAME_TEMPLATE);
+    }
+
+    #[test]
+    fn page_titles_are_not_expanded_as_templates() {
+        let request = request(json!({ "url": "https://example.com/video.mp4", "title": "{id} {{x}}" }));
+
+        let (_, filename, _) = task_parameters(&request, &Config::default()).unwrap();
+
+        assert_eq!(filename, "{{id}} {{{{x}}}}");
+    }
+
+    #[test]
+    fn page_titles_stay_a_single_path_component() {
+        for (title, expected) in [("AC/DC Live", "AC_DC Live"), ("../..", ".._"), ("a\\..\\b: c?", "a_.._b_ c_")] {
+            let request = request(json!({ "url": "https://example.com/video.mp4", "title": title }));
+
+            let (_, filename, _) = task_parameters(&request, &Config::default()).unwrap();
+
+            assert_eq!(filename, expected, "{}", title);
+        }
+    }
+
+    #[test]
+    fn unsupported_formats_are_rejected() {
+        let request = request(json!({ "url": "https://example.com/video.mp4", "format": "avi" }));
+
+        let err = task_parameters(&request, &Config::default()).unwrap_err();
+
+        assert!(err.to_string().contains("avi"), "{}", err);
+    }
+
+    #[test]
+    fn output_paths_are_kept_inside_the_download_directory() {
+        let config = Config {
+            defaults: OptionOverrides {
+                output_dir: Some(PathBuf::from("/config/downloads")),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let output_path = |path: &str| {
+            task_parameters(&request(json!({ "url": "https://example.com/video.mp4", "output_path": path })), &config)
+                .map(|(output_path, _, _)| output_path)
+        };
+
+        assert_eq!(output_path("videos/music").unwrap(), PathBuf::from("/config/downloads/videos/music"));
+        assert_eq!(output_path("/config/downloads").unwrap(), PathBuf::from("/config/downloads"));
+        assert!(output_path("/etc").is_err());
+        assert!(output_path("/config/downloads-other").is_err());
+        assert!(output_path("../outside").is_err());
+        assert!(output_path("/config/downloads/../../etc").is_err());
+    }
+}
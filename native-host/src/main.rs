@@ -0,0 +1,134 @@
+use std::sync::Arc;
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use nextdownloader_core::{Config, DownloadManager, QueueConfig};
+
+mod host;
+mod manifest;
+mod protocol;
+
+use crate::manifest::{Allowlist, Browser};
+
+/// NextDownloader ネイティブメッセージングホスト
+#[derive(Parser)]
+#[clap(name = "nextdownloader-native-host")]
+#[clap(about = "ブラウザ拡張機能からのダウンロード要求を受け付けるネイティブメッセージングホスト", long_about = None)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// ブラウザにホストのマニフェストをインストール
+    Install {
+        /// 呼び出しを許可する拡張機能のID（複数指定可）
+        #[clap(short, long = "extension-id", required = true)]
+        extension_ids: Vec<String>,
+
+        /// インストール先のブラウザ（省略時はすべて）
+        #[clap(short, long, value_enum)]
+        browser: Vec<Browser>,
+    },
+
+    /// ブラウザからホストのマニフェストを削除
+    Uninstall {
+        /// 削除するブラウザ（省略時はすべて）
+        #[clap(short, long, value_enum)]
+        browser: Vec<Browser>,
+    },
+
+    /// ホストのマニフェストを表示
+    Manifest {
+        /// 呼び出しを許可する拡張機能のID（複数指定可）
+        #[clap(short, long = "extension-id", required = true)]
+        extension_ids: Vec<String>,
+    },
+
+    /// キューに登録されたダウンロードを実行（ホストが切断時に起動する）
+    #[clap(hide = true)]
+    Worker,
+}
+
+/// メイン関数
+#[tokio::main]
+async fn main() -> Result<()> {
+    // ブラウザは呼び出し元の拡張機能のオリジンを最初の引数にしてホストを起動する
+    if let Some(origin) = std::env::args().nth(1).filter(|arg| arg.starts_with("chrome-extension://")) {
+        return host::run(&origin).await;
+    }
+
+    let cli = Cli::parse();
+
+    match cli.command {
+        Commands::Install { extension_ids, browser } => {
+            install_command(&extension_ids, &browser)?;
+        }
+        Commands::Uninstall { browser } => {
+            for browser in browsers(&browser) {
+                match manifest::uninstall(*browser)? {
+                    Some(path) => println!("削除しました: {}", path.to_string_lossy()),
+                    None => println!("{:?} にはインストールされていません", browser),
+                }
+            }
+        }
+        Commands::Manifest { extension_ids } => {
+            let origins: Vec<String> = extension_ids.iter().map(|id| manifest::extension_origin(id)).collect();
+            let manifest = manifest::manifest(&host_path()?, &origins);
+            println!("{}", serde_json::to_string_pretty(&manifest)?);
+        }
+        Commands::Worker => {
+            worker_command().await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// インストールコマンドの実装
+fn install_command(extension_ids: &[String], browser: &[Browser]) -> Result<()> {
+    let mut allowlist = Allowlist::load()?;
+    for extension_id in extension_ids {
+        allowlist.add(manifest::extension_origin(extension_id));
+    }
+    allowlist.save().context("許可リストの保存に失敗しました")?;
+
+    let host_path = host_path()?;
+    for browser in browsers(browser) {
+        let path = manifest::install(*browser, &host_path, &allowlist.allowed_origins)?;
+        println!("インストールしました: {}", path.to_string_lossy());
+    }
+
+    Ok(())
+}
+
+/// ワーカーコマンドの実装
+///
+/// 複数のホストから起動されてもタスクを実行するのはキューの実行ロックを取得した1つだけにする。
+/// 他のプロセスが実行中の場合はその終了を待ち、その間に追加されたタスクを取りこぼさないようにする。
+async fn worker_command() -> Result<()> {
+    let config = Config::load().context("設定ファイルの読み込みに失敗しました")?;
+    let downloader = Arc::new(
+        DownloadManager::with_queue_config(QueueConfig::persistent())
+            .context("キューの読み込みに失敗しました")?
+            .with_tool_paths(config.tools)
+            .with_bandwidth(config.bandwidth)
+    );
+    downloader.run_until_idle().await?;
+
+    Ok(())
+}
+
+/// 対象のブラウザ（省略時はすべて）
+fn browsers(browser: &[Browser]) -> &[Browser] {
+    if browser.is_empty() {
+        &Browser::ALL
+    } else {
+        browser
+    }
+}
+
+/// マニフェストに記載するホストの実行ファイルのパス
+fn host_path() -> Result<std::path::PathBuf> {
+    Ok(std::env::current_exe()?.canonicalize()?)
+}
@@ -0,0 +1,165 @@
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// ネイティブメッセージングホストの名前（拡張機能の `sendNativeMessage` で指定する）
+pub const HOST_NAME: &str = "com.nextdownloader.app";
+
+/// マニフェストをインストールするブラウザ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Browser {
+    /// Google Chrome
+    Chrome,
+    /// Chromium
+    Chromium,
+}
+
+impl Browser {
+    /// すべてのブラウザ
+    pub const ALL: [Browser; 2] = [Browser::Chrome, Browser::Chromium];
+
+    /// ユーザー単位のマニフェストの配置先
+    pub fn manifest_dir(&self) -> Result<PathBuf> {
+        let config_dir = dirs::config_dir().ok_or_else(|| anyhow!("設定ディレクトリが見つかりません"))?;
+
+        #[cfg(target_os = "macos")]
+        let browser_dir = match self {
+            Browser::Chrome => "Google/Chrome",
+            Browser::Chromium => "Chromium",
+        };
+
+        #[cfg(not(target_os = "macos"))]
+        let browser_dir = match self {
+            Browser::Chrome => "google-chrome",
+            Browser::Chromium => "chromium",
+        };
+
+        Ok(config_dir.join(browser_dir).join("NativeMessagingHosts"))
+    }
+
+    /// マニフェストファイルのパス
+    pub fn manifest_path(&self) -> Result<PathBuf> {
+        Ok(self.manifest_dir()?.join(format!("{}.json", HOST_NAME)))
+    }
+}
+
+/// 拡張機能IDから `chrome-extension://<ID>/` 形式のオリジンを作成
+pub fn extension_origin(extension_id: &str) -> String {
+    format!("chrome-extension://{}/", extension_id)
+}
+
+/// ホストのマニフェストを作成
+pub fn manifest(host_path: &Path, allowed_origins: &[String]) -> serde_json::Value {
+    json!({
+        "name": HOST_NAME,
+        "description": "NextDownloader",
+        "path": host_path,
+        "type": "stdio",
+        "allowed_origins": allowed_origins,
+    })
+}
+
+/// マニフェストを書き込み、書き込んだパスを返す
+pub fn install(browser: Browser, host_path: &Path, allowed_origins: &[String]) -> Result<PathBuf> {
+    if cfg!(target_os = "windows") {
+        return Err(anyhow!("Windowsではレジストリへの登録が必要なため、この方法ではインストールできません"));
+    }
+
+    let path = browser.manifest_path()?;
+    std::fs::create_dir_all(browser.manifest_dir()?)?;
+    std::fs::write(&path, serde_json::to_string_pretty(&manifest(host_path, allowed_origins))?)
+        .with_context(|| format!("{} に書き込めませんでした", path.to_string_lossy()))?;
+    Ok(path)
+}
+
+/// マニフェストを削除し、削除したパスを返す
+pub fn uninstall(browser: Browser) -> Result<Option<PathBuf>> {
+    let path = browser.manifest_path()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    std::fs::remove_file(&path)?;
+    Ok(Some(path))
+}
+
+/// ホストの呼び出しを許可する拡張機能のオリジン
+///
+/// ブラウザもマニフェストの `allowed_origins` で確認するが、マニフェストが
+/// 書き換えられた場合に備えてホスト側でも確認する。
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Allowlist {
+    /// 許可するオリジン
+    pub allowed_origins: Vec<String>,
+}
+
+impl Allowlist {
+    /// 設定ファイルのパス
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("NextDownloader").join("native-host.json"))
+    }
+
+    /// 設定ファイルを読み込む（存在しない場合は空）
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::path().filter(|path| path.exists()) else {
+            return Ok(Self::default());
+        };
+        let json = std::fs::read_to_string(&path)?;
+        serde_json::from_str(&json).with_context(|| format!("{} の解析に失敗しました", path.to_string_lossy()))
+    }
+
+    /// 設定ファイルに保存
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path().ok_or_else(|| anyhow!("設定ディレクトリが見つかりません"))?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// オリジンを追加
+    pub fn add(&mut self, origin: String) {
+        if !self.allowed_origins.contains(&origin) {
+            self.allowed_origins.push(origin);
+        }
+    }
+
+    /// 許可されたオリジンか
+    pub fn allows(&self, origin: &str) -> bool {
+        self.allowed_origins.iter().any(|allowed| allowed == origin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowlist_matches_whole_origins_only() {
+        let mut allowlist = Allowlist::default();
+        assert!(!allowlist.allows("chrome-extension://abcdefghijklmnop/"));
+
+        allowlist.add(extension_origin("abcdefghijklmnop"));
+        allowlist.add(extension_origin("abcdefghijklmnop"));
+        assert_eq!(allowlist.allowed_origins, vec!["chrome-extension://abcdefghijklmnop/".to_string()]);
+
+        assert!(allowlist.allows("chrome-extension://abcdefghijklmnop/"));
+        assert!(!allowlist.allows("chrome-extension://abcdefghijklmno/"));
+        assert!(!allowlist.allows("chrome-extension://abcdefghijklmnopq/"));
+        assert!(!allowlist.allows("chrome-extension://ABCDEFGHIJKLMNOP/"));
+        assert!(!allowlist.allows(""));
+    }
+
+    #[test]
+    fn manifest_lists_the_allowed_origins() {
+        let origins = vec![extension_origin("abcdefghijklmnop")];
+        let manifest = manifest(Path::new("/opt/nextdownloader/native-host"), &origins);
+
+        assert_eq!(manifest["name"], HOST_NAME);
+        assert_eq!(manifest["type"], "stdio");
+        assert_eq!(manifest["path"], "/opt/nextdownloader/native-host");
+        assert_eq!(manifest["allowed_origins"], json!(["chrome-extension://abcdefghijklmnop/"]));
+    }
+}
@@ -0,0 +1,106 @@
+use anyhow::{bail, Result};
+use serde_json::Value;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// ブラウザから受け取るメッセージの上限（異常な長さで大量に確保しないため）
+const MAX_INCOMING_SIZE: u32 = 64 * 1024 * 1024;
+
+/// ブラウザに送れるメッセージの上限（Chromeの仕様で1MB）
+const MAX_OUTGOING_SIZE: usize = 1024 * 1024;
+
+/// 長さ（ネイティブのバイト順の32ビット）とJSON本体からなるメッセージを1つ読み込む
+///
+/// 入力が閉じられた（ブラウザ側で切断された）場合は `None` を返す。
+pub async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<Value>> {
+    let mut length = [0u8; 4];
+    match reader.read_exact(&mut length).await {
+        Ok(_) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+
+    let length = u32::from_ne_bytes(length);
+    if length > MAX_INCOMING_SIZE {
+        bail!("メッセージが大きすぎます（{}バイト）", length);
+    }
+
+    let mut body = vec![0u8; length as usize];
+    reader.read_exact(&mut body).await?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+/// メッセージを長さ付きで書き込む
+pub async fn write_message<W: AsyncWrite + Unpin>(writer: &mut W, message: &Value) -> Result<()> {
+    let body = serde_json::to_vec(message)?;
+    if body.len() > MAX_OUTGOING_SIZE {
+        bail!("送信するメッセージが大きすぎます（{}バイト）", body.len());
+    }
+
+    writer.write_all(&(body.len() as u32).to_ne_bytes()).await?;
+    writer.write_all(&body).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// 長さ付きのメッセージを組み立てる
+    fn frame(body: &[u8]) -> Vec<u8> {
+        let mut bytes = (body.len() as u32).to_ne_bytes().to_vec();
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    #[tokio::test]
+    async fn reads_consecutive_messages_until_the_input_is_closed() {
+        let mut input = frame(br#"{"url":"https://example.com/a.mp4"}"#);
+        input.extend(frame(br#"{"action":"list","id":1}"#));
+        let mut reader = input.as_slice();
+
+        assert_eq!(
+            read_message(&mut reader).await.unwrap(),
+            Some(json!({ "url": "https://example.com/a.mp4" }))
+        );
+        assert_eq!(read_message(&mut reader).await.unwrap(), Some(json!({ "action": "list", "id": 1 })));
+        assert_eq!(read_message(&mut reader).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_and_truncated_messages() {
+        // 本体を確保する前に長さで拒否する
+        let oversized = (MAX_INCOMING_SIZE + 1).to_ne_bytes();
+        let err = read_message(&mut oversized.as_slice()).await.unwrap_err();
+        assert!(err.to_string().contains("大きすぎます"), "{}", err);
+
+        // 長さより本体が短い
+        let mut truncated = frame(br#"{"action":"list"}"#);
+        truncated.truncate(10);
+        assert!(read_message(&mut truncated.as_slice()).await.is_err());
+
+        // JSONではない
+        assert!(read_message(&mut frame(b"not json").as_slice()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn writes_length_prefixed_messages() {
+        let message = json!({ "type": "queued", "task_id": "abc" });
+        let mut output = Vec::new();
+        write_message(&mut output, &message).await.unwrap();
+
+        assert_eq!(output, frame(&serde_json::to_vec(&message).unwrap()));
+        assert_eq!(read_message(&mut output.as_slice()).await.unwrap(), Some(message));
+    }
+
+    #[tokio::test]
+    async fn refuses_to_write_messages_over_the_browser_limit() {
+        let message = json!({ "tasks": "x".repeat(MAX_OUTGOING_SIZE) });
+        let mut output = Vec::new();
+
+        assert!(write_message(&mut output, &message).await.is_err());
+        // 途中まで書き込んでメッセージの境界を壊さない
+        assert!(output.is_empty());
+    }
+}